@@ -3,6 +3,7 @@
 
 pub mod acceptance;
 pub mod http_client;
+pub mod report_uploader;
 mod test_durations;
 pub mod test_routes;
 
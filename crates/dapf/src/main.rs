@@ -5,7 +5,9 @@ use anyhow::{anyhow, Context, Result};
 use clap::{builder::PossibleValue, Parser, Subcommand, ValueEnum};
 use dapf::{
     acceptance::{load_testing, LoadControlParams, LoadControlStride, TestOptions},
-    deduce_dap_version_from_url, response_to_anyhow, HttpClient,
+    deduce_dap_version_from_url,
+    report_uploader::ReportUploader,
+    response_to_anyhow, HttpClient,
 };
 use daphne::{
     constants::DapMediaType,
@@ -20,6 +22,7 @@ use prio::codec::{ParameterizedDecode, ParameterizedEncode};
 use rand::{thread_rng, Rng};
 use std::{
     io::{stdin, Read},
+    num::NonZeroUsize,
     path::PathBuf,
     process::Command,
     str::FromStr,
@@ -128,7 +131,8 @@ struct Cli {
 
 #[derive(Debug, Subcommand)]
 enum LeaderAction {
-    /// Upload a report to a DAP Leader using the JSON-formatted measurement provided on stdin.
+    /// Upload reports to a DAP Leader using the JSON-formatted measurements provided on stdin.
+    /// Measurements are read one after the other until stdin is closed.
     Upload {
         /// Base URL of the Leader
         #[clap(long, env)]
@@ -149,6 +153,15 @@ enum LeaderAction {
         /// DAP task ID (base64, URL-safe encoding)
         #[arg(short, long, env, value_parser = parse_id)]
         task_id: TaskId,
+
+        /// Number of reports to buffer before uploading them
+        #[arg(long, env, default_value = "1")]
+        max_buffered_reports: NonZeroUsize,
+
+        /// Number of seconds after which buffered reports are uploaded, even if fewer than
+        /// `max_buffered_reports` are pending
+        #[arg(long, env, default_value_t = 10)]
+        buffer_window_secs: u64,
     },
     /// Collect an aggregate result from the DAP Leader using the JSON-formatted batch selector
     /// provided on stdin.
@@ -366,6 +379,8 @@ impl ValueEnum for KemAlg {
         Some(match self.0 {
             HpkeKemId::X25519HkdfSha256 => PossibleValue::new("x25519_hkdf_sha256"),
             HpkeKemId::P256HkdfSha256 => PossibleValue::new("p256_hkdf_sha256"),
+            HpkeKemId::P521HkdfSha512 => PossibleValue::new("p521_hkdf_sha512"),
+            HpkeKemId::X448HkdfSha512 => PossibleValue::new("x448_hkdf_sha512"),
             HpkeKemId::NotImplemented(id) => unreachable!("unhandled HPKE KEM ID {id}"),
         })
     }
@@ -425,10 +440,6 @@ async fn handle_leader_actions(
     leader: LeaderAction,
     http_client: HttpClient,
 ) -> anyhow::Result<()> {
-    let now = SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_secs();
-
     match leader {
         LeaderAction::Upload {
             leader_url,
@@ -436,16 +447,9 @@ async fn handle_leader_actions(
             vdaf_config,
             certificate_file,
             task_id,
+            max_buffered_reports,
+            buffer_window_secs,
         } => {
-            // Read the measurement from stdin.
-            let mut buf = String::new();
-            stdin()
-                .lock()
-                .read_to_string(&mut buf)
-                .with_context(|| "failed to read measurement from stdin")?;
-            let measurement: DapMeasurement =
-                serde_json::from_str(&buf).with_context(|| "failed to parse JSON from stdin")?;
-
             // Get the Aggregators' HPKE configs.
             let leader_hpke_config = http_client
                 .get_hpke_config(&leader_url, certificate_file.as_deref())
@@ -461,41 +465,41 @@ async fn handle_leader_actions(
                 .swap_remove(0);
 
             let version = deduce_dap_version_from_url(&leader_url)?;
-            // Generate a report for the measurement.
-            let report = vdaf_config
-                .into_vdaf()
-                .produce_report(
-                    &[leader_hpke_config, helper_hpke_config],
-                    now,
-                    &task_id,
-                    measurement,
-                    version,
-                )
-                .with_context(|| "failed to produce report")?;
-
-            // Post the report to the Leader.
-            let mut headers = reqwest::header::HeaderMap::new();
-            headers.insert(
-                reqwest::header::CONTENT_TYPE,
-                reqwest::header::HeaderValue::from_str(
-                    DapMediaType::Report
-                        .as_str_for_version(version)
-                        .ok_or_else(|| anyhow!("invalid content-type for dap version"))?,
-                )
-                .expect("failecd to construct content-type header"),
+            let vdaf = vdaf_config.into_vdaf();
+            let mut uploader = ReportUploader::new(
+                max_buffered_reports,
+                Duration::from_secs(buffer_window_secs),
             );
-            let resp = http_client
-                .post(leader_url.join("upload")?)
-                .body(report.get_encoded_with_param(&version)?)
-                .headers(headers)
-                .send()
-                .await?;
-            if resp.status() == 400 {
-                let problem_details: ProblemDetails = serde_json::from_str(&resp.text().await?)
-                    .with_context(|| "unexpected response")?;
-                return Err(anyhow!(serde_json::to_string(&problem_details)?));
-            } else if resp.status() != 200 {
-                return Err(response_to_anyhow(resp).await);
+
+            // Read the measurements from stdin.
+            for measurement in
+                serde_json::Deserializer::from_reader(stdin()).into_iter::<DapMeasurement>()
+            {
+                let measurement = measurement.with_context(|| "failed to parse JSON from stdin")?;
+
+                // Generate a report for the measurement.
+                let now = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)?
+                    .as_secs();
+                let report = vdaf
+                    .produce_report(
+                        &[leader_hpke_config.clone(), helper_hpke_config.clone()],
+                        now,
+                        &task_id,
+                        measurement,
+                        version,
+                    )
+                    .with_context(|| "failed to produce report")?;
+
+                // Post the buffered reports to the Leader once the buffer is full or the window
+                // has elapsed.
+                if let Some(reports) = uploader.enqueue(report) {
+                    reports.upload(&http_client, &leader_url, version).await?;
+                }
+            }
+
+            if let Some(reports) = uploader.flush() {
+                reports.upload(&http_client, &leader_url, version).await?;
             }
 
             Ok(())
@@ -0,0 +1,201 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Client-side buffering of reports. High-frequency clients can enqueue reports as they are
+//! generated and upload them in batches, either once enough reports have accumulated or once the
+//! oldest buffered report has waited for long enough.
+
+use std::{
+    mem,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use daphne::{
+    constants::DapMediaType, error::aborts::ProblemDetails, messages::Report, DapVersion,
+};
+use prio::codec::ParameterizedEncode;
+use url::Url;
+
+use crate::{response_to_anyhow, HttpClient};
+
+/// A batch of reports flushed by a [`ReportUploader`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReportList {
+    pub reports: Vec<Report>,
+}
+
+impl From<Vec<Report>> for ReportList {
+    fn from(reports: Vec<Report>) -> Self {
+        Self { reports }
+    }
+}
+
+impl ReportList {
+    /// Upload the reports to the Leader. DAP has no message for uploading several reports at
+    /// once, so each report is sent in its own request, reusing the client's connection.
+    pub async fn upload(
+        self,
+        http_client: &HttpClient,
+        leader_url: &Url,
+        version: DapVersion,
+    ) -> anyhow::Result<()> {
+        let content_type = DapMediaType::Report
+            .as_str_for_version(version)
+            .ok_or_else(|| anyhow!("invalid content-type for dap version"))?;
+        let upload_url = leader_url.join("upload")?;
+        for report in self.reports {
+            let resp = http_client
+                .post(upload_url.clone())
+                .body(report.get_encoded_with_param(&version)?)
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .send()
+                .await?;
+            if resp.status() == 400 {
+                let problem_details: ProblemDetails = serde_json::from_str(&resp.text().await?)
+                    .with_context(|| "unexpected response")?;
+                return Err(anyhow!(serde_json::to_string(&problem_details)?));
+            } else if resp.status() != 200 && resp.status() != 201 {
+                return Err(response_to_anyhow(resp).await);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Buffers reports until either `max_reports` reports are pending or the oldest pending report
+/// has been buffered for `window`, whichever comes first.
+pub struct ReportUploader {
+    max_reports: NonZeroUsize,
+    window: Duration,
+    pending: Vec<Report>,
+    /// Time at which the oldest pending report was enqueued.
+    window_start: Option<Instant>,
+}
+
+impl ReportUploader {
+    pub fn new(max_reports: NonZeroUsize, window: Duration) -> Self {
+        Self {
+            max_reports,
+            window,
+            pending: Vec::new(),
+            window_start: None,
+        }
+    }
+
+    /// Number of reports waiting to be flushed.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Buffer a report. If this fills the buffer or the window has elapsed, then the buffered
+    /// reports are returned for upload.
+    pub fn enqueue(&mut self, report: Report) -> Option<ReportList> {
+        self.enqueue_at(report, Instant::now())
+    }
+
+    /// Return the buffered reports if the window has elapsed. Clients are expected to call this
+    /// periodically so that reports are not held back indefinitely when the upload rate drops.
+    pub fn poll(&mut self) -> Option<ReportList> {
+        self.poll_at(Instant::now())
+    }
+
+    /// Return the buffered reports, if any, regardless of the threshold or the window.
+    pub fn flush(&mut self) -> Option<ReportList> {
+        self.window_start = None;
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(mem::take(&mut self.pending).into())
+        }
+    }
+
+    fn enqueue_at(&mut self, report: Report, now: Instant) -> Option<ReportList> {
+        self.window_start.get_or_insert(now);
+        self.pending.push(report);
+        if self.pending.len() >= self.max_reports.get() {
+            self.flush()
+        } else {
+            self.poll_at(now)
+        }
+    }
+
+    fn poll_at(&mut self, now: Instant) -> Option<ReportList> {
+        match self.window_start {
+            Some(window_start) if now.duration_since(window_start) >= self.window => self.flush(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroUsize,
+        time::{Duration, Instant},
+    };
+
+    use daphne::messages::{HpkeCiphertext, Report, ReportId, ReportMetadata};
+
+    use super::ReportUploader;
+
+    fn report(id: u8) -> Report {
+        let ciphertext = HpkeCiphertext {
+            config_id: 0,
+            enc: Vec::new(),
+            payload: Vec::new(),
+        };
+        Report {
+            report_metadata: ReportMetadata {
+                id: ReportId([id; 16]),
+                time: 1_637_361_337,
+            },
+            public_share: Vec::new(),
+            encrypted_input_shares: [ciphertext.clone(), ciphertext],
+        }
+    }
+
+    #[test]
+    fn flush_when_window_elapses() {
+        let window = Duration::from_secs(10);
+        let mut uploader = ReportUploader::new(NonZeroUsize::new(5).unwrap(), window);
+        let start = Instant::now();
+
+        // Below the threshold and within the window, nothing is flushed.
+        assert_eq!(uploader.enqueue_at(report(0), start), None);
+        assert_eq!(
+            uploader.enqueue_at(report(1), start + Duration::from_secs(1)),
+            None
+        );
+        assert_eq!(uploader.poll_at(start + window / 2), None);
+        assert_eq!(uploader.pending(), 2);
+
+        // Once the window elapses, the buffered reports are flushed as one batch.
+        let list = uploader.poll_at(start + window).unwrap();
+        assert_eq!(list.reports, vec![report(0), report(1)]);
+        assert_eq!(uploader.pending(), 0);
+        assert_eq!(uploader.poll_at(start + 2 * window), None);
+    }
+
+    #[test]
+    fn flush_when_threshold_is_reached() {
+        let mut uploader =
+            ReportUploader::new(NonZeroUsize::new(3).unwrap(), Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert_eq!(uploader.enqueue_at(report(0), start), None);
+        assert_eq!(uploader.enqueue_at(report(1), start), None);
+        let list = uploader.enqueue_at(report(2), start).unwrap();
+        assert_eq!(list.reports, vec![report(0), report(1), report(2)]);
+
+        // The window restarts with the next report.
+        assert_eq!(
+            uploader.enqueue_at(report(3), start + Duration::from_secs(9)),
+            None
+        );
+        assert_eq!(uploader.poll_at(start + Duration::from_secs(10)), None);
+        assert_eq!(uploader.flush().unwrap().reports, vec![report(3)]);
+        assert_eq!(uploader.flush(), None);
+    }
+}
@@ -16,6 +16,9 @@ use url::Url;
 struct Config {
     service: DaphneServiceConfig,
     port: u16,
+    /// The port to serve the operator endpoints on, e.g. the metrics. They are not served if
+    /// unset.
+    admin_port: Option<u16>,
     storage_proxy: StorageProxyConfig,
 }
 
@@ -106,11 +109,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
 
     let role = config.service.role;
     // Configure the application
-    let app = App::new(config.storage_proxy, daphne_service_metrics, config.service)?;
+    let app = App::new(
+        config.storage_proxy,
+        daphne_service_metrics.clone(),
+        config.service,
+    )?;
 
     // create the router that will handle the protocol's http requests
     let router = router::new(role, app);
 
+    // create the router for the operator endpoints, served on a separate port
+    let admin_router = router::new_admin(daphne_service_metrics);
+
     // initialize tracing in a very default way.
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
@@ -123,10 +133,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Sync + Send>> {
     ))
     .serve(router.into_make_service());
 
+    let serve_admin = async {
+        match config.admin_port {
+            Some(port) => {
+                axum::Server::bind(&std::net::SocketAddr::new(
+                    "127.0.0.1".parse().unwrap(),
+                    port,
+                ))
+                .serve(admin_router.into_make_service())
+                .await
+            }
+            None => std::future::pending().await,
+        }
+    };
+
     let ctrl_c = tokio::signal::ctrl_c();
 
     tokio::select! {
         _ = serve => {}
+        _ = serve_admin => {}
         _ = ctrl_c => {}
     }
 
@@ -0,0 +1,120 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Support for gzip content-encoding (RFC 9110, Section 8.4.1.3) of DAP message bodies.
+
+use std::io::{self, Read, Write};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+/// Content-coding token for gzip.
+pub(crate) const GZIP: &str = "gzip";
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum GzipError {
+    #[error("malformed gzip body: {0}")]
+    Malformed(#[from] io::Error),
+    #[error("decompressed body exceeds the limit of {limit} bytes")]
+    TooLarge { limit: usize },
+}
+
+/// Returns true if the value of an `Accept-Encoding` header allows a gzip-encoded response. An
+/// entry for gzip takes precedence over the `*` wildcard.
+pub(crate) fn accepts_gzip(accept_encoding: &str) -> bool {
+    let mut gzip = None;
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or_default().trim();
+        let accepted = !params.any(|param| {
+            param
+                .trim()
+                .strip_prefix("q=")
+                .and_then(|q| q.trim().parse::<f32>().ok())
+                .is_some_and(|q| q <= 0.0)
+        });
+        if coding.eq_ignore_ascii_case(GZIP) {
+            gzip = Some(accepted);
+        } else if coding == "*" {
+            wildcard = Some(accepted);
+        }
+    }
+    gzip.or(wildcard).unwrap_or(false)
+}
+
+/// Encode `data` as a gzip stream.
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a `Vec` can't fail.
+    encoder.write_all(data).expect("failed to gzip-encode body");
+    encoder.finish().expect("failed to gzip-encode body")
+}
+
+/// Decode a gzip stream. Decompression stops, and the body is rejected, as soon as the output
+/// would exceed `limit` bytes.
+pub(crate) fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>, GzipError> {
+    let mut inflated = Vec::new();
+    // Read one byte past the limit so that a body that exceeds it can be told apart from one that
+    // fills it exactly.
+    GzDecoder::new(data)
+        .take(u64::try_from(limit).unwrap_or(u64::MAX).saturating_add(1))
+        .read_to_end(&mut inflated)?;
+    if inflated.len() > limit {
+        return Err(GzipError::TooLarge { limit });
+    }
+    Ok(inflated)
+}
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::{accepts_gzip, gunzip, gzip, GzipError};
+
+    #[test]
+    fn roundtrip() {
+        let data = b"hello, DAP! ".repeat(1000);
+        let compressed = gzip(&data);
+        assert!(compressed.len() < data.len());
+        assert_eq!(gunzip(&compressed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn decompression_bomb_is_rejected() {
+        // 16 MiB of zeros compresses to a few kilobytes.
+        let data = vec![0; 16 * 1024 * 1024];
+        let compressed = gzip(&data);
+        assert!(compressed.len() < 64 * 1024);
+        assert_matches!(
+            gunzip(&compressed, 1024 * 1024),
+            Err(GzipError::TooLarge { limit: 1_048_576 })
+        );
+    }
+
+    #[test]
+    fn corrupted_body_is_rejected() {
+        let mut compressed = gzip(b"some payload");
+        let crc_byte = compressed.len() - 8;
+        compressed[crc_byte] ^= 1;
+        assert_matches!(gunzip(&compressed, 1024), Err(GzipError::Malformed(..)));
+
+        assert_matches!(
+            gunzip(b"not a gzip stream", 1024),
+            Err(GzipError::Malformed(..))
+        );
+        assert_matches!(gunzip(&[0x1f, 0x8b], 1024), Err(GzipError::Malformed(..)));
+    }
+
+    #[test]
+    fn accept_encoding() {
+        assert!(accepts_gzip("gzip"));
+        assert!(accepts_gzip("deflate, GZIP;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("identity"));
+        assert!(!accepts_gzip("br, gzip;q=0"));
+        assert!(!accepts_gzip("*, gzip;q=0"));
+        assert!(!accepts_gzip("gzip;q=0, *"));
+        assert!(accepts_gzip("*;q=0, gzip"));
+        assert!(!accepts_gzip(""));
+    }
+}
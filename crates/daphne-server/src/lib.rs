@@ -1,21 +1,42 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, PoisonError},
+    time::SystemTime,
+};
 
 use daphne::{
     audit_log::{AuditLog, NoopAuditLog},
     auth::BearerToken,
-    roles::leader::in_memory_leader::InMemoryLeaderState,
-    DapError,
+    fatal_error,
+    fixture::AggregationFixture,
+    hpke::HpkeDecrypter,
+    messages::{AggregationJobId, Base64Encode, Duration, ReportId, TaskId, Time},
+    roles::{leader::in_memory_leader::InMemoryLeaderState, DapAggregator},
+    DapAbort, DapError, DapRequest, DapSender, DapVersion,
+};
+use daphne_service_utils::{
+    auth::DaphneAuth, config::DaphneServiceConfig, ed25519_signature::Ed25519SigningKey,
+    metrics::DaphneServiceMetrics,
 };
-use daphne_service_utils::{config::DaphneServiceConfig, metrics::DaphneServiceMetrics};
 use futures::lock::Mutex;
 use serde::{Deserialize, Serialize};
-use storage_proxy_connection::{kv, Do, Kv};
+#[cfg(feature = "test-utils")]
+pub use storage_proxy_connection::storage::InMemoryStorage;
+use storage_proxy_connection::{
+    kv::{self, KvGetOptions},
+    Do, Kv,
+};
+pub use storage_proxy_connection::{
+    storage::{DapStorage, HttpStorage},
+    Error as StorageError, RetryPolicy,
+};
 use tokio::sync::RwLock;
 use url::Url;
 
+mod content_encoding;
 mod roles;
 pub mod router;
 mod storage_proxy_connection;
@@ -28,7 +49,8 @@ mod storage_proxy_connection;
 ///
 /// It can be constructed from:
 /// - a `url` that points to a cloudflare worker which serves as proxy for the storage
-///   implementation.
+///   implementation. Key-value storage can be moved to another backend with
+///   [`App::set_storage`], but durable objects are always accessed through the proxy.
 /// - an implementation of [`DaphneServiceMetrics`].
 /// - a [`DaphneServiceConfig`].
 ///
@@ -43,6 +65,7 @@ mod storage_proxy_connection;
 /// let storage_proxy_settings = StorageProxyConfig {
 ///     url: Url::parse("http://example.com").unwrap(),
 ///     auth_token: "some-token".into(),
+///     retry: Default::default(),
 /// };
 /// let registry = prometheus::Registry::new();
 /// let daphne_service_metrics = DaphnePromServiceMetrics::register(&registry).unwrap();
@@ -53,6 +76,19 @@ mod storage_proxy_connection;
 ///     supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
 ///     allow_taskprov: true,
 ///     default_num_agg_span_shards: NonZeroUsize::new(2).unwrap(),
+///     require_https: false,
+///     max_reports_per_agg_job: None,
+///     max_prio2_dimension: None,
+///     helper_abort_policy: Default::default(),
+///     allow_partial_batch: false,
+///     agg_share_report_count_tolerance: 0,
+///     collect_aggregation_deadline: None,
+///     future_report_handling: Default::default(),
+///     future_report_skew_tolerance: 0,
+///     report_retention: None,
+///     record_agg_job_diagnostics: false,
+///     agg_job_report_concurrency: NonZeroUsize::new(1).unwrap(),
+///     query_type_strictness: Default::default(),
 /// };
 /// let service_config = DaphneServiceConfig {
 ///     role: DapRole::Helper,
@@ -62,7 +98,14 @@ mod storage_proxy_connection;
 ///     default_version: DapVersion::Draft09,
 ///     report_storage_epoch_duration: 300,
 ///     report_storage_max_future_time_skew: 300,
+///     max_agg_init_body_bytes: None,
+///     max_decompressed_body_bytes: 64 * 1024 * 1024,
+///     agg_job_resp_cache_secs: None,
+///     agg_share_resp_cache_secs: None,
+///     max_hpke_configs: None,
 ///     signing_key: None,
+///     collection_signing_key: None,
+///     agg_share_signing_key: None,
 /// };
 /// let app = App::new(storage_proxy_settings, daphne_service_metrics, service_config)?;
 ///
@@ -75,21 +118,49 @@ mod storage_proxy_connection;
 pub struct App {
     storage_proxy_config: StorageProxyConfig,
     http: reqwest::Client,
+    storage: Box<dyn DapStorage>,
     cache: RwLock<kv::Cache>,
     metrics: Box<dyn DaphneServiceMetrics>,
     service_config: DaphneServiceConfig,
     audit_log: Box<dyn AuditLog + Send + Sync>,
+    clock: Box<dyn Clock + Send + Sync>,
 
     /// Volatile memory for the Leader, including the work queue, pending reports, and pending
     /// colleciton requests. Note that in a production Leader, it is necessary to store this state
     /// across requsets.
     test_leader_state: Arc<Mutex<InMemoryLeaderState>>,
+
+    /// Helper: The time of the last activity of each aggregation job that is being processed.
+    agg_job_activity: std::sync::Mutex<HashMap<(TaskId, AggregationJobId), Time>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Source of the current time. [`App`] reads the system clock unless another clock is set with
+/// [`App::set_clock`].
+pub trait Clock {
+    /// Get the current time (number of seconds since the beginning of UNIX time).
+    fn now(&self) -> Time;
+}
+
+/// The system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Time {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .expect("now should always be after unix epoch")
+            .as_secs()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StorageProxyConfig {
     pub url: Url,
     pub auth_token: BearerToken,
+
+    /// How requests to purge storage or check that it is ready are retried.
+    #[serde(default)]
+    pub retry: RetryPolicy,
 }
 
 impl router::DaphneService for App {
@@ -100,6 +171,18 @@ impl router::DaphneService for App {
     fn signing_key(&self) -> Option<&p256::ecdsa::SigningKey> {
         self.service_config.signing_key.as_ref()
     }
+
+    fn collection_signing_key(&self) -> Option<&Ed25519SigningKey> {
+        self.service_config.collection_signing_key.as_ref()
+    }
+
+    fn agg_share_signing_key(&self) -> Option<&Ed25519SigningKey> {
+        self.service_config.agg_share_signing_key.as_ref()
+    }
+
+    fn max_decompressed_body_bytes(&self) -> usize {
+        self.service_config.max_decompressed_body_bytes
+    }
 }
 
 impl App {
@@ -112,14 +195,18 @@ impl App {
     where
         M: DaphneServiceMetrics + 'static,
     {
+        let http = reqwest::Client::new();
         Ok(Self {
+            storage: Box::new(HttpStorage::new(storage_proxy_config.clone(), http.clone())),
             storage_proxy_config,
-            http: reqwest::Client::new(),
+            http,
             cache: Default::default(),
             metrics: Box::new(daphne_service_metrics),
             audit_log: Box::new(NoopAuditLog),
+            clock: Box::new(SystemClock),
             service_config,
             test_leader_state: Default::default(),
+            agg_job_activity: Default::default(),
         })
     }
 
@@ -130,11 +217,968 @@ impl App {
         self.audit_log = Box::new(audit_log);
     }
 
+    /// Replace the key-value storage, which by default is the storage proxy.
+    pub fn set_storage<S>(&mut self, storage: S)
+    where
+        S: DapStorage + 'static,
+    {
+        self.storage = Box::new(storage);
+    }
+
+    /// Replace the clock used to determine the current time, e.g., to control time in tests.
+    pub fn set_clock<C>(&mut self, clock: C)
+    where
+        C: Clock + Send + Sync + 'static,
+    {
+        self.clock = Box::new(clock);
+    }
+
+    /// Return the IDs of the tasks for which reports were uploaded under the given HPKE config
+    /// within the last report storage epoch, in sorted order. When the config is rotated, Clients
+    /// of these tasks may still be sending reports encrypted under it.
+    pub async fn tasks_using_hpke_config(
+        &self,
+        version: DapVersion,
+        config_id: u8,
+    ) -> Result<Vec<TaskId>, DapError> {
+        let keys = self
+            .kv()
+            .list_under::<kv::prefix::HpkeConfigUsage>(&format!("{version}/{config_id}/"))
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to list hpke config usage in kv"))?;
+
+        let mut task_ids = keys
+            .iter()
+            .filter_map(|task_id| {
+                hex::decode(task_id)
+                    .ok()
+                    .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                    .map(TaskId)
+            })
+            .collect::<Vec<_>>();
+        task_ids.sort_unstable();
+        Ok(task_ids)
+    }
+
+    /// Helper: Return the aggregation jobs that started, but haven't finished, and whose last
+    /// activity is more than `older_than` seconds ago, in sorted order.
+    pub fn list_stalled_agg_jobs(
+        &self,
+        older_than: Duration,
+    ) -> Result<Vec<(TaskId, AggregationJobId)>, DapError> {
+        let now = self.get_current_time();
+        let agg_job_activity = self
+            .agg_job_activity
+            .lock()
+            .map_err(|_| fatal_error!(err = "agg_job_activity poisoned"))?;
+        let mut stalled = agg_job_activity
+            .iter()
+            .filter(|(_, last_activity)| now.saturating_sub(**last_activity) > older_than)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        stalled.sort_unstable();
+        Ok(stalled)
+    }
+
+    /// Return the IDs of the tasks provisioned in KV, in sorted order. Expired tasks are excluded,
+    /// including any that expire after their key is listed.
+    pub async fn list_task_ids(&self) -> Result<Vec<TaskId>, DapError> {
+        let now = self.get_current_time();
+        let keys = self
+            .kv()
+            .list::<kv::prefix::TaskConfig>()
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to list task configs in kv"))?;
+
+        let mut task_ids = Vec::with_capacity(keys.len());
+        for key in keys {
+            let Some(task_id) = hex::decode(&key)
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .map(TaskId)
+            else {
+                tracing::warn!(key, "skipping malformed task config key");
+                continue;
+            };
+
+            // The config is fetched rather than trusting the listing so that a task that expires
+            // mid-scan is handled the same way as one that expired before it.
+            let task_config = self
+                .kv()
+                .get_cloned::<kv::prefix::TaskConfig>(&task_id, &KvGetOptions::default())
+                .await
+                .map_err(
+                    |e| fatal_error!(err = ?e, "failed to get a task config from kv: {task_id}"),
+                )?;
+            if task_config.is_some_and(|task_config| task_config.not_after > now) {
+                task_ids.push(task_id);
+            }
+        }
+        task_ids.sort_unstable();
+        Ok(task_ids)
+    }
+
+    /// Capture the inputs to the aggregation of the given reports so that it can be replayed
+    /// offline. The reports must not have been aggregated yet. The Leader's input shares are
+    /// decrypted with this instance's HPKE receiver configs and the Helper's with
+    /// `helper_decrypter`.
+    pub async fn capture_aggregation_fixture(
+        &self,
+        helper_decrypter: &impl HpkeDecrypter,
+        task_id: &TaskId,
+        report_ids: &[ReportId],
+    ) -> Result<AggregationFixture, DapError> {
+        let task_config = self
+            .get_task_config_for(task_id)
+            .await?
+            .ok_or_else(|| fatal_error!(err = format!("unrecognized task {task_id}")))?;
+
+        let reports = self
+            .test_leader_state
+            .lock()
+            .await
+            .get_reports(task_id, report_ids);
+        if let Some(missing) = report_ids.iter().find(|id| {
+            !reports
+                .iter()
+                .any(|report| report.report_metadata.id == **id)
+        }) {
+            return Err(fatal_error!(
+                err = format!("report {} not found", missing.to_base64url())
+            ));
+        }
+
+        AggregationFixture::capture(self, helper_decrypter, task_id, &task_config, reports).await
+    }
+
+    /// The name of the VDAF configured for the given task. A Collector can compare this with the
+    /// VDAF it expects using
+    /// [`VdafConfig::check_task_vdaf_name`](daphne::vdaf::VdafConfig::check_task_vdaf_name).
+    pub async fn task_vdaf_name(&self, task_id: &TaskId) -> Result<String, DapError> {
+        let task_config = self
+            .get_task_config_for(task_id)
+            .await?
+            .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
+        Ok(task_config.vdaf.to_string())
+    }
+
+    /// Replace the bearer token with which the Leader authenticates to the Helper for the given
+    /// task. If `grace_period` is set, the previous token is still accepted for that many seconds.
+    pub async fn rotate_leader_bearer_token(
+        &self,
+        task_id: &TaskId,
+        new_token: BearerToken,
+        grace_period: Option<Duration>,
+    ) -> Result<(), DapError> {
+        use kv::prefix::{LeaderBearerToken, RetiredLeaderBearerToken};
+        self.rotate_bearer_token::<LeaderBearerToken, RetiredLeaderBearerToken>(
+            task_id,
+            new_token,
+            grace_period,
+        )
+        .await
+    }
+
+    /// Replace the bearer token with which the Collector authenticates to the Leader for the given
+    /// task. If `grace_period` is set, the previous token is still accepted for that many seconds.
+    pub async fn rotate_collector_bearer_token(
+        &self,
+        task_id: &TaskId,
+        new_token: BearerToken,
+        grace_period: Option<Duration>,
+    ) -> Result<(), DapError> {
+        use kv::prefix::{CollectorBearerToken, RetiredCollectorBearerToken};
+        self.rotate_bearer_token::<CollectorBearerToken, RetiredCollectorBearerToken>(
+            task_id,
+            new_token,
+            grace_period,
+        )
+        .await
+    }
+
+    async fn rotate_bearer_token<P, R>(
+        &self,
+        task_id: &TaskId,
+        new_token: BearerToken,
+        grace_period: Option<Duration>,
+    ) -> Result<(), DapError>
+    where
+        P: kv::KvPrefix<Key = TaskId, Value = BearerToken>,
+        R: kv::KvPrefix<Key = TaskId, Value = kv::prefix::RetiredBearerToken>,
+    {
+        let old_token = self
+            .kv()
+            .get_cloned::<P>(task_id, &KvGetOptions::default())
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to get the bearer token"))?
+            .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
+
+        // Always overwrite the retired token, even without a grace period, so that a token retired
+        // by an earlier rotation stops being accepted.
+        self.kv()
+            .put::<R>(
+                task_id,
+                kv::prefix::RetiredBearerToken {
+                    token: old_token,
+                    valid_until: self.clock.now() + grace_period.unwrap_or(0),
+                },
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to put the retired bearer token"))?;
+
+        self.kv()
+            .put::<P>(task_id, new_token)
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to put the bearer token"))
+    }
+
+    /// Check whether the request carries a bearer token that was rotated out by
+    /// [`App::rotate_leader_bearer_token`] or [`App::rotate_collector_bearer_token`] and whose
+    /// grace period has not yet elapsed.
+    pub(crate) async fn retired_bearer_token_accepted(
+        &self,
+        req: &DapRequest<DaphneAuth>,
+    ) -> Result<bool, DapError> {
+        let (Some(task_id), Some(got)) = (
+            req.task_id.as_ref(),
+            req.sender_auth
+                .as_ref()
+                .and_then(|auth| auth.bearer_token.as_ref()),
+        ) else {
+            return Ok(false);
+        };
+
+        let retired = match req.sender() {
+            Some(DapSender::Leader) => {
+                self.kv()
+                    .get_cloned::<kv::prefix::RetiredLeaderBearerToken>(
+                        task_id,
+                        &KvGetOptions::default(),
+                    )
+                    .await
+            }
+            Some(DapSender::Collector) => {
+                self.kv()
+                    .get_cloned::<kv::prefix::RetiredCollectorBearerToken>(
+                        task_id,
+                        &KvGetOptions::default(),
+                    )
+                    .await
+            }
+            _ => return Ok(false),
+        }
+        .map_err(|e| fatal_error!(err = ?e, "failed to get the retired bearer token"))?;
+
+        Ok(retired
+            .is_some_and(|retired| retired.token == *got && self.clock.now() < retired.valid_until))
+    }
+
+    /// Record that a report for the given task was uploaded under the given HPKE config. The
+    /// record expires after the report storage epoch, or at `task_expiration` if that is sooner,
+    /// so that a config stops counting as in use once Clients have stopped using it. A later
+    /// upload under the config creates a new record.
+    pub(crate) async fn record_hpke_config_usage(
+        &self,
+        version: DapVersion,
+        config_id: u8,
+        task_id: &TaskId,
+        task_expiration: Time,
+    ) -> Result<(), DapError> {
+        let key = kv::prefix::HpkeConfigUsageKey {
+            version,
+            config_id,
+            task_id: *task_id,
+        };
+
+        // Only the first upload under the config is written, the others are served by the cache
+        // until the record expires.
+        if self
+            .kv()
+            .get::<kv::prefix::HpkeConfigUsage>(&key, &KvGetOptions::default())
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to get hpke config usage from kv"))?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let now = self.get_current_time();
+        self.kv()
+            .put_if_not_exists_with_expiration::<kv::prefix::HpkeConfigUsage>(
+                &key,
+                now,
+                now.saturating_add(self.service_config.report_storage_epoch_duration)
+                    .min(task_expiration),
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to put hpke config usage in kv"))?;
+        Ok(())
+    }
+
+    /// Helper: Record activity on the given aggregation job at the current time. The job is
+    /// tracked until the returned guard is dropped, i.e., until the request processing it
+    /// returns, fails, panics or is cancelled.
+    pub(crate) fn record_agg_job_activity(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<AggJobActivityGuard<'_>, DapError> {
+        let agg_job = (*task_id, *agg_job_id);
+        let now = self.get_current_time();
+        self.agg_job_activity
+            .lock()
+            .map_err(|_| fatal_error!(err = "agg_job_activity poisoned"))?
+            .insert(agg_job, now);
+        Ok(AggJobActivityGuard { app: self, agg_job })
+    }
+
     pub(crate) fn durable(&self) -> Do<'_> {
         Do::new(&self.storage_proxy_config, &self.http)
     }
 
     pub(crate) fn kv(&self) -> Kv<'_> {
-        Kv::new(&self.storage_proxy_config, &self.http, &self.cache)
+        Kv::new(&*self.storage, &self.cache)
+    }
+}
+
+/// Helper: Stops tracking the activity of an aggregation job when dropped. Returned by
+/// [`App::record_agg_job_activity`].
+#[must_use]
+pub(crate) struct AggJobActivityGuard<'a> {
+    app: &'a App,
+    agg_job: (TaskId, AggregationJobId),
+}
+
+impl Drop for AggJobActivityGuard<'_> {
+    fn drop(&mut self) {
+        // Removing an entry can't leave the map in an inconsistent state, so a poisoned lock is
+        // safe to use here.
+        self.app
+            .agg_job_activity
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.agg_job);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        num::{NonZeroU32, NonZeroUsize},
+        sync::{
+            atomic::{AtomicU64, AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use assert_matches::assert_matches;
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{Method, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        Router,
+    };
+    use daphne::{
+        auth::BearerToken,
+        constants::DapMediaType,
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{AggregationJobId, TaskId, Time},
+        roles::{leader::handle_upload_req, DapAggregator, DapReportInitializer},
+        testing::AggregationJobTest,
+        vdaf::{Prio3Config, VdafConfig},
+        DapAbort, DapAggregateResult, DapAggregationParam, DapError, DapGlobalConfig,
+        DapMeasurement, DapRequest, DapResource, DapTaskParameters, DapVersion,
+    };
+    use daphne_service_utils::{
+        auth::DaphneAuth, config::DaphneServiceConfig, metrics::DaphnePromServiceMetrics, DapRole,
+    };
+    use prio::codec::ParameterizedEncode;
+    use url::Url;
+
+    use crate::{
+        storage_proxy_connection::kv, App, Clock, DapStorage, HttpStorage, InMemoryStorage,
+        RetryPolicy, StorageError, StorageProxyConfig,
+    };
+
+    /// A clock whose time is set by the test.
+    pub(crate) struct MockClock(pub(crate) Arc<AtomicU64>);
+
+    impl Clock for MockClock {
+        fn now(&self) -> Time {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    pub(crate) fn test_app() -> App {
+        test_app_with_storage_proxy(Url::parse("http://example.com").unwrap())
+    }
+
+    pub(crate) fn test_app_with_storage_proxy(url: Url) -> App {
+        let registry = prometheus::Registry::new();
+        test_app_with(url, DaphnePromServiceMetrics::register(&registry).unwrap())
+    }
+
+    pub(crate) fn test_app_with(url: Url, metrics: DaphnePromServiceMetrics) -> App {
+        App::new(
+            StorageProxyConfig {
+                url,
+                auth_token: "some-token".into(),
+                retry: Default::default(),
+            },
+            metrics,
+            DaphneServiceConfig {
+                role: DapRole::Leader,
+                global: DapGlobalConfig {
+                    max_batch_duration: 360_000,
+                    min_batch_interval_start: 259_200,
+                    max_batch_interval_end: 259_200,
+                    supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
+                    allow_taskprov: false,
+                    default_num_agg_span_shards: NonZeroUsize::new(2).unwrap(),
+                    require_https: false,
+                    max_reports_per_agg_job: None,
+                    max_prio2_dimension: None,
+                    helper_abort_policy: Default::default(),
+                    allow_partial_batch: false,
+                    agg_share_report_count_tolerance: 0,
+                    collect_aggregation_deadline: None,
+                    future_report_handling: Default::default(),
+                    future_report_skew_tolerance: 0,
+                    report_retention: None,
+                    record_agg_job_diagnostics: false,
+                    agg_job_report_concurrency: NonZeroUsize::new(1).unwrap(),
+                    query_type_strictness: Default::default(),
+                },
+                base_url: None,
+                taskprov: None,
+                default_version: DapVersion::Draft09,
+                report_storage_epoch_duration: 300,
+                report_storage_max_future_time_skew: 300,
+                max_agg_init_body_bytes: None,
+                max_decompressed_body_bytes: 64 * 1024 * 1024,
+                agg_job_resp_cache_secs: None,
+                agg_share_resp_cache_secs: None,
+                max_hpke_configs: None,
+                signing_key: None,
+                collection_signing_key: None,
+                agg_share_signing_key: None,
+            },
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn tasks_using_hpke_config() {
+        let mut app = test_app();
+        app.set_storage(InMemoryStorage::default());
+        let now = 1_637_359_200; // a multiple of the time precision
+        app.set_clock(MockClock(Arc::new(AtomicU64::new(now))));
+
+        let hpke_receiver_configs =
+            [7, 8].map(|id| HpkeReceiverConfig::gen(id, HpkeKemId::X25519HkdfSha256).unwrap());
+
+        // Reports for the first two tasks are uploaded under config 7, the third under config 8.
+        let mut tasks = Vec::new();
+        for (task_info, hpke_receiver_config) in [
+            (b"task 1", &hpke_receiver_configs[0]),
+            (b"task 2", &hpke_receiver_configs[0]),
+            (b"task 3", &hpke_receiver_configs[1]),
+        ] {
+            let (task_config, task_id, _taskprov_advertisement) = DapTaskParameters::default()
+                .to_config_with_taskprov(
+                    task_info.to_vec(),
+                    now,
+                    &[0; 32],
+                    &hpke_receiver_config.config,
+                )
+                .unwrap();
+            app.kv()
+                .put::<kv::prefix::TaskConfig>(&task_id, task_config.clone())
+                .await
+                .unwrap();
+            tasks.push((task_id, task_config, hpke_receiver_config));
+        }
+        let version = tasks[0].1.version;
+        app.kv()
+            .put::<kv::prefix::HpkeReceiverConfigSet>(&version, hpke_receiver_configs.to_vec())
+            .await
+            .unwrap();
+
+        for (task_id, task_config, hpke_receiver_config) in &tasks {
+            for _ in 0..2 {
+                let report = task_config
+                    .vdaf
+                    .produce_report(
+                        &[
+                            hpke_receiver_config.config.clone(),
+                            hpke_receiver_config.config.clone(),
+                        ],
+                        now,
+                        task_id,
+                        DapMeasurement::U32Vec(vec![1; 10]),
+                        task_config.version,
+                    )
+                    .unwrap();
+                let req = DapRequest {
+                    version: task_config.version,
+                    media_type: Some(DapMediaType::Report),
+                    task_id: Some(*task_id),
+                    resource: DapResource::Undefined,
+                    payload: report.get_encoded_with_param(&task_config.version).unwrap(),
+                    sender_auth: None,
+                    taskprov: None,
+                };
+                handle_upload_req(&app, &req).await.unwrap();
+            }
+        }
+
+        let mut task_ids = tasks
+            .iter()
+            .map(|(task_id, ..)| *task_id)
+            .collect::<Vec<_>>();
+        task_ids[..2].sort_unstable();
+        assert_eq!(
+            app.tasks_using_hpke_config(version, 7).await.unwrap(),
+            task_ids[..2]
+        );
+        assert_eq!(
+            app.tasks_using_hpke_config(version, 8).await.unwrap(),
+            task_ids[2..]
+        );
+        assert!(app
+            .tasks_using_hpke_config(version, 9)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn capture_aggregation_fixture() {
+        let mut app = test_app();
+        app.set_storage(InMemoryStorage::default());
+        let now = 1_637_359_200; // a multiple of the time precision
+        app.set_clock(MockClock(Arc::new(AtomicU64::new(now))));
+
+        // This instance only holds the Leader's HPKE receiver config.
+        let leader_hpke_receiver_config =
+            HpkeReceiverConfig::gen(7, HpkeKemId::X25519HkdfSha256).unwrap();
+        let helper_hpke_receiver_config =
+            HpkeReceiverConfig::gen(8, HpkeKemId::X25519HkdfSha256).unwrap();
+        let (task_config, task_id, _taskprov_advertisement) = DapTaskParameters::default()
+            .to_config_with_taskprov(
+                b"some task".to_vec(),
+                now,
+                &[0; 32],
+                &leader_hpke_receiver_config.config,
+            )
+            .unwrap();
+        app.kv()
+            .put::<kv::prefix::TaskConfig>(&task_id, task_config.clone())
+            .await
+            .unwrap();
+        app.kv()
+            .put::<kv::prefix::HpkeReceiverConfigSet>(
+                &task_config.version,
+                vec![leader_hpke_receiver_config.clone()],
+            )
+            .await
+            .unwrap();
+
+        let mut report_ids = Vec::new();
+        for _ in 0..2 {
+            let report = task_config
+                .vdaf
+                .produce_report(
+                    &[
+                        leader_hpke_receiver_config.config.clone(),
+                        helper_hpke_receiver_config.config.clone(),
+                    ],
+                    now,
+                    &task_id,
+                    DapMeasurement::U32Vec(vec![1; 10]),
+                    task_config.version,
+                )
+                .unwrap();
+            report_ids.push(report.report_metadata.id);
+            let req = DapRequest {
+                version: task_config.version,
+                media_type: Some(DapMediaType::Report),
+                task_id: Some(task_id),
+                resource: DapResource::Undefined,
+                payload: report.get_encoded_with_param(&task_config.version).unwrap(),
+                sender_auth: None,
+                taskprov: None,
+            };
+            handle_upload_req(&app, &req).await.unwrap();
+        }
+
+        // The Helper's input shares can't be decrypted with the Leader's config.
+        app.capture_aggregation_fixture(&leader_hpke_receiver_config, &task_id, &report_ids)
+            .await
+            .unwrap_err();
+
+        let fixture = app
+            .capture_aggregation_fixture(&helper_hpke_receiver_config, &task_id, &report_ids)
+            .await
+            .unwrap();
+        assert_eq!(fixture.task_id, task_id);
+        assert_eq!(fixture.reports.len(), 2);
+        assert_eq!(
+            fixture.replay(DapAggregationParam::Empty).await.unwrap(),
+            DapAggregateResult::U32Vec(vec![2; 10])
+        );
+    }
+
+    #[test]
+    fn current_time_is_read_from_clock() {
+        let mut app = test_app();
+        let now = Arc::new(AtomicU64::new(1_637_361_337));
+        app.set_clock(MockClock(now.clone()));
+        assert_eq!(app.get_current_time(), 1_637_361_337);
+
+        now.store(1_637_361_400, Ordering::Relaxed);
+        assert_eq!(app.get_current_time(), 1_637_361_400);
+        assert_eq!(app.valid_report_time_range(), 1_637_361_100..1_637_361_700);
+    }
+
+    /// Serve a minimal stand-in for the KV API of the storage proxy. Return its URL.
+    pub(crate) fn spawn_kv_storage_proxy() -> Url {
+        async fn handle(
+            State(kv): State<Arc<Mutex<HashMap<String, Bytes>>>>,
+            method: Method,
+            uri: Uri,
+            body: Bytes,
+        ) -> Response {
+            let mut kv = kv.lock().unwrap();
+            let key = uri.path().to_string();
+            match method {
+                Method::GET => match kv.get(&key) {
+                    Some(value) => value.clone().into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                },
+                Method::PUT if kv.contains_key(&key) => StatusCode::CONFLICT.into_response(),
+                Method::POST | Method::PUT => {
+                    kv.insert(key, body);
+                    StatusCode::OK.into_response()
+                }
+                _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+            }
+        }
+
+        let router = Router::new()
+            .fallback(handle)
+            .with_state(Arc::new(Mutex::new(HashMap::new())));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        url
+    }
+
+    /// Serve a stand-in for the storage proxy that fails the first `failures` requests with an
+    /// internal server error and accepts the following ones. Return its URL and the number of
+    /// requests it received.
+    fn spawn_flaky_storage_proxy(failures: usize) -> (Url, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .fallback(move |State(requests): State<Arc<AtomicUsize>>| async move {
+                if requests.fetch_add(1, Ordering::Relaxed) < failures {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                } else {
+                    StatusCode::OK
+                }
+            })
+            .with_state(requests.clone());
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        (url, requests)
+    }
+
+    fn http_storage_with_retries(url: Url) -> HttpStorage {
+        HttpStorage::new(
+            StorageProxyConfig {
+                url,
+                auth_token: "some-token".into(),
+                retry: RetryPolicy {
+                    max_attempts: NonZeroU32::new(3).unwrap(),
+                    backoff_ms: 1,
+                },
+            },
+            reqwest::Client::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn storage_proxy_transient_failures_are_retried() {
+        let (url, requests) = spawn_flaky_storage_proxy(2);
+        http_storage_with_retries(url).ready().await.unwrap();
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn storage_proxy_retry_backoff_is_capped() {
+        assert_eq!(
+            RetryPolicy::next_backoff(Duration::from_millis(100)),
+            Duration::from_millis(200)
+        );
+        assert_eq!(
+            RetryPolicy::next_backoff(RetryPolicy::MAX_BACKOFF),
+            RetryPolicy::MAX_BACKOFF
+        );
+        assert_eq!(
+            RetryPolicy::next_backoff(Duration::MAX),
+            RetryPolicy::MAX_BACKOFF
+        );
+    }
+
+    #[tokio::test]
+    async fn storage_proxy_retries_give_up_after_max_attempts() {
+        let (url, requests) = spawn_flaky_storage_proxy(usize::MAX);
+        assert_matches!(
+            http_storage_with_retries(url).delete_all().await,
+            Err(StorageError::Reqwest(e)) if e.status() == Some(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+        assert_eq!(requests.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn rotate_bearer_tokens() {
+        let mut app = test_app_with_storage_proxy(spawn_kv_storage_proxy());
+        let now = Arc::new(AtomicU64::new(1_637_361_337));
+        app.set_clock(MockClock(now.clone()));
+
+        let (task_config, task_id, _taskprov_advertisement) = DapTaskParameters::default()
+            .to_config_with_taskprov(
+                b"cool task".to_vec(),
+                0,
+                &[0; 32],
+                &daphne::hpke::HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+                    .unwrap()
+                    .config,
+            )
+            .unwrap();
+        app.kv()
+            .put::<kv::prefix::LeaderBearerToken>(&task_id, "old leader token".into())
+            .await
+            .unwrap();
+        app.kv()
+            .put::<kv::prefix::CollectorBearerToken>(&task_id, "old collector token".into())
+            .await
+            .unwrap();
+
+        let (app, task_config) = (&app, &task_config);
+        let is_authorized = move |media_type, token: &str| {
+            let req = DapRequest {
+                version: task_config.version,
+                media_type: Some(media_type),
+                task_id: Some(task_id),
+                resource: DapResource::Undefined,
+                payload: Vec::new(),
+                sender_auth: Some(DaphneAuth {
+                    bearer_token: Some(BearerToken::from(token)),
+                    cf_tls_client_auth: None,
+                }),
+                taskprov: None,
+            };
+            async move {
+                app.unauthorized_reason(task_config, &req)
+                    .await
+                    .unwrap()
+                    .is_none()
+            }
+        };
+        let leader = DapMediaType::AggregationJobInitReq;
+        let collector = DapMediaType::CollectReq;
+
+        app.rotate_leader_bearer_token(&task_id, "new leader token".into(), Some(60))
+            .await
+            .unwrap();
+        app.rotate_collector_bearer_token(&task_id, "new collector token".into(), Some(60))
+            .await
+            .unwrap();
+
+        // Within the grace period, both the old and new tokens are accepted.
+        now.fetch_add(59, Ordering::Relaxed);
+        assert!(is_authorized(leader, "old leader token").await);
+        assert!(is_authorized(leader, "new leader token").await);
+        assert!(is_authorized(collector, "old collector token").await);
+        assert!(is_authorized(collector, "new collector token").await);
+        assert!(!is_authorized(leader, "old collector token").await);
+
+        // Once the grace period is over, only the new tokens are accepted.
+        now.fetch_add(1, Ordering::Relaxed);
+        assert!(!is_authorized(leader, "old leader token").await);
+        assert!(is_authorized(leader, "new leader token").await);
+        assert!(!is_authorized(collector, "old collector token").await);
+        assert!(is_authorized(collector, "new collector token").await);
+    }
+
+    #[tokio::test]
+    async fn upload_bearer_token() {
+        let mut app = test_app_with_storage_proxy(spawn_kv_storage_proxy());
+        let now = 1_637_359_200; // a multiple of the time precision
+        app.set_clock(MockClock(Arc::new(AtomicU64::new(now))));
+
+        let hpke_receiver_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256).unwrap();
+        let (task_config, task_id, _taskprov_advertisement) = DapTaskParameters::default()
+            .to_config_with_taskprov(
+                b"cool task".to_vec(),
+                now,
+                &[0; 32],
+                &hpke_receiver_config.config,
+            )
+            .unwrap();
+        app.kv()
+            .put::<kv::prefix::HpkeReceiverConfigSet>(
+                &task_config.version,
+                vec![hpke_receiver_config.clone()],
+            )
+            .await
+            .unwrap();
+        app.kv()
+            .put::<kv::prefix::TaskConfig>(&task_id, task_config.clone())
+            .await
+            .unwrap();
+
+        let (app, task_config) = (&app, &task_config);
+        let upload = move |token: Option<&str>| {
+            let report = task_config
+                .vdaf
+                .produce_report(
+                    &[
+                        hpke_receiver_config.config.clone(),
+                        hpke_receiver_config.config.clone(),
+                    ],
+                    now,
+                    &task_id,
+                    DapMeasurement::U32Vec(vec![1; 10]),
+                    task_config.version,
+                )
+                .unwrap();
+            let req = DapRequest {
+                version: task_config.version,
+                media_type: Some(DapMediaType::Report),
+                task_id: Some(task_id),
+                resource: DapResource::Undefined,
+                payload: report.get_encoded_with_param(&task_config.version).unwrap(),
+                sender_auth: Some(DaphneAuth {
+                    bearer_token: token.map(BearerToken::from),
+                    cf_tls_client_auth: None,
+                }),
+                taskprov: None,
+            };
+            async move { handle_upload_req(app, &req).await }
+        };
+
+        // Uploads are not authenticated unless the task requires it.
+        upload(None).await.unwrap();
+
+        app.kv()
+            .put::<kv::prefix::UploadBearerToken>(&task_id, "upload token".into())
+            .await
+            .unwrap();
+        assert_matches!(
+            upload(None).await,
+            Err(DapError::Abort(DapAbort::UnauthorizedRequest { .. }))
+        );
+        assert_matches!(
+            upload(Some("some other token")).await,
+            Err(DapError::Abort(DapAbort::UnauthorizedRequest { .. }))
+        );
+        upload(Some("upload token")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn collect_with_mismatched_vdaf() {
+        let t = AggregationJobTest::new(
+            &VdafConfig::Prio3(Prio3Config::Sum { bits: 8 }),
+            HpkeKemId::X25519HkdfSha256,
+            DapVersion::Draft09,
+        );
+        let mut app = test_app();
+        app.set_storage(InMemoryStorage::default());
+        app.set_clock(MockClock(Arc::new(AtomicU64::new(t.now()))));
+        assert_matches!(
+            app.task_vdaf_name(t.task_id()).await,
+            Err(DapError::Abort(DapAbort::UnrecognizedTask { .. }))
+        );
+        app.kv()
+            .put::<kv::prefix::TaskConfig>(t.task_id(), t.task_config().clone())
+            .await
+            .unwrap();
+
+        let reports = t.produce_reports(vec![DapMeasurement::U64(23), DapMeasurement::U64(19)]);
+        let (batch_selector, report_count, encrypted_agg_shares) = t
+            .collect_reports(&DapAggregationParam::Empty, reports)
+            .await;
+        let task_vdaf_name = app.task_vdaf_name(t.task_id()).await.unwrap();
+
+        // A Collector expecting a Histogram is stopped before it decodes the Sum result.
+        let expected_vdaf = VdafConfig::Prio3(Prio3Config::Histogram {
+            length: 4,
+            chunk_length: 2,
+        });
+        let err = expected_vdaf
+            .check_task_vdaf_name(&task_vdaf_name)
+            .unwrap_err();
+        assert!(err.to_string().contains(&task_vdaf_name), "{err}");
+
+        // A Collector expecting the task's VDAF decodes the result.
+        t.task_config()
+            .vdaf
+            .check_task_vdaf_name(&task_vdaf_name)
+            .unwrap();
+        assert_eq!(
+            t.consume_encrypted_agg_shares(
+                &batch_selector,
+                report_count,
+                &DapAggregationParam::Empty,
+                encrypted_agg_shares,
+            )
+            .await,
+            DapAggregateResult::U128(42)
+        );
+    }
+
+    #[test]
+    fn list_stalled_agg_jobs() {
+        let mut app = test_app();
+        let now = Arc::new(AtomicU64::new(1_637_361_337));
+        app.set_clock(MockClock(now.clone()));
+        let task_id = TaskId([1; 32]);
+        let (stalled, finished, active) = (
+            AggregationJobId([1; 16]),
+            AggregationJobId([2; 16]),
+            AggregationJobId([3; 16]),
+        );
+
+        let _stalled_guard = app.record_agg_job_activity(&task_id, &stalled).unwrap();
+        let finished_guard = app.record_agg_job_activity(&task_id, &finished).unwrap();
+        now.fetch_add(30, Ordering::Relaxed);
+        let _active_guard = app.record_agg_job_activity(&task_id, &active).unwrap();
+        drop(finished_guard);
+        assert!(app.list_stalled_agg_jobs(60).unwrap().is_empty());
+
+        now.fetch_add(31, Ordering::Relaxed);
+        assert_eq!(app.list_stalled_agg_jobs(60).unwrap(), [(task_id, stalled)]);
+
+        // Activity on a job resets its timer.
+        let _stalled_guard = app.record_agg_job_activity(&task_id, &stalled).unwrap();
+        assert!(app.list_stalled_agg_jobs(60).unwrap().is_empty());
     }
 }
@@ -1,7 +1,7 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use std::{borrow::Cow, future::ready, num::NonZeroUsize, ops::Range, time::SystemTime};
+use std::{borrow::Cow, future::ready, num::NonZeroUsize, ops::Range};
 
 use axum::async_trait;
 use daphne::{
@@ -104,9 +104,10 @@ impl DapAggregator<DaphneAuth> for crate::App {
                     .send(),
             );
         }
-        let responses: Vec<DapAggregateShare> = try_join_all(requests)
-            .await
-            .map_err(|e| fatal_error!(err = ?e, "failed to get agg shares from durable objects"))?;
+        let responses: Vec<DapAggregateShare> = try_join_all(requests).await.map_err(|e| {
+            fatal_error!(err = ?e, "failed to get agg shares from durable objects")
+                .with_kind(e.kind())
+        })?;
         let mut agg_share = DapAggregateShare::default();
         for agg_share_delta in responses {
             agg_share.merge(agg_share_delta)?;
@@ -141,9 +142,9 @@ impl DapAggregator<DaphneAuth> for crate::App {
             );
         }
 
-        try_join_all(requests)
-            .await
-            .map_err(|e| fatal_error!(err = ?e, "failed to mark agg shares as collected"))?;
+        try_join_all(requests).await.map_err(|e| {
+            fatal_error!(err = ?e, "failed to mark agg shares as collected").with_kind(e.kind())
+        })?;
         Ok(())
     }
 
@@ -167,7 +168,10 @@ impl DapAggregator<DaphneAuth> for crate::App {
             if let Some(unauthorized_reason) =
                 self.bearer_token_authorized(task_config, req).await?
             {
-                return Ok(Some(unauthorized_reason));
+                // The token may have been rotated out recently.
+                if !self.retired_bearer_token_accepted(req).await? {
+                    return Ok(Some(unauthorized_reason));
+                }
             }
             authorized = true;
         }
@@ -216,11 +220,27 @@ impl DapAggregator<DaphneAuth> for crate::App {
                 &opt,
             )
             .await
-            .map_err(|e| fatal_error!(err = ?e, "failed to get global override for the default_num_agg_span_shards"))?
+            .map_err(|e| fatal_error!(err = ?e, "failed to get global override for the default_num_agg_span_shards").with_kind(e.kind()))?
         {
             global_config.default_num_agg_span_shards = default_num_agg_span_shards;
         }
 
+        // "global_config/override/allow_partial_batch"
+        if let Some(allow_partial_batch) = self
+            .kv()
+            .get_cloned::<kv::prefix::GlobalConfigOverride<bool>>(
+                &kv::prefix::GlobalOverrides::AllowPartialBatch,
+                &opt,
+            )
+            .await
+            .map_err(|e| {
+                fatal_error!(err = ?e, "failed to get global override for allow_partial_batch")
+                    .with_kind(e.kind())
+            })?
+        {
+            global_config.allow_partial_batch = allow_partial_batch;
+        }
+
         Ok(global_config)
     }
 
@@ -248,7 +268,10 @@ impl DapAggregator<DaphneAuth> for crate::App {
             .kv()
             .get_cloned::<kv::prefix::TaskprovOptInParam>(task_id, &KvGetOptions::default())
             .await
-            .map_err(|e| fatal_error!(err = ?e, "failed to get TaskprovOptInParam from kv"))?
+            .map_err(|e| {
+                fatal_error!(err = ?e, "failed to get TaskprovOptInParam from kv")
+                    .with_kind(e.kind())
+            })?
         {
             Ok(task_config.into_opted_in(&param))
         } else {
@@ -292,7 +315,10 @@ impl DapAggregator<DaphneAuth> for crate::App {
                     expiration_time,
                 )
                 .await
-                .map_err(|e| fatal_error!(err = ?e, "failed to put the a task config in kv"))?;
+                .map_err(|e| {
+                    fatal_error!(err = ?e, "failed to put the a task config in kv")
+                        .with_kind(e.kind())
+                })?;
         } else {
             self.kv()
                 .only_cache_put::<kv::prefix::TaskConfig>(task_id, task_config)
@@ -308,14 +334,14 @@ impl DapAggregator<DaphneAuth> for crate::App {
         self.kv()
             .get_cloned::<kv::prefix::TaskConfig>(task_id, &KvGetOptions::default())
             .await
-            .map_err(|e| fatal_error!(err = ?e, "failed to get a task config from kv: {task_id}"))
+            .map_err(|e| {
+                fatal_error!(err = ?e, "failed to get a task config from kv: {task_id}")
+                    .with_kind(e.kind())
+            })
     }
 
     fn get_current_time(&self) -> Time {
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+        self.clock.now()
     }
 
     async fn is_batch_overlapping(
@@ -347,9 +373,10 @@ impl DapAggregator<DaphneAuth> for crate::App {
                 .buffer_unordered(usize::MAX)
                 .try_any(ready)
                 .await
-                .map_err(
-                    |e| fatal_error!(err = ?e, "failed to check if agg shares are collected"),
-                )?,
+                .map_err(|e| {
+                    fatal_error!(err = ?e, "failed to check if agg shares are collected")
+                        .with_kind(e.kind())
+                })?,
         )
     }
 
@@ -377,7 +404,9 @@ impl DapAggregator<DaphneAuth> for crate::App {
                         )
                         .send::<DapAggregateShare>()
                         .await
-                        .map_err(|e| fatal_error!(err = ?e, "failed to get an agg share"))?
+                        .map_err(|e| {
+                            fatal_error!(err = ?e, "failed to get an agg share").with_kind(e.kind())
+                        })?
                         .empty(),
                 )
             })
@@ -401,10 +430,7 @@ impl DapAggregator<DaphneAuth> for crate::App {
 #[async_trait]
 impl DapReportInitializer for crate::App {
     fn valid_report_time_range(&self) -> Range<messages::Time> {
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .expect("now should always be after unix epoch")
-            .as_secs();
+        let now = self.clock.now();
 
         let start = now.saturating_sub(self.service_config.report_storage_epoch_duration);
         let end = now.saturating_add(self.service_config.report_storage_max_future_time_skew);
@@ -470,6 +496,33 @@ impl HpkeProvider for crate::App {
             .ok_or_else(|| fatal_error!(err = "there are no hpke configs in kv!!", %version))
     }
 
+    async fn get_hpke_config_list_for(
+        &self,
+        version: DapVersion,
+        _task_id: Option<&TaskId>,
+    ) -> Result<Vec<HpkeConfig>, DapError> {
+        // The receiver configs are stored in order of preference.
+        let hpke_configs = self
+            .kv()
+            .peek::<kv::prefix::HpkeReceiverConfigSet, _, _>(
+                &version,
+                &KvGetOptions::default(),
+                |config_list| {
+                    config_list
+                        .iter()
+                        .map(|receiver| receiver.config.clone())
+                        .collect::<Vec<_>>()
+                },
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to get the hpke config list"))?
+            .unwrap_or_default();
+        if hpke_configs.is_empty() {
+            return Err(fatal_error!(err = "there are no hpke configs in kv!!", %version));
+        }
+        Ok(hpke_configs)
+    }
+
     async fn can_hpke_decrypt(&self, task_id: &TaskId, config_id: u8) -> Result<bool, DapError> {
         let version = self
             .get_task_config_for(task_id)
@@ -507,7 +560,8 @@ impl HpkeDecrypter for crate::App {
             .as_ref()
             .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?
             .version;
-        self.kv()
+        let receiver = self
+            .kv()
             .peek::<kv::prefix::HpkeReceiverConfigSet, _, _>(
                 &version,
                 &KvGetOptions::default(),
@@ -515,13 +569,21 @@ impl HpkeDecrypter for crate::App {
                     config_list
                         .iter()
                         .find(|receiver| receiver.config.id == ciphertext.config_id)
-                        .map(|receiver| receiver.decrypt(info, aad, ciphertext))
+                        .cloned()
                 },
             )
             .await
             .map_err(|e| fatal_error!(err = ?e, "failed to get the hpke config"))?
             .flatten()
-            .ok_or(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))?
+            .ok_or(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))?;
+
+        // Decryption is CPU-bound, so run it on the blocking pool. This lets the Helper decrypt
+        // the reports of an aggregation job in parallel, see
+        // `DapGlobalConfig::agg_job_report_concurrency`.
+        let (info, aad, ciphertext) = (info.to_vec(), aad.to_vec(), ciphertext.clone());
+        tokio::task::spawn_blocking(move || receiver.decrypt(&info, &aad, &ciphertext))
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "decrypting the input share panicked"))?
     }
 }
 
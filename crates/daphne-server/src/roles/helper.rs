@@ -2,8 +2,192 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use axum::async_trait;
-use daphne::roles::DapHelper;
+use daphne::{
+    fatal_error,
+    messages::{AggregationJobId, Duration, TaskId},
+    roles::{
+        AggShareReqKey, AggregationJobDiagnostics, DapAggregator, DapHelper,
+        StoredAggregateShareResp, StoredAggregationJobResp,
+    },
+    DapError,
+};
 use daphne_service_utils::auth::DaphneAuth;
 
+use crate::storage_proxy_connection::kv::{self, KvGetOptions};
+
+/// Number of seconds for which the diagnostics of an aggregation job are kept. They are fetched by
+/// the Leader as soon as it gets the response to the job.
+const AGG_JOB_DIAGNOSTICS_TTL_SECS: Duration = 24 * 60 * 60;
+
 #[async_trait]
-impl DapHelper<DaphneAuth> for crate::App {}
+impl DapHelper<DaphneAuth> for crate::App {
+    async fn abort_agg_job(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<(), DapError> {
+        let task_config = self
+            .get_task_config_for(task_id)
+            .await?
+            .ok_or_else(|| fatal_error!(err = "task not found", %task_id))?;
+
+        // The Helper doesn't store any preparation state for an aggregation job, so it suffices to
+        // record that the job was aborted. The record is kept until the task expires. KV has no
+        // deletion, so the stored response and diagnostics of the job, if any, are left to expire
+        // on their own. The response is no longer served, as the job is checked first.
+        self.kv()
+            .put_with_expiration::<kv::prefix::AbortedAggregationJob>(
+                &kv::prefix::TaskAggJobId {
+                    task_id: *task_id,
+                    agg_job_id: *agg_job_id,
+                },
+                self.get_current_time(),
+                task_config.not_after,
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to mark aggregation job as aborted"))
+    }
+
+    async fn agg_job_aborted(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<bool, DapError> {
+        // Aggregation jobs are only aborted through the internal test routes. Skip the lookup
+        // otherwise, so that aggregation job requests don't pay for it.
+        if !cfg!(feature = "test-utils") {
+            return Ok(false);
+        }
+
+        Ok(self
+            .kv()
+            .get_cloned::<kv::prefix::AbortedAggregationJob>(
+                &kv::prefix::TaskAggJobId {
+                    task_id: *task_id,
+                    agg_job_id: *agg_job_id,
+                },
+                &KvGetOptions::default(),
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to get aborted aggregation job from kv"))?
+            .is_some())
+    }
+
+    async fn put_agg_job_resp(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        agg_job_resp: StoredAggregationJobResp,
+    ) -> Result<(), DapError> {
+        let Some(cache_secs) = self.service_config.agg_job_resp_cache_secs else {
+            return Ok(());
+        };
+
+        self.kv()
+            .put_with_expiration::<kv::prefix::AggregationJobResp>(
+                &kv::prefix::TaskAggJobId {
+                    task_id: *task_id,
+                    agg_job_id: *agg_job_id,
+                },
+                agg_job_resp,
+                self.get_current_time().saturating_add(cache_secs),
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to put aggregation job response in kv"))
+    }
+
+    async fn get_agg_job_resp(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<StoredAggregationJobResp>, DapError> {
+        if self.service_config.agg_job_resp_cache_secs.is_none() {
+            return Ok(None);
+        }
+
+        self.kv()
+            .get_cloned::<kv::prefix::AggregationJobResp>(
+                &kv::prefix::TaskAggJobId {
+                    task_id: *task_id,
+                    agg_job_id: *agg_job_id,
+                },
+                &KvGetOptions::default(),
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to get aggregation job response from kv"))
+    }
+
+    async fn put_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        diagnostics: AggregationJobDiagnostics,
+    ) -> Result<(), DapError> {
+        self.kv()
+            .put_with_expiration::<kv::prefix::AggregationJobDiagnostics>(
+                &kv::prefix::TaskAggJobId {
+                    task_id: *task_id,
+                    agg_job_id: *agg_job_id,
+                },
+                diagnostics,
+                self.get_current_time()
+                    .saturating_add(AGG_JOB_DIAGNOSTICS_TTL_SECS),
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to put aggregation job diagnostics in kv"))
+    }
+
+    async fn get_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobDiagnostics>, DapError> {
+        self.kv()
+            .get_cloned::<kv::prefix::AggregationJobDiagnostics>(
+                &kv::prefix::TaskAggJobId {
+                    task_id: *task_id,
+                    agg_job_id: *agg_job_id,
+                },
+                &KvGetOptions::default(),
+            )
+            .await
+            .map_err(
+                |e| fatal_error!(err = ?e, "failed to get aggregation job diagnostics from kv"),
+            )
+    }
+
+    async fn put_agg_share_resp(
+        &self,
+        key: &AggShareReqKey,
+        agg_share_resp: StoredAggregateShareResp,
+    ) -> Result<(), DapError> {
+        let Some(cache_secs) = self.service_config.agg_share_resp_cache_secs else {
+            return Ok(());
+        };
+
+        // The response is only stored once the batch has been marked as collected, after which no
+        // more reports can be aggregated into it, so the entry never needs to be invalidated.
+        self.kv()
+            .put_with_expiration::<kv::prefix::AggregateShareResp>(
+                key,
+                agg_share_resp,
+                self.get_current_time().saturating_add(cache_secs),
+            )
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to put aggregate share response in kv"))
+    }
+
+    async fn get_agg_share_resp(
+        &self,
+        key: &AggShareReqKey,
+    ) -> Result<Option<StoredAggregateShareResp>, DapError> {
+        if self.service_config.agg_share_resp_cache_secs.is_none() {
+            return Ok(None);
+        }
+
+        self.kv()
+            .get_cloned::<kv::prefix::AggregateShareResp>(key, &KvGetOptions::default())
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to get aggregate share response from kv"))
+    }
+}
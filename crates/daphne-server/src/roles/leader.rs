@@ -7,18 +7,29 @@ use std::time::Instant;
 
 use axum::{async_trait, http::Method};
 use daphne::{
-    auth::BearerTokenProvider,
+    auth::{BearerToken, BearerTokenProvider},
     constants::DapMediaType,
     error::DapAbort,
     fatal_error,
-    messages::{BatchId, BatchSelector, Collection, CollectionJobId, Report, TaskId},
-    roles::{leader::WorkItem, DapAggregator, DapAuthorizedSender, DapLeader},
+    messages::{
+        AggregationJobId, Base64Encode, BatchId, BatchSelector, Collection, CollectionJobId,
+        Report, TaskId, Time,
+    },
+    roles::{
+        leader::{QuarantinedAggJob, WorkItem},
+        AggregationJobDiagnostics, DapAggregator, DapAuthorizedSender, DapLeader,
+    },
     DapAggregationParam, DapCollectionJob, DapError, DapRequest, DapResponse, DapTaskConfig,
 };
 use daphne_service_utils::{auth::DaphneAuth, http_headers};
 use tracing::{error, info};
 use url::Url;
 
+use crate::{
+    content_encoding,
+    storage_proxy_connection::kv::{self, KvGetOptions},
+};
+
 #[async_trait]
 impl DapAuthorizedSender<DaphneAuth> for crate::App {
     async fn authorize(
@@ -43,12 +54,38 @@ impl DapAuthorizedSender<DaphneAuth> for crate::App {
 
 #[async_trait]
 impl DapLeader<DaphneAuth> for crate::App {
+    async fn upload_unauthorized_reason(
+        &self,
+        _task_config: &DapTaskConfig,
+        req: &DapRequest<DaphneAuth>,
+    ) -> Result<Option<String>, DapError> {
+        let task_id = req.task_id()?;
+        let expected = self
+            .kv()
+            .get_cloned::<kv::prefix::UploadBearerToken>(task_id, &KvGetOptions::default())
+            .await
+            .map_err(|e| fatal_error!(err = ?e, "failed to get the upload bearer token"))?;
+
+        Ok(check_upload_bearer_token(
+            expected.as_ref(),
+            req.sender_auth.as_ref(),
+        ))
+    }
+
     async fn put_report(&self, report: &Report, task_id: &TaskId) -> Result<(), DapError> {
         let task_config = self
             .get_task_config_for(task_id)
             .await?
             .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
 
+        self.record_hpke_config_usage(
+            task_config.version,
+            report.encrypted_input_shares[0].config_id,
+            task_id,
+            task_config.not_after,
+        )
+        .await?;
+
         self.test_leader_state
             .lock()
             .await
@@ -87,6 +124,7 @@ impl DapLeader<DaphneAuth> for crate::App {
             coll_job_id,
             batch_sel,
             agg_param,
+            self.get_current_time(),
         )
     }
 
@@ -113,6 +151,26 @@ impl DapLeader<DaphneAuth> for crate::App {
             .finish_collect_job(task_id, coll_job_id, collection)
     }
 
+    async fn fail_collect_job(
+        &self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        reason: String,
+    ) -> Result<(), DapError> {
+        self.test_leader_state
+            .lock()
+            .await
+            .fail_collect_job(task_id, coll_job_id, reason)
+    }
+
+    async fn sweep_expired_reports(&self, before: Time) -> Result<u64, DapError> {
+        Ok(self
+            .test_leader_state
+            .lock()
+            .await
+            .sweep_expired_reports(before))
+    }
+
     async fn dequeue_work(&self, num_items: usize) -> Result<Vec<WorkItem>, DapError> {
         self.test_leader_state.lock().await.dequeue_work(num_items)
     }
@@ -121,6 +179,36 @@ impl DapLeader<DaphneAuth> for crate::App {
         self.test_leader_state.lock().await.enqueue_work(items)
     }
 
+    async fn export_collect_jobs(&self) -> Result<Vec<u8>, DapError> {
+        self.test_leader_state.lock().await.export_collect_jobs()
+    }
+
+    async fn import_collect_jobs(&self, bytes: &[u8]) -> Result<(), DapError> {
+        self.test_leader_state
+            .lock()
+            .await
+            .import_collect_jobs(bytes, self.get_current_time())
+    }
+
+    async fn quarantine_agg_job(&self, job: QuarantinedAggJob) -> Result<(), DapError> {
+        self.test_leader_state
+            .lock()
+            .await
+            .quarantine_mut()
+            .put(job);
+        Ok(())
+    }
+
+    async fn quarantined_agg_jobs(&self) -> Result<Vec<QuarantinedAggJob>, DapError> {
+        Ok(self
+            .test_leader_state
+            .lock()
+            .await
+            .quarantine()
+            .list()
+            .to_vec())
+    }
+
     async fn send_http_post(
         &self,
         req: DapRequest<DaphneAuth>,
@@ -136,6 +224,41 @@ impl DapLeader<DaphneAuth> for crate::App {
     ) -> Result<DapResponse, DapError> {
         self.send_http(req, Method::PUT, url).await
     }
+
+    async fn get_helper_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobDiagnostics>, DapError> {
+        let url = task_config
+            .helper_url
+            .join(&format!(
+                "/internal/agg_job_diagnostics/task/{}/agg_job/{}",
+                task_id.to_base64url(),
+                agg_job_id.to_base64url()
+            ))
+            .map_err(|e| fatal_error!(err = ?e, "failed to construct diagnostics url"))?;
+        let resp =
+            self.http.get(url).send().await.map_err(
+                |e| fatal_error!(err = ?e, "failed to fetch diagnostics from the helper"),
+            )?;
+        match resp.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let body = resp.bytes().await.map_err(
+                    |e| fatal_error!(err = ?e, "failed to read diagnostics from the helper"),
+                )?;
+                serde_json::from_slice(&body)
+                    .map(Some)
+                    .map_err(|e| fatal_error!(err = ?e, "failed to parse helper diagnostics"))
+            }
+            status => Err(fatal_error!(
+                err = "unexpected status for diagnostics request",
+                %status
+            )),
+        }
+    }
 }
 
 impl crate::App {
@@ -147,6 +270,8 @@ impl crate::App {
     ) -> Result<DapResponse, DapError> {
         use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
 
+        let task_id = req.task_id;
+
         let content_type = req
             .media_type
             .and_then(|mt| mt.as_str_for_version(req.version))
@@ -164,6 +289,10 @@ impl crate::App {
             HeaderValue::from_str(content_type)
                 .map_err(|e| fatal_error!(err = ?e, "failed to construct content-type header"))?,
         );
+        headers.insert(
+            header::ACCEPT_ENCODING,
+            HeaderValue::from_static(content_encoding::GZIP),
+        );
 
         if let Some(bearer_token) = req.sender_auth.and_then(|auth| auth.bearer_token) {
             headers.insert(
@@ -210,12 +339,22 @@ impl crate::App {
                 .filter_map(|h| h.to_str().ok())
                 .find_map(|h| DapMediaType::from_str_for_version(req.version, h))
                 .ok_or_else(|| fatal_error!(err = "peer response is missing media type"))?;
+            let gzip_encoded = reqwest_resp
+                .headers()
+                .get(header::CONTENT_ENCODING)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|h| h.trim().eq_ignore_ascii_case(content_encoding::GZIP));
 
             let payload = reqwest_resp
                 .bytes()
                 .await
-                .map_err(|e| fatal_error!(err = ?e, "failed to read body of helper response"))?
-                .to_vec();
+                .map_err(|e| fatal_error!(err = ?e, "failed to read body of helper response"))?;
+            let payload = if gzip_encoded {
+                content_encoding::gunzip(&payload, self.service_config.max_decompressed_body_bytes)
+                    .map_err(|e| fatal_error!(err = ?e, "failed to decompress helper response"))?
+            } else {
+                payload.to_vec()
+            };
 
             Ok(DapResponse {
                 version: req.version,
@@ -224,21 +363,74 @@ impl crate::App {
             })
         } else {
             error!("{}: request failed: {:?}", url, reqwest_resp);
-            if status == 400 {
-                if let Some(content_type) =
-                    reqwest_resp.headers().get(reqwest::header::CONTENT_TYPE)
+            let is_problem_details = status == 400
+                && reqwest_resp
+                    .headers()
+                    .get(reqwest::header::CONTENT_TYPE)
+                    .is_some_and(|content_type| content_type == "application/problem+json");
+            if is_problem_details {
+                let problem_details = reqwest_resp.text().await.map_err(
+                    |e| fatal_error!(err = ?e, "failed to read body of helper error response"),
+                )?;
+                error!("Problem details: {}", problem_details);
+                // Pass the Helper's abort on so that the caller can tell it apart from a failure
+                // to reach the Helper.
+                if let (Ok(problem_details), Some(task_id)) =
+                    (serde_json::from_str(&problem_details), task_id)
                 {
-                    if content_type == "application/problem+json" {
-                        error!(
-                            "Problem details: {}",
-                            reqwest_resp.text().await.map_err(
-                                |e| fatal_error!(err = ?e, "failed to read body of helper error response")
-                            )?
-                        );
-                    }
+                    return Err(DapAbort::from_problem_details(problem_details, task_id).into());
                 }
             }
             Err(fatal_error!(err = "request aborted by peer"))
         }
     }
 }
+
+/// Check the bearer token presented by a Client against the one configured for the task, if any.
+/// The return value has the same meaning as for [`DapLeader::upload_unauthorized_reason`].
+fn check_upload_bearer_token(
+    expected: Option<&BearerToken>,
+    sender_auth: Option<&DaphneAuth>,
+) -> Option<String> {
+    let expected = expected?;
+    match sender_auth.and_then(|auth| auth.bearer_token.as_ref()) {
+        Some(got) if got == expected => None,
+        Some(_) => Some("The indicated bearer token is incorrect for the Client.".into()),
+        None => Some("Missing authorization.".into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use daphne::auth::BearerToken;
+    use daphne_service_utils::auth::DaphneAuth;
+
+    use super::check_upload_bearer_token;
+
+    fn auth(token: Option<&str>) -> DaphneAuth {
+        DaphneAuth {
+            bearer_token: token.map(BearerToken::from),
+            cf_tls_client_auth: None,
+        }
+    }
+
+    #[test]
+    fn upload_bearer_token() {
+        let expected = BearerToken::from("upload token");
+
+        // Uploads are not authenticated unless the task requires it.
+        assert_eq!(check_upload_bearer_token(None, None), None);
+        assert_eq!(check_upload_bearer_token(None, Some(&auth(None))), None);
+
+        assert!(check_upload_bearer_token(Some(&expected), None).is_some());
+        assert!(check_upload_bearer_token(Some(&expected), Some(&auth(None))).is_some());
+        assert!(
+            check_upload_bearer_token(Some(&expected), Some(&auth(Some("some other token"))))
+                .is_some()
+        );
+        assert_eq!(
+            check_upload_bearer_token(Some(&expected), Some(&auth(Some("upload token")))),
+            None
+        );
+    }
+}
@@ -36,15 +36,16 @@ pub async fn fetch_replay_protection_override(kv: Kv<'_>) -> ReplayProtection {
 mod test_utils {
     use daphne::{
         auth::BearerToken,
+        error::DapAbort,
         fatal_error,
         hpke::{HpkeConfig, HpkeReceiverConfig},
-        messages::decode_base64url_vec,
-        roles::DapAggregator,
+        messages::{decode_base64url_vec_detailed, Base64Encode, BatchId, HpkeConfigList, TaskId},
+        roles::{leader, DapAggregator},
         vdaf::{Prio3Config, VdafConfig},
         DapError, DapQueryConfig, DapTaskConfig, DapVersion,
     };
     use daphne_service_utils::{
-        test_route_types::{InternalTestAddTask, InternalTestEndpointForTask},
+        test_route_types::{InternalApiError, InternalTestAddTask, InternalTestEndpointForTask},
         DapRole,
     };
     use prio::codec::Decode;
@@ -53,37 +54,23 @@ mod test_utils {
     use crate::storage_proxy_connection::kv;
 
     impl crate::App {
-        pub(crate) async fn internal_delete_all(&self) -> Result<(), DapError> {
+        pub(crate) async fn internal_delete_all(&self) -> Result<(), InternalApiError> {
             self.test_leader_state.lock().await.delete_all();
 
-            use daphne_service_utils::durable_requests::PURGE_STORAGE;
             *self.cache.write().await = Default::default();
 
-            self.http
-                .delete(self.storage_proxy_config.url.join(PURGE_STORAGE).unwrap())
-                .bearer_auth(&self.storage_proxy_config.auth_token)
-                .send()
-                .await
-                .map_err(
-                    |e| fatal_error!(err = ?e, "failed to send delete request to storage proxy"),
-                )?
-                .error_for_status()
-                .map_err(|e| fatal_error!(err = ?e, "failed to clear storage proxy"))?;
+            self.storage.delete_all().await.map_err(|e| {
+                InternalApiError::StorageError(format!("failed to clear storage: {e}"))
+            })?;
 
             Ok(())
         }
 
         pub(crate) async fn storage_ready_check(&self) -> Result<(), DapError> {
-            use daphne_service_utils::durable_requests::STORAGE_READY;
-            self.http
-                .get(self.storage_proxy_config.url.join(STORAGE_READY).unwrap())
-                .bearer_auth(&self.storage_proxy_config.auth_token)
-                .send()
+            self.storage
+                .ready()
                 .await
-                .map_err(|e| fatal_error!(err = ?e, "failed to send ready check request to storage proxy"))?
-                .error_for_status()
-                .map_err(|e| fatal_error!(err = ?e, "storage proxy is not ready"))?;
-            Ok(())
+                .map_err(|e| fatal_error!(err = ?e, "storage is not ready"))
         }
 
         pub(crate) fn internal_endpoint_for_task(
@@ -103,54 +90,174 @@ mod test_utils {
             Ok(format!("{path}{}/", version.as_ref()))
         }
 
-        pub(crate) async fn internal_add_task(
+        /// Build the configuration of the task described by `cmd`. The task becomes valid at the
+        /// current time.
+        fn internal_task_config(
             &self,
             version: DapVersion,
-            cmd: InternalTestAddTask,
-        ) -> Result<(), DapError> {
+            cmd: &InternalTestAddTask,
+        ) -> Result<DapTaskConfig, InternalApiError> {
             // VDAF config.
             let vdaf = match (
-                cmd.vdaf.typ.as_ref(),
-                cmd.vdaf.bits,
-                cmd.vdaf.length,
-                cmd.vdaf.chunk_length,
+                cmd.vdaf.typ.as_str(),
+                cmd.vdaf.bits.as_deref(),
+                cmd.vdaf.length.as_deref(),
+                cmd.vdaf.chunk_length.as_deref(),
             ) {
                 ("Prio3Count", None, None, None) => VdafConfig::Prio3(Prio3Config::Count),
                 ("Prio3Sum", Some(bits), None, None) => VdafConfig::Prio3(Prio3Config::Sum {
-                    bits: bits.parse().map_err(|e| fatal_error!(err = ?e, "failed to parse bits for Prio3Config::Sum"))?,
+                    bits: bits.parse().map_err(|e| {
+                        InternalApiError::InvalidCommand(format!(
+                            "failed to parse bits for Prio3Config::Sum: {e:?}"
+                        ))
+                    })?,
                 }),
                 ("Prio3SumVec", Some(bits), Some(length), Some(chunk_length)) => {
                     VdafConfig::Prio3(Prio3Config::SumVec {
-                        bits: bits.parse().map_err(|e| fatal_error!(err = ?e, "failed to parse bits fro Prio3Config::SumVec"))?,
-                        length: length.parse().map_err(|e| fatal_error!(err = ?e, "failed to parse length fro Prio3Config::SumVec"))?,
-                        chunk_length: chunk_length.parse().map_err(|e| fatal_error!(err = ?e, "failed to parse chunk_length fro Prio3Config::SumVec"))?,
+                        bits: bits.parse().map_err(|e| {
+                            InternalApiError::InvalidCommand(format!(
+                                "failed to parse bits fro Prio3Config::SumVec: {e:?}"
+                            ))
+                        })?,
+                        length: length.parse().map_err(|e| {
+                            InternalApiError::InvalidCommand(format!(
+                                "failed to parse length fro Prio3Config::SumVec: {e:?}"
+                            ))
+                        })?,
+                        chunk_length: chunk_length.parse().map_err(|e| {
+                            InternalApiError::InvalidCommand(format!(
+                                "failed to parse chunk_length fro Prio3Config::SumVec: {e:?}"
+                            ))
+                        })?,
+                        client_element_max: None,
                     })
                 }
                 ("Prio3Histogram", None, Some(length), Some(chunk_length)) => {
                     VdafConfig::Prio3(Prio3Config::Histogram {
-                        length: length.parse().map_err(|e| fatal_error!(err = ?e, "failed to parse length fro Prio3Config::Histogram"))?,
-                        chunk_length: chunk_length.parse().map_err(|e| fatal_error!(err = ?e, "failed to parse chunk_length fro Prio3Config::Histogram"))?,
+                        length: length.parse().map_err(|e| {
+                            InternalApiError::InvalidCommand(format!(
+                                "failed to parse length fro Prio3Config::Histogram: {e:?}"
+                            ))
+                        })?,
+                        chunk_length: chunk_length.parse().map_err(|e| {
+                            InternalApiError::InvalidCommand(format!(
+                                "failed to parse chunk_length fro Prio3Config::Histogram: {e:?}"
+                            ))
+                        })?,
                     })
                 }
-                _ => return Err(fatal_error!(err = "command failed: unrecognized VDAF")),
+                ("Prio2", None, Some(dimension), None) => VdafConfig::Prio2 {
+                    dimension: dimension.parse().map_err(|e| {
+                        InternalApiError::InvalidCommand(format!(
+                            "failed to parse dimension for Prio2: {e:?}"
+                        ))
+                    })?,
+                },
+                _ => return Err(InternalApiError::InvalidCommand("unrecognized VDAF".into())),
             };
 
             // VDAF verification key.
-            let vdaf_verify_key_data = decode_base64url_vec(cmd.vdaf_verify_key.as_bytes())
-                .ok_or_else(|| {
-                    fatal_error!(err = "VDAF verify key is not valid URL-safe base64")
+            let vdaf_verify_key_data =
+                decode_base64url_vec_detailed(cmd.vdaf_verify_key.as_bytes()).map_err(|e| {
+                    InternalApiError::InvalidCommand(format!(
+                        "VDAF verify key is not valid URL-safe base64: {e}"
+                    ))
                 })?;
-            let vdaf_verify_key = vdaf
-                .get_decoded_verify_key(&vdaf_verify_key_data)
-                .map_err(|e| fatal_error!(err = ?e, "failed to decode verify key"))?;
+            if vdaf_verify_key_data.len() != vdaf.verify_key_len() {
+                return Err(InternalApiError::InvalidCommand(format!(
+                    "VDAF verify key has length {}, expected {} for {vdaf}",
+                    vdaf_verify_key_data.len(),
+                    vdaf.verify_key_len(),
+                )));
+            }
+            let vdaf_verify_key =
+                vdaf.get_decoded_verify_key(&vdaf_verify_key_data)
+                    .map_err(|e| {
+                        InternalApiError::InvalidCommand(format!(
+                            "failed to decode verify key: {e:?}"
+                        ))
+                    })?;
 
             // Collector HPKE config.
-            let collector_hpke_config_data =
-                decode_base64url_vec(cmd.collector_hpke_config.as_bytes()).ok_or_else(|| {
-                    fatal_error!(err = "HPKE collector config is not valid URL-safe base64")
-                })?;
+            let collector_hpke_config_data = decode_base64url_vec_detailed(
+                cmd.collector_hpke_config.as_bytes(),
+            )
+            .map_err(|e| {
+                InternalApiError::InvalidCommand(format!(
+                    "HPKE collector config is not valid URL-safe base64: {e}"
+                ))
+            })?;
             let collector_hpke_config = HpkeConfig::get_decoded(&collector_hpke_config_data)
-                .map_err(|e| fatal_error!(err = ?e, "failed to decode hpke config"))?;
+                .map_err(|e| {
+                    InternalApiError::InvalidCommand(format!("failed to decode hpke config: {e:?}"))
+                })?;
+            if !collector_hpke_config.is_supported() {
+                return Err(InternalApiError::InvalidCommand(format!(
+                    "unsupported HPKE ciphersuite for collector: {:?}/{:?}/{:?}",
+                    collector_hpke_config.kem_id,
+                    collector_hpke_config.kdf_id,
+                    collector_hpke_config.aead_id,
+                )));
+            }
+
+            // Query configuraiton.
+            let query = match (cmd.query_type, cmd.max_batch_size) {
+                (1, None) => DapQueryConfig::TimeInterval,
+                (1, Some(..)) => {
+                    return Err(InternalApiError::InvalidCommand(
+                        "unexpected max batch size".into(),
+                    ))
+                }
+                // A batch could never be filled, which would stall aggregation.
+                (2, Some(0)) => {
+                    return Err(InternalApiError::InvalidCommand(
+                        "max batch size must be positive".into(),
+                    ))
+                }
+                (2, max_batch_size) => DapQueryConfig::FixedSize { max_batch_size },
+                _ => {
+                    return Err(InternalApiError::InvalidCommand(
+                        "unrecognized query type".into(),
+                    ))
+                }
+            };
+
+            // The task starts at the beginning of the current time precision window: Clients round
+            // their report timestamps down, so starting mid-window would reject every report of
+            // that window.
+            let now = self.get_current_time();
+            let task_config = DapTaskConfig {
+                version,
+                leader_url: cmd.leader.clone(),
+                helper_url: cmd.helper.clone(),
+                time_precision: cmd.time_precision,
+                not_before: now - now.checked_rem(cmd.time_precision).unwrap_or_default(),
+                not_after: cmd.task_expiration,
+                min_batch_size: cmd.min_batch_size,
+                query,
+                vdaf,
+                vdaf_verify_key,
+                vdaf_verify_keys_by_epoch: Default::default(),
+                collector_hpke_config,
+                method: Default::default(),
+                num_agg_span_shards: NonZeroUsize::new(4).unwrap(),
+                max_batch_query_count: 1,
+            };
+            task_config
+                .check_urls(&cmd.task_id, self.service_config.global.require_https)
+                .map_err(|e| InternalApiError::InvalidCommand(format!("{e:?}")))?;
+            task_config
+                .check_batch_size_bounds(&cmd.task_id)
+                .map_err(|e| InternalApiError::InvalidCommand(format!("{e:?}")))?;
+            Ok(task_config)
+        }
+
+        pub(crate) async fn internal_add_task(
+            &self,
+            version: DapVersion,
+            cmd: InternalTestAddTask,
+        ) -> Result<(), InternalApiError> {
+            let task_config = self.internal_task_config(version, &cmd)?;
 
             // Leader authentication token.
             let token = BearerToken::from(cmd.leader_authentication_token);
@@ -158,13 +265,17 @@ mod test_utils {
                 .kv()
                 .put_if_not_exists::<kv::prefix::LeaderBearerToken>(&cmd.task_id, token)
                 .await
-                .map_err(|e| fatal_error!(err = ?e, "failed to fetch leader bearer token"))?
+                .map_err(|e| {
+                    InternalApiError::StorageError(format!(
+                        "failed to fetch leader bearer token: {e:?}"
+                    ))
+                })?
                 .is_some()
             {
-                return Err(fatal_error!(
-                    err = "command failed: token already exists for the given task and bearer role (leader)",
-                    task_id = %cmd.task_id,
-                ));
+                return Err(InternalApiError::TaskAlreadyExists(format!(
+                    "token already exists for the given task ({}) and bearer role (leader)",
+                    cmd.task_id
+                )));
             }
 
             // Collector authentication token.
@@ -175,77 +286,51 @@ mod test_utils {
                         .kv()
                         .put_if_not_exists::<kv::prefix::CollectorBearerToken>(&cmd.task_id, token)
                         .await
-                        .map_err(
-                            |e| fatal_error!(err = ?e, "failed to put collector bearer token"),
-                        )?
+                        .map_err(|e| {
+                            InternalApiError::StorageError(format!(
+                                "failed to put collector bearer token: {e:?}"
+                            ))
+                        })?
                         .is_some()
                     {
-                        return Err(fatal_error!(err = format!(
-                            "command failed: token already exists for the given task ({}) and bearer role (collector)",
+                        return Err(InternalApiError::TaskAlreadyExists(format!(
+                            "token already exists for the given task ({}) and bearer role (collector)",
                             cmd.task_id
                         )));
                     }
                 }
                 (DapRole::Leader, None) => {
-                    return Err(fatal_error!(
-                        err = "command failed: missing collector authentication token",
+                    return Err(InternalApiError::InvalidCommand(
+                        "missing collector authentication token".into(),
                     ))
                 }
                 (DapRole::Helper, None) => (),
                 (DapRole::Helper, Some(..)) => {
-                    return Err(fatal_error!(
-                        err = "command failed: unexpected collector authentication token",
+                    return Err(InternalApiError::InvalidCommand(
+                        "unexpected collector authentication token".into(),
                     ));
                 }
             };
 
-            // Query configuraiton.
-            let query = match (cmd.query_type, cmd.max_batch_size) {
-                (1, None) => DapQueryConfig::TimeInterval,
-                (1, Some(..)) => {
-                    return Err(fatal_error!(
-                        err = "command failed: unexpected max batch size"
-                    ))
-                }
-                (2, max_batch_size) => DapQueryConfig::FixedSize { max_batch_size },
-                _ => {
-                    return Err(fatal_error!(
-                        err = "command failed: unrecognized query type"
-                    ))
-                }
-            };
-
             if self
                 .kv()
                 .put_if_not_exists_with_expiration::<kv::prefix::TaskConfig>(
                     &cmd.task_id,
-                    DapTaskConfig {
-                        version,
-                        leader_url: cmd.leader,
-                        helper_url: cmd.helper,
-                        time_precision: cmd.time_precision,
-                        not_before: self.get_current_time(),
-                        not_after: cmd.task_expiration,
-                        min_batch_size: cmd.min_batch_size,
-                        query,
-                        vdaf,
-                        vdaf_verify_key,
-                        collector_hpke_config,
-                        method: Default::default(),
-                        num_agg_span_shards: NonZeroUsize::new(4).unwrap(),
-                    },
+                    task_config,
                     cmd.task_expiration,
                 )
                 .await
-                .map_err(|e| fatal_error!(err = ?e, "failed to put task config in kv"))?
+                .map_err(|e| {
+                    InternalApiError::StorageError(format!(
+                        "failed to put task config in kv: {e:?}"
+                    ))
+                })?
                 .is_some()
             {
-                Err(fatal_error!(
-                    err = format!(
-                        "command failed: config already exists for the given task ({})",
-                        cmd.task_id
-                    )
-                ))
+                Err(InternalApiError::TaskAlreadyExists(format!(
+                    "config already exists for the given task ({})",
+                    cmd.task_id
+                )))
             } else {
                 Ok(())
             }
@@ -255,33 +340,486 @@ mod test_utils {
             &self,
             version: DapVersion,
             new_receiver: HpkeReceiverConfig,
-        ) -> Result<(), DapError> {
+        ) -> Result<(), InternalApiError> {
             let mut config_list = self
                 .kv()
                 .get_cloned::<kv::prefix::HpkeReceiverConfigSet>(&version, &Default::default())
                 .await
-                .map_err(|e| fatal_error!(err = ?e, "failed to get hpke config"))?
+                .map_err(|e| {
+                    InternalApiError::StorageError(format!("failed to get hpke config: {e:?}"))
+                })?
                 .unwrap_or_default();
 
             if config_list
                 .iter()
                 .any(|receiver| new_receiver.config.id == receiver.config.id)
             {
-                return Err(fatal_error!(
-                    err = format!(
-                        "receiver config with id {} already exists",
+                return Err(InternalApiError::HpkeConfigAlreadyExists(format!(
+                    "receiver config with id {} already exists",
+                    new_receiver.config.id
+                )));
+            }
+
+            let mut added_at = self
+                .kv()
+                .get_cloned::<kv::prefix::HpkeReceiverConfigAddedAt>(&version, &Default::default())
+                .await
+                .map_err(|e| {
+                    InternalApiError::StorageError(format!(
+                        "failed to get hpke config rotation times: {e:?}"
+                    ))
+                })?
+                .unwrap_or_default();
+
+            if let Some(max_hpke_configs) = self.service_config.max_hpke_configs {
+                // Evict the least recently added configs to make room for the new one. The first
+                // config is the one advertised to Clients and is never evicted, nor are those that
+                // Clients may still be using. Configs with no recorded rotation time are evicted
+                // first.
+                let mut evictable = Vec::new();
+                for receiver in config_list.iter().skip(1) {
+                    let tasks = self
+                        .tasks_using_hpke_config(version, receiver.config.id)
+                        .await
+                        .map_err(|e| {
+                            InternalApiError::StorageError(format!(
+                                "failed to get hpke config usage: {e:?}"
+                            ))
+                        })?;
+                    if tasks.is_empty() {
+                        evictable.push(receiver.config.id);
+                    }
+                }
+                evictable.sort_by_key(|id| added_at.get(id).copied().unwrap_or_default());
+
+                let excess = (config_list.len() + 1).saturating_sub(max_hpke_configs);
+                if excess > evictable.len() {
+                    return Err(InternalApiError::InvalidCommand(format!(
+                        "cannot add receiver config with id {}: the limit of {max_hpke_configs} configs is reached and the remaining configs are advertised or in use",
                         new_receiver.config.id
-                    )
-                ));
+                    )));
+                }
+                let evicted = &evictable[..excess];
+                config_list.retain(|receiver| !evicted.contains(&receiver.config.id));
+                added_at.retain(|id, _| !evicted.contains(id));
             }
 
+            added_at.insert(new_receiver.config.id, self.clock.now());
             config_list.push(new_receiver);
 
             self.kv()
                 .put::<kv::prefix::HpkeReceiverConfigSet>(&version, config_list)
                 .await
-                .map_err(|e| fatal_error!(err = ?e, "failed to put hpke config"))?;
+                .map_err(|e| {
+                    InternalApiError::StorageError(format!("failed to put hpke config: {e:?}"))
+                })?;
+            self.kv()
+                .put::<kv::prefix::HpkeReceiverConfigAddedAt>(&version, added_at)
+                .await
+                .map_err(|e| {
+                    InternalApiError::StorageError(format!(
+                        "failed to put hpke config rotation times: {e:?}"
+                    ))
+                })?;
             Ok(())
         }
+
+        /// Upload `count` reports with random measurements for the given task. The reports are
+        /// encrypted to the first supported HPKE config advertised by the Helper.
+        pub(crate) async fn internal_inject_reports(
+            &self,
+            task_id: &TaskId,
+            count: usize,
+        ) -> Result<usize, DapError> {
+            let task_config = self
+                .get_task_config_for(task_id)
+                .await?
+                .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
+            let mut url = task_config
+                .helper_url
+                .join("hpke_config")
+                .map_err(|e| fatal_error!(err = ?e, "failed to build helper hpke_config URL"))?;
+            url.query_pairs_mut()
+                .append_pair("task_id", &task_id.to_base64url());
+            let resp = self
+                .http
+                .get(url)
+                .send()
+                .await
+                .map_err(|e| fatal_error!(err = ?e, "failed to fetch the helper's HPKE configs"))?
+                .error_for_status()
+                .map_err(|e| fatal_error!(err = ?e, "helper rejected the HPKE config request"))?
+                .bytes()
+                .await
+                .map_err(|e| fatal_error!(err = ?e, "failed to read the helper's HPKE configs"))?;
+            let helper_hpke_config = HpkeConfigList::get_decoded(&resp)
+                .map_err(DapError::encoding)?
+                .hpke_configs
+                .into_iter()
+                .find(HpkeConfig::is_supported)
+                .ok_or_else(|| fatal_error!(err = "helper advertises no supported HPKE config"))?;
+
+            let measurements =
+                leader::inject_synthetic_reports(self, task_id, &helper_hpke_config, count).await?;
+            Ok(measurements.len())
+        }
+
+        /// Fixed-size tasks: List the batches that are still being filled, oldest first, along
+        /// with their current report counts.
+        pub(crate) async fn internal_list_current_batches(
+            &self,
+            task_id: &TaskId,
+        ) -> Result<Vec<(BatchId, u64)>, DapError> {
+            let task_config = self
+                .get_task_config_for(task_id)
+                .await?
+                .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
+            self.test_leader_state
+                .lock()
+                .await
+                .list_current_batches(task_id, &task_config)
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        };
+
+        use assert_matches::assert_matches;
+        use daphne::{
+            hpke::{HpkeAeadId, HpkeKemId, HpkeReceiverConfig},
+            messages::{encode_base64url, HpkeConfigList, TaskId},
+            roles::aggregator,
+            DapQueryConfig, DapRequest, DapResource, DapVersion,
+        };
+        use daphne_service_utils::{
+            test_route_types::{InternalApiError, InternalTestAddTask, InternalTestVdaf},
+            DapRole,
+        };
+        use prio::codec::{Decode, Encode};
+
+        use crate::{
+            storage_proxy_connection::kv,
+            test::{test_app, MockClock},
+            InMemoryStorage,
+        };
+
+        const NOW: u64 = 1_637_361_337;
+
+        fn add_task_cmd(leader: &str) -> InternalTestAddTask {
+            let collector_hpke_config = HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+                .unwrap()
+                .config;
+            InternalTestAddTask {
+                task_id: TaskId([1; 32]),
+                leader: leader.parse().unwrap(),
+                helper: "https://helper.example.com/".parse().unwrap(),
+                vdaf: InternalTestVdaf {
+                    typ: "Prio3Count".into(),
+                    bits: None,
+                    length: None,
+                    chunk_length: None,
+                },
+                leader_authentication_token: "leader token".into(),
+                collector_authentication_token: Some("collector token".into()),
+                role: DapRole::Leader,
+                vdaf_verify_key: encode_base64url([0; 16]),
+                query_type: 1,
+                min_batch_size: 10,
+                max_batch_size: None,
+                time_precision: 3600,
+                collector_hpke_config: encode_base64url(
+                    collector_hpke_config.get_encoded().unwrap(),
+                ),
+                task_expiration: NOW + 86400,
+            }
+        }
+
+        #[test]
+        fn internal_task_config_not_before_is_current_time() {
+            let mut app = test_app();
+            let now = Arc::new(AtomicU64::new(0));
+            app.set_clock(MockClock(now.clone()));
+            let cmd = add_task_cmd("https://leader.example.com/");
+
+            // A task added at time T rejects reports from before T.
+            let t = NOW - NOW % cmd.time_precision;
+            now.store(t, Ordering::Relaxed);
+            let task_config = app.internal_task_config(DapVersion::Draft09, &cmd).unwrap();
+            assert_eq!(task_config.not_before, t);
+            assert!(!task_config.report_time_is_valid(t - 1));
+            assert!(task_config.report_time_is_valid(t));
+
+            // A task added in the middle of a time precision window starts at the beginning of
+            // the window, as Clients round their report timestamps down.
+            now.store(NOW, Ordering::Relaxed);
+            let task_config = app.internal_task_config(DapVersion::Draft09, &cmd).unwrap();
+            assert_eq!(task_config.not_before, t);
+            assert!(!task_config.report_time_is_valid(t - 1));
+            assert!(task_config.report_time_is_valid(t));
+            assert!(task_config.report_time_is_valid(NOW));
+        }
+
+        #[test]
+        fn internal_task_config_require_https() {
+            let mut app = test_app();
+
+            app.service_config.global.require_https = true;
+            assert_matches!(
+                app.internal_task_config(
+                    DapVersion::Draft09,
+                    &add_task_cmd("http://leader.example.com/")
+                ),
+                Err(InternalApiError::InvalidCommand(..))
+            );
+            for leader in ["https://leader.example.com/", "http://localhost:8787/"] {
+                app.internal_task_config(DapVersion::Draft09, &add_task_cmd(leader))
+                    .unwrap();
+            }
+
+            app.service_config.global.require_https = false;
+            app.internal_task_config(
+                DapVersion::Draft09,
+                &add_task_cmd("http://leader.example.com/"),
+            )
+            .unwrap();
+        }
+
+        #[test]
+        fn internal_task_config_unsupported_hpke_suite() {
+            let app = test_app();
+            let mut collector_hpke_config = HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+                .unwrap()
+                .config;
+            collector_hpke_config.aead_id = HpkeAeadId::NotImplemented(0x0003);
+            let cmd = InternalTestAddTask {
+                collector_hpke_config: encode_base64url(
+                    collector_hpke_config.get_encoded().unwrap(),
+                ),
+                ..add_task_cmd("https://leader.example.com/")
+            };
+
+            assert_matches!(
+                app.internal_task_config(DapVersion::Draft09, &cmd),
+                Err(InternalApiError::InvalidCommand(..))
+            );
+        }
+
+        #[test]
+        fn internal_task_config_verify_key_len() {
+            let app = test_app();
+            let prio3_sum = || InternalTestVdaf {
+                typ: "Prio3Sum".into(),
+                bits: Some("8".into()),
+                length: None,
+                chunk_length: None,
+            };
+
+            let cmd = InternalTestAddTask {
+                vdaf: prio3_sum(),
+                vdaf_verify_key: encode_base64url([0; 16]),
+                ..add_task_cmd("https://leader.example.com/")
+            };
+            app.internal_task_config(DapVersion::Draft09, &cmd).unwrap();
+
+            let cmd = InternalTestAddTask {
+                vdaf: prio3_sum(),
+                vdaf_verify_key: encode_base64url([0; 8]),
+                ..add_task_cmd("https://leader.example.com/")
+            };
+            assert_matches!(
+                app.internal_task_config(DapVersion::Draft09, &cmd),
+                Err(InternalApiError::InvalidCommand(detail))
+                    if detail.contains("length 8, expected 16")
+            );
+        }
+
+        #[test]
+        fn internal_task_config_fixed_size_max_batch_size() {
+            let app = test_app();
+            let cmd = |min_batch_size, max_batch_size| InternalTestAddTask {
+                query_type: 2,
+                min_batch_size,
+                max_batch_size,
+                ..add_task_cmd("https://leader.example.com/")
+            };
+
+            // Rejected even though the minimum batch size doesn't exceed it.
+            assert_matches!(
+                app.internal_task_config(DapVersion::Draft09, &cmd(0, Some(0))),
+                Err(InternalApiError::InvalidCommand(detail))
+                    if detail == "max batch size must be positive"
+            );
+
+            let task_config = app
+                .internal_task_config(DapVersion::Draft09, &cmd(1, Some(1)))
+                .unwrap();
+            assert_eq!(
+                task_config.query,
+                DapQueryConfig::FixedSize {
+                    max_batch_size: Some(1)
+                }
+            );
+
+            let task_config = app
+                .internal_task_config(DapVersion::Draft09, &cmd(10, None))
+                .unwrap();
+            assert_eq!(
+                task_config.query,
+                DapQueryConfig::FixedSize {
+                    max_batch_size: None
+                }
+            );
+        }
+
+        #[tokio::test]
+        async fn internal_add_hpke_config_max_hpke_configs() {
+            let mut app = test_app();
+            app.set_storage(InMemoryStorage::default());
+            let now = Arc::new(AtomicU64::new(NOW));
+            app.set_clock(MockClock(now.clone()));
+            app.service_config.max_hpke_configs = Some(2);
+            let version = DapVersion::Draft09;
+            let add_hpke_config = |id| {
+                let app = &app;
+                async move {
+                    app.internal_add_hpke_config(
+                        version,
+                        HpkeReceiverConfig::gen(id, HpkeKemId::X25519HkdfSha256).unwrap(),
+                    )
+                    .await
+                }
+            };
+            let config_ids = || async {
+                app.kv()
+                    .get_cloned::<kv::prefix::HpkeReceiverConfigSet>(&version, &Default::default())
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .iter()
+                    .map(|receiver| receiver.config.id)
+                    .collect::<Vec<_>>()
+            };
+
+            now.store(NOW, Ordering::Relaxed);
+            add_hpke_config(1).await.unwrap();
+            now.store(NOW + 1, Ordering::Relaxed);
+            add_hpke_config(2).await.unwrap();
+            assert_eq!(config_ids().await, [1, 2]);
+
+            // Promote the newest config so that it is advertised to Clients.
+            let mut config_list = app
+                .kv()
+                .get_cloned::<kv::prefix::HpkeReceiverConfigSet>(&version, &Default::default())
+                .await
+                .unwrap()
+                .unwrap();
+            config_list.reverse();
+            app.kv()
+                .put::<kv::prefix::HpkeReceiverConfigSet>(&version, config_list)
+                .await
+                .unwrap();
+
+            // The oldest config is evicted, even though it's not first in the list.
+            now.store(NOW + 2, Ordering::Relaxed);
+            add_hpke_config(3).await.unwrap();
+            assert_eq!(config_ids().await, [2, 3]);
+
+            // The advertised config is never evicted.
+            now.store(NOW + 3, Ordering::Relaxed);
+            add_hpke_config(4).await.unwrap();
+            assert_eq!(config_ids().await, [2, 4]);
+
+            // If the remaining configs are in use, then the limit can't be maintained.
+            app.record_hpke_config_usage(version, 4, &TaskId([1; 32]), NOW + 86400)
+                .await
+                .unwrap();
+            assert_matches!(
+                add_hpke_config(5).await,
+                Err(InternalApiError::InvalidCommand(..))
+            );
+            assert_eq!(config_ids().await, [2, 4]);
+        }
+
+        #[tokio::test]
+        async fn hpke_config_req_lists_all_configs() {
+            let mut app = test_app();
+            app.set_storage(InMemoryStorage::default());
+            let version = DapVersion::Draft09;
+            let mut hpke_configs = Vec::new();
+            for id in 1..=3 {
+                let receiver = HpkeReceiverConfig::gen(id, HpkeKemId::X25519HkdfSha256).unwrap();
+                hpke_configs.push(receiver.config.clone());
+                app.internal_add_hpke_config(version, receiver)
+                    .await
+                    .unwrap();
+            }
+
+            let req = DapRequest {
+                version,
+                media_type: None,
+                task_id: None,
+                resource: DapResource::Undefined,
+                payload: Vec::new(),
+                sender_auth: None,
+                taskprov: None,
+            };
+            let resp = aggregator::handle_hpke_config_req(&app, &req, None)
+                .await
+                .unwrap();
+            assert_eq!(
+                HpkeConfigList::get_decoded(&resp.payload).unwrap(),
+                HpkeConfigList { hpke_configs }
+            );
+        }
+
+        #[tokio::test]
+        async fn internal_delete_all_clears_storage() {
+            let mut app = test_app();
+            app.set_storage(InMemoryStorage::default());
+            app.set_clock(MockClock(Arc::new(AtomicU64::new(NOW))));
+            app.internal_add_task(
+                DapVersion::Draft09,
+                add_task_cmd("https://leader.example.com/"),
+            )
+            .await
+            .unwrap();
+            let task_id = TaskId([1; 32]);
+            assert!(app
+                .kv()
+                .get::<kv::prefix::TaskConfig>(&task_id, &Default::default())
+                .await
+                .unwrap()
+                .is_some());
+            assert_eq!(
+                app.kv().list::<kv::prefix::TaskConfig>().await.unwrap(),
+                [task_id.to_string()]
+            );
+
+            app.internal_delete_all().await.unwrap();
+            assert!(app
+                .kv()
+                .get::<kv::prefix::TaskConfig>(&task_id, &Default::default())
+                .await
+                .unwrap()
+                .is_none());
+            assert!(app
+                .kv()
+                .list::<kv::prefix::TaskConfig>()
+                .await
+                .unwrap()
+                .is_empty());
+
+            // The task can be added again.
+            app.internal_add_task(
+                DapVersion::Draft09,
+                add_task_cmd("https://leader.example.com/"),
+            )
+            .await
+            .unwrap();
+        }
     }
 }
@@ -8,6 +8,7 @@ use axum::{
     extract::{Query, State},
     response::{AppendHeaders, IntoResponse},
     routing::get,
+    Json,
 };
 use daphne::{
     constants::DapMediaType,
@@ -27,9 +28,16 @@ where
     A: DapAggregator<DaphneAuth> + DaphneService + Send + Sync + 'static,
     B: Send + HttpBody + 'static,
     B::Data: Send,
-    B::Error: Send + Sync,
+    B::Error: Send + Sync + Into<Box<dyn std::error::Error + Send + Sync>>,
 {
-    router.route("/:version/hpke_config", get(hpke_config))
+    router
+        .route("/:version/hpke_config", get(hpke_config))
+        .route("/hpke_suites", get(hpke_suites))
+}
+
+/// Advertise the HPKE ciphersuites this build can decrypt, as `[kem, kdf, aead]` triples.
+async fn hpke_suites() -> impl IntoResponse {
+    Json(daphne::hpke::supported_hpke_suites())
 }
 
 #[derive(Deserialize)]
@@ -86,6 +94,7 @@ mod test {
         routing::get,
         Router,
     };
+    use daphne::hpke::{supported_hpke_suites, HpkeAeadId, HpkeKdfId, HpkeKemId};
     use daphne::messages::{Base64Encode, TaskId};
     use daphne::{
         constants::DapMediaType, messages::decode_base64url_vec, DapResponse, DapVersion,
@@ -96,7 +105,7 @@ mod test {
     use tower::ServiceExt;
     use webpki::{EndEntityCert, ECDSA_P256_SHA256};
 
-    use super::{sign_dap_response, QueryTaskId};
+    use super::{hpke_suites, sign_dap_response, QueryTaskId};
 
     #[tokio::test]
     async fn can_parse_task_id() {
@@ -140,6 +149,32 @@ mod test {
         assert_eq!(status, StatusCode::OK);
     }
 
+    #[tokio::test]
+    async fn advertises_supported_hpke_suites() {
+        let router: Router = Router::new().route("/hpke_suites", get(hpke_suites));
+
+        let resp = router
+            .oneshot(
+                Request::builder()
+                    .uri("/hpke_suites")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let suites: Vec<(HpkeKemId, HpkeKdfId, HpkeAeadId)> =
+            serde_json::from_slice(&body).unwrap();
+        assert_eq!(suites, supported_hpke_suites());
+        assert!(suites.contains(&(
+            HpkeKemId::X25519HkdfSha256,
+            HpkeKdfId::HkdfSha256,
+            HpkeAeadId::Aes128Gcm
+        )));
+    }
+
     // Check that a signature produced by Daphne-Worker will be verified properly by the Clients.
     #[test]
     fn rondtrip_sign_hpke_config() {
@@ -5,31 +5,42 @@ use std::sync::Arc;
 
 use axum::{
     body::HttpBody,
-    extract::State,
+    extract::{DefaultBodyLimit, State},
+    response::{AppendHeaders, IntoResponse, Response},
     routing::{post, put},
 };
 use daphne::{
     constants::DapMediaType,
     error::DapAbort,
     roles::{helper, DapHelper},
+    DapRequest, DapResource,
 };
-use daphne_service_utils::auth::DaphneAuth;
+use daphne_service_utils::{auth::DaphneAuth, http_headers};
 use http::StatusCode;
 
 use crate::{roles::fetch_replay_protection_override, App};
 
 use super::{AxumDapResponse, DapRequestExtractor, DaphneService};
 
-pub(super) fn add_helper_routes<B>(router: super::Router<App, B>) -> super::Router<App, B>
+/// Add the Helper's routes to the router. If `max_agg_init_body_bytes` is set, aggregation job
+/// initialization requests with a larger body are rejected while the body is read.
+pub(super) fn add_helper_routes<B>(
+    router: super::Router<App, B>,
+    max_agg_init_body_bytes: Option<usize>,
+) -> super::Router<App, B>
 where
     B: Send + HttpBody + 'static,
     B::Data: Send,
-    B::Error: Send + Sync,
+    B::Error: Send + Sync + Into<Box<dyn std::error::Error + Send + Sync>>,
 {
+    let agg_job_route = match max_agg_init_body_bytes {
+        Some(max_bytes) => put(agg_job).layer(DefaultBodyLimit::max(max_bytes)),
+        None => put(agg_job),
+    };
     router
         .route(
             "/:version/tasks/:task_id/aggregation_jobs/:agg_job_id",
-            put(agg_job),
+            agg_job_route,
         )
         .route("/:version/tasks/:task_id/aggregate_shares", post(agg_share))
 }
@@ -48,6 +59,22 @@ async fn agg_job(
 ) -> AxumDapResponse {
     match req.media_type {
         Some(DapMediaType::AggregationJobInitReq) => {
+            if let Err(e) =
+                check_agg_init_body_size(&req, app.service_config.max_agg_init_body_bytes)
+            {
+                return AxumDapResponse::new_error(e, app.server_metrics());
+            }
+            // Track the job while it is being processed so that stalled jobs can be detected. The
+            // job stops being tracked when the guard is dropped, even if this future is cancelled.
+            let _activity_guard = match (req.task_id(), &req.resource) {
+                (Ok(task_id), DapResource::AggregationJob(agg_job_id)) => {
+                    match app.record_agg_job_activity(task_id, agg_job_id) {
+                        Ok(guard) => Some(guard),
+                        Err(e) => return AxumDapResponse::new_error(e, app.server_metrics()),
+                    }
+                }
+                _ => None,
+            };
             let resp = helper::handle_agg_job_init_req(
                 &*app,
                 &req,
@@ -60,13 +87,32 @@ async fn agg_job(
                 StatusCode::CREATED,
             )
         }
-        m => AxumDapResponse::new_error(
-            DapAbort::BadRequest(format!("unexpected media type: {m:?}")),
+        _ => AxumDapResponse::new_error(
+            DapAbort::content_type(&req, DapMediaType::AggregationJobInitReq),
             app.server_metrics(),
         ),
     }
 }
 
+/// Reject an aggregation job initialization request whose body is larger than `max_bytes`. The body
+/// as received is already bounded by the route; this also bounds it once its content-encoding is
+/// removed. The check is done on the raw payload so that oversized requests are never decoded.
+fn check_agg_init_body_size(
+    req: &DapRequest<DaphneAuth>,
+    max_bytes: Option<usize>,
+) -> Result<(), DapAbort> {
+    match max_bytes {
+        Some(max_bytes) if req.payload.len() > max_bytes => Err(DapAbort::RequestTooLarge {
+            detail: format!(
+                "aggregation job initialization request body is {} bytes; the limit is {max_bytes} bytes",
+                req.payload.len(),
+            ),
+            task_id: *req.task_id()?,
+        }),
+        _ => Ok(()),
+    }
+}
+
 #[tracing::instrument(
     skip_all,
     fields(
@@ -78,12 +124,247 @@ async fn agg_job(
 async fn agg_share<A>(
     State(app): State<Arc<A>>,
     DapRequestExtractor(req): DapRequestExtractor,
-) -> AxumDapResponse
+) -> Response
 where
     A: DapHelper<DaphneAuth> + DaphneService + Send + Sync,
 {
-    AxumDapResponse::from_result(
-        helper::handle_agg_share_req(&*app, &req).await,
-        app.server_metrics(),
-    )
+    let resp = match helper::handle_agg_share_req(&*app, &req).await {
+        Ok(resp) => resp,
+        Err(e) => return AxumDapResponse::new_error(e, app.server_metrics()).into_response(),
+    };
+    let signature = app
+        .agg_share_signing_key()
+        .map(|key| key.sign(&resp.payload));
+    let resp = AxumDapResponse::new_success(resp, app.server_metrics());
+    match signature {
+        Some(signature) => (
+            AppendHeaders([(http_headers::AGGREGATE_SHARE_SIGNATURE, signature)]),
+            resp,
+        )
+            .into_response(),
+        None => resp.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::pending,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+    };
+
+    use axum::{
+        async_trait,
+        body::Body,
+        extract::State,
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+        response::IntoResponse,
+    };
+    use daphne::{
+        constants::DapMediaType,
+        error::DapAbort,
+        messages::{
+            AggregationJobId, AggregationJobInitReq, Base64Encode, PartialBatchSelector, TaskId,
+            Time,
+        },
+        DapRequest, DapResource, DapVersion,
+    };
+    use daphne_service_utils::{auth::DaphneAuth, durable_requests::KvListPage, DapRole};
+    use prio::codec::{ParameterizedDecode, ParameterizedEncode};
+    use rand::{thread_rng, Rng};
+    use tokio::sync::Notify;
+    use tower::ServiceExt;
+
+    use super::{agg_job, check_agg_init_body_size};
+    use crate::{
+        router::DapRequestExtractor,
+        test::{test_app, MockClock},
+        DapStorage, InMemoryStorage, StorageError,
+    };
+
+    /// Storage whose reads never complete. Signals when the first read starts.
+    struct StalledStorage(Arc<Notify>);
+
+    #[async_trait]
+    impl DapStorage for StalledStorage {
+        async fn get(&self, _key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+            self.0.notify_one();
+            pending().await
+        }
+
+        async fn put(
+            &self,
+            _key: &str,
+            _value: Vec<u8>,
+            _expiration: Option<Time>,
+        ) -> Result<(), StorageError> {
+            pending().await
+        }
+
+        async fn put_if_not_exists(
+            &self,
+            _key: &str,
+            _value: Vec<u8>,
+            _expiration: Option<Time>,
+        ) -> Result<bool, StorageError> {
+            pending().await
+        }
+
+        async fn list(
+            &self,
+            _prefix: &str,
+            _cursor: Option<&str>,
+        ) -> Result<KvListPage, StorageError> {
+            pending().await
+        }
+
+        async fn delete_all(&self) -> Result<(), StorageError> {
+            pending().await
+        }
+
+        async fn ready(&self) -> Result<(), StorageError> {
+            pending().await
+        }
+    }
+
+    fn empty_agg_job_req(task_id: TaskId, agg_job_id: AggregationJobId) -> DapRequest<DaphneAuth> {
+        let payload = AggregationJobInitReq {
+            agg_param: Vec::new(),
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            prep_inits: Vec::new(),
+        }
+        .get_encoded_with_param(&DapVersion::Draft09)
+        .unwrap();
+        DapRequest {
+            resource: DapResource::AggregationJob(agg_job_id),
+            ..agg_init_req(task_id, payload)
+        }
+    }
+
+    fn agg_init_req(task_id: TaskId, payload: Vec<u8>) -> DapRequest<DaphneAuth> {
+        DapRequest {
+            version: DapVersion::Draft09,
+            media_type: Some(DapMediaType::AggregationJobInitReq),
+            task_id: Some(task_id),
+            resource: DapResource::Undefined,
+            payload,
+            sender_auth: None,
+            taskprov: None,
+        }
+    }
+
+    #[test]
+    fn agg_init_body_over_limit_is_rejected_before_decode() {
+        let task_id = TaskId(thread_rng().gen());
+        // The payload is not a valid encoding: if it were decoded, the abort would be different.
+        let req = agg_init_req(task_id, vec![0xff; 101]);
+
+        assert!(matches!(
+            check_agg_init_body_size(&req, Some(100)),
+            Err(DapAbort::RequestTooLarge { task_id: t, .. }) if t == task_id
+        ));
+    }
+
+    #[test]
+    fn agg_init_body_under_limit_decodes() {
+        let task_id = TaskId(thread_rng().gen());
+        let agg_job_init_req = AggregationJobInitReq {
+            agg_param: Vec::new(),
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            prep_inits: Vec::new(),
+        };
+        let payload = agg_job_init_req
+            .get_encoded_with_param(&DapVersion::Draft09)
+            .unwrap();
+        let req = agg_init_req(task_id, payload);
+
+        check_agg_init_body_size(&req, Some(req.payload.len())).unwrap();
+        check_agg_init_body_size(&req, None).unwrap();
+        assert_eq!(
+            AggregationJobInitReq::get_decoded_with_param(&req.version, &req.payload).unwrap(),
+            agg_job_init_req,
+        );
+    }
+
+    #[tokio::test]
+    async fn agg_init_body_over_limit_is_rejected_while_read() {
+        let mut app = test_app();
+        app.service_config.max_agg_init_body_bytes = Some(100);
+        let router = crate::router::new(DapRole::Helper, app);
+
+        let version = DapVersion::Draft09;
+        let task_id = TaskId(thread_rng().gen());
+        let agg_job_id = AggregationJobId(thread_rng().gen());
+        let req = Request::builder()
+            .method("PUT")
+            .uri(format!(
+                "/{version}/tasks/{}/aggregation_jobs/{}",
+                task_id.to_base64url(),
+                agg_job_id.to_base64url(),
+            ))
+            .header(
+                CONTENT_TYPE,
+                DapMediaType::AggregationJobInitReq
+                    .as_str_for_version(version)
+                    .unwrap(),
+            )
+            .body(Body::from(vec![0xff; 101]))
+            .unwrap();
+
+        let resp = router.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn agg_job_activity_is_cleared_when_job_fails() {
+        let mut app = test_app();
+        let now = Arc::new(AtomicU64::new(1_637_361_337));
+        app.set_clock(MockClock(now.clone()));
+        app.set_storage(InMemoryStorage::default());
+        let app = Arc::new(app);
+
+        // The task is not provisioned, so the job is rejected.
+        let req = empty_agg_job_req(
+            TaskId(thread_rng().gen()),
+            AggregationJobId(thread_rng().gen()),
+        );
+        let resp = agg_job(State(app.clone()), DapRequestExtractor(req))
+            .await
+            .into_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        now.fetch_add(61, Ordering::Relaxed);
+        assert!(app.list_stalled_agg_jobs(60).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn agg_job_activity_is_cleared_when_cancelled() {
+        let mut app = test_app();
+        let now = Arc::new(AtomicU64::new(1_637_361_337));
+        app.set_clock(MockClock(now.clone()));
+        let reading = Arc::new(Notify::new());
+        app.set_storage(StalledStorage(reading.clone()));
+        let app = Arc::new(app);
+
+        let task_id = TaskId(thread_rng().gen());
+        let agg_job_id = AggregationJobId(thread_rng().gen());
+        let req = empty_agg_job_req(task_id, agg_job_id);
+        let handle = tokio::spawn(agg_job(State(app.clone()), DapRequestExtractor(req)));
+
+        // The job is tracked while the Helper waits on storage.
+        reading.notified().await;
+        now.fetch_add(61, Ordering::Relaxed);
+        assert_eq!(
+            app.list_stalled_agg_jobs(60).unwrap(),
+            [(task_id, agg_job_id)]
+        );
+
+        // It stops being tracked once the request is cancelled.
+        handle.abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+        assert!(app.list_stalled_agg_jobs(60).unwrap().is_empty());
+    }
 }
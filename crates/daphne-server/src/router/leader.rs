@@ -7,7 +7,7 @@ use axum::{
     body::HttpBody,
     extract::State,
     http::StatusCode,
-    response::{IntoResponse, Response},
+    response::{AppendHeaders, IntoResponse, Response},
     routing::{post, put},
 };
 use daphne::{
@@ -16,7 +16,7 @@ use daphne::{
     roles::leader::{self, DapLeader},
     DapError, DapVersion,
 };
-use daphne_service_utils::auth::DaphneAuth;
+use daphne_service_utils::{auth::DaphneAuth, http_headers};
 use prio::codec::ParameterizedEncode;
 
 use super::{AxumDapResponse, DapRequestExtractor, DaphneService};
@@ -26,7 +26,7 @@ where
     A: DapLeader<DaphneAuth> + DaphneService + Send + Sync + 'static,
     B: Send + HttpBody + 'static,
     B::Data: Send,
-    B::Error: Send + Sync,
+    B::Error: Send + Sync + Into<Box<dyn std::error::Error + Send + Sync>>,
 {
     router
         .route(
@@ -55,7 +55,7 @@ where
     A: DapLeader<DaphneAuth> + DaphneService + Send + Sync,
 {
     match leader::handle_upload_req(&*app, &req).await {
-        Ok(()) => StatusCode::OK.into_response(),
+        Ok(()) => StatusCode::CREATED.into_response(),
         Err(e) => AxumDapResponse::new_error(e, app.server_metrics()).into_response(),
     }
 }
@@ -105,25 +105,41 @@ where
         Err(e) => return AxumDapResponse::new_error(e, app.server_metrics()).into_response(),
     };
     match app.poll_collect_job(task_id, collect_id).await {
-        Ok(daphne::DapCollectionJob::Done(collect_resp)) => AxumDapResponse::new_success(
-            daphne::DapResponse {
-                version: req.version,
-                media_type: DapMediaType::Collection,
-                payload: match collect_resp.get_encoded_with_param(&req.version) {
-                    Ok(payload) => payload,
-                    Err(e) => {
-                        return AxumDapResponse::new_error(
-                            DapError::encoding(e),
-                            app.server_metrics(),
-                        )
+        Ok(daphne::DapCollectionJob::Done(collect_resp)) => {
+            let payload = match collect_resp.get_encoded_with_param(&req.version) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    return AxumDapResponse::new_error(DapError::encoding(e), app.server_metrics())
                         .into_response()
-                    }
+                }
+            };
+            let signature = app.collection_signing_key().map(|k| k.sign(&payload));
+            let resp = AxumDapResponse::new_success(
+                daphne::DapResponse {
+                    version: req.version,
+                    media_type: DapMediaType::Collection,
+                    payload,
                 },
+                app.server_metrics(),
+            );
+            match signature {
+                Some(signature) => (
+                    AppendHeaders([(http_headers::COLLECTION_SIGNATURE, signature)]),
+                    resp,
+                )
+                    .into_response(),
+                None => resp.into_response(),
+            }
+        }
+        Ok(daphne::DapCollectionJob::Pending) => StatusCode::ACCEPTED.into_response(),
+        Ok(daphne::DapCollectionJob::Failed(reason)) => AxumDapResponse::new_error(
+            DapAbort::CollectionJobFailed {
+                detail: reason,
+                task_id: *task_id,
             },
             app.server_metrics(),
         )
         .into_response(),
-        Ok(daphne::DapCollectionJob::Pending) => StatusCode::ACCEPTED.into_response(),
         Ok(daphne::DapCollectionJob::Unknown) => AxumDapResponse::new_error(
             DapAbort::BadRequest("unknown collection job id".into()),
             app.server_metrics(),
@@ -11,23 +11,27 @@ use std::sync::Arc;
 
 use axum::{
     async_trait,
-    body::HttpBody,
-    extract::{FromRequest, FromRequestParts, Path, State},
-    http::{header::CONTENT_TYPE, HeaderValue, StatusCode},
+    body::{Bytes, HttpBody},
+    extract::{DefaultBodyLimit, FromRequest, FromRequestParts, Path, State},
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        HeaderValue, StatusCode,
+    },
     middleware::Next,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Json,
 };
 use daphne::{
     auth::BearerToken,
     constants::DapMediaType,
-    error::DapAbort,
+    error::{DapAbort, DapErrorKind},
     fatal_error,
     messages::{AggregationJobId, CollectionJobId, TaskId},
     DapError, DapRequest, DapResource, DapResponse, DapVersion,
 };
 use daphne_service_utils::{
     auth::{DaphneAuth, TlsClientAuth},
+    ed25519_signature::Ed25519SigningKey,
     http_headers,
     metrics::{self, DaphneServiceMetrics},
     DapRole,
@@ -35,7 +39,10 @@ use daphne_service_utils::{
 use http::Request;
 use serde::Deserialize;
 
-use crate::App;
+use crate::{
+    content_encoding::{self, GzipError},
+    App,
+};
 
 type Router<A, B> = axum::Router<Arc<A>, B>;
 
@@ -47,6 +54,18 @@ pub trait DaphneService {
     fn signing_key(&self) -> Option<&p256::ecdsa::SigningKey> {
         None
     }
+
+    fn collection_signing_key(&self) -> Option<&Ed25519SigningKey> {
+        None
+    }
+
+    fn agg_share_signing_key(&self) -> Option<&Ed25519SigningKey> {
+        None
+    }
+
+    /// The maximum size of a request body. This bounds both the body as received and, if it is
+    /// gzip-encoded, the body once decompressed.
+    fn max_decompressed_body_bytes(&self) -> usize;
 }
 
 impl<S> DaphneService for Arc<S>
@@ -60,6 +79,18 @@ where
     fn signing_key(&self) -> Option<&p256::ecdsa::SigningKey> {
         S::signing_key(&**self)
     }
+
+    fn collection_signing_key(&self) -> Option<&Ed25519SigningKey> {
+        S::collection_signing_key(&**self)
+    }
+
+    fn agg_share_signing_key(&self) -> Option<&Ed25519SigningKey> {
+        S::agg_share_signing_key(&**self)
+    }
+
+    fn max_decompressed_body_bytes(&self) -> usize {
+        S::max_decompressed_body_bytes(&**self)
+    }
 }
 
 pub fn new<B>(role: DapRole, aggregator: App) -> axum::Router<(), B>
@@ -74,7 +105,9 @@ where
 
     let router = match role {
         DapRole::Leader => leader::add_leader_routes(router),
-        DapRole::Helper => helper::add_helper_routes(router),
+        DapRole::Helper => {
+            helper::add_helper_routes(router, aggregator.service_config.max_agg_init_body_bytes)
+        }
     };
 
     #[cfg(feature = "test-utils")]
@@ -102,15 +135,83 @@ where
         resp
     }
 
+    let max_body_bytes = aggregator.max_decompressed_body_bytes();
     let app = Arc::new(aggregator);
-    router
-        .with_state(app.clone())
-        .layer(
-            tower::ServiceBuilder::new().layer(axum::middleware::from_fn_with_state(
+    router.with_state(app.clone()).layer(
+        tower::ServiceBuilder::new()
+            .layer(DefaultBodyLimit::max(max_body_bytes))
+            .layer(axum::middleware::from_fn_with_state(
                 app.clone(),
                 request_metrics,
-            )),
-        )
+            ))
+            .layer(axum::middleware::from_fn(compress_response)),
+    )
+}
+
+/// Create the router for operator endpoints. For now this only serves the service metrics on
+/// `GET /metrics`.
+///
+/// These endpoints are not authenticated and expose operational data, so they are not part of the
+/// router returned by [`new`]. Serve this router on a separate listener that DAP peers can't reach.
+pub fn new_admin<M, B>(metrics: M) -> axum::Router<(), B>
+where
+    M: DaphneServiceMetrics + 'static,
+    B: Send + HttpBody + 'static,
+    B::Data: Send,
+    B::Error: Send + Sync + Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let metrics: Arc<dyn DaphneServiceMetrics> = Arc::new(metrics);
+    axum::Router::new()
+        .route("/metrics", axum::routing::get(export_metrics))
+        .with_state(metrics)
+}
+
+/// Export the service metrics in the Prometheus text exposition format.
+async fn export_metrics(State(metrics): State<Arc<dyn DaphneServiceMetrics>>) -> Response {
+    match metrics.encode_text() {
+        Ok(text) => ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], text).into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*metrics).into_response(),
+    }
+}
+
+/// Compress successful responses with gzip if the client advertises support for it in its
+/// `Accept-Encoding` header.
+async fn compress_response<B>(req: Request<B>, next: Next<B>) -> Response {
+    let accepts_gzip = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(content_encoding::accepts_gzip);
+    let resp = next.run(req).await;
+    if !accepts_gzip || !resp.status().is_success() || resp.headers().contains_key(CONTENT_ENCODING)
+    {
+        return resp;
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!(error = ?e, "failed to read response body");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if body.is_empty() {
+        return Response::from_parts(parts, axum::body::boxed(axum::body::Full::from(body)));
+    }
+
+    parts.headers.insert(
+        CONTENT_ENCODING,
+        HeaderValue::from_static(content_encoding::GZIP),
+    );
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .append(VARY, HeaderValue::from_static("accept-encoding"));
+    Response::from_parts(
+        parts,
+        axum::body::boxed(axum::body::Full::from(content_encoding::gzip(&body))),
+    )
 }
 
 struct AxumDapResponse(axum::response::Response);
@@ -153,12 +254,18 @@ impl AxumDapResponse {
             DapError::Fatal(e) => Err(e),
             DapError::Abort(abort) => Ok(abort),
         };
-        let status = if let Err(_e) = &error {
-            // TODO(mendess) uncomment the line below
-            // self.error_reporter.report_abort(&e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        } else {
-            StatusCode::BAD_REQUEST
+        let status = match &error {
+            // The request may succeed if the client tries again later.
+            Err(e) if e.kind() == DapErrorKind::Transient => StatusCode::SERVICE_UNAVAILABLE,
+            Err(_e) => {
+                // TODO(mendess) uncomment the line below
+                // self.error_reporter.report_abort(&e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Ok(DapAbort::RequestTooLarge { .. }) => StatusCode::PAYLOAD_TOO_LARGE,
+            // The request is fine, but the Leader could not complete the job it refers to.
+            Ok(DapAbort::CollectionJobFailed { .. }) => StatusCode::INTERNAL_SERVER_ERROR,
+            Ok(_) => StatusCode::BAD_REQUEST,
         };
         let problem_details = match error {
             Ok(error) => {
@@ -219,6 +326,7 @@ where
     S: DaphneService + Send + Sync,
     B: HttpBody + Send + 'static,
     <B as HttpBody>::Data: Send,
+    <B as HttpBody>::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
 {
     type Rejection = (StatusCode, String);
 
@@ -226,7 +334,7 @@ where
         #[derive(Debug, Deserialize)]
         #[serde(deny_unknown_fields)]
         struct PathParams {
-            version: DapVersion,
+            version: String,
             #[serde(default, with = "daphne::messages::base64url_option")]
             task_id: Option<TaskId>,
             #[serde(default, with = "daphne::messages::base64url_option")]
@@ -244,6 +352,10 @@ where
         }) = Path::from_request_parts(&mut parts, state)
             .await
             .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        let version = version.parse::<DapVersion>().map_err(|e| {
+            let detail = e.into_problem_details().detail.unwrap_or_default();
+            (StatusCode::BAD_REQUEST, detail)
+        })?;
 
         let extract_header_as_string = |header: &'static str| -> Option<String> {
             parts
@@ -287,15 +399,38 @@ where
 
         let taskprov = extract_header_as_string(http_headers::DAP_TASKPROV);
 
+        let encoding = parts.headers.get(CONTENT_ENCODING).cloned();
+
         // TODO(mendess): this is very eager, we could redesign DapResponse later to allow for
         // streaming of data.
-        let payload = hyper::body::to_bytes(body).await;
+        //
+        // The body is buffered by the `Bytes` extractor so that the `DefaultBodyLimit` set on the
+        // route is enforced while the body is read.
+        let payload = Bytes::from_request(Request::from_parts(parts, body), state)
+            .await
+            .map_err(|e| (e.status(), e.body_text()))?;
 
-        let Ok(payload) = payload else {
-            return Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "failed to get payload".into(),
-            ));
+        let payload = match encoding.as_ref().map(HeaderValue::to_str) {
+            None => payload.to_vec(),
+            Some(Ok(encoding)) if encoding.trim().eq_ignore_ascii_case("identity") => {
+                payload.to_vec()
+            }
+            Some(Ok(encoding)) if encoding.trim().eq_ignore_ascii_case(content_encoding::GZIP) => {
+                content_encoding::gunzip(&payload, state.max_decompressed_body_bytes()).map_err(
+                    |e| match e {
+                        GzipError::TooLarge { .. } => {
+                            (StatusCode::PAYLOAD_TOO_LARGE, e.to_string())
+                        }
+                        GzipError::Malformed(..) => (StatusCode::BAD_REQUEST, e.to_string()),
+                    },
+                )?
+            }
+            Some(_) => {
+                return Err((
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "unsupported content encoding".into(),
+                ))
+            }
         };
 
         let (task_id, resource) = {
@@ -328,7 +463,7 @@ where
             version,
             task_id,
             resource,
-            payload: payload.to_vec(),
+            payload,
             media_type,
             sender_auth: Some(sender_auth),
             taskprov,
@@ -343,23 +478,36 @@ mod test {
     use axum::{
         body::{Body, HttpBody},
         extract::State,
-        http::{header::CONTENT_TYPE, Request, StatusCode},
+        http::{
+            header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE},
+            Request, StatusCode,
+        },
         response::IntoResponse,
         routing::get,
         Router,
     };
     use daphne::{
         async_test_version, async_test_versions,
-        messages::{AggregationJobId, Base64Encode, TaskId},
+        constants::DapMediaType,
+        error::DapErrorKind,
+        fatal_error,
+        messages::{
+            AggregationJobId, AggregationJobInitReq, Base64Encode, HpkeCiphertext,
+            PartialBatchSelector, PrepareInit, ReportId, ReportMetadata, ReportShare, TaskId,
+        },
         DapRequest, DapResource, DapVersion,
     };
-    use daphne_service_utils::{auth::DaphneAuth, metrics::DaphnePromServiceMetrics};
+    use daphne_service_utils::{auth::DaphneAuth, metrics::DaphnePromServiceMetrics, DapRole};
     use futures::future::BoxFuture;
+    use prio::codec::{ParameterizedDecode, ParameterizedEncode};
     use rand::{thread_rng, Rng};
     use tokio::sync::mpsc::{self, Sender};
     use tower::ServiceExt;
 
-    use super::DapRequestExtractor;
+    use super::{compress_response, AxumDapResponse, DapRequestExtractor};
+    use crate::content_encoding;
+
+    const MAX_DECOMPRESSED_BODY_BYTES: usize = 1024 * 1024;
 
     /// Return a function that will parse a request using the [`DapRequestExtractor`] and return
     /// the parsed request.
@@ -373,7 +521,7 @@ mod test {
     where
         B: Send + Sync + 'static + HttpBody,
         B::Data: Send,
-        B::Error: Send + Sync + std::error::Error,
+        B::Error: Send + Sync + Into<Box<dyn std::error::Error + Send + Sync>>,
     {
         type Channel = Sender<DapRequest<DaphneAuth>>;
 
@@ -390,6 +538,10 @@ mod test {
             fn signing_key(&self) -> Option<&p256::ecdsa::SigningKey> {
                 None
             }
+
+            fn max_decompressed_body_bytes(&self) -> usize {
+                MAX_DECOMPRESSED_BODY_BYTES
+            }
         }
 
         async fn handler(
@@ -495,4 +647,228 @@ mod test {
 
     async_test_version! { parse_agg_job_id, Draft09 }
     async_test_version! { parse_agg_job_id, Latest }
+
+    async fn parse_gzip_agg_job_init_req(version: DapVersion) {
+        let test = test_router();
+
+        let agg_job_id = AggregationJobId(thread_rng().gen());
+        let agg_job_init_req = AggregationJobInitReq {
+            agg_param: Vec::new(),
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            prep_inits: (0..1000)
+                .map(|_| PrepareInit {
+                    report_share: ReportShare {
+                        report_metadata: ReportMetadata {
+                            id: ReportId(thread_rng().gen()),
+                            time: 1_637_361_337,
+                        },
+                        public_share: vec![0; 16],
+                        encrypted_input_share: HpkeCiphertext {
+                            config_id: 23,
+                            enc: vec![1; 32],
+                            payload: vec![2; 64],
+                        },
+                    },
+                    payload: vec![3; 32],
+                })
+                .collect(),
+        };
+        let payload = agg_job_init_req.get_encoded_with_param(&version).unwrap();
+        let compressed = content_encoding::gzip(&payload);
+        assert!(compressed.len() < payload.len());
+
+        let req = test(
+            Request::builder()
+                .uri(format!(
+                    "/{version}/{}/parse-agg-job-id",
+                    agg_job_id.to_base64url()
+                ))
+                .header(CONTENT_TYPE, "application/dap-aggregation-job-init-req")
+                .header(CONTENT_ENCODING, "gzip")
+                .body(Body::from(compressed))
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(req.payload, payload);
+        assert_eq!(
+            AggregationJobInitReq::get_decoded_with_param(&version, &req.payload).unwrap(),
+            agg_job_init_req
+        );
+    }
+
+    async_test_version! { parse_gzip_agg_job_init_req, Draft09 }
+    async_test_version! { parse_gzip_agg_job_init_req, Latest }
+
+    #[tokio::test]
+    async fn export_metrics() {
+        let metrics = DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap();
+        let mut app =
+            crate::test::test_app_with("http://example.com".parse().unwrap(), metrics.clone());
+        app.set_storage(crate::InMemoryStorage::default());
+        let router = super::new::<Body>(DapRole::Leader, app);
+        let admin_router = super::new_admin::<_, Body>(metrics);
+
+        // No HPKE config has been provisioned, so this request fails.
+        let resp = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{}/hpke_config", DapVersion::Latest))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // The metrics are not served to DAP peers.
+        let resp = router
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let resp = admin_router
+            .oneshot(
+                Request::builder()
+                    .uri("/metrics")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_TYPE], prometheus::TEXT_FORMAT);
+        let text = String::from_utf8(
+            hyper::body::to_bytes(resp.into_body())
+                .await
+                .unwrap()
+                .to_vec(),
+        )
+        .unwrap();
+        for line in [
+            r#"dap_abort{reason="Internal server error"} 1"#,
+            r#"http_status_code{code="500"} 1"#,
+        ] {
+            assert!(text.lines().any(|l| l == line), "{line} not in:\n{text}");
+        }
+    }
+
+    #[test]
+    fn transient_error_is_service_unavailable() {
+        let metrics = DaphnePromServiceMetrics::register(&prometheus::Registry::new()).unwrap();
+
+        let resp = AxumDapResponse::new_error(
+            fatal_error!(err = "storage request timed out").with_kind(DapErrorKind::Transient),
+            &metrics,
+        );
+        assert_eq!(
+            resp.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        let resp =
+            AxumDapResponse::new_error(fatal_error!(err = "failed to parse config"), &metrics);
+        assert_eq!(
+            resp.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_gzip_bomb() {
+        let router = Router::new()
+            .route(
+                "/:version/parse-version",
+                get(|DapRequestExtractor(_): DapRequestExtractor| async {}),
+            )
+            .with_state(Arc::new(mpsc::channel::<DapRequest<DaphneAuth>>(1).0));
+
+        let bomb = content_encoding::gzip(&vec![0; MAX_DECOMPRESSED_BODY_BYTES + 1]);
+        let resp = router
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/{}/parse-version", DapVersion::Latest))
+                    .header(CONTENT_ENCODING, "gzip")
+                    .body(Body::from(bomb))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn reject_body_over_limit() {
+        let app = crate::test::test_app();
+        let body = vec![0; app.service_config.max_decompressed_body_bytes + 1];
+        let router = super::new(DapRole::Leader, app);
+        let version = DapVersion::Latest;
+        let task_id = TaskId(thread_rng().gen());
+
+        let resp = router
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!(
+                        "/{version}/tasks/{}/reports",
+                        task_id.to_base64url()
+                    ))
+                    .header(
+                        CONTENT_TYPE,
+                        DapMediaType::Report.as_str_for_version(version).unwrap(),
+                    )
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn compress_response_if_accepted() {
+        let body = "some DAP message ".repeat(100);
+        let router = {
+            let body = body.clone();
+            Router::new()
+                .route("/", get(|| async move { body }))
+                .layer(axum::middleware::from_fn(compress_response))
+        };
+
+        let resp = router
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/")
+                    .header(ACCEPT_ENCODING, "br, gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()[CONTENT_ENCODING], "gzip");
+        let compressed = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            content_encoding::gunzip(&compressed, body.len()).unwrap(),
+            body.as_bytes()
+        );
+
+        // The response is left as is if the client doesn't accept gzip.
+        let resp = router
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(!resp.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(
+            hyper::body::to_bytes(resp.into_body()).await.unwrap(),
+            body.as_bytes()
+        );
+    }
 }
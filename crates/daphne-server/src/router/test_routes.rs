@@ -13,12 +13,16 @@ use axum::{
 };
 use daphne::{
     hpke::HpkeReceiverConfig,
-    messages::{Base64Encode, TaskId},
-    roles::{leader, DapLeader},
+    messages::{AggregationJobId, Base64Encode, TaskId},
+    roles::{helper, leader, DapHelper, DapLeader},
     DapVersion,
 };
 use daphne_service_utils::{
-    test_route_types::{InternalTestAddTask, InternalTestEndpointForTask},
+    test_route_types::{
+        InternalApiError, InternalTestAddTask, InternalTestCurrentBatch,
+        InternalTestEndpointForTask, InternalTestInjectReports, InternalTestReady,
+        InternalTestStatus,
+    },
     DapRole,
 };
 use serde::Deserialize;
@@ -40,13 +44,38 @@ where
                 "/internal/current_batch/task/:task_id",
                 get(leader_current_batch),
             )
+            .route(
+                "/internal/current_batches/task/:task_id",
+                get(leader_list_current_batches),
+            )
+            .route(
+                "/internal/sweep_expired_reports",
+                post(leader_sweep_expired_reports),
+            )
+            .route(
+                "/internal/quarantined_agg_jobs",
+                get(leader_quarantined_agg_jobs),
+            )
+            .route(
+                "/internal/test/inject_reports/task/:task_id",
+                post(leader_inject_reports),
+            )
     } else {
         router
+            .route(
+                "/internal/cancel_agg_job/task/:task_id/agg_job/:agg_job_id",
+                post(helper_cancel_agg_job),
+            )
+            .route(
+                "/internal/agg_job_diagnostics/task/:task_id/agg_job/:agg_job_id",
+                get(helper_agg_job_diagnostics),
+            )
     };
 
     router
         .route("/internal/delete_all", post(delete_all))
         .route("/internal/test/ready", post(check_storage_readyness))
+        .route("/internal/test/list_tasks", get(list_tasks))
         .route(
             "/internal/test/endpoint_for_task",
             post(endpoint_for_task_default),
@@ -69,10 +98,25 @@ where
 
 #[tracing::instrument(skip(app))]
 async fn check_storage_readyness(State(app): State<Arc<App>>) -> Response {
-    match app.storage_ready_check().await {
-        Ok(()) => StatusCode::OK.into_response(),
-        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
-    }
+    let (status_code, status, error) = match app.storage_ready_check().await {
+        Ok(()) => (StatusCode::OK, InternalTestStatus::Success, None),
+        Err(e) => {
+            tracing::error!(error = ?e, "storage is not ready");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                InternalTestStatus::Error,
+                Some(e.to_string()),
+            )
+        }
+    };
+    let ready = InternalTestReady {
+        status,
+        ready: error.is_none(),
+        role: app.service_config.role,
+        version: app.service_config.default_version,
+        error,
+    };
+    (status_code, Json(ready)).into_response()
 }
 
 #[tracing::instrument(skip(app))]
@@ -83,6 +127,14 @@ async fn leader_process(State(app): State<Arc<App>>) -> Response {
     }
 }
 
+#[tracing::instrument(skip(app))]
+async fn leader_sweep_expired_reports(State(app): State<Arc<App>>) -> Response {
+    match leader::sweep_expired_reports(&*app).await {
+        Ok(count) => (StatusCode::OK, Json(count)).into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+    }
+}
+
 #[derive(Deserialize)]
 struct PathTaskId {
     #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
@@ -100,11 +152,121 @@ async fn leader_current_batch(
     }
 }
 
+#[tracing::instrument(skip(app))]
+async fn leader_list_current_batches(
+    State(app): State<Arc<App>>,
+    Path(PathTaskId { task_id }): Path<PathTaskId>,
+) -> Response {
+    match app.internal_list_current_batches(&task_id).await {
+        Ok(batches) => (
+            StatusCode::OK,
+            Json(
+                batches
+                    .into_iter()
+                    .map(|(batch_id, report_count)| InternalTestCurrentBatch {
+                        batch_id,
+                        report_count,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+        )
+            .into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn leader_quarantined_agg_jobs(State(app): State<Arc<App>>) -> Response {
+    match app.quarantined_agg_jobs().await {
+        Ok(jobs) => (StatusCode::OK, Json(jobs)).into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn leader_inject_reports(
+    State(app): State<Arc<App>>,
+    Path(PathTaskId { task_id }): Path<PathTaskId>,
+    Json(cmd): Json<InternalTestInjectReports>,
+) -> Response {
+    match app.internal_inject_reports(&task_id, cmd.count).await {
+        Ok(count) => (StatusCode::OK, Json(InternalTestInjectReports { count })).into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+struct PathTaskIdAggJobId {
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    task_id: TaskId,
+    #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    agg_job_id: AggregationJobId,
+}
+
+#[tracing::instrument(skip(app))]
+async fn helper_cancel_agg_job(
+    State(app): State<Arc<App>>,
+    Path(PathTaskIdAggJobId {
+        task_id,
+        agg_job_id,
+    }): Path<PathTaskIdAggJobId>,
+) -> impl IntoResponse {
+    match helper::cancel_aggregation_job(&*app, &task_id, &agg_job_id).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn helper_agg_job_diagnostics(
+    State(app): State<Arc<App>>,
+    Path(PathTaskIdAggJobId {
+        task_id,
+        agg_job_id,
+    }): Path<PathTaskIdAggJobId>,
+) -> Response {
+    match app.get_agg_job_diagnostics(&task_id, &agg_job_id).await {
+        Ok(Some(diagnostics)) => (StatusCode::OK, Json(diagnostics)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+    }
+}
+
+#[tracing::instrument(skip(app))]
+async fn list_tasks(State(app): State<Arc<App>>) -> impl IntoResponse {
+    match app.list_task_ids().await {
+        Ok(task_ids) => (
+            StatusCode::OK,
+            Json(
+                task_ids
+                    .iter()
+                    .map(Base64Encode::to_base64url)
+                    .collect::<Vec<_>>(),
+            ),
+        )
+            .into_response(),
+        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+    }
+}
+
+/// Respond to a failed internal command with the JSON encoding of the error.
+fn internal_api_error(e: InternalApiError) -> Response {
+    tracing::error!(error = %e, "internal command failed");
+    let status = match e {
+        InternalApiError::InvalidCommand(..) => StatusCode::BAD_REQUEST,
+        InternalApiError::TaskAlreadyExists(..) | InternalApiError::HpkeConfigAlreadyExists(..) => {
+            StatusCode::CONFLICT
+        }
+        InternalApiError::StorageError(..) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(e)).into_response()
+}
+
 #[tracing::instrument(skip(app))]
 async fn delete_all(State(app): State<Arc<App>>) -> impl IntoResponse {
     match app.internal_delete_all().await {
         Ok(()) => StatusCode::OK.into_response(),
-        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+        Err(e) => internal_api_error(e),
     }
 }
 
@@ -144,7 +306,7 @@ async fn add_task(
             Json(serde_json::json!({ "status": "success" })),
         )
             .into_response(),
-        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+        Err(e) => internal_api_error(e),
     }
 }
 
@@ -169,7 +331,7 @@ async fn add_hpke_config(
             Json(serde_json::json!({ "status": "success" })),
         )
             .into_response(),
-        Err(e) => AxumDapResponse::new_error(e, &*app.metrics).into_response(),
+        Err(e) => internal_api_error(e),
     }
 }
 
@@ -181,3 +343,110 @@ async fn add_hpke_config_default(
     let version = app.service_config.default_version;
     add_hpke_config(State(app), Path(version), json).await
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use axum::{
+        extract::{Path, State},
+        http::StatusCode,
+        response::IntoResponse,
+        Json,
+    };
+    use daphne::{
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{encode_base64url, TaskId},
+        DapVersion,
+    };
+    use daphne_service_utils::{
+        durable_requests::STORAGE_READY,
+        test_route_types::{InternalTestReady, InternalTestStatus},
+        DapRole,
+    };
+    use prio::codec::Encode;
+    use serde_json::json;
+
+    use crate::test::{spawn_kv_storage_proxy, test_app, test_app_with_storage_proxy};
+
+    #[tokio::test]
+    async fn check_storage_readyness() {
+        let storage_proxy_url = spawn_kv_storage_proxy();
+        let app = Arc::new(test_app_with_storage_proxy(storage_proxy_url.clone()));
+        let ready = || {
+            let app = app.clone();
+            async move {
+                let resp = super::check_storage_readyness(State(app))
+                    .await
+                    .into_response();
+                let status = resp.status();
+                let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<InternalTestReady>(&body).unwrap(),
+                )
+            }
+        };
+
+        // The storage proxy stand-in doesn't know the readiness endpoint yet.
+        let (status, resp) = ready().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.status, InternalTestStatus::Error);
+        assert!(!resp.ready);
+        assert!(resp.error.is_some());
+
+        reqwest::Client::new()
+            .post(storage_proxy_url.join(STORAGE_READY).unwrap())
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        let (status, resp) = ready().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            resp,
+            InternalTestReady {
+                status: InternalTestStatus::Success,
+                ready: true,
+                role: DapRole::Leader,
+                version: DapVersion::Draft09,
+                error: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn add_task_unrecognized_vdaf() {
+        let collector_hpke_config = HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let cmd = serde_json::from_value(json!({
+            "task_id": encode_base64url(TaskId([1; 32])),
+            "leader": "https://leader.example.com/",
+            "helper": "https://helper.example.com/",
+            "vdaf": { "type": "Prio9000" },
+            "leader_authentication_token": "leader token",
+            "collector_authentication_token": "collector token",
+            "role": "leader",
+            "vdaf_verify_key": encode_base64url([0; 16]),
+            "query_type": 1,
+            "min_batch_size": 10,
+            "time_precision": 3600,
+            "collector_hpke_config": encode_base64url(collector_hpke_config.get_encoded().unwrap()),
+            "task_expiration": 1_637_361_337,
+        }))
+        .unwrap();
+
+        // An unrecognized VDAF is a problem with the command, not an internal failure.
+        let resp = super::add_task(
+            State(Arc::new(test_app())),
+            Path(DapVersion::Draft09),
+            Json(cmd),
+        )
+        .await
+        .into_response();
+        assert!(resp.status().is_client_error(), "{}", resp.status());
+    }
+}
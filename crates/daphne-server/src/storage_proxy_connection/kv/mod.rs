@@ -5,23 +5,17 @@ pub(super) mod cache;
 
 use std::{any::Any, fmt::Display};
 
-use axum::http::StatusCode;
-use daphne_service_utils::durable_requests::KV_PATH_PREFIX;
 use mappable_rc::Marc;
 use serde::{de::DeserializeOwned, Serialize};
 use tokio::sync::RwLock;
 use tracing::{info_span, Instrument};
 
-use crate::StorageProxyConfig;
-
-use super::Error;
+use super::{storage::DapStorage, Error};
 pub(crate) use cache::Cache;
 use daphne::messages::Time;
-use daphne_service_utils::http_headers::STORAGE_PROXY_PUT_KV_EXPIRATION;
 
 pub(crate) struct Kv<'h> {
-    config: &'h StorageProxyConfig,
-    http: &'h reqwest::Client,
+    storage: &'h dyn DapStorage,
     cache: &'h RwLock<Cache>,
 }
 
@@ -33,9 +27,14 @@ pub trait KvPrefix {
 }
 
 pub mod prefix {
-    use std::{fmt::Display, marker::PhantomData};
-
-    use daphne::{auth::BearerToken, messages::TaskId, taskprov, DapTaskConfig, DapVersion};
+    use std::{collections::HashMap, fmt::Display, marker::PhantomData};
+
+    use daphne::{
+        auth::BearerToken,
+        messages::{AggregationJobId, TaskId, Time},
+        roles::{AggShareReqKey, StoredAggregateShareResp, StoredAggregationJobResp},
+        taskprov, DapTaskConfig, DapVersion,
+    };
     use daphne_service_utils::config::HpkeRecieverConfigList;
     use serde::{de::DeserializeOwned, Serialize};
 
@@ -52,6 +51,8 @@ pub mod prefix {
         SkipReplayProtection,
         /// The default number of aggregate span shards to use in new tasks.
         DefaultNumAggSpanShards,
+        /// A `bool` describing whether to collect batches below the task's minimum batch size.
+        AllowPartialBatch,
     }
 
     impl Display for GlobalOverrides {
@@ -59,6 +60,7 @@ pub mod prefix {
             let key = match self {
                 Self::SkipReplayProtection => "skip_replay_protection",
                 Self::DefaultNumAggSpanShards => "default_num_agg_span_shards",
+                Self::AllowPartialBatch => "allow_partial_batch",
             };
             f.write_str(key)
         }
@@ -98,6 +100,40 @@ pub mod prefix {
         type Value = HpkeRecieverConfigList;
     }
 
+    /// The time at which each HPKE receiver config was added, by config ID.
+    pub struct HpkeReceiverConfigAddedAt();
+    impl KvPrefix for HpkeReceiverConfigAddedAt {
+        const PREFIX: &'static str = "hpke_receiver_config_added_at";
+
+        type Key = DapVersion;
+        type Value = HashMap<u8, Time>;
+    }
+
+    /// The tasks for which reports were recently uploaded under each HPKE receiver config, keyed
+    /// by config ID so that the tasks using a config can be listed under a single prefix. The
+    /// value is the time of the upload that created the entry. Entries expire one report storage
+    /// epoch after they are created, or along with the task if that is sooner.
+    pub struct HpkeConfigUsage();
+    impl KvPrefix for HpkeConfigUsage {
+        const PREFIX: &'static str = "hpke_config_usage";
+
+        type Key = HpkeConfigUsageKey;
+        type Value = Time;
+    }
+
+    #[derive(Debug)]
+    pub struct HpkeConfigUsageKey {
+        pub version: DapVersion,
+        pub config_id: u8,
+        pub task_id: TaskId,
+    }
+
+    impl Display for HpkeConfigUsageKey {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}/{}/{}", self.version, self.config_id, self.task_id)
+        }
+    }
+
     pub struct LeaderBearerToken();
     impl KvPrefix for LeaderBearerToken {
         const PREFIX: &'static str = "bearer_token/leader/task";
@@ -106,6 +142,16 @@ pub mod prefix {
         type Value = BearerToken;
     }
 
+    /// The bearer token Clients must present when uploading reports for a task. Uploads for tasks
+    /// without one are not authenticated.
+    pub struct UploadBearerToken();
+    impl KvPrefix for UploadBearerToken {
+        const PREFIX: &'static str = "bearer_token/upload/task";
+
+        type Key = TaskId;
+        type Value = BearerToken;
+    }
+
     pub struct CollectorBearerToken();
     impl KvPrefix for CollectorBearerToken {
         const PREFIX: &'static str = "bearer_token/collector/task";
@@ -113,6 +159,79 @@ pub mod prefix {
         type Key = TaskId;
         type Value = BearerToken;
     }
+
+    /// A bearer token that was replaced, along with the time until which it is still accepted.
+    #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+    pub struct RetiredBearerToken {
+        pub token: BearerToken,
+        pub valid_until: Time,
+    }
+
+    pub struct RetiredLeaderBearerToken();
+    impl KvPrefix for RetiredLeaderBearerToken {
+        const PREFIX: &'static str = "bearer_token/leader/retired/task";
+
+        type Key = TaskId;
+        type Value = RetiredBearerToken;
+    }
+
+    pub struct RetiredCollectorBearerToken();
+    impl KvPrefix for RetiredCollectorBearerToken {
+        const PREFIX: &'static str = "bearer_token/collector/retired/task";
+
+        type Key = TaskId;
+        type Value = RetiredBearerToken;
+    }
+
+    /// Identifies an aggregation job of a task.
+    #[derive(Debug)]
+    pub struct TaskAggJobId {
+        pub task_id: TaskId,
+        pub agg_job_id: AggregationJobId,
+    }
+
+    impl Display for TaskAggJobId {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}/{}", self.task_id, self.agg_job_id)
+        }
+    }
+
+    /// Aggregation jobs that were aborted. The value is the time at which the job was aborted.
+    pub struct AbortedAggregationJob();
+    impl KvPrefix for AbortedAggregationJob {
+        const PREFIX: &'static str = "aggregation_job/aborted/task";
+
+        type Key = TaskAggJobId;
+        type Value = Time;
+    }
+
+    /// Responses to aggregation job requests, kept so that retried requests can be answered.
+    pub struct AggregationJobResp();
+    impl KvPrefix for AggregationJobResp {
+        const PREFIX: &'static str = "aggregation_job/resp/task";
+
+        type Key = TaskAggJobId;
+        type Value = StoredAggregationJobResp;
+    }
+
+    /// Diagnostics of aggregation jobs, recorded if the global config asks for them.
+    pub struct AggregationJobDiagnostics();
+    impl KvPrefix for AggregationJobDiagnostics {
+        const PREFIX: &'static str = "aggregation_job/diagnostics/task";
+
+        type Key = TaskAggJobId;
+        type Value = daphne::roles::AggregationJobDiagnostics;
+    }
+
+    /// Responses to aggregate share requests, kept for a short time so that retried requests can
+    /// be answered without recomputing the aggregate share.
+    pub struct AggregateShareResp();
+    impl KvPrefix for AggregateShareResp {
+        const PREFIX: &'static str = "aggregate_share/resp/task";
+
+        type Key = AggShareReqKey;
+        type Value = StoredAggregateShareResp;
+    }
 }
 
 /// Options for getting items from KV.
@@ -130,16 +249,8 @@ pub(crate) struct KvGetOptions {
 }
 
 impl<'h> Kv<'h> {
-    pub fn new(
-        config: &'h StorageProxyConfig,
-        client: &'h reqwest::Client,
-        cache: &'h RwLock<Cache>,
-    ) -> Self {
-        Self {
-            config,
-            http: client,
-            cache,
-        }
+    pub fn new(storage: &'h dyn DapStorage, cache: &'h RwLock<Cache>) -> Self {
+        Self { storage, cache }
     }
 
     pub async fn get<P>(
@@ -230,24 +341,16 @@ impl<'h> Kv<'h> {
             prefix = std::any::type_name::<P>()
         );
         async {
-            let resp = self
-                .http
-                .get(self.config.url.join(&key).unwrap())
-                .bearer_auth(&self.config.auth_token)
-                .send()
-                .await?;
-            if resp.status() == StatusCode::NOT_FOUND {
+            let Some(value) = self.storage.get(&key).await? else {
                 if opt.cache_not_found {
                     self.cache.write().await.put::<P>(key, None);
                 }
-                Ok(None)
-            } else {
-                let resp = resp.error_for_status()?;
-                let t = Marc::new(resp.json::<P::Value>().await?);
-                let r = mapper(t.clone());
-                self.cache.write().await.put::<P>(key, Some(t));
-                Ok(Some(r))
-            }
+                return Ok(None);
+            };
+            let t = Marc::new(serde_json::from_slice::<P::Value>(&value)?);
+            let r = mapper(t.clone());
+            self.cache.write().await.put::<P>(key, Some(t));
+            Ok(Some(r))
         }
         .instrument(span)
         .await
@@ -272,17 +375,9 @@ impl<'h> Kv<'h> {
         let key = Self::to_key::<P>(key);
         tracing::debug!(key, "PUT");
 
-        let mut request = self
-            .http
-            .post(self.config.url.join(&key).unwrap())
-            .bearer_auth(&self.config.auth_token)
-            .body(serde_json::to_vec(&value).unwrap());
-
-        if let Some(expiration) = expiration {
-            request = request.header(STORAGE_PROXY_PUT_KV_EXPIRATION, expiration);
-        }
-
-        request.send().await?.error_for_status()?;
+        self.storage
+            .put(&key, serde_json::to_vec(&value)?, expiration)
+            .await?;
 
         self.cache.write().await.put::<P>(key, Some(value.into()));
         Ok(())
@@ -333,24 +428,15 @@ impl<'h> Kv<'h> {
 
         tracing::debug!(key, "PUT if not exists");
 
-        let mut request = self
-            .http
-            .put(self.config.url.join(&key).unwrap())
-            .bearer_auth(&self.config.auth_token)
-            .body(serde_json::to_vec(&value).unwrap());
-
-        if let Some(expiration) = expiration {
-            request = request.header(STORAGE_PROXY_PUT_KV_EXPIRATION, expiration);
-        }
-
-        let response = request.send().await?;
-
-        if response.status() == StatusCode::CONFLICT {
-            Ok(Some(value))
-        } else {
-            response.error_for_status()?;
+        if self
+            .storage
+            .put_if_not_exists(&key, serde_json::to_vec(&value)?, expiration)
+            .await?
+        {
             self.cache.write().await.put::<P>(key, Some(value.into()));
             Ok(None)
+        } else {
+            Ok(Some(value))
         }
     }
 
@@ -392,7 +478,34 @@ impl<'h> Kv<'h> {
         self.cache.write().await.put::<P>(key, Some(value.into()));
     }
 
+    /// List the keys stored under the prefix `P`, with the prefix stripped. Every page returned
+    /// by the storage is fetched, so this may take several requests. The cache is bypassed.
+    pub async fn list<P: KvPrefix>(&self) -> Result<Vec<String>, Error> {
+        self.list_under::<P>("").await
+    }
+
+    /// Like [`Self::list`], but only list the keys of `P` that start with `key_prefix`. Both
+    /// prefixes are stripped from the returned keys.
+    pub async fn list_under<P: KvPrefix>(&self, key_prefix: &str) -> Result<Vec<String>, Error> {
+        let prefix = format!("{}/{key_prefix}", P::PREFIX);
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = self.storage.list(&prefix, cursor.as_deref()).await?;
+            keys.extend(
+                page.keys
+                    .into_iter()
+                    .filter_map(|key| key.strip_prefix(&prefix).map(String::from)),
+            );
+
+            cursor = page.cursor;
+            if cursor.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
     fn to_key<P: KvPrefix>(key: &P::Key) -> String {
-        format!("{KV_PATH_PREFIX}/{}/{key}", P::PREFIX)
+        format!("{}/{key}", P::PREFIX)
     }
 }
@@ -2,28 +2,112 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 pub(crate) mod kv;
+pub(crate) mod storage;
 
-use std::fmt::Debug;
+use std::{fmt::Debug, future::Future, num::NonZeroU32, time::Duration};
 
 use axum::http::StatusCode;
+use daphne::error::DapErrorKind;
 use daphne_service_utils::durable_requests::{
     bindings::{DurableMethod, DurableRequestPayload, DurableRequestPayloadExt},
     DurableRequest, ObjectIdFrom, DO_PATH_PREFIX,
 };
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 pub(crate) use kv::Kv;
 
 use crate::StorageProxyConfig;
 
 #[derive(Debug, thiserror::Error)]
-pub(crate) enum Error {
+pub enum Error {
     #[error("serialization error: {0}")]
     Serde(#[from] serde_json::Error),
     #[error("network error: {0}")]
     Reqwest(#[from] reqwest::Error),
     #[error("http error. request returned status code {status} with the body {body}")]
     Http { status: StatusCode, body: String },
+    #[error("storage backend error: {0}")]
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Whether retrying the request to the storage proxy might succeed. Timeouts, connection
+    /// failures, server-side errors and rate limiting are transient. Anything else, including
+    /// requests that couldn't be built or sent, responses that can't be parsed and failures of the
+    /// storage backend itself, is not.
+    pub(crate) fn kind(&self) -> DapErrorKind {
+        let is_transient_status = |status: StatusCode| {
+            status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+        };
+        match self {
+            Self::Reqwest(e)
+                if e.is_timeout()
+                    || e.is_connect()
+                    || e.status().is_some_and(is_transient_status) =>
+            {
+                DapErrorKind::Transient
+            }
+            Self::Http { status, .. } if is_transient_status(*status) => DapErrorKind::Transient,
+            Self::Serde(..) | Self::Reqwest(..) | Self::Http { .. } | Self::Backend(..) => {
+                DapErrorKind::Permanent
+            }
+        }
+    }
+}
+
+/// How idempotent requests to the storage proxy are retried when they fail with a transient
+/// error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: NonZeroU32,
+
+    /// Delay before the first retry, in milliseconds. The delay doubles after each retry, up to
+    /// [`RetryPolicy::MAX_BACKOFF`].
+    pub backoff_ms: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: NonZeroU32::new(3).unwrap(),
+            backoff_ms: 100,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The longest delay between two attempts.
+    pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+    /// The delay before the attempt following one that was delayed by `backoff`.
+    pub(crate) fn next_backoff(backoff: Duration) -> Duration {
+        backoff.saturating_mul(2).min(Self::MAX_BACKOFF)
+    }
+
+    /// Run `op` until it succeeds, fails with a permanent error, or the maximum number of
+    /// attempts is reached. The last error is returned in the latter cases.
+    pub(crate) async fn retry<T, F, Fut>(&self, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let mut backoff = Duration::from_millis(self.backoff_ms).min(Self::MAX_BACKOFF);
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(e)
+                    if e.kind() == DapErrorKind::Transient && attempt < self.max_attempts.get() =>
+                {
+                    tracing::warn!(error = ?e, attempt, "storage proxy request failed, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff = Self::next_backoff(backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -144,3 +228,29 @@ impl<'w> Do<'w> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use daphne::error::DapErrorKind;
+
+    use super::Error;
+
+    #[test]
+    fn error_kind() {
+        let timeout = Error::Http {
+            status: StatusCode::GATEWAY_TIMEOUT,
+            body: "upstream request timed out".into(),
+        };
+        assert_eq!(timeout.kind(), DapErrorKind::Transient);
+
+        let not_found = Error::Http {
+            status: StatusCode::NOT_FOUND,
+            body: String::new(),
+        };
+        assert_eq!(not_found.kind(), DapErrorKind::Permanent);
+
+        let parse_failure = Error::Serde(serde_json::from_str::<u64>("not a number").unwrap_err());
+        assert_eq!(parse_failure.kind(), DapErrorKind::Permanent);
+    }
+}
@@ -0,0 +1,247 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+use axum::{async_trait, http::StatusCode};
+use daphne::messages::Time;
+use daphne_service_utils::{
+    durable_requests::{
+        KvListPage, KV_LIST_PATH_PREFIX, KV_PATH_PREFIX, PURGE_STORAGE, STORAGE_READY,
+    },
+    http_headers::STORAGE_PROXY_PUT_KV_EXPIRATION,
+};
+
+use crate::StorageProxyConfig;
+
+use super::Error;
+
+/// Key-value storage used by [`App`](crate::App) for task configurations, bearer tokens, HPKE
+/// receiver configs and the like.
+///
+/// Keys have the form `<prefix>/<key>`, e.g., `config/task/<task_id>`, and values are JSON. By
+/// default the storage proxy is used, via [`HttpStorage`]. Other backends can be plugged in with
+/// [`App::set_storage`](crate::App::set_storage).
+#[async_trait]
+pub trait DapStorage: Send + Sync {
+    /// Get the value stored under `key`, if any.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Store `value` under `key`, replacing the current value if there is one. If `expiration` is
+    /// set, then the value may be deleted after that time.
+    async fn put(&self, key: &str, value: Vec<u8>, expiration: Option<Time>) -> Result<(), Error>;
+
+    /// Store `value` under `key` unless a value is already stored there. Returns `false`, and
+    /// leaves the current value untouched, in the latter case.
+    async fn put_if_not_exists(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        expiration: Option<Time>,
+    ) -> Result<bool, Error>;
+
+    /// List the keys that start with `prefix`. Keys are returned in pages: if the returned page
+    /// has a cursor, then the next page is obtained by calling this method again with it.
+    async fn list(&self, prefix: &str, cursor: Option<&str>) -> Result<KvListPage, Error>;
+
+    /// Delete everything in storage.
+    async fn delete_all(&self) -> Result<(), Error>;
+
+    /// Check that the storage can be reached.
+    async fn ready(&self) -> Result<(), Error>;
+}
+
+/// [`DapStorage`] backed by the storage proxy.
+pub struct HttpStorage {
+    config: StorageProxyConfig,
+    http: reqwest::Client,
+}
+
+impl HttpStorage {
+    pub fn new(config: StorageProxyConfig, http: reqwest::Client) -> Self {
+        Self { config, http }
+    }
+
+    fn kv_url(&self, key: &str) -> url::Url {
+        self.config
+            .url
+            .join(&format!("{KV_PATH_PREFIX}/{key}"))
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl DapStorage for HttpStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let resp = self
+            .http
+            .get(self.kv_url(key))
+            .bearer_auth(&self.config.auth_token)
+            .send()
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            Ok(None)
+        } else {
+            Ok(Some(resp.error_for_status()?.bytes().await?.to_vec()))
+        }
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, expiration: Option<Time>) -> Result<(), Error> {
+        let mut request = self
+            .http
+            .post(self.kv_url(key))
+            .bearer_auth(&self.config.auth_token)
+            .body(value);
+
+        if let Some(expiration) = expiration {
+            request = request.header(STORAGE_PROXY_PUT_KV_EXPIRATION, expiration);
+        }
+
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    async fn put_if_not_exists(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        expiration: Option<Time>,
+    ) -> Result<bool, Error> {
+        let mut request = self
+            .http
+            .put(self.kv_url(key))
+            .bearer_auth(&self.config.auth_token)
+            .body(value);
+
+        if let Some(expiration) = expiration {
+            request = request.header(STORAGE_PROXY_PUT_KV_EXPIRATION, expiration);
+        }
+
+        let response = request.send().await?;
+        if response.status() == StatusCode::CONFLICT {
+            Ok(false)
+        } else {
+            response.error_for_status()?;
+            Ok(true)
+        }
+    }
+
+    async fn list(&self, prefix: &str, cursor: Option<&str>) -> Result<KvListPage, Error> {
+        let mut url = self
+            .config
+            .url
+            .join(&format!("{KV_LIST_PATH_PREFIX}/{prefix}"))
+            .unwrap();
+        if let Some(cursor) = cursor {
+            url.query_pairs_mut().append_pair("cursor", cursor);
+        }
+        tracing::debug!(%url, "LIST");
+
+        Ok(self
+            .http
+            .get(url)
+            .bearer_auth(&self.config.auth_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<KvListPage>()
+            .await?)
+    }
+
+    async fn delete_all(&self) -> Result<(), Error> {
+        // Purging storage is idempotent, so it can safely be retried.
+        self.config
+            .retry
+            .retry(|| async {
+                self.http
+                    .delete(self.config.url.join(PURGE_STORAGE).unwrap())
+                    .bearer_auth(&self.config.auth_token)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok::<_, Error>(())
+            })
+            .await
+    }
+
+    async fn ready(&self) -> Result<(), Error> {
+        self.config
+            .retry
+            .retry(|| async {
+                self.http
+                    .get(self.config.url.join(STORAGE_READY).unwrap())
+                    .bearer_auth(&self.config.auth_token)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok::<_, Error>(())
+            })
+            .await
+    }
+}
+
+/// [`DapStorage`] that keeps everything in memory. Expiration times are ignored and all keys are
+/// listed in a single page.
+#[cfg(feature = "test-utils")]
+#[derive(Default)]
+pub struct InMemoryStorage {
+    kv: std::sync::Mutex<std::collections::BTreeMap<String, Vec<u8>>>,
+}
+
+#[cfg(feature = "test-utils")]
+impl InMemoryStorage {
+    fn kv(
+        &self,
+    ) -> Result<std::sync::MutexGuard<'_, std::collections::BTreeMap<String, Vec<u8>>>, Error> {
+        self.kv
+            .lock()
+            .map_err(|_| Error::Backend("in-memory storage poisoned".into()))
+    }
+}
+
+#[cfg(feature = "test-utils")]
+#[async_trait]
+impl DapStorage for InMemoryStorage {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.kv()?.get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, value: Vec<u8>, _expiration: Option<Time>) -> Result<(), Error> {
+        self.kv()?.insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn put_if_not_exists(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        _expiration: Option<Time>,
+    ) -> Result<bool, Error> {
+        let mut kv = self.kv()?;
+        if kv.contains_key(key) {
+            Ok(false)
+        } else {
+            kv.insert(key.to_string(), value);
+            Ok(true)
+        }
+    }
+
+    async fn list(&self, prefix: &str, _cursor: Option<&str>) -> Result<KvListPage, Error> {
+        Ok(KvListPage {
+            keys: self
+                .kv()?
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect(),
+            cursor: None,
+        })
+    }
+
+    async fn delete_all(&self) -> Result<(), Error> {
+        self.kv()?.clear();
+        Ok(())
+    }
+
+    async fn ready(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -8,8 +8,9 @@ use daphne::{
     constants::DapMediaType,
     messages::{
         decode_base64url_vec, Base64Encode, BatchSelector, Collection, CollectionReq, Extension,
-        HpkeCiphertext, Interval, Query, Report, ReportId, ReportMetadata, TaskId,
+        Interval, Query, ReportId, TaskId,
     },
+    vdaf::VdafConfig,
     DapAggregateResult, DapAggregationParam, DapMeasurement, DapQueryConfig, DapTaskParameters,
     DapVersion,
 };
@@ -118,6 +119,53 @@ async fn helper_endpoint_for_task_prefixed(version: DapVersion) {
 
 async_test_versions! { helper_endpoint_for_task_prefixed }
 
+async fn leader_list_tasks(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+    let mut rng = thread_rng();
+
+    let task_ids = [TaskId(rng.gen()), TaskId(rng.gen()), TaskId(rng.gen())];
+    for task_id in &task_ids {
+        t.leader_add_task_copy(task_id, t.task_config.not_after)
+            .await
+            .unwrap();
+    }
+
+    let listed = t.internal_list_tasks().await.unwrap();
+    for task_id in task_ids.iter().chain([&t.task_id]) {
+        assert!(listed.contains(task_id), "{task_id} is not in {listed:?}");
+    }
+}
+
+async_test_versions! { leader_list_tasks }
+
+async fn leader_list_tasks_excludes_expired(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+
+    let expired_task_id = TaskId(thread_rng().gen());
+    t.leader_add_task_copy(&expired_task_id, t.now - 1)
+        .await
+        .unwrap();
+
+    let listed = t.internal_list_tasks().await.unwrap();
+    assert!(!listed.contains(&expired_task_id));
+    assert!(listed.contains(&t.task_id));
+}
+
+async_test_versions! { leader_list_tasks_excludes_expired }
+
+async fn leader_add_task_already_exists(version: DapVersion) {
+    let t = TestRunner::default_with_version(version).await;
+
+    // The runner's task has already been added to the Leader.
+    let err = t
+        .leader_add_task_copy_expect_error(&t.task_id, t.task_config.not_after)
+        .await
+        .unwrap();
+    assert_eq!(err.code(), "TASK_ALREADY_EXISTS", "{err}");
+}
+
+async_test_versions! { leader_add_task_already_exists }
+
 async fn leader_hpke_config(version: DapVersion) {
     let t = TestRunner::default_with_version(version).await;
     let client = t.http_client();
@@ -226,7 +274,7 @@ async fn leader_upload(version: DapVersion) {
         DapMediaType::Report,
         report.get_encoded_with_param(&version).unwrap(),
         400,
-        "reportRejected",
+        "outdatedConfig",
     )
     .await
     .unwrap();
@@ -271,51 +319,54 @@ async fn leader_upload(version: DapVersion) {
     // Upload a fixed report. This is a sanity check to make sure that the test resets the Leader's
     // state each time the test is run. If it didn't, this would result in an error due to the
     // report ID being repeated.
-    let url = t.leader_url.join(&path).unwrap();
-    let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert(
-        reqwest::header::CONTENT_TYPE,
-        DapMediaType::Report
-            .as_str_for_version(version)
+    t.leader_put_expect_ok(
+        client,
+        &path,
+        DapMediaType::Report,
+        None,
+        t.task_config
+            .vdaf
+            .produce_report_with_id(
+                &hpke_config_list,
+                t.now,
+                &t.task_id,
+                ReportId([1; 16]),
+                DapMeasurement::U64(1),
+                version,
+            )
             .unwrap()
-            .parse()
-            .unwrap(),
-    );
-    let builder = client.put(url.as_str());
-    let resp = builder
-        .body(
-            Report {
-                report_metadata: ReportMetadata {
-                    id: ReportId([1; 16]),
-                    time: t.now,
-                },
-                public_share: b"public share".to_vec(),
-                encrypted_input_shares: [
-                    HpkeCiphertext {
-                        config_id: hpke_config_list[0].id,
-                        enc: b"encapsulated key".to_vec(),
-                        payload: b"ciphertext".to_vec(),
-                    },
-                    HpkeCiphertext {
-                        config_id: hpke_config_list[1].id,
-                        enc: b"encapsulated key".to_vec(),
-                        payload: b"ciphertext".to_vec(),
-                    },
-                ],
-            }
             .get_encoded_with_param(&version)
             .unwrap(),
+    )
+    .await
+    .unwrap();
+
+    // Try uploading a report with the Leader's and Helper's input shares swapped. The Leader
+    // can't decrypt the first share, so the report is rejected at upload.
+    let mut report = t
+        .task_config
+        .vdaf
+        .produce_report(
+            &hpke_config_list,
+            t.now,
+            &t.task_id,
+            DapMeasurement::U64(1),
+            version,
         )
-        .headers(headers)
-        .send()
-        .await
-        .expect("request failed");
-    assert_eq!(
-        200,
-        resp.status(),
-        "unexpected response status: {:?}",
-        resp.text().await.unwrap()
-    );
+        .unwrap();
+    report.encrypted_input_shares.swap(0, 1);
+    report.encrypted_input_shares[0].config_id = hpke_config_list[0].id;
+    t.leader_put_expect_abort(
+        client,
+        None, // dap_auth_token
+        &path,
+        DapMediaType::Report,
+        report.get_encoded_with_param(&version).unwrap(),
+        400,
+        "invalidMessage",
+    )
+    .await
+    .unwrap();
 }
 
 async_test_versions! { leader_upload }
@@ -404,7 +455,9 @@ async fn internal_leader_process(version: DapVersion) {
     // Upload a number of reports (a few more than the aggregation rate).
     let mut rng = thread_rng();
     for _ in 0..t.task_config.min_batch_size + 3 {
-        let now = rng.gen_range(TestRunner::report_interval(&batch_interval));
+        let now = t.task_config.quantized_time_lower_bound(
+            rng.gen_range(TestRunner::report_interval(&batch_interval)),
+        );
         t.leader_put_expect_ok(
             client,
             &path,
@@ -477,7 +530,9 @@ async fn leader_collect_ok(version: DapVersion) {
     let mut time_min = u64::MAX;
     let mut time_max = 0u64;
     for _ in 0..t.task_config.min_batch_size {
-        let now = rng.gen_range(TestRunner::report_interval(&batch_interval));
+        let now = t.task_config.quantized_time_lower_bound(
+            rng.gen_range(TestRunner::report_interval(&batch_interval)),
+        );
         time_min = min(time_min, now);
         time_max = max(time_max, now);
         t.leader_put_expect_ok(
@@ -601,6 +656,89 @@ async fn leader_collect_ok(version: DapVersion) {
 
 async_test_versions! { leader_collect_ok }
 
+async fn leader_collect_prio2_ok(version: DapVersion) {
+    let t = TestRunner::with_vdaf(version, &VdafConfig::Prio2 { dimension: 4 }).await;
+    let batch_interval = t.batch_interval();
+
+    let client = t.http_client();
+    let hpke_config_list = t.get_hpke_configs(version, client).await.unwrap();
+    let path = t.upload_path();
+
+    let mut rng = thread_rng();
+    let mut expected = vec![0; 4];
+    for _ in 0..t.task_config.min_batch_size {
+        let measurement: Vec<u32> = (0..4).map(|_| rng.gen_range(0..2)).collect();
+        for (sum, bit) in expected.iter_mut().zip(&measurement) {
+            *sum += bit;
+        }
+        let now = t.task_config.quantized_time_lower_bound(
+            rng.gen_range(TestRunner::report_interval(&batch_interval)),
+        );
+        t.leader_put_expect_ok(
+            client,
+            &path,
+            DapMediaType::Report,
+            None,
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &hpke_config_list,
+                    now,
+                    &t.task_id,
+                    DapMeasurement::U32Vec(measurement),
+                    version,
+                )
+                .unwrap()
+                .get_encoded_with_param(&version)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let agg_param = DapAggregationParam::Empty;
+    let collect_req = CollectionReq {
+        query: Query::TimeInterval { batch_interval },
+        agg_param: agg_param.get_encoded().unwrap(),
+    };
+    let collect_uri = t
+        .leader_post_collect(
+            client,
+            collect_req.get_encoded_with_param(&t.version).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let agg_telem = t.internal_process(client).await.unwrap();
+    assert_eq!(
+        agg_telem.reports_aggregated, t.task_config.min_batch_size,
+        "reports aggregated"
+    );
+
+    let resp = t.poll_collection_url(client, &collect_uri).await.unwrap();
+    assert_eq!(resp.status(), 200);
+    let collection =
+        Collection::get_decoded_with_param(&t.version, &resp.bytes().await.unwrap()).unwrap();
+    assert_eq!(collection.report_count, t.task_config.min_batch_size);
+    let agg_res = t
+        .task_config
+        .vdaf
+        .consume_encrypted_agg_shares(
+            &t.collector_hpke_receiver,
+            &t.task_id,
+            &BatchSelector::TimeInterval { batch_interval },
+            collection.report_count,
+            &agg_param,
+            collection.encrypted_agg_shares.to_vec(),
+            version,
+        )
+        .await
+        .unwrap();
+    assert_eq!(agg_res, DapAggregateResult::U32Vec(expected));
+}
+
+async_test_versions! { leader_collect_prio2_ok }
+
 // Test that collect jobs complete even if the request is issued after all reports for the task
 // have been processed.
 async fn leader_collect_ok_interleaved(version: DapVersion) {
@@ -613,7 +751,9 @@ async fn leader_collect_ok_interleaved(version: DapVersion) {
     // The reports are uploaded ...
     let mut rng = thread_rng();
     for _ in 0..t.task_config.min_batch_size {
-        let now = rng.gen_range(TestRunner::report_interval(&batch_interval));
+        let now = t.task_config.quantized_time_lower_bound(
+            rng.gen_range(TestRunner::report_interval(&batch_interval)),
+        );
         t.leader_put_expect_ok(
             client,
             &path,
@@ -672,7 +812,9 @@ async fn leader_collect_not_ready_min_batch_size(version: DapVersion) {
     // A number of reports are uploaded, but not enough to meet the minimum batch requirement.
     let mut rng = thread_rng();
     for _ in 0..t.task_config.min_batch_size - 1 {
-        let now = rng.gen_range(TestRunner::report_interval(&batch_interval));
+        let now = t.task_config.quantized_time_lower_bound(
+            rng.gen_range(TestRunner::report_interval(&batch_interval)),
+        );
         t.leader_put_expect_ok(
             client,
             &path,
@@ -831,7 +973,9 @@ async fn leader_collect_abort_overlapping_batch_interval(version: DapVersion) {
     // The reports are uploaded in the background.
     let mut rng = thread_rng();
     for _ in 0..t.task_config.min_batch_size {
-        let now = rng.gen_range(TestRunner::report_interval(&batch_interval));
+        let now = t.task_config.quantized_time_lower_bound(
+            rng.gen_range(TestRunner::report_interval(&batch_interval)),
+        );
         t.leader_put_expect_ok(
             client,
             &path,
@@ -1115,7 +1259,9 @@ async fn leader_collect_taskprov_ok(version: DapVersion) {
     let mut rng = thread_rng();
     for _ in 0..t.task_config.min_batch_size {
         let extensions = vec![Extension::Taskprov];
-        let now = rng.gen_range(TestRunner::report_interval(&batch_interval));
+        let now = t.task_config.quantized_time_lower_bound(
+            rng.gen_range(TestRunner::report_interval(&batch_interval)),
+        );
         t.leader_put_expect_ok(
             client,
             &path,
@@ -2,7 +2,6 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use anyhow::Context;
-use assert_matches::assert_matches;
 use daphne::{
     constants::DapMediaType,
     hpke::{HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId, HpkeReceiverConfig},
@@ -13,7 +12,7 @@ use daphne::{
     vdaf::{Prio3Config, VdafConfig},
     DapGlobalConfig, DapLeaderProcessTelemetry, DapQueryConfig, DapTaskConfig, DapVersion,
 };
-use daphne_service_utils::http_headers;
+use daphne_service_utils::{http_headers, test_route_types::InternalApiError};
 use futures::StreamExt;
 use hpke_rs::{HpkePrivateKey, HpkePublicKey};
 use prio::codec::{Decode, Encode};
@@ -58,7 +57,7 @@ pub struct TestRunner {
 
 impl TestRunner {
     pub async fn default_with_version(version: DapVersion) -> Self {
-        Self::with(version, &DapQueryConfig::TimeInterval).await
+        Self::with(version, &DapQueryConfig::TimeInterval, VDAF_CONFIG).await
     }
 
     pub async fn fixed_size(version: DapVersion) -> Self {
@@ -67,16 +66,27 @@ impl TestRunner {
             &DapQueryConfig::FixedSize {
                 max_batch_size: Some(MAX_BATCH_SIZE),
             },
+            VDAF_CONFIG,
         )
         .await
     }
 
-    async fn with(version: DapVersion, query_config: &DapQueryConfig) -> Self {
+    pub async fn with_vdaf(version: DapVersion, vdaf_config: &VdafConfig) -> Self {
+        Self::with(version, &DapQueryConfig::TimeInterval, vdaf_config).await
+    }
+
+    async fn with(
+        version: DapVersion,
+        query_config: &DapQueryConfig,
+        vdaf_config: &VdafConfig,
+    ) -> Self {
         let mut rng = thread_rng();
+        // Clients round report timestamps down to a multiple of the time precision.
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
+        let now = now - (now % TIME_PRECISION);
 
         let task_id = TaskId(rng.gen());
 
@@ -104,6 +114,19 @@ impl TestRunner {
             supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
             allow_taskprov: true,
             default_num_agg_span_shards: NonZeroUsize::new(1).unwrap(),
+            require_https: false,
+            max_reports_per_agg_job: None,
+            max_prio2_dimension: None,
+            helper_abort_policy: Default::default(),
+            allow_partial_batch: false,
+            agg_share_report_count_tolerance: 0,
+            collect_aggregation_deadline: None,
+            future_report_handling: Default::default(),
+            future_report_skew_tolerance: 0,
+            report_retention: None,
+            record_agg_job_diagnostics: false,
+            agg_job_report_concurrency: NonZeroUsize::new(1).unwrap(),
+            query_type_strictness: Default::default(),
         };
 
         let task_config = DapTaskConfig {
@@ -115,11 +138,13 @@ impl TestRunner {
             time_precision: TIME_PRECISION,
             min_batch_size: MIN_BATCH_SIZE,
             query: query_config.clone(),
-            vdaf: *VDAF_CONFIG,
-            vdaf_verify_key: VDAF_CONFIG.gen_verify_key(),
+            vdaf: *vdaf_config,
+            vdaf_verify_key: vdaf_config.gen_verify_key(),
+            vdaf_verify_keys_by_epoch: Default::default(),
             collector_hpke_config: collector_hpke_receiver.config.clone(),
             method: Default::default(),
             num_agg_span_shards: global_config.default_num_agg_span_shards,
+            max_batch_query_count: 1,
         };
 
         // This block needs to be kept in-sync with daphne-worker-test/wrangler.toml.
@@ -165,13 +190,7 @@ impl TestRunner {
         let collector_hpke_config_base64url =
             encode_base64url(t.collector_hpke_receiver.config.get_encoded().unwrap());
 
-        let vdaf = json!({
-            "type": "Prio3Sum",
-            "bits": assert_matches!(
-                t.task_config.vdaf,
-                VdafConfig::Prio3(Prio3Config::Sum{ bits }) => format!("{bits}")
-            ),
-        });
+        let vdaf = t.vdaf_cmd();
 
         let (query_type, max_batch_size) = match t.task_config.query {
             DapQueryConfig::TimeInterval => (1, None),
@@ -501,10 +520,10 @@ impl TestRunner {
             .context("request failed")?;
 
         anyhow::ensure!(
-            resp.status() == reqwest::StatusCode::OK,
+            resp.status() == reqwest::StatusCode::CREATED,
             "unexpected response status. Expected {} got {}: Body is {:?}",
             resp.status(),
-            reqwest::StatusCode::OK,
+            reqwest::StatusCode::CREATED,
             resp.text().await?,
         );
         Ok(())
@@ -714,6 +733,119 @@ impl TestRunner {
         Ok(())
     }
 
+    /// Provision the Leader with a copy of this runner's task under a different task ID and
+    /// expiration time.
+    pub async fn leader_add_task_copy(
+        &self,
+        task_id: &TaskId,
+        task_expiration: u64,
+    ) -> anyhow::Result<()> {
+        let res: InternalTestCommandResult = self
+            .leader_post_internal(
+                &format!("{}/internal/test/add_task", self.version.as_ref()),
+                &self.leader_add_task_copy_cmd(task_id, task_expiration),
+            )
+            .await?;
+        anyhow::ensure!(
+            res.status == "success",
+            "response status: {}, error: {:?}",
+            res.status,
+            res.error
+        );
+        Ok(())
+    }
+
+    /// Like [`Self::leader_add_task_copy`], but expect the command to fail.
+    pub async fn leader_add_task_copy_expect_error(
+        &self,
+        task_id: &TaskId,
+        task_expiration: u64,
+    ) -> anyhow::Result<InternalApiError> {
+        let mut url = self.leader_url.clone();
+        url.set_path(&format!("{}/internal/test/add_task", self.version.as_ref()));
+        let resp = self
+            .http_client()
+            .post(url.clone())
+            .json(&self.leader_add_task_copy_cmd(task_id, task_expiration))
+            .send()
+            .await
+            .context("request failed")?;
+        anyhow::ensure!(
+            !resp.status().is_success(),
+            "request to {url} unexpectedly succeeded"
+        );
+        resp.json()
+            .await
+            .context("failed to deserialize internal API error")
+    }
+
+    /// The VDAF of the task, as expected by the internal add task route.
+    fn vdaf_cmd(&self) -> serde_json::Value {
+        match self.task_config.vdaf {
+            VdafConfig::Prio3(Prio3Config::Sum { bits }) => json!({
+                "type": "Prio3Sum",
+                "bits": format!("{bits}"),
+            }),
+            VdafConfig::Prio2 { dimension } => json!({
+                "type": "Prio2",
+                "length": format!("{dimension}"),
+            }),
+            vdaf => panic!("VDAF {vdaf} is not supported by the test runner"),
+        }
+    }
+
+    fn leader_add_task_copy_cmd(
+        &self,
+        task_id: &TaskId,
+        task_expiration: u64,
+    ) -> serde_json::Value {
+        let (query_type, max_batch_size) = match self.task_config.query {
+            DapQueryConfig::TimeInterval => (1, None),
+            DapQueryConfig::FixedSize { max_batch_size } => (2, Some(max_batch_size)),
+        };
+        json!({
+            "task_id": task_id.to_base64url(),
+            "leader": self.leader_url,
+            "helper": self.helper_url,
+            "vdaf": self.vdaf_cmd(),
+            "leader_authentication_token": self.leader_bearer_token.clone(),
+            "collector_authentication_token": self.collector_bearer_token.clone(),
+            "role": "leader",
+            "vdaf_verify_key": encode_base64url(self.task_config.vdaf_verify_key.as_ref()),
+            "query_type": query_type,
+            "min_batch_size": self.task_config.min_batch_size,
+            "max_batch_size": max_batch_size,
+            "time_precision": self.task_config.time_precision,
+            "collector_hpke_config": encode_base64url(
+                self.collector_hpke_receiver.config.get_encoded().unwrap()
+            ),
+            "task_expiration": task_expiration,
+        })
+    }
+
+    pub async fn internal_list_tasks(&self) -> anyhow::Result<Vec<TaskId>> {
+        let client = self.http_client();
+        let mut url = self.leader_url.clone();
+        url.set_path("internal/test/list_tasks");
+        let resp = client
+            .get(url.clone())
+            .send()
+            .await
+            .context("request failed")?;
+        anyhow::ensure!(
+            resp.status() == 200,
+            "request to {url} failed: response: {resp:?}"
+        );
+        resp.json::<Vec<String>>()
+            .await?
+            .into_iter()
+            .map(|task_id| {
+                TaskId::try_from_base64url(&task_id)
+                    .with_context(|| format!("malformed task ID {task_id:?}"))
+            })
+            .collect()
+    }
+
     pub async fn internal_current_batch(&self, task_id: &TaskId) -> anyhow::Result<BatchId> {
         let client = self.http_client();
         let mut url = self.leader_url.clone();
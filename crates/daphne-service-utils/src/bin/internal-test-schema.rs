@@ -0,0 +1,25 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Print the JSON Schemas of the bodies of the internal test routes.
+//!
+//! Usage: `internal-test-schema [add_task|endpoint_for_task]`. Both schemas are printed, keyed by
+//! route, if no route is given.
+
+use daphne_service_utils::test_route_types::schema;
+
+fn main() {
+    let schema = match std::env::args().nth(1).as_deref() {
+        Some("add_task") => schema::internal_test_add_task(),
+        Some("endpoint_for_task") => schema::internal_test_endpoint_for_task(),
+        None => serde_json::json!({
+            "add_task": schema::internal_test_add_task(),
+            "endpoint_for_task": schema::internal_test_endpoint_for_task(),
+        }),
+        Some(route) => {
+            eprintln!("unknown route {route:?}: expected add_task or endpoint_for_task");
+            std::process::exit(2);
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&schema).unwrap());
+}
@@ -9,7 +9,7 @@ use p256::ecdsa::SigningKey;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::{auth::DaphneWorkerAuthMethod, DapRole};
+use crate::{auth::DaphneWorkerAuthMethod, ed25519_signature::Ed25519SigningKey, DapRole};
 
 /// draft-wang-ppm-dap-taskprov: Long-lived parameters for the taskprov extension.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -68,6 +68,42 @@ pub struct DaphneServiceConfig {
     #[serde(default = "default_report_storage_max_future_time_skew")]
     pub report_storage_max_future_time_skew: daphne::messages::Duration,
 
+    /// Maximum size, in bytes, of the body of an aggregation job initialization request. Requests
+    /// with larger bodies are rejected before they are decoded. If not set, no limit is enforced.
+    #[serde(default)]
+    pub max_agg_init_body_bytes: Option<usize>,
+
+    /// Maximum size, in bytes, of a request body, both as received and, if it is gzip-encoded,
+    /// once decompressed. This also bounds the decompressed size of gzip-encoded responses from
+    /// the Helper. It keeps a small compressed body from exhausting the memory of the service.
+    #[serde(default = "default_max_decompressed_body_bytes")]
+    pub max_decompressed_body_bytes: usize,
+
+    /// Number of seconds for which the Helper keeps its response to an aggregation job request, so
+    /// that a retried request is answered as before rather than having its reports rejected as
+    /// replays. The Leader is expected to retry well within this window. If not set, then
+    /// responses are not kept.
+    ///
+    /// Enabling this costs a KV read and a write of the encoded response per aggregation job. The
+    /// check for a previous response isn't atomic with storing the new one, so two concurrent
+    /// requests for the same job with different payloads may both be processed.
+    #[serde(default)]
+    pub agg_job_resp_cache_secs: Option<daphne::messages::Duration>,
+
+    /// Number of seconds for which the Helper keeps its response to an aggregate share request, so
+    /// that a retried request is answered without recomputing the aggregate share. If not set, then
+    /// responses are not kept.
+    #[serde(default)]
+    pub agg_share_resp_cache_secs: Option<daphne::messages::Duration>,
+
+    /// Maximum number of HPKE receiver configs kept for each DAP version. When adding a config
+    /// would exceed the limit, the least recently added configs are evicted. The advertised config
+    /// is never evicted, nor are those under which reports have been uploaded within the last
+    /// report storage epoch, as Clients may still be using them. If the limit can't be met
+    /// because of this, then the new config is rejected. If not set, no limit is enforced.
+    #[serde(default)]
+    pub max_hpke_configs: Option<usize>,
+
     /// ECDSA signing key for signing messages. If set, then every response to HPKE
     /// configuration endpoint will include a header "x-hpke-config-signature" with a
     /// URL-safe, base64-encoded signature of the HPKE config.
@@ -84,12 +120,36 @@ pub struct DaphneServiceConfig {
         skip_serializing
     )]
     pub signing_key: Option<SigningKey>,
+
+    /// Leader: Ed25519 signing key for collection responses. If set, then every collection
+    /// returned to the Collector includes a header "x-collection-signature" with a URL-safe,
+    /// base64-encoded signature of the encoded collection.
+    ///
+    /// The expected payload is a hex-encoded PKCS#8 Ed25519 private key, e.g., the output of
+    ///
+    /// ```text
+    /// $ openssl genpkey -algorithm ed25519 -outform DER | xxd -p -c0
+    /// ```
+    #[serde(default, skip_serializing)]
+    pub collection_signing_key: Option<Ed25519SigningKey>,
+
+    /// Helper: Ed25519 signing key for aggregate share responses. If set, then every aggregate
+    /// share returned to the Leader includes a header "x-aggregate-share-signature" with a
+    /// URL-safe, base64-encoded signature of the encoded aggregate share.
+    ///
+    /// The expected format is the same as for `collection_signing_key`.
+    #[serde(default, skip_serializing)]
+    pub agg_share_signing_key: Option<Ed25519SigningKey>,
 }
 
 fn default_report_storage_max_future_time_skew() -> daphne::messages::Duration {
     300
 }
 
+fn default_max_decompressed_body_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
 mod signing_key_serializer {
     use p256::ecdsa::SigningKey;
     use serde::{de, Deserialize, Deserializer};
@@ -66,6 +66,8 @@ use crate::durable_request_capnp;
 
 /// The base of a request path that points to a key in KV.
 pub const KV_PATH_PREFIX: &str = "/v1/kv";
+/// The base of a request path that lists the keys in KV that start with a given prefix.
+pub const KV_LIST_PATH_PREFIX: &str = "/v1/kv_list";
 /// The base of a request path that points to a durable object.
 pub const DO_PATH_PREFIX: &str = "/v1/do";
 #[cfg(feature = "test-utils")]
@@ -75,6 +77,14 @@ pub const PURGE_STORAGE: &str = "/v1/purge";
 /// The path used to check for readyness
 pub const STORAGE_READY: &str = "/v1/ready";
 
+/// A page of keys returned by a KV list request. If `cursor` is set, then there are more keys to
+/// list: they are returned by repeating the request with the query parameter `cursor={cursor}`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct KvListPage {
+    pub keys: Vec<String>,
+    pub cursor: Option<String>,
+}
+
 /// The way the target object's id will be obtained.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ObjectIdFrom {
@@ -0,0 +1,152 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Detached Ed25519 signatures over DAP responses. When configured with a signing key, the Leader
+//! signs the encoded `Collection` it returns to the Collector and the Helper signs the encoded
+//! `AggregateShare` it returns to the Leader, so that the recipient can check the provenance of
+//! the response. The signature travels in a header and is not part of the DAP message itself.
+
+use std::{fmt, sync::Arc};
+
+use daphne::messages::{decode_base64url_vec, encode_base64url};
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{de, Deserialize, Deserializer};
+
+/// Ed25519 key used to sign DAP responses.
+#[derive(Clone)]
+pub struct Ed25519SigningKey(Arc<Ed25519KeyPair>);
+
+impl Ed25519SigningKey {
+    /// Parse a PKCS#8 (v1 or v2) encoded Ed25519 private key.
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<Self, ring::error::KeyRejected> {
+        Ed25519KeyPair::from_pkcs8_maybe_unchecked(pkcs8).map(|key| Self(Arc::new(key)))
+    }
+
+    /// The public key the recipient uses to verify signatures.
+    pub fn public_key(&self) -> &[u8] {
+        self.0.public_key().as_ref()
+    }
+
+    /// Sign the encoded response. Returns the URL-safe, base64-encoded signature.
+    pub fn sign(&self, payload: &[u8]) -> String {
+        encode_base64url(self.0.sign(payload))
+    }
+}
+
+impl fmt::Debug for Ed25519SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Ed25519SigningKey")
+            .field(&hex::encode(self.public_key()))
+            .finish()
+    }
+}
+
+impl<'de> Deserialize<'de> for Ed25519SigningKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let pkcs8 = hex::decode(String::deserialize(deserializer)?).map_err(de::Error::custom)?;
+        Self::from_pkcs8(&pkcs8).map_err(de::Error::custom)
+    }
+}
+
+/// Check the signature attached to an encoded response. Returns `false` if the signature is
+/// malformed or doesn't match the payload under `public_key`.
+pub fn verify_signature(public_key: &[u8], payload: &[u8], signature: &str) -> bool {
+    let Some(signature) = decode_base64url_vec(signature) else {
+        return false;
+    };
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(payload, &signature)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use daphne::{
+        messages::{
+            AggregateShare, Collection, HpkeCiphertext, Interval, PartialBatchSelector, Time,
+        },
+        DapVersion,
+    };
+    use prio::codec::{Encode, ParameterizedEncode};
+    use ring::{rand::SystemRandom, signature::Ed25519KeyPair};
+
+    use super::{verify_signature, Ed25519SigningKey};
+
+    fn ciphertext(config_id: u8, payload: Vec<u8>) -> HpkeCiphertext {
+        HpkeCiphertext {
+            config_id,
+            enc: vec![0x01; 32],
+            payload,
+        }
+    }
+
+    fn encoded_collection(version: DapVersion, report_count: u64) -> Vec<u8> {
+        Collection {
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            report_count,
+            interval: Interval {
+                start: Time::default(),
+                duration: 3600,
+            },
+            encrypted_agg_shares: [ciphertext(1, vec![0x02; 64]), ciphertext(2, vec![0x02; 64])],
+        }
+        .get_encoded_with_param(&version)
+        .unwrap()
+    }
+
+    fn encoded_agg_share(payload: Vec<u8>) -> Vec<u8> {
+        AggregateShare {
+            encrypted_agg_share: ciphertext(1, payload),
+        }
+        .get_encoded()
+        .unwrap()
+    }
+
+    fn generate_key() -> Ed25519SigningKey {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        serde_json::from_value(serde_json::json!(hex::encode(pkcs8.as_ref()))).unwrap()
+    }
+
+    #[test]
+    fn roundtrip_collection() {
+        let key = generate_key();
+        let payload = encoded_collection(DapVersion::Latest, 10);
+        let signature = key.sign(&payload);
+        assert!(verify_signature(key.public_key(), &payload, &signature));
+
+        // Changing the report count invalidates the signature.
+        let tampered = encoded_collection(DapVersion::Latest, 11);
+        assert!(!verify_signature(key.public_key(), &tampered, &signature));
+
+        // So does changing a byte of an encrypted aggregate share.
+        let mut tampered = payload.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(!verify_signature(key.public_key(), &tampered, &signature));
+
+        // A signature under a different key is rejected.
+        let other_key = generate_key();
+        assert!(!verify_signature(
+            other_key.public_key(),
+            &payload,
+            &signature
+        ));
+
+        // So is a malformed signature.
+        assert!(!verify_signature(key.public_key(), &payload, "not base64!"));
+    }
+
+    #[test]
+    fn roundtrip_agg_share() {
+        let key = generate_key();
+        let payload = encoded_agg_share(vec![0x02; 64]);
+        let signature = key.sign(&payload);
+        assert!(verify_signature(key.public_key(), &payload, &signature));
+
+        // Tampering with the encrypted aggregate share invalidates the signature.
+        let tampered = encoded_agg_share(vec![0x03; 64]);
+        assert!(!verify_signature(key.public_key(), &tampered, &signature));
+    }
+}
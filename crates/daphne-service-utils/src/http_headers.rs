@@ -2,6 +2,8 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 pub const HPKE_SIGNATURE: &str = "x-hpke-config-signature";
+pub const COLLECTION_SIGNATURE: &str = "x-collection-signature";
+pub const AGGREGATE_SHARE_SIGNATURE: &str = "x-aggregate-share-signature";
 pub const DAP_AUTH_TOKEN: &str = "dap-auth-token";
 pub const DAP_TASKPROV: &str = "dap-taskprov";
 pub const STORAGE_PROXY_PUT_KV_EXPIRATION: &str = "x-daphne-storage-proxy-kv-put-expiration";
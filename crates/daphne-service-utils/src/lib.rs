@@ -9,6 +9,7 @@ pub mod auth;
 pub mod config;
 #[cfg(feature = "durable_requests")]
 pub mod durable_requests;
+pub mod ed25519_signature;
 pub mod http_headers;
 pub mod metrics;
 pub mod test_route_types;
@@ -25,6 +26,7 @@ mod durable_request_capnp {
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub enum DapRole {
     Leader,
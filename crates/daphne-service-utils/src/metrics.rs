@@ -3,13 +3,16 @@
 
 //! Daphne-Worker metrics.
 
-use daphne::metrics::DaphneMetrics;
+use daphne::{metrics::DaphneMetrics, DapError};
 
 pub trait DaphneServiceMetrics: DaphneMetrics {
     fn abort_count_inc(&self, label: &str);
     fn count_http_status_code(&self, status_code: u16);
     fn daphne(&self) -> &dyn DaphneMetrics;
     fn auth_method_inc(&self, method: AuthMethod);
+
+    /// Encode the current value of every metric in the Prometheus text exposition format.
+    fn encode_text(&self) -> Result<String, DapError>;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -26,7 +29,9 @@ mod prometheus {
         metrics::{prometheus::DaphnePromMetrics, DaphneMetrics, ReportStatus},
         DapError,
     };
-    use prometheus::{register_int_counter_vec_with_registry, IntCounterVec, Registry};
+    use prometheus::{
+        register_int_counter_vec_with_registry, IntCounterVec, Registry, TextEncoder,
+    };
 
     impl DaphneMetrics for DaphnePromServiceMetrics {
         fn report_inc_by(&self, status: ReportStatus, val: u64) {
@@ -76,6 +81,12 @@ mod prometheus {
         fn daphne(&self) -> &dyn DaphneMetrics {
             self
         }
+
+        fn encode_text(&self) -> Result<String, DapError> {
+            TextEncoder::new()
+                .encode_to_string(&self.registry.gather())
+                .map_err(|e| fatal_error!(err = ?e, "failed to encode metrics"))
+        }
     }
 
     #[derive(Clone)]
@@ -91,6 +102,9 @@ mod prometheus {
 
         /// Counts the used authentication methods
         auth_method: IntCounterVec,
+
+        /// The registry the metrics are registered with, used to export them.
+        registry: Registry,
     }
 
     impl DaphnePromServiceMetrics {
@@ -126,6 +140,7 @@ mod prometheus {
                 http_status_code_counter,
                 dap_abort_counter,
                 auth_method,
+                registry: registry.clone(),
             })
         }
     }
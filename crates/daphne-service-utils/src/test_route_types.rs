@@ -1,17 +1,24 @@
 // Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use daphne::messages::{Duration, TaskId, Time};
-use serde::Deserialize;
+use std::fmt;
+
+use daphne::{
+    messages::{BatchId, Duration, TaskId, Time},
+    DapVersion,
+};
+use serde::{Deserialize, Serialize};
 use url::Url;
 
 #[derive(Deserialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct InternalTestEndpointForTask {
     pub role: super::DapRole,
 }
 
 #[derive(Deserialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 pub struct InternalTestVdaf {
     #[serde(rename = "type")]
     pub typ: String,
@@ -24,9 +31,11 @@ pub struct InternalTestVdaf {
 }
 
 #[derive(Deserialize)]
+#[cfg_attr(feature = "json-schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "snake_case")]
 pub struct InternalTestAddTask {
     #[serde(deserialize_with = "daphne::messages::base64url::deserialize")]
+    #[cfg_attr(feature = "json-schema", schemars(with = "String"))]
     pub task_id: TaskId, // base64url
     pub leader: Url,
     pub helper: Url,
@@ -44,3 +53,220 @@ pub struct InternalTestAddTask {
     pub collector_hpke_config: String, // base64url
     pub task_expiration: Time,
 }
+
+/// Request to `/internal/test/inject_reports/task/:task_id`: upload `count` reports with random
+/// measurements to the Leader. The response has the same shape and holds the number of reports
+/// that were uploaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestInjectReports {
+    pub count: usize,
+}
+
+/// Element of the response to `/internal/current_batches/task/:task_id`: a fixed-size batch that
+/// has not yet been collected and the number of reports assigned to it so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestCurrentBatch {
+    #[serde(with = "daphne::messages::base64url")]
+    pub batch_id: BatchId, // base64url
+    pub report_count: u64,
+}
+
+/// Outcome of an internal test command, as defined in draft-dcook-ppm-dap-interop-test-design-02.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalTestStatus {
+    Success,
+    Error,
+}
+
+/// Response to `/internal/test/ready`. Follows the status object of
+/// draft-dcook-ppm-dap-interop-test-design-02, extended with the role and default DAP version of
+/// the service so that test harnesses can check they are talking to the right Aggregator.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct InternalTestReady {
+    pub status: InternalTestStatus,
+    /// Whether the service is able to handle requests, i.e., its storage is reachable.
+    pub ready: bool,
+    pub role: super::DapRole,
+    pub version: DapVersion,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// An error returned by the internal test routes. It is serialized as a JSON object with a stable
+/// `code` that test harnesses can match on and a human-readable `message`, e.g.,
+/// `{"code":"TASK_ALREADY_EXISTS","message":"..."}`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "code", content = "message", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum InternalApiError {
+    /// The command is malformed or asks for an unsupported configuration.
+    InvalidCommand(String),
+    /// A task with the given ID has already been added.
+    TaskAlreadyExists(String),
+    /// An HPKE receiver config with the given ID has already been added.
+    HpkeConfigAlreadyExists(String),
+    /// The command could not be completed because storage could not be reached.
+    StorageError(String),
+}
+
+impl InternalApiError {
+    /// The code under which this error is serialized.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidCommand(..) => "INVALID_COMMAND",
+            Self::TaskAlreadyExists(..) => "TASK_ALREADY_EXISTS",
+            Self::HpkeConfigAlreadyExists(..) => "HPKE_CONFIG_ALREADY_EXISTS",
+            Self::StorageError(..) => "STORAGE_ERROR",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::InvalidCommand(message)
+            | Self::TaskAlreadyExists(message)
+            | Self::HpkeConfigAlreadyExists(message)
+            | Self::StorageError(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for InternalApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl std::error::Error for InternalApiError {}
+
+/// JSON Schemas for the bodies of the internal test routes, for validating payloads before they
+/// are sent. The schemas are derived from the types above.
+#[cfg(feature = "json-schema")]
+pub mod schema {
+    use schemars::schema_for;
+    use serde_json::Value;
+
+    /// JSON Schema for [`InternalTestEndpointForTask`](super::InternalTestEndpointForTask).
+    pub fn internal_test_endpoint_for_task() -> Value {
+        serde_json::to_value(schema_for!(super::InternalTestEndpointForTask)).unwrap()
+    }
+
+    /// JSON Schema for [`InternalTestAddTask`](super::InternalTestAddTask).
+    pub fn internal_test_add_task() -> Value {
+        serde_json::to_value(schema_for!(super::InternalTestAddTask)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use daphne::DapVersion;
+
+    #[cfg(feature = "json-schema")]
+    use super::{schema, InternalTestAddTask, InternalTestEndpointForTask};
+    use super::{InternalApiError, InternalTestReady, InternalTestStatus};
+    use crate::DapRole;
+
+    #[test]
+    fn internal_test_ready_json() {
+        let ready = InternalTestReady {
+            status: InternalTestStatus::Success,
+            ready: true,
+            role: DapRole::Helper,
+            version: DapVersion::Draft09,
+            error: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&ready).unwrap(),
+            serde_json::json!({
+                "status": "success",
+                "ready": true,
+                "role": "helper",
+                "version": "v09",
+            })
+        );
+
+        let not_ready = InternalTestReady {
+            status: InternalTestStatus::Error,
+            ready: false,
+            role: DapRole::Leader,
+            version: DapVersion::Latest,
+            error: Some("storage is unreachable".into()),
+        };
+        let json = serde_json::to_value(&not_ready).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "status": "error",
+                "ready": false,
+                "role": "leader",
+                "version": "v10",
+                "error": "storage is unreachable",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<InternalTestReady>(json).unwrap(),
+            not_ready
+        );
+    }
+
+    #[test]
+    fn internal_api_error_json() {
+        let errors = [
+            InternalApiError::InvalidCommand("bad".into()),
+            InternalApiError::TaskAlreadyExists("dup".into()),
+            InternalApiError::HpkeConfigAlreadyExists("dup".into()),
+            InternalApiError::StorageError("down".into()),
+        ];
+        for error in errors {
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(json["code"], error.code());
+            assert_eq!(json["message"], error.message());
+            assert_eq!(
+                serde_json::from_value::<InternalApiError>(json).unwrap(),
+                error
+            );
+        }
+    }
+
+    #[cfg(feature = "json-schema")]
+    #[test]
+    fn internal_test_add_task_schema() {
+        let schema = schema::internal_test_add_task();
+        let required = schema["required"].as_array().unwrap();
+        for field in ["vdaf", "query_type", "leader_authentication_token", "role"] {
+            assert!(required.contains(&field.into()), "{field} is not required");
+        }
+        assert!(!required.contains(&"collector_authentication_token".into()));
+
+        // A payload with exactly the required properties is accepted by the route.
+        let payload = serde_json::json!({
+            "task_id": "8TuT5Z5fAuutsX9DZWSqkUw6pzDl96d3tdsDJgWH2VY",
+            "leader": "https://leader.example/",
+            "helper": "https://helper.example/",
+            "vdaf": { "type": "Prio3Count" },
+            "leader_authentication_token": "leader token",
+            "role": "helper",
+            "vdaf_verify_key": "AAAAAAAAAAAAAAAAAAAAAA",
+            "query_type": 1,
+            "min_batch_size": 10,
+            "time_precision": 3600,
+            "collector_hpke_config": "AQAgAAEAAQAg",
+            "task_expiration": 1_700_000_000,
+        });
+        let keys = payload.as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(keys.len(), required.len());
+        for key in keys {
+            assert!(required.contains(&key.as_str().into()), "{key}");
+        }
+        serde_json::from_value::<InternalTestAddTask>(payload).unwrap();
+
+        let schema = schema::internal_test_endpoint_for_task();
+        assert_eq!(schema["required"], serde_json::json!(["role"]));
+        serde_json::from_value::<InternalTestEndpointForTask>(serde_json::json!({
+            "role": "leader"
+        }))
+        .unwrap();
+    }
+}
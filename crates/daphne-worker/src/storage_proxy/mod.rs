@@ -28,6 +28,12 @@
 //!
 //! Make a `DELETE` request with uri `{KV_PATH_PREFIX}/path/to/key`.
 //!
+//! ## Listing keys
+//!
+//! Make a `GET` request with uri `{KV_LIST_PATH_PREFIX}/path/to/`. The response is a JSON-encoded
+//! [`KvListPage`] of the keys that start with `path/to/`. If the page has a cursor, the next page
+//! is fetched with `{KV_LIST_PATH_PREFIX}/path/to/?cursor={cursor}`.
+//!
 //!
 //! # Durable Objects
 //!
@@ -85,11 +91,11 @@ use axum_extra::TypedHeader;
 use bytes::Bytes;
 use daphne::messages::Time;
 use daphne_service_utils::durable_requests::{
-    DurableRequest, ObjectIdFrom, DO_PATH_PREFIX, KV_PATH_PREFIX,
+    DurableRequest, KvListPage, ObjectIdFrom, DO_PATH_PREFIX, KV_LIST_PATH_PREFIX, KV_PATH_PREFIX,
 };
 use daphne_service_utils::http_headers::STORAGE_PROXY_PUT_KV_EXPIRATION;
 use headers::Header;
-use http::{HeaderMap, StatusCode};
+use http::{header::CONTENT_TYPE, HeaderMap, StatusCode, Uri};
 use prometheus::Registry;
 use tower_service::Service;
 use tracing::warn;
@@ -144,6 +150,10 @@ pub async fn handle_request(req: HttpRequest, env: Env, registry: &Registry) ->
                     middleware::time_kv_requests,
                 )),
         )
+        .route(
+            constcat::concat!(KV_LIST_PATH_PREFIX, "/*prefix"),
+            routing::get(kv_list),
+        )
         .route(
             constcat::concat!(DO_PATH_PREFIX, "/*path"),
             routing::any(handle_do_request).layer(from_fn_with_state(
@@ -292,6 +302,49 @@ async fn kv_get(
     }
 }
 
+#[tracing::instrument(skip(ctx))]
+#[worker::send]
+async fn kv_list(
+    ctx: State<Arc<RequestContext>>,
+    Path(prefix): Path<String>,
+    uri: Uri,
+) -> Result<impl IntoResponse, Error> {
+    let cursor = uri.query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == "cursor")
+            .map(|(_, cursor)| cursor.into_owned())
+    });
+
+    let mut list = ctx.env.kv(KV_BINDING_DAP_CONFIG)?.list().prefix(prefix);
+    if let Some(cursor) = cursor {
+        list = list.cursor(cursor);
+    }
+    let resp = list.execute().await?;
+
+    // KV may still list keys for a short while after they expire, so filter them out here.
+    let now = Date::now().as_millis() / 1000;
+    let page = KvListPage {
+        keys: resp
+            .keys
+            .into_iter()
+            .filter(|key| key.expiration.map_or(true, |expiration| expiration > now))
+            .map(|key| key.name)
+            .collect(),
+        cursor: if resp.list_complete {
+            None
+        } else {
+            resp.cursor
+        },
+    };
+
+    Ok((
+        StatusCode::OK,
+        [(CONTENT_TYPE, "application/json")],
+        serde_json::to_vec(&page).map_err(|e| Error(worker::Error::from(e)))?,
+    )
+        .into_response())
+}
+
 #[tracing::instrument(skip(ctx, body))]
 #[worker::send]
 async fn kv_put(
@@ -39,6 +39,17 @@ pub enum DapAbort {
     #[error("batchOverlap")]
     BatchOverlap { detail: String, task_id: TaskId },
 
+    /// Batch queried too many times. Sent in response to a
+    /// [`CollectionReq`](crate::messages::CollectionReq) that would cause a batch to be queried
+    /// more times than the task allows.
+    #[error("batchQueriedTooManyTimes")]
+    BatchQueriedTooManyTimes { detail: String, task_id: TaskId },
+
+    /// Collection job failed. Sent in response to a poll of a collection job that the Leader
+    /// gave up on. This is not a DAP abort: it has no DAP problem type.
+    #[error("collectionJobFailed")]
+    CollectionJobFailed { detail: String, task_id: TaskId },
+
     /// Invalid batch size (either too small or too large). Sent in response to a `CollectReq` or
     /// `AggregateShareReq`.
     #[error("invalidBatchSize")]
@@ -52,6 +63,12 @@ pub enum DapAbort {
     #[error("missingTaskID")]
     MissingTaskId,
 
+    /// Outdated configuration. Sent in response to an upload request containing a Report that was
+    /// encrypted under an HPKE configuration the Leader no longer recognizes. The Client is
+    /// expected to fetch the current configuration and re-encrypt the Report.
+    #[error("outdatedConfig")]
+    OutdatedConfig { detail: String, task_id: TaskId },
+
     /// Query mismatch. Sent in response to a [`CollectionReq`](crate::messages::CollectionReq) or
     /// [`AggregateShareReq`](crate::messages::AggregateShareReq).
     #[error("queryMismatch")]
@@ -67,6 +84,11 @@ pub enum DapAbort {
     #[error("reportTooLate")]
     ReportTooLate { report_id: ReportId },
 
+    /// Report too early. Sent in response to an upload request containing a Report whose
+    /// timestamp precedes the start of the task's validity window.
+    #[error("reportTooEarly")]
+    ReportTooEarly { report_id: ReportId },
+
     /// Round mismatch. The aggregators disagree on the current round of the VDAF preparation protocol.
     /// This abort occurs during the aggregation sub-protocol.
     #[error("roundMismatch")]
@@ -76,6 +98,11 @@ pub enum DapAbort {
         agg_job_id: AggregationJobId,
     },
 
+    /// Request too large. Sent in response to a request whose body exceeds the size limit
+    /// configured for the endpoint. The body is rejected before it is decoded.
+    #[error("requestTooLarge")]
+    RequestTooLarge { detail: String, task_id: TaskId },
+
     /// Unauthorized HTTP request.
     #[error("unauthorizedRequest")]
     UnauthorizedRequest { detail: String, task_id: TaskId },
@@ -113,8 +140,12 @@ impl DapAbort {
             | Self::InvalidTask { detail, task_id }
             | Self::BatchMismatch { detail, task_id }
             | Self::BatchOverlap { detail, task_id }
+            | Self::BatchQueriedTooManyTimes { detail, task_id }
+            | Self::CollectionJobFailed { detail, task_id }
             | Self::InvalidBatchSize { detail, task_id }
+            | Self::OutdatedConfig { detail, task_id }
             | Self::QueryMismatch { detail, task_id }
+            | Self::RequestTooLarge { detail, task_id }
             | Self::UnauthorizedRequest { detail, task_id }
             | Self::InvalidMessage { detail, task_id } => (
                 Some(task_id),
@@ -162,6 +193,12 @@ impl DapAbort {
                 None,
                 to_instance(format_args!("report_id={report_id}")),
             ),
+            Self::ReportTooEarly { report_id } => (
+                None,
+                Some("one of the reports' timestamp was too early".into()),
+                None,
+                to_instance(format_args!("report_id={report_id}")),
+            ),
             Self::UnrecognizedTask { task_id } => (
                 Some(task_id),
                 None,
@@ -212,8 +249,12 @@ impl DapAbort {
     }
 
     #[inline]
-    pub(crate) fn version_unknown() -> Self {
-        DapAbort::BadRequest("DAP version of request is not recognized".into())
+    pub(crate) fn version_unknown(version: &str) -> Self {
+        DapAbort::BadRequest(format!(
+            "DAP version of request is not recognized: got {version:?}; want one of {:?} or {:?}",
+            DapVersion::Draft09.as_ref(),
+            DapVersion::Latest.as_ref(),
+        ))
     }
 
     #[inline]
@@ -272,8 +313,16 @@ impl DapAbort {
                 "The selected batch overlaps with a previous batch",
                 Some(self.to_string()),
             ),
+            Self::BatchQueriedTooManyTimes { .. } => (
+                "The batch has been queried too many times",
+                Some(self.to_string()),
+            ),
             Self::InvalidBatchSize { .. } => ("Batch size is invalid", Some(self.to_string())),
             Self::InvalidTask { .. } => ("Opted out of Taskprov task", Some(self.to_string())),
+            Self::OutdatedConfig { .. } => (
+                "The message was generated using an outdated configuration",
+                Some(self.to_string()),
+            ),
             Self::QueryMismatch { .. } => {
                 ("Query type does not match the task", Some(self.to_string()))
             }
@@ -290,6 +339,10 @@ impl DapAbort {
                 "The requested task expires after report timestamp",
                 Some(self.to_string()),
             ),
+            Self::ReportTooEarly { .. } => (
+                "The requested task starts after report timestamp",
+                Some(self.to_string()),
+            ),
             Self::UnauthorizedRequest { .. } => {
                 ("Request authorization failed", Some(self.to_string()))
             }
@@ -301,6 +354,8 @@ impl DapAbort {
                 "Task indicated by request is not recognized",
                 Some(self.to_string()),
             ),
+            Self::CollectionJobFailed { .. } => ("Collection job failed", None),
+            Self::RequestTooLarge { .. } => ("Request body too large", None),
             Self::BadRequest(..) => ("Bad request", None),
         };
 
@@ -325,6 +380,46 @@ impl DapAbort {
             task_id,
         }
     }
+
+    /// Reconstruct the abort sent by the peer from its problem details document. `task_id` is the
+    /// task of the request, used if the document doesn't indicate one. Aborts that can't be
+    /// reconstructed, e.g., because the document lacks the report ID, are returned as
+    /// [`DapAbort::BadRequest`].
+    pub fn from_problem_details(problem: ProblemDetails, task_id: TaskId) -> Self {
+        let typ = problem
+            .typ
+            .as_deref()
+            .and_then(|typ| typ.strip_prefix("urn:ietf:params:ppm:dap:error:"));
+        let task_id = problem.task_id.unwrap_or(task_id);
+        let detail = problem.detail.unwrap_or(problem.title);
+        match (typ, problem.agg_job_id) {
+            (Some("batchInvalid"), _) => Self::BatchInvalid { detail, task_id },
+            (Some("batchMismatch"), _) => Self::BatchMismatch { detail, task_id },
+            (Some("batchOverlap"), _) => Self::BatchOverlap { detail, task_id },
+            (Some("batchQueriedTooManyTimes"), _) => {
+                Self::BatchQueriedTooManyTimes { detail, task_id }
+            }
+            (Some("invalidBatchSize"), _) => Self::InvalidBatchSize { detail, task_id },
+            (Some("invalidTask"), _) => Self::InvalidTask { detail, task_id },
+            (Some("outdatedConfig"), _) => Self::OutdatedConfig { detail, task_id },
+            (Some("queryMismatch"), _) => Self::QueryMismatch { detail, task_id },
+            (Some("unauthorizedRequest"), _) => Self::UnauthorizedRequest { detail, task_id },
+            (Some("invalidMessage"), _) => Self::InvalidMessage { detail, task_id },
+            (Some("unrecognizedTask"), _) => Self::UnrecognizedTask { task_id },
+            (Some("roundMismatch"), Some(agg_job_id)) => Self::RoundMismatch {
+                detail,
+                task_id,
+                agg_job_id,
+            },
+            (Some("unrecognizedAggregationJob"), Some(agg_job_id)) => {
+                Self::UnrecognizedAggregationJob {
+                    task_id,
+                    agg_job_id,
+                }
+            }
+            _ => Self::BadRequest(detail),
+        }
+    }
 }
 
 /// A problem details document compatible with RFC 7807.
@@ -360,6 +455,10 @@ pub struct ProblemDetails {
 
 #[cfg(test)]
 mod test {
+    use std::iter::zip;
+
+    use assert_matches::assert_matches;
+
     use crate::messages::{AggregationJobId, ReportId, TaskId};
 
     use super::{DapAbort, ProblemDetails};
@@ -383,6 +482,14 @@ mod test {
                 detail: detail.clone(),
                 task_id,
             },
+            DapAbort::BatchQueriedTooManyTimes {
+                detail: detail.clone(),
+                task_id,
+            },
+            DapAbort::CollectionJobFailed {
+                detail: detail.clone(),
+                task_id,
+            },
             DapAbort::InvalidBatchSize {
                 detail: detail.clone(),
                 task_id,
@@ -392,6 +499,10 @@ mod test {
                 task_id,
             },
             DapAbort::MissingTaskId,
+            DapAbort::OutdatedConfig {
+                detail: detail.clone(),
+                task_id,
+            },
             DapAbort::QueryMismatch {
                 detail: detail.clone(),
                 task_id,
@@ -400,11 +511,16 @@ mod test {
                 detail: detail.clone(),
             },
             DapAbort::ReportTooLate { report_id },
+            DapAbort::ReportTooEarly { report_id },
             DapAbort::RoundMismatch {
                 detail: detail.clone(),
                 task_id,
                 agg_job_id,
             },
+            DapAbort::RequestTooLarge {
+                detail: detail.clone(),
+                task_id,
+            },
             DapAbort::UnauthorizedRequest {
                 detail: detail.clone(),
                 task_id,
@@ -427,4 +543,42 @@ mod test {
             assert!(instance_url.is_ok(), "{instance:?} is not url safe");
         }
     }
+
+    #[test]
+    fn from_problem_details() {
+        let task_id = TaskId([1; 32]);
+        let agg_job_id = AggregationJobId([2; 16]);
+        let aborts = || {
+            [
+                DapAbort::BatchMismatch {
+                    detail: "detail".into(),
+                    task_id,
+                },
+                DapAbort::UnrecognizedTask { task_id },
+                DapAbort::UnrecognizedAggregationJob {
+                    task_id,
+                    agg_job_id,
+                },
+            ]
+        };
+        for (abort, expected) in zip(aborts(), aborts()) {
+            // Round-trip the document as it is sent over the wire.
+            let problem = serde_json::to_string(&abort.into_problem_details()).unwrap();
+            let problem = serde_json::from_str(&problem).unwrap();
+            assert_eq!(
+                DapAbort::from_problem_details(problem, TaskId([0; 32])),
+                expected
+            );
+        }
+
+        // The report ID is not part of the document.
+        let problem = DapAbort::ReportTooEarly {
+            report_id: ReportId([3; 16]),
+        }
+        .into_problem_details();
+        assert_matches!(
+            DapAbort::from_problem_details(problem, task_id),
+            DapAbort::BadRequest(..)
+        );
+    }
 }
@@ -31,7 +31,35 @@ pub enum DapError {
     Transition(#[from] TransitionFailure),
 }
 
+/// Whether retrying the operation that failed might succeed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DapErrorKind {
+    /// The failure is temporary, e.g., a storage request timed out. The request may be retried.
+    Transient,
+
+    /// Retrying won't help, e.g., the request is malformed or the configuration is invalid.
+    Permanent,
+}
+
 impl DapError {
+    /// Classify the error. Only fatal errors can be transient: aborts and transition failures
+    /// are caused by the content of the request and are always permanent.
+    pub fn kind(&self) -> DapErrorKind {
+        match self {
+            Self::Fatal(e) => e.kind(),
+            Self::Abort(..) | Self::Transition(..) => DapErrorKind::Permanent,
+        }
+    }
+
+    /// Set the kind of a fatal error. Other errors are returned unchanged.
+    #[must_use]
+    pub fn with_kind(self, kind: DapErrorKind) -> Self {
+        match self {
+            Self::Fatal(FatalDapError(s, _)) => Self::Fatal(FatalDapError(s, kind)),
+            e => e,
+        }
+    }
+
     pub fn into_problem_details(self) -> ProblemDetails {
         if let Self::Abort(a) = self {
             return a.into_problem_details();
@@ -49,9 +77,10 @@ impl DapError {
 
     /// Construct a fatal encoding error.
     pub fn encoding(e: CodecError) -> DapError {
-        DapError::Fatal(FatalDapError(format!(
-            "encountered fatal error during encoding: {e}"
-        )))
+        DapError::Fatal(FatalDapError(
+            format!("encountered fatal error during encoding: {e}"),
+            DapErrorKind::Permanent,
+        ))
     }
 
     pub(crate) fn from_vdaf(e: VdafError) -> Self {
@@ -66,7 +95,7 @@ impl DapError {
 }
 
 #[derive(Clone, PartialEq, Eq)]
-pub struct FatalDapError(pub(crate) String);
+pub struct FatalDapError(pub(crate) String, pub(crate) DapErrorKind);
 
 impl std::error::Error for FatalDapError {}
 
@@ -83,9 +112,14 @@ impl Debug for FatalDapError {
 }
 
 impl FatalDapError {
+    /// Fatal errors are permanent unless marked otherwise with [`DapError::with_kind`].
+    pub fn kind(&self) -> DapErrorKind {
+        self.1
+    }
+
     #[doc(hidden)]
     pub fn __use_the_macro(s: String) -> Self {
-        FatalDapError(s)
+        FatalDapError(s, DapErrorKind::Permanent)
     }
 }
 
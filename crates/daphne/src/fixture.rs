@@ -0,0 +1,151 @@
+// Copyright (c) 2024 Cloudflare, Inc. All rights reserved.
+// SPDX-License-Identifier: BSD-3-Clause
+
+//! Capture of the inputs to an aggregation, so that an unexpected result can be reproduced
+//! offline.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fatal_error,
+    hpke::HpkeDecrypter,
+    messages::{Base64Encode, Report, ReportMetadata, TaskId},
+    protocol::aggregator::decrypt_input_share,
+    DapError, DapTaskConfig,
+};
+
+/// Everything needed to replay the aggregation of a set of reports offline: the task config,
+/// including the VDAF verification key, and the reports with their input shares decrypted. No HPKE
+/// private key is included.
+#[derive(Clone, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(Debug))]
+pub struct AggregationFixture {
+    pub task_id: TaskId,
+    pub task_config: DapTaskConfig,
+    pub reports: Vec<AggregationFixtureReport>,
+}
+
+/// A report captured in an [`AggregationFixture`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AggregationFixtureReport {
+    pub report_metadata: ReportMetadata,
+
+    #[serde(with = "hex")]
+    pub public_share: Vec<u8>,
+
+    /// The Leader's encoded [`PlaintextInputShare`](crate::messages::PlaintextInputShare).
+    #[serde(with = "hex")]
+    pub leader_input_share: Vec<u8>,
+
+    /// The Helper's encoded [`PlaintextInputShare`](crate::messages::PlaintextInputShare).
+    #[serde(with = "hex")]
+    pub helper_input_share: Vec<u8>,
+}
+
+impl AggregationFixture {
+    /// Capture the given reports. Both input shares of each report are decrypted, so the Leader's
+    /// and the Helper's HPKE receiver configs are both required.
+    pub async fn capture(
+        leader_decrypter: &impl HpkeDecrypter,
+        helper_decrypter: &impl HpkeDecrypter,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        reports: impl IntoIterator<Item = Report>,
+    ) -> Result<Self, DapError> {
+        let mut captured = Vec::new();
+        for report in reports {
+            let report_id = report.report_metadata.id;
+            let leader_input_share = decrypt_input_share(
+                leader_decrypter,
+                task_id,
+                task_config.version,
+                &report,
+                true,
+            )
+            .await
+            .map_err(|e| {
+                fatal_error!(
+                    err = format!(
+                        "failed to decrypt the Leader's input share of report {}: {e}",
+                        report_id.to_base64url()
+                    )
+                )
+            })?;
+            let helper_input_share = decrypt_input_share(
+                helper_decrypter,
+                task_id,
+                task_config.version,
+                &report,
+                false,
+            )
+            .await
+            .map_err(|e| {
+                fatal_error!(
+                    err = format!(
+                        "failed to decrypt the Helper's input share of report {}: {e}",
+                        report_id.to_base64url()
+                    )
+                )
+            })?;
+            captured.push(AggregationFixtureReport {
+                report_metadata: report.report_metadata,
+                public_share: report.public_share,
+                leader_input_share,
+                helper_input_share,
+            });
+        }
+
+        Ok(Self {
+            task_id: *task_id,
+            task_config: task_config.clone(),
+            reports: captured,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AggregationFixture;
+    use crate::{
+        async_test_versions,
+        hpke::HpkeKemId,
+        testing::AggregationJobTest,
+        vdaf::{Prio3Config, VdafConfig},
+        DapAggregateResult, DapAggregationParam, DapMeasurement, DapVersion,
+    };
+
+    async fn capture_and_replay(version: DapVersion) {
+        let mut t = AggregationJobTest::new(
+            &VdafConfig::Prio3(Prio3Config::Sum { bits: 8 }),
+            HpkeKemId::X25519HkdfSha256,
+            version,
+        );
+        let reports = t.produce_reports(vec![DapMeasurement::U64(23), DapMeasurement::U64(19)]);
+
+        let fixture = AggregationFixture::capture(
+            &t.leader_hpke_receiver_config,
+            &t.helper_hpke_receiver_config,
+            &t.task_id,
+            &t.task_config,
+            reports.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fixture.reports.len(), 2);
+
+        // The fixture is meant to be written to disk and loaded elsewhere.
+        let fixture: AggregationFixture =
+            serde_json::from_str(&serde_json::to_string(&fixture).unwrap()).unwrap();
+
+        let agg_result = t
+            .roundtrip_reports(DapAggregationParam::Empty, reports)
+            .await;
+        assert_eq!(agg_result, DapAggregateResult::U128(42));
+        assert_eq!(
+            fixture.replay(DapAggregationParam::Empty).await.unwrap(),
+            agg_result
+        );
+    }
+
+    async_test_versions! { capture_and_replay }
+}
@@ -3,7 +3,7 @@
 
 //! Hybrid Public-Key Encryption ([HPKE](https://datatracker.ietf.org/doc/rfc9180/)).
 
-use hpke_rs::{Hpke, HpkeError, HpkePrivateKey, HpkePublicKey, Mode};
+use hpke_rs::{Hpke, HpkeError, HpkeKeyPair, HpkePrivateKey, HpkePublicKey, Mode};
 use hpke_rs_crypto::{
     error::Error,
     types::{AeadAlgorithm, KdfAlgorithm, KemAlgorithm},
@@ -13,16 +13,23 @@ use hpke_rs_rust_crypto::HpkeRustCrypto as ImplHpkeCrypto;
 
 use crate::{
     fatal_error,
-    messages::{HpkeCiphertext, TaskId, TransitionFailure},
-    DapError, DapVersion,
+    messages::{
+        encode_u32_bytes, encode_u32_prefixed, BatchSelector, HpkeCiphertext, ReportMetadata,
+        TaskId, TransitionFailure,
+    },
+    DapAggregationParam, DapError, DapVersion,
 };
 use async_trait::async_trait;
+use prio::codec::{Encode, ParameterizedEncode};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 
 // Various algorithm constants
 const KEM_ID_X25519_HKDF_SHA256: u16 = 0x0020;
+const KEM_ID_X448_HKDF_SHA512: u16 = 0x0021;
 const KEM_ID_P256_HKDF_SHA256: u16 = 0x0010;
+const KEM_ID_P521_HKDF_SHA512: u16 = 0x0012;
 const KDF_ID_HKDF_SHA256: u16 = 0x0001;
 const AEAD_ID_AES128GCM: u16 = 0x0001;
 
@@ -53,9 +60,15 @@ fn check_suite<T: HpkeCrypto>(
     let kem = KemAlgorithm::try_from(u16::from(kem_id)).map_err(maperr)?;
     let kdf = KdfAlgorithm::try_from(u16::from(kdf_id)).map_err(maperr)?;
     let aead = AeadAlgorithm::try_from(u16::from(aead_id)).map_err(maperr)?;
+    // The KEM is recognized, but it may not be implemented by the crypto backend.
+    T::supports_kem(kem)
+        .map_err(|_| fatal_error!(err = s, "KEM not supported by the crypto backend: {kem}"))?;
     match (kem, kdf, aead) {
         (
-            KemAlgorithm::DhKemP256 | KemAlgorithm::DhKem25519,
+            KemAlgorithm::DhKemP256
+            | KemAlgorithm::DhKem25519
+            | KemAlgorithm::DhKemP521
+            | KemAlgorithm::DhKem448,
             KdfAlgorithm::HkdfSha256,
             AeadAlgorithm::Aes128Gcm,
         ) => Ok(Hpke::new(Mode::Base, kem, kdf, aead)),
@@ -66,6 +79,81 @@ fn check_suite<T: HpkeCrypto>(
     }
 }
 
+/// The HPKE ciphersuites supported by the compiled-in crypto backend, in the order in which they
+/// should be advertised.
+pub fn supported_hpke_suites() -> Vec<(HpkeKemId, HpkeKdfId, HpkeAeadId)> {
+    [
+        HpkeKemId::X25519HkdfSha256,
+        HpkeKemId::P256HkdfSha256,
+        HpkeKemId::P521HkdfSha512,
+        HpkeKemId::X448HkdfSha512,
+    ]
+    .into_iter()
+    .filter(|kem_id| kem_id.is_supported())
+    .map(|kem_id| (kem_id, HpkeKdfId::HkdfSha256, HpkeAeadId::Aes128Gcm))
+    .collect()
+}
+
+/// Pick which of the Collector's HPKE configs to encrypt the aggregate share to.
+///
+/// `preferred_kems` is the Aggregator's policy, most preferred KEM first (e.g.,
+/// [`DapGlobalConfig::supported_hpke_kems`](crate::DapGlobalConfig)). The first KEM for which the
+/// Collector offers a config with a supported ciphersuite wins. If the Collector offers several
+/// configs for that KEM, the first one is selected.
+pub fn select_collector_hpke_config<'a>(
+    configs: &'a [HpkeConfig],
+    preferred_kems: &[HpkeKemId],
+) -> Result<&'a HpkeConfig, DapError> {
+    preferred_kems
+        .iter()
+        .find_map(|kem_id| {
+            configs
+                .iter()
+                .find(|config| config.kem_id == *kem_id && config.is_supported())
+        })
+        .ok_or_else(|| {
+            fatal_error!(
+                err = "no mutually supported HPKE config for the Collector",
+                offered = ?configs.iter().map(|config| config.kem_id).collect::<Vec<_>>(),
+                preferred = ?preferred_kems
+            )
+        })
+}
+
+/// The HPKE AAD for the input shares of a report: the task ID, the report metadata, and the
+/// length-prefixed public share. The sender and receiver roles are bound by the HPKE info string,
+/// not the AAD.
+pub fn hpke_aad_for_input_share(
+    task_id: &TaskId,
+    version: DapVersion,
+    report_metadata: &ReportMetadata,
+    public_share: &[u8],
+) -> Result<Vec<u8>, DapError> {
+    let mut aad = Vec::with_capacity(58);
+    task_id.encode(&mut aad).map_err(DapError::encoding)?;
+    report_metadata
+        .encode_with_param(&version, &mut aad)
+        .map_err(DapError::encoding)?;
+    encode_u32_bytes(&mut aad, public_share).map_err(DapError::encoding)?;
+    Ok(aad)
+}
+
+/// The HPKE AAD for an aggregate share: the task ID, the length-prefixed aggregation parameter,
+/// and the batch selector.
+pub fn hpke_aad_for_agg_share(
+    task_id: &TaskId,
+    batch_sel: &BatchSelector,
+    agg_param: &DapAggregationParam,
+    version: DapVersion,
+) -> Result<Vec<u8>, DapError> {
+    let mut aad = Vec::with_capacity(40);
+    task_id.encode(&mut aad).map_err(DapError::encoding)?;
+    encode_u32_prefixed(version, &mut aad, |_version, bytes| agg_param.encode(bytes))
+        .map_err(DapError::encoding)?;
+    batch_sel.encode(&mut aad).map_err(DapError::encoding)?;
+    Ok(aad)
+}
+
 /// Codepoint for KEM schemes compatible with HPKE.
 #[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -73,14 +161,32 @@ fn check_suite<T: HpkeCrypto>(
 pub enum HpkeKemId {
     P256HkdfSha256,
     X25519HkdfSha256,
+    P521HkdfSha512,
+    X448HkdfSha512,
     NotImplemented(u16),
 }
 
+impl HpkeKemId {
+    /// Returns true if the KEM is known and implemented by the compiled-in crypto backend.
+    pub fn is_supported(self) -> bool {
+        let kem = match self {
+            Self::P256HkdfSha256 => KemAlgorithm::DhKemP256,
+            Self::X25519HkdfSha256 => KemAlgorithm::DhKem25519,
+            Self::P521HkdfSha512 => KemAlgorithm::DhKemP521,
+            Self::X448HkdfSha512 => KemAlgorithm::DhKem448,
+            Self::NotImplemented(..) => return false,
+        };
+        ImplHpkeCrypto::supports_kem(kem).is_ok()
+    }
+}
+
 impl From<HpkeKemId> for u16 {
     fn from(kem_id: HpkeKemId) -> Self {
         match kem_id {
             HpkeKemId::P256HkdfSha256 => KEM_ID_P256_HKDF_SHA256,
             HpkeKemId::X25519HkdfSha256 => KEM_ID_X25519_HKDF_SHA256,
+            HpkeKemId::P521HkdfSha512 => KEM_ID_P521_HKDF_SHA512,
+            HpkeKemId::X448HkdfSha512 => KEM_ID_X448_HKDF_SHA512,
             HpkeKemId::NotImplemented(x) => x,
         }
     }
@@ -91,6 +197,8 @@ impl From<u16> for HpkeKemId {
         match value {
             KEM_ID_P256_HKDF_SHA256 => Self::P256HkdfSha256,
             KEM_ID_X25519_HKDF_SHA256 => Self::X25519HkdfSha256,
+            KEM_ID_P521_HKDF_SHA512 => Self::P521HkdfSha512,
+            KEM_ID_X448_HKDF_SHA512 => Self::X448HkdfSha512,
             x => Self::NotImplemented(x),
         }
     }
@@ -105,6 +213,13 @@ pub enum HpkeKdfId {
     NotImplemented(u16),
 }
 
+impl HpkeKdfId {
+    /// Returns true if the KDF is known and implemented.
+    pub fn is_supported(self) -> bool {
+        !matches!(self, Self::NotImplemented(..))
+    }
+}
+
 impl From<HpkeKdfId> for u16 {
     fn from(kdf_id: HpkeKdfId) -> Self {
         match kdf_id {
@@ -132,6 +247,17 @@ pub enum HpkeAeadId {
     NotImplemented(u16),
 }
 
+impl HpkeAeadId {
+    /// Returns true if the AEAD is known and implemented.
+    pub fn is_supported(self) -> bool {
+        !matches!(self, Self::NotImplemented(..))
+    }
+}
+
+/// Length of the authentication tag appended to a plaintext sealed with HPKE. It is the same for
+/// every supported AEAD.
+pub(crate) const HPKE_AEAD_TAG_LEN: usize = 16;
+
 impl From<HpkeAeadId> for u16 {
     fn from(aead_id: HpkeAeadId) -> Self {
         match aead_id {
@@ -161,6 +287,14 @@ pub struct HpkeConfig {
     pub public_key: HpkePublicKey,
 }
 
+impl HpkeConfig {
+    /// Returns true if we can encrypt to this config, i.e., each algorithm of its ciphersuite is
+    /// supported.
+    pub fn is_supported(&self) -> bool {
+        self.kem_id.is_supported() && self.kdf_id.is_supported() && self.aead_id.is_supported()
+    }
+}
+
 #[cfg(any(test, feature = "test-utils"))]
 impl deepsize::DeepSizeOf for HpkeConfig {
     fn deep_size_of_children(&self, context: &mut deepsize::Context) -> usize {
@@ -228,8 +362,34 @@ pub trait HpkeProvider: HpkeDecrypter {
         task_id: Option<&TaskId>,
     ) -> Result<Self::WrappedHpkeConfig<'s>, DapError>;
 
+    /// Look up the HPKE configurations to advertise for the given task ID (if specified), in order
+    /// of preference. By default, this is the config returned by
+    /// [`get_hpke_config_for()`](Self::get_hpke_config_for).
+    async fn get_hpke_config_list_for(
+        &self,
+        version: DapVersion,
+        task_id: Option<&TaskId>,
+    ) -> Result<Vec<HpkeConfig>, DapError> {
+        let hpke_config = self.get_hpke_config_for(version, task_id).await?;
+        Ok(vec![HpkeConfig::clone(&hpke_config)])
+    }
+
     /// Returns `true` if a ciphertext with the HPKE config ID can be consumed in the current task.
     async fn can_hpke_decrypt(&self, task_id: &TaskId, config_id: u8) -> Result<bool, DapError>;
+
+    /// Check that the HPKE config ID refers to one of the currently active receiver configs. If
+    /// not, fail with the same transition failure that aggregation would produce.
+    async fn check_hpke_config_id_is_active(
+        &self,
+        task_id: &TaskId,
+        config_id: u8,
+    ) -> Result<(), DapError> {
+        if self.can_hpke_decrypt(task_id, config_id).await? {
+            Ok(())
+        } else {
+            Err(DapError::Transition(TransitionFailure::HpkeUnknownConfigId))
+        }
+    }
 }
 
 #[async_trait]
@@ -284,17 +444,43 @@ impl HpkeReceiverConfig {
 
     /// Generate and return a new HPKE receiver context given a HPKE config ID and HPKE KEM.
     pub fn gen(id: u8, kem_id: HpkeKemId) -> Result<Self, DapError> {
-        let kem = match kem_id {
-            HpkeKemId::P256HkdfSha256 => KemAlgorithm::DhKemP256,
-            HpkeKemId::X25519HkdfSha256 => KemAlgorithm::DhKem25519,
-            HpkeKemId::NotImplemented(x) => {
-                return Err(fatal_error!(err = "Unsupported KEM", kem = ?x))
-            }
-        };
+        let mut generator = Self::generator(kem_id)?;
+        Self::from_key_pair(id, kem_id, generator.generate_key_pair())
+    }
+
+    /// Like [`Self::gen`], except that the key pair is derived from randomness drawn from `rng`.
+    /// This lets the caller choose the source of randomness, e.g., on platforms where
+    /// `rand::thread_rng()` is not available.
+    pub fn gen_with_rng(
+        id: u8,
+        kem_id: HpkeKemId,
+        rng: &mut impl RngCore,
+    ) -> Result<Self, DapError> {
+        // Input keying material must be at least as long as the private key, the longest of which
+        // is P-521's.
+        let mut ikm = [0; 66];
+        rng.fill_bytes(&mut ikm);
+        let generator = Self::generator(kem_id)?;
+        Self::from_key_pair(id, kem_id, generator.derive_key_pair(&ikm))
+    }
+
+    fn generator(kem_id: HpkeKemId) -> Result<Hpke<ImplHpkeCrypto>, DapError> {
+        if !kem_id.is_supported() {
+            return Err(fatal_error!(err = "Unsupported KEM", ?kem_id));
+        }
+        let kem = KemAlgorithm::try_from(u16::from(kem_id))
+            .map_err(|_| fatal_error!(err = "Unsupported KEM", ?kem_id))?;
         let kdf = KdfAlgorithm::HkdfSha256;
         let aead = AeadAlgorithm::Aes128Gcm;
-        let mut generator = Hpke::<ImplHpkeCrypto>::new(Mode::Base, kem, kdf, aead);
-        match generator.generate_key_pair() {
+        Ok(Hpke::<ImplHpkeCrypto>::new(Mode::Base, kem, kdf, aead))
+    }
+
+    fn from_key_pair(
+        id: u8,
+        kem_id: HpkeKemId,
+        keypair: Result<HpkeKeyPair, HpkeError>,
+    ) -> Result<Self, DapError> {
+        match keypair {
             Ok(keypair) => {
                 let (private_key, public_key) = keypair.into_keys();
                 Ok(HpkeReceiverConfig {
@@ -322,8 +508,18 @@ impl TryFrom<(HpkeConfig, HpkePrivateKey)> for HpkeReceiverConfig {
     /// Create a new HPKE receiver context given an `HpkeConfig` and a corresponding private key.
     /// Returns an error if the public key does not correspond to the `private_key`.
     fn try_from((config, private_key): (HpkeConfig, HpkePrivateKey)) -> Result<Self, Self::Error> {
+        if !config.is_supported() {
+            return Err(fatal_error!(
+                err = "HPKE ciphersuite not supported",
+                kem_id = ?config.kem_id,
+                kdf_id = ?config.kdf_id,
+                aead_id = ?config.aead_id
+            ));
+        }
         let kem_id_u16: u16 = config.kem_id.into();
-        let kem_id: KemAlgorithm = kem_id_u16.try_into().unwrap();
+        let kem_id: KemAlgorithm = kem_id_u16
+            .try_into()
+            .map_err(|_| fatal_error!(err = "Unsupported KEM", kem_id = ?config.kem_id))?;
         let public_key = HpkePublicKey::from(ImplHpkeCrypto::kem_derive_base(
             kem_id,
             private_key.as_slice(),
@@ -399,10 +595,79 @@ impl From<HpkePrivateKeySerde> for HpkePrivateKey {
 
 #[cfg(test)]
 mod test {
-    use crate::hpke::{HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId, HpkeReceiverConfig};
+    use crate::{
+        hpke::{
+            hpke_aad_for_agg_share, hpke_aad_for_input_share, select_collector_hpke_config,
+            supported_hpke_suites, HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId,
+            HpkeReceiverConfig,
+        },
+        messages::{BatchId, BatchSelector, Interval, ReportId, ReportMetadata, TaskId},
+        test_versions, DapAggregationParam, DapVersion,
+    };
     use hpke_rs::{Hpke, HpkePrivateKey, HpkePublicKey, Mode};
-    use hpke_rs_crypto::types::{AeadAlgorithm, KdfAlgorithm, KemAlgorithm};
+    use hpke_rs_crypto::{
+        types::{AeadAlgorithm, KdfAlgorithm, KemAlgorithm},
+        HpkeCrypto,
+    };
     use hpke_rs_rust_crypto::HpkeRustCrypto as ImplHpkeCrypto;
+    use prio::codec::{Decode, Encode};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn encrypt_roundtrip_supported_suites() {
+        let info = b"info string";
+        let aad = b"associated data";
+        let plaintext = b"plaintext";
+        let suites = supported_hpke_suites();
+        assert!(suites.len() >= 2, "{suites:?}");
+        for (kem_id, kdf_id, aead_id) in suites {
+            let config = HpkeReceiverConfig::gen(23, kem_id).unwrap();
+            assert_eq!(config.config.kdf_id, kdf_id);
+            assert_eq!(config.config.aead_id, aead_id);
+            assert_eq!(
+                HpkeConfig::get_decoded(&config.config.get_encoded().unwrap()).unwrap(),
+                config.config
+            );
+            let ciphertext = config.encrypt(info, aad, plaintext).unwrap();
+            assert_eq!(
+                config.decrypt(info, aad, &ciphertext).unwrap(),
+                plaintext,
+                "{kem_id:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn gen_unsupported_kem() {
+        for kem_id in [
+            HpkeKemId::P521HkdfSha512,
+            HpkeKemId::X448HkdfSha512,
+            HpkeKemId::NotImplemented(0x0011),
+        ] {
+            if !kem_id.is_supported() {
+                assert!(HpkeReceiverConfig::gen(23, kem_id).is_err(), "{kem_id:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn read_hpke_config_new_kems() {
+        for (kem_id, codepoint) in [
+            (HpkeKemId::P521HkdfSha512, 0x0012),
+            (HpkeKemId::X448HkdfSha512, 0x0021),
+        ] {
+            let config = HpkeConfig {
+                id: 23,
+                kem_id,
+                kdf_id: HpkeKdfId::HkdfSha256,
+                aead_id: HpkeAeadId::Aes128Gcm,
+                public_key: HpkePublicKey::from(vec![1; 32]),
+            };
+            let encoded = config.get_encoded().unwrap();
+            assert_eq!(encoded[1..3], u16::to_be_bytes(codepoint));
+            assert_eq!(HpkeConfig::get_decoded(&encoded).unwrap(), config);
+        }
+    }
 
     #[test]
     fn encrypt_roundtrip_x25519_hkdf_sha256() {
@@ -456,4 +721,174 @@ mod test {
         let bad_private_key = HpkePrivateKey::from(vec![0; 20]);
         assert!(HpkeReceiverConfig::try_from((config, bad_private_key)).is_err());
     }
+
+    #[test]
+    fn codepoints_are_supported() {
+        for (kem_id, kem) in [
+            (0x0010, KemAlgorithm::DhKemP256),
+            (0x0012, KemAlgorithm::DhKemP521),
+            (0x0020, KemAlgorithm::DhKem25519),
+            (0x0021, KemAlgorithm::DhKem448),
+        ] {
+            assert_eq!(
+                HpkeKemId::from(kem_id).is_supported(),
+                ImplHpkeCrypto::supports_kem(kem).is_ok(),
+                "{kem_id:#06x}"
+            );
+        }
+        assert!(HpkeKemId::from(0x0020).is_supported());
+        assert!(HpkeKdfId::from(0x0001).is_supported());
+        assert!(HpkeAeadId::from(0x0001).is_supported());
+
+        // Codepoints we decode but don't implement.
+        assert!(!HpkeKemId::from(0x0011).is_supported());
+        assert!(!HpkeKdfId::from(0x0002).is_supported());
+        assert!(!HpkeAeadId::from(0x0003).is_supported());
+
+        let mut config = HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        assert!(config.is_supported());
+        config.aead_id = HpkeAeadId::from(0x0003);
+        assert!(!config.is_supported());
+    }
+
+    #[test]
+    fn select_collector_hpke_config_by_policy() {
+        let x25519 = HpkeReceiverConfig::gen(1, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        // Only the ciphersuite matters for selection, not the public key.
+        let p256 = HpkeConfig {
+            id: 2,
+            kem_id: HpkeKemId::P256HkdfSha256,
+            ..x25519.clone()
+        };
+        let configs = [p256.clone(), x25519.clone()];
+
+        let selected = select_collector_hpke_config(
+            &configs,
+            &[HpkeKemId::X25519HkdfSha256, HpkeKemId::P256HkdfSha256],
+        )
+        .unwrap();
+        assert_eq!(selected, &x25519);
+
+        // If the preferred KEM is unavailable, fall back to the next one.
+        let selected = select_collector_hpke_config(
+            &configs,
+            &[HpkeKemId::X448HkdfSha512, HpkeKemId::X25519HkdfSha256],
+        )
+        .unwrap();
+        assert_eq!(selected, &x25519);
+
+        // Fail if the Collector offers nothing the policy allows.
+        assert!(select_collector_hpke_config(&[p256], &[HpkeKemId::X25519HkdfSha256]).is_err());
+        assert!(select_collector_hpke_config(&[], &[HpkeKemId::X25519HkdfSha256]).is_err());
+    }
+
+    #[test]
+    fn supported_hpke_suites_reflects_backend() {
+        let suites = supported_hpke_suites();
+        assert_eq!(
+            suites.first(),
+            Some(&(
+                HpkeKemId::X25519HkdfSha256,
+                HpkeKdfId::HkdfSha256,
+                HpkeAeadId::Aes128Gcm
+            ))
+        );
+        for (kem_id, kem) in [
+            (HpkeKemId::P256HkdfSha256, KemAlgorithm::DhKemP256),
+            (HpkeKemId::P521HkdfSha512, KemAlgorithm::DhKemP521),
+            (HpkeKemId::X448HkdfSha512, KemAlgorithm::DhKem448),
+        ] {
+            assert_eq!(
+                suites.contains(&(kem_id, HpkeKdfId::HkdfSha256, HpkeAeadId::Aes128Gcm)),
+                ImplHpkeCrypto::supports_kem(kem).is_ok(),
+                "{kem_id:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn gen_with_seeded_rng_is_deterministic() {
+        for kem_id in [HpkeKemId::X25519HkdfSha256, HpkeKemId::P256HkdfSha256] {
+            let gen = |seed| {
+                HpkeReceiverConfig::gen_with_rng(23, kem_id, &mut StdRng::seed_from_u64(seed))
+                    .unwrap()
+            };
+            let config = gen(1337);
+            assert_eq!(config, gen(1337));
+            assert_ne!(config.config.public_key, gen(1338).config.public_key);
+
+            // The derived key pair is usable.
+            let ciphertext = config.encrypt(b"info", b"aad", b"plaintext").unwrap();
+            assert_eq!(
+                config.decrypt(b"info", b"aad", &ciphertext).unwrap(),
+                b"plaintext"
+            );
+        }
+    }
+
+    fn aad_for_input_share_layout(version: DapVersion) {
+        let aad = hpke_aad_for_input_share(
+            &TaskId([1; 32]),
+            version,
+            &ReportMetadata {
+                id: ReportId([2; 16]),
+                time: 0x0102_0304_0506_0708,
+            },
+            b"public",
+        )
+        .unwrap();
+
+        let mut expected = vec![1; 32]; // task ID
+        expected.extend_from_slice(&[2; 16]); // report ID
+        expected.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]); // report time
+        expected.extend_from_slice(&[0, 0, 0, 6]); // public share length
+        expected.extend_from_slice(b"public");
+        assert_eq!(aad, expected);
+    }
+
+    test_versions! { aad_for_input_share_layout }
+
+    fn aad_for_agg_share_layout(version: DapVersion) {
+        let task_id = TaskId([1; 32]);
+
+        let aad = hpke_aad_for_agg_share(
+            &task_id,
+            &BatchSelector::TimeInterval {
+                batch_interval: Interval {
+                    start: 0x0102_0304_0506_0708,
+                    duration: 3600,
+                },
+            },
+            &DapAggregationParam::Empty,
+            version,
+        )
+        .unwrap();
+        let mut expected = vec![1; 32]; // task ID
+        expected.extend_from_slice(&[0, 0, 0, 0]); // aggregation parameter length
+        expected.push(1); // time-interval query type
+        expected.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]); // batch interval start
+        expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0x0e, 0x10]); // batch interval duration
+        assert_eq!(aad, expected);
+
+        let aad = hpke_aad_for_agg_share(
+            &task_id,
+            &BatchSelector::FixedSizeByBatchId {
+                batch_id: BatchId([3; 32]),
+            },
+            &DapAggregationParam::Empty,
+            version,
+        )
+        .unwrap();
+        let mut expected = vec![1; 32]; // task ID
+        expected.extend_from_slice(&[0, 0, 0, 0]); // aggregation parameter length
+        expected.push(2); // fixed-size query type
+        expected.extend_from_slice(&[3; 32]); // batch ID
+        assert_eq!(aad, expected);
+    }
+
+    test_versions! { aad_for_agg_share_layout }
 }
@@ -44,6 +44,7 @@ pub mod audit_log;
 pub mod auth;
 pub mod constants;
 pub mod error;
+pub mod fixture;
 pub mod hpke;
 pub mod messages;
 pub mod metrics;
@@ -60,7 +61,7 @@ use crate::{
     hpke::HpkeReceiverConfig,
     messages::{
         AggregationJobId, BatchId, BatchSelector, Collection, CollectionJobId, Duration, Interval,
-        PartialBatchSelector, ReportId, TaskId, Time,
+        PartialBatchSelector, QueryTypeStrictness, ReportId, TaskId, Time,
     },
     vdaf::{
         Prio3Config, VdafAggregateShare, VdafConfig, VdafPrepShare, VdafPrepState, VdafVerifyKey,
@@ -68,7 +69,7 @@ use crate::{
 };
 use constants::DapMediaType;
 pub use error::DapError;
-use error::FatalDapError;
+use error::{DapErrorKind, FatalDapError};
 use hpke::{HpkeConfig, HpkeKemId};
 use messages::encode_base64url;
 #[cfg(feature = "experimental")]
@@ -79,6 +80,7 @@ use prio::{
     codec::{CodecError, Encode, ParameterizedDecode, ParameterizedEncode},
     vdaf::Aggregatable as AggregatableTrait,
 };
+pub use protocol::agg_share_aad_for_collector;
 pub use protocol::aggregator::ReplayProtection;
 use serde::{Deserialize, Serialize};
 use std::{
@@ -108,13 +110,15 @@ pub enum DapVersion {
     Latest,
 }
 
+/// Parse a DAP version from the string used to indicate it on the wire, e.g., in the path of a
+/// request URL. Unknown versions result in a "bad request" abort.
 impl FromStr for DapVersion {
     type Err = DapAbort;
     fn from_str(version: &str) -> Result<Self, Self::Err> {
         match version {
             "v09" => Ok(DapVersion::Draft09),
             "v10" => Ok(DapVersion::Latest),
-            _ => Err(DapAbort::version_unknown()),
+            _ => Err(DapAbort::version_unknown(version)),
         }
     }
 }
@@ -182,12 +186,110 @@ pub struct DapGlobalConfig {
     ///    that have already been aggregated.
     #[serde(default = "default_num_agg_span_shards")]
     pub default_num_agg_span_shards: NonZeroUsize,
+
+    /// Require the Leader and Helper URLs of each task to use HTTPS. This should be enabled in
+    /// production deployments. It is disabled by default so that tasks can be configured with
+    /// `http://localhost` URLs for testing.
+    #[serde(default)]
+    pub require_https: bool,
+
+    /// Maximum number of reports the Helper accepts in a single aggregation job. Larger jobs are
+    /// rejected before any report is decrypted. If not set, no limit is enforced.
+    #[serde(default)]
+    pub max_reports_per_agg_job: Option<NonZeroUsize>,
+
+    /// draft-wang-ppm-dap-taskprov: Maximum dimension of a Prio2 task configured via taskprov.
+    /// Advertisements for larger dimensions are rejected. If not set, no limit is enforced.
+    #[serde(default)]
+    pub max_prio2_dimension: Option<usize>,
+
+    /// What the Leader does with an aggregation job that the Helper aborts.
+    #[serde(default)]
+    pub helper_abort_policy: HelperAbortPolicy,
+
+    /// Collect batches that have not reached the task's `min_batch_size`, as long as they contain
+    /// at least one report. This is meant for debugging and must never be enabled in production,
+    /// as it defeats the privacy guarantee provided by the minimum batch size.
+    #[serde(default)]
+    pub allow_partial_batch: bool,
+
+    /// Helper: Maximum difference between the Leader's and Helper's report counts for which an
+    /// aggregate share request is still answered. The mismatch is logged, but the aggregate result
+    /// is computed over different sets of reports. This is only meant to ease the migration of
+    /// aggregate storage between backends and should otherwise be left at zero, the default.
+    #[serde(default)]
+    pub agg_share_report_count_tolerance: u64,
+
+    /// Leader: Number of seconds after which a collection job that is still pending is marked as
+    /// failed, e.g., because aggregation with the Helper is too slow or keeps failing. If not set,
+    /// collection jobs stay pending until they complete.
+    #[serde(default)]
+    pub collect_aggregation_deadline: Option<Duration>,
+
+    /// Leader: What to do with an uploaded report whose time is more than
+    /// `future_report_skew_tolerance` seconds ahead of the Leader's clock.
+    #[serde(default)]
+    pub future_report_handling: FutureReportHandling,
+
+    /// Leader: Number of seconds by which a report's time may be ahead of the Leader's clock, e.g.,
+    /// because the Client's clock is fast, before `future_report_handling` applies.
+    #[serde(default)]
+    pub future_report_skew_tolerance: Duration,
+
+    /// Leader: Number of seconds past its timestamp for which a report that has not been
+    /// aggregated is kept. Older reports are deleted by
+    /// [`roles::leader::sweep_expired_reports`]. If not set, reports are kept until they are
+    /// collected.
+    #[serde(default)]
+    pub report_retention: Option<Duration>,
+
+    /// Helper: Record why the reports of each aggregation job were rejected, see
+    /// [`roles::helper::AggregationJobDiagnostics`]. Leader: Fetch and log these diagnostics for
+    /// each aggregation job with rejected reports. This is a debugging aid that is not part of DAP
+    /// and should otherwise be left disabled, the default.
+    #[serde(default)]
+    pub record_agg_job_diagnostics: bool,
+
+    /// Helper: Maximum number of reports of an aggregation job whose input shares are decrypted
+    /// concurrently. Decryption only runs in parallel if the [`hpke::HpkeDecrypter`] offloads it
+    /// from the calling task, e.g., to a thread pool. The transitions in the response are in the
+    /// same order as the reports regardless. Defaults to 1, i.e., reports are decrypted one at a
+    /// time.
+    #[serde(default = "default_agg_job_report_concurrency")]
+    pub agg_job_report_concurrency: NonZeroUsize,
+
+    /// draft-wang-ppm-dap-taskprov: How to decode a task advertisement whose query type is not
+    /// recognized. If strict, the advertisement is rejected as malformed; otherwise it is decoded
+    /// and the task is rejected as invalid, the default.
+    #[serde(default)]
+    pub query_type_strictness: QueryTypeStrictness,
+}
+
+/// How the Leader handles an aggregation job that the Helper aborts.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HelperAbortPolicy {
+    /// Stop processing the work queue and return the error.
+    #[default]
+    FailFast,
+
+    /// Move the job to the quarantine for an operator to review and carry on with the remaining
+    /// work.
+    Quarantine,
 }
 
 fn default_num_agg_span_shards() -> NonZeroUsize {
     NonZeroUsize::new(1).unwrap()
 }
 
+fn default_agg_job_report_concurrency() -> NonZeroUsize {
+    NonZeroUsize::new(1).unwrap()
+}
+
+fn default_max_batch_query_count() -> u16 {
+    1
+}
+
 #[cfg(test)]
 impl Default for DapGlobalConfig {
     fn default() -> Self {
@@ -198,6 +300,19 @@ impl Default for DapGlobalConfig {
             supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
             allow_taskprov: false,
             default_num_agg_span_shards: NonZeroUsize::new(1).unwrap(),
+            require_https: false,
+            max_reports_per_agg_job: None,
+            max_prio2_dimension: None,
+            helper_abort_policy: HelperAbortPolicy::FailFast,
+            allow_partial_batch: false,
+            agg_share_report_count_tolerance: 0,
+            collect_aggregation_deadline: None,
+            future_report_handling: FutureReportHandling::Accept,
+            future_report_skew_tolerance: 0,
+            report_retention: None,
+            record_agg_job_diagnostics: false,
+            agg_job_report_concurrency: NonZeroUsize::new(1).unwrap(),
+            query_type_strictness: QueryTypeStrictness::default(),
         }
     }
 }
@@ -211,10 +326,13 @@ impl DapGlobalConfig {
         first_config_id: u8,
     ) -> Result<Vec<HpkeReceiverConfig>, DapError> {
         if u8::try_from(self.supported_hpke_kems.len()).is_err() {
-            return Err(DapError::Fatal(FatalDapError(format!(
-                "maximum config list length is 256: got {}",
-                self.supported_hpke_kems.len()
-            ))));
+            return Err(DapError::Fatal(FatalDapError(
+                format!(
+                    "maximum config list length is 256: got {}",
+                    self.supported_hpke_kems.len()
+                ),
+                DapErrorKind::Permanent,
+            )));
         }
 
         self.supported_hpke_kems
@@ -394,7 +512,7 @@ impl DapAggregateSpan<DapAggregateShare> {
         let shard = report_id.shard(task_config.num_agg_span_shards);
         let bucket = match part_batch_sel {
             PartialBatchSelector::TimeInterval => DapBatchBucket::TimeInterval {
-                batch_window: task_config.quantized_time_lower_bound(time),
+                batch_window: task_config.batch_bucket_start(time),
                 shard,
             },
             PartialBatchSelector::FixedSizeByBatchId { batch_id } => DapBatchBucket::FixedSize {
@@ -497,6 +615,19 @@ impl Extend<(DapBatchBucket, (ReportId, Time))> for DapAggregateSpan<()> {
     }
 }
 
+/// How the Leader handles an uploaded report whose time is in the future. See
+/// [`DapGlobalConfig::future_report_handling`].
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FutureReportHandling {
+    /// Store the report as is. The Helper may still reject it during aggregation.
+    #[default]
+    Accept,
+
+    /// Reject the report with "reportTooEarly".
+    Reject,
+}
+
 /// Method for configuring tasks.
 #[derive(Clone, Default, Deserialize, Serialize)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(PartialEq, Debug))]
@@ -588,6 +719,7 @@ impl DapTaskParameters {
             taskprov_config,
             vdaf_verify_key_init,
             collector_hpke_config,
+            None, // max_prio2_dimension
         )
         .unwrap()
         .into_opted_in(&taskprov::OptInParam {
@@ -641,6 +773,16 @@ pub struct DapTaskConfig {
     /// VDAF verification key shared by the Aggregators. Used to aggregate reports.
     pub vdaf_verify_key: VdafVerifyKey,
 
+    /// Additional VDAF verification keys, indexed by epoch. A report that carries the
+    /// [`Extension::VerifyKeyEpoch`](messages::Extension::VerifyKeyEpoch) extension is aggregated
+    /// with the key for the indicated epoch; reports without it use `vdaf_verify_key`. This allows
+    /// the Aggregators to rotate the verification key without dropping in-flight reports.
+    ///
+    /// The epoch is chosen by the Client, so any key in this map can be selected by any report. A
+    /// compromised key remains usable until its epoch is removed from the map.
+    #[serde(default)]
+    pub vdaf_verify_keys_by_epoch: HashMap<u8, VdafVerifyKey>,
+
     /// The Collector's HPKE configuration for this task.
     pub collector_hpke_config: HpkeConfig,
 
@@ -650,6 +792,10 @@ pub struct DapTaskConfig {
     /// Number of aggregate span shards for this task. See [`DapGlobalConfig`] for details.
     #[serde(default = "default_num_agg_span_shards")]
     pub num_agg_span_shards: NonZeroUsize,
+
+    /// The maximum number of times the Leader accepts a collection job for any given batch.
+    #[serde(default = "default_max_batch_query_count")]
+    pub max_batch_query_count: u16,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -664,6 +810,8 @@ struct ShadowDapTaskConfig {
     not_after: Time,
     not_before: Time,
     vdaf_verify_key: VdafVerifyKey,
+    #[serde(default)]
+    vdaf_verify_keys_by_epoch: HashMap<u8, VdafVerifyKey>,
     collector_hpke_config: HpkeConfig,
     #[serde(default)]
     method: DapTaskConfigMethod,
@@ -674,12 +822,19 @@ struct ShadowDapTaskConfig {
     deprecated_taskprov: bool,
 
     num_agg_span_shards: NonZeroUsize,
+
+    #[serde(default = "default_max_batch_query_count")]
+    max_batch_query_count: u16,
 }
 
 impl TryFrom<ShadowDapTaskConfig> for DapTaskConfig {
     type Error = DapError;
 
     fn try_from(shadow: ShadowDapTaskConfig) -> Result<Self, DapError> {
+        if let VdafConfig::Prio3(prio3_config) = &shadow.vdaf {
+            prio3_config.validate()?;
+        }
+
         Ok(Self {
             version: shadow.version,
             leader_url: shadow.leader_url,
@@ -691,6 +846,7 @@ impl TryFrom<ShadowDapTaskConfig> for DapTaskConfig {
             not_before: shadow.not_before,
             not_after: shadow.not_after,
             vdaf_verify_key: shadow.vdaf_verify_key,
+            vdaf_verify_keys_by_epoch: shadow.vdaf_verify_keys_by_epoch,
             collector_hpke_config: shadow.collector_hpke_config,
             method: match shadow.method {
                 // If the configuration method is unknown or unspecified, but the deprecated
@@ -701,6 +857,7 @@ impl TryFrom<ShadowDapTaskConfig> for DapTaskConfig {
                 method => method,
             },
             num_agg_span_shards: shadow.num_agg_span_shards,
+            max_batch_query_count: shadow.max_batch_query_count,
         })
     }
 }
@@ -718,6 +875,9 @@ impl deepsize::DeepSizeOf for DapTaskConfig {
             + self.query.deep_size_of_children(context)
             + self.vdaf.deep_size_of_children(context)
             + self.vdaf_verify_key.deep_size_of_children(context)
+            + self
+                .vdaf_verify_keys_by_epoch
+                .deep_size_of_children(context)
             + self.collector_hpke_config.deep_size_of_children(context)
     }
 }
@@ -742,11 +902,101 @@ impl DapTaskConfig {
         time - (time % self.time_precision)
     }
 
+    /// Return the start of the time bucket a report with the given timestamp belongs to, i.e., the
+    /// timestamp rounded down to a multiple of the `time_precision`. Reports are grouped into
+    /// batches by bucket for time-interval queries.
+    pub fn batch_bucket_start(&self, time: Time) -> Time {
+        self.quantized_time_lower_bound(time)
+    }
+
     /// Return the least multiple of the `time_precision` which is greater than the specified time.
     pub fn quantized_time_upper_bound(&self, time: Time) -> Time {
         self.quantized_time_lower_bound(time) + self.time_precision
     }
 
+    /// If `require_https` is set, check that the Leader and Helper URLs for the task use HTTPS.
+    /// Report shares are sent to these URLs, so plain HTTP would leak them in transit. Loopback
+    /// URLs are exempt so that tasks can still be run locally for development.
+    pub fn check_urls(&self, task_id: &TaskId, require_https: bool) -> Result<(), DapAbort> {
+        if !require_https {
+            return Ok(());
+        }
+
+        for (role, url) in [("Leader", &self.leader_url), ("Helper", &self.helper_url)] {
+            let is_loopback = match url.host() {
+                Some(url::Host::Domain(domain)) => domain == "localhost",
+                Some(url::Host::Ipv4(addr)) => addr.is_loopback(),
+                Some(url::Host::Ipv6(addr)) => addr.is_loopback(),
+                None => false,
+            };
+            if url.scheme() != "https" && !is_loopback {
+                return Err(DapAbort::InvalidTask {
+                    detail: format!("The {role} URL ({url}) does not use HTTPS."),
+                    task_id: *task_id,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// For fixed-size tasks with a bounded batch size, check that `min_batch_size` does not exceed
+    /// `max_batch_size`. Otherwise no batch could ever be collected.
+    pub fn check_batch_size_bounds(&self, task_id: &TaskId) -> Result<(), DapAbort> {
+        if let DapQueryConfig::FixedSize {
+            max_batch_size: Some(max_batch_size),
+        } = self.query
+        {
+            if self.min_batch_size > max_batch_size {
+                return Err(DapAbort::InvalidTask {
+                    detail: format!(
+                        "The minimum batch size ({}) exceeds the maximum batch size ({max_batch_size}).",
+                        self.min_batch_size
+                    ),
+                    task_id: *task_id,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether a report with the given timestamp falls within the task's validity window,
+    /// i.e., at or after `not_before` and before `not_after`.
+    pub fn report_time_is_valid(&self, time: Time) -> bool {
+        self.not_before <= time && time < self.not_after
+    }
+
+    /// Check whether the report timestamp has been rounded down to a multiple of the
+    /// `time_precision`, as is required of Clients.
+    pub fn report_time_is_rounded(&self, time: Time) -> bool {
+        time % self.time_precision == 0
+    }
+
+    /// Compute a stable identifier for the batch of a time-interval task determined by
+    /// `interval`. The ID is the SHA-256 hash of the task ID followed by the encoded interval, so
+    /// it can be used as a key for bookkeeping of time-interval collections.
+    ///
+    /// Note that overlapping intervals get unrelated IDs: bookkeeping that must account for
+    /// overlap, like enforcing `max_batch_query_count`, should use [`Self::batch_span_for_sel`].
+    ///
+    /// Returns an error if the task is not a time-interval task.
+    pub fn time_interval_batch_id(
+        &self,
+        task_id: &TaskId,
+        interval: &Interval,
+    ) -> Result<BatchId, DapError> {
+        if !matches!(self.query, DapQueryConfig::TimeInterval) {
+            return Err(fatal_error!(
+                err = "time-interval batch ID requested for a fixed-size task"
+            ));
+        }
+
+        let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+        ctx.update(&task_id.0);
+        ctx.update(&interval.start.to_be_bytes());
+        ctx.update(&interval.duration.to_be_bytes());
+        Ok(BatchId(ctx.finish().as_ref().try_into().unwrap()))
+    }
+
     /// Return the batch span determined by the given batch selector. The span includes every
     /// bucket to which a report that matches the batch selector could be assigned.
     pub fn batch_span_for_sel(
@@ -791,10 +1041,12 @@ impl DapTaskConfig {
     }
 
     /// Check if the batch size is too small. Returns an error if the report count is too large.
+    /// If `allow_partial_batch` is set, then any non-empty batch is considered large enough.
     pub(crate) fn is_report_count_compatible(
         &self,
         task_id: &TaskId,
         report_count: u64,
+        allow_partial_batch: bool,
     ) -> Result<bool, DapAbort> {
         match self.query {
             DapQueryConfig::FixedSize {
@@ -815,7 +1067,7 @@ impl DapTaskConfig {
             } => (),
         };
 
-        Ok(report_count >= self.min_batch_size)
+        Ok(report_count >= self.min_batch_size || (allow_partial_batch && report_count > 0))
     }
 
     /// Leader: Resolve taskprov advertisement to send in a request to the Helper.
@@ -842,6 +1094,29 @@ impl DapTaskConfig {
     pub fn method_is_taskprov(&self) -> bool {
         matches!(self.method, DapTaskConfigMethod::Taskprov { .. })
     }
+
+    /// A one-line, human-readable summary of the task parameters for operators. Secrets, i.e.,
+    /// the VDAF verification keys, are left out.
+    pub fn summary(&self) -> String {
+        let max_batch_size = match self.query {
+            DapQueryConfig::FixedSize {
+                max_batch_size: Some(max_batch_size),
+            } => max_batch_size.to_string(),
+            DapQueryConfig::TimeInterval | DapQueryConfig::FixedSize { .. } => "none".into(),
+        };
+        format!(
+            "version={} vdaf={} query={} min_batch_size={} max_batch_size={max_batch_size} \
+            time_precision={} not_before={} not_after={} taskprov={}",
+            self.version,
+            self.vdaf,
+            self.query,
+            self.min_batch_size,
+            self.time_precision,
+            self.not_before,
+            self.not_after,
+            self.method_is_taskprov(),
+        )
+    }
 }
 
 impl AsRef<DapTaskConfig> for DapTaskConfig {
@@ -852,7 +1127,7 @@ impl AsRef<DapTaskConfig> for DapTaskConfig {
 
 /// A measurement from which a Client generates a report.
 #[derive(Clone, Deserialize, Serialize)]
-#[cfg_attr(any(test, feature = "test-utils"), derive(Debug))]
+#[cfg_attr(any(test, feature = "test-utils"), derive(Debug, PartialEq))]
 #[serde(rename_all = "snake_case")]
 pub enum DapMeasurement {
     U64(u64),
@@ -911,8 +1186,43 @@ impl ParameterizedDecode<VdafConfig> for DapAggregationParam {
     }
 }
 
+/// Serialized form of [`DapAggregationParam`]. Aggregation parameters that carry data are
+/// represented by their encoding.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum SerializedDapAggregationParam {
+    Empty,
+    #[cfg(feature = "experimental")]
+    Mastic(#[serde(with = "hex")] Vec<u8>),
+}
+
+impl Serialize for DapAggregationParam {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Empty => SerializedDapAggregationParam::Empty,
+            #[cfg(feature = "experimental")]
+            Self::Mastic(agg_param) => SerializedDapAggregationParam::Mastic(
+                agg_param.get_encoded().map_err(serde::ser::Error::custom)?,
+            ),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DapAggregationParam {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match SerializedDapAggregationParam::deserialize(deserializer)? {
+            SerializedDapAggregationParam::Empty => Ok(Self::Empty),
+            #[cfg(feature = "experimental")]
+            SerializedDapAggregationParam::Mastic(bytes) => Ok(Self::Mastic(
+                Poplar1AggregationParam::get_decoded(&bytes).map_err(serde::de::Error::custom)?,
+            )),
+        }
+    }
+}
+
 /// The aggregate result computed by the Collector.
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DapAggregateResult {
     U32Vec(Vec<u32>),
@@ -923,6 +1233,54 @@ pub enum DapAggregateResult {
     F64Vec(Vec<f64>),
 }
 
+impl DapAggregateResult {
+    /// Compute the element-wise difference `self - other`, e.g., to compare the results of
+    /// aggregating the same batch twice. A scalar result is treated as a vector of length one.
+    /// Returns an error if the results are of different types or lengths, or if they are
+    /// floating-point vectors.
+    pub fn diff(&self, other: &Self) -> Result<Vec<i128>, DapError> {
+        fn diff_elements<T: Copy + Into<u128>>(
+            left: &[T],
+            right: &[T],
+        ) -> Result<Vec<i128>, DapError> {
+            if left.len() != right.len() {
+                return Err(fatal_error!(
+                    err = format!(
+                        "aggregate result length mismatch: {} != {}",
+                        left.len(),
+                        right.len()
+                    )
+                ));
+            }
+
+            left.iter()
+                .zip(right)
+                .map(|(&left, &right)| {
+                    let (left, right) = (left.into(), right.into());
+                    let delta = if left >= right {
+                        i128::try_from(left - right)
+                    } else {
+                        i128::try_from(right - left).map(|delta| -delta)
+                    };
+                    delta.map_err(|_| fatal_error!(err = "aggregate result difference overflows"))
+                })
+                .collect()
+        }
+
+        match (self, other) {
+            (Self::U32Vec(left), Self::U32Vec(right)) => diff_elements(left, right),
+            (Self::U64(left), Self::U64(right)) => diff_elements(&[*left], &[*right]),
+            (Self::U64Vec(left), Self::U64Vec(right)) => diff_elements(left, right),
+            (Self::U128(left), Self::U128(right)) => diff_elements(&[*left], &[*right]),
+            (Self::U128Vec(left), Self::U128Vec(right)) => diff_elements(left, right),
+            (Self::F64Vec(..), Self::F64Vec(..)) => Err(fatal_error!(
+                err = "cannot compute the difference of floating-point aggregate results"
+            )),
+            _ => Err(fatal_error!(err = "aggregate result type mismatch")),
+        }
+    }
+}
+
 #[derive(Clone)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(Debug, deepsize::DeepSizeOf))]
 pub(crate) struct AggregationJobReportState {
@@ -1008,6 +1366,36 @@ impl DapAggregateShare {
         Ok(())
     }
 
+    /// Best-effort check of the invariants relating the report count to the rest of the
+    /// aggregate share. An empty share must have a zero checksum, no time range and no data; a
+    /// non-empty share must have data, a non-zero checksum and a well-formed time range. (The
+    /// checksum of a non-empty batch is zero only with negligible probability.)
+    ///
+    /// This catches storage corruption that zeroes the count or the checksum, but not a count
+    /// that is merely wrong: neither the checksum nor the aggregate encodes the number of
+    /// reports, so a share claiming 9 reports where 10 were aggregated passes. The report count
+    /// is ultimately checked against the Leader's by comparing checksums.
+    pub fn check_invariants(&self) -> Result<(), DapError> {
+        let zero_checksum = self.checksum == [0; 32];
+        let consistent = if self.report_count == 0 {
+            zero_checksum && self.min_time == 0 && self.max_time == 0 && self.data.is_none()
+        } else {
+            !zero_checksum && self.min_time <= self.max_time && self.data.is_some()
+        };
+        if consistent {
+            Ok(())
+        } else {
+            Err(fatal_error!(
+                err = "aggregate share is inconsistent",
+                report_count = self.report_count,
+                checksum = hex::encode(self.checksum),
+                min_time = self.min_time,
+                max_time = self.max_time,
+                has_data = self.data.is_some(),
+            ))
+        }
+    }
+
     /// Return `true` if the aggregate share contains no reports.
     pub fn empty(&self) -> bool {
         self.report_count == 0
@@ -1157,6 +1545,9 @@ pub struct DapResponse {
 pub enum DapCollectionJob {
     Done(Collection),
     Pending,
+    /// The collection job could not be completed. The string is a diagnostic for the Collector:
+    /// internal error details are logged rather than included.
+    Failed(String),
     Unknown,
 }
 
@@ -1174,3 +1565,351 @@ pub struct DapLeaderProcessTelemetry {
     /// The number of reports processed.
     pub reports_processed: u64,
 }
+
+#[cfg(test)]
+mod test {
+    use prio::field::Field64;
+
+    use crate::{
+        error::DapAbort,
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{Interval, ReportId, TaskId},
+        vdaf::{Prio3Config, VdafAggregateShare, VdafConfig},
+        DapAggregateResult, DapAggregateShare, DapError, DapQueryConfig, DapTaskConfig,
+        DapTaskParameters, DapVersion,
+    };
+
+    #[test]
+    fn parse_version() {
+        for version in [DapVersion::Draft09, DapVersion::Latest] {
+            assert_eq!(version.as_ref().parse::<DapVersion>(), Ok(version));
+        }
+        assert_eq!("v09".parse::<DapVersion>(), Ok(DapVersion::Draft09));
+        assert_eq!("v10".parse::<DapVersion>(), Ok(DapVersion::Latest));
+    }
+
+    #[test]
+    fn parse_version_unknown() {
+        let DapAbort::BadRequest(detail) = "draft02".parse::<DapVersion>().unwrap_err() else {
+            panic!("unexpected abort");
+        };
+        assert!(detail.contains("\"draft02\""), "{detail}");
+    }
+
+    #[test]
+    fn agg_share_check_invariants() {
+        let mut agg_share = DapAggregateShare::default();
+        agg_share.check_invariants().unwrap();
+
+        agg_share
+            .add_out_share(
+                &ReportId([1; 16]),
+                1_000,
+                VdafAggregateShare::Field64(prio::vdaf::AggregateShare::from(vec![Field64::from(
+                    1,
+                )])),
+            )
+            .unwrap();
+        agg_share
+            .add_out_share(
+                &ReportId([2; 16]),
+                2_000,
+                VdafAggregateShare::Field64(prio::vdaf::AggregateShare::from(vec![Field64::from(
+                    1,
+                )])),
+            )
+            .unwrap();
+        agg_share.check_invariants().unwrap();
+
+        // The count claims no reports were aggregated, but the checksum says otherwise.
+        let mut corrupted = agg_share.clone();
+        corrupted.report_count = 0;
+        assert!(matches!(
+            corrupted.check_invariants(),
+            Err(DapError::Fatal(..))
+        ));
+
+        // The count claims reports were aggregated, but the checksum is empty.
+        let mut corrupted = agg_share;
+        corrupted.checksum = [0; 32];
+        assert!(matches!(
+            corrupted.check_invariants(),
+            Err(DapError::Fatal(..))
+        ));
+    }
+
+    #[test]
+    fn task_config_summary() {
+        let collector_hpke_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let (task_config, _task_id, _taskprov_advertisement) = DapTaskParameters {
+            min_batch_size: 10,
+            query: DapQueryConfig::FixedSize {
+                max_batch_size: Some(20),
+            },
+            vdaf: VdafConfig::Prio2 { dimension: 10 },
+            ..Default::default()
+        }
+        .to_config_with_taskprov(b"cool task".to_vec(), 0, &[1; 32], &collector_hpke_config)
+        .unwrap();
+
+        let summary = task_config.summary();
+        assert_eq!(
+            summary,
+            format!(
+                "version={} vdaf=Prio2(10) query=fixed_size min_batch_size=10 max_batch_size=20 \
+                time_precision=3600 not_before=0 not_after={} taskprov=true",
+                task_config.version, task_config.not_after
+            )
+        );
+        assert!(!summary.contains(&hex::encode(task_config.vdaf_verify_key.as_ref())));
+    }
+
+    #[test]
+    fn task_config_sum_vec_client_element_max() {
+        let collector_hpke_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let (mut task_config, _task_id, _taskprov_advertisement) = DapTaskParameters::default()
+            .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+            .unwrap();
+        let sum_vec = |client_element_max| {
+            VdafConfig::Prio3(Prio3Config::SumVec {
+                bits: 3,
+                length: 2,
+                chunk_length: 1,
+                client_element_max: Some(client_element_max),
+            })
+        };
+
+        task_config.vdaf = sum_vec(7);
+        let json = serde_json::to_string(&task_config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<DapTaskConfig>(&json).unwrap(),
+            task_config
+        );
+
+        // The bound doesn't fit in `bits`.
+        task_config.vdaf = sum_vec(8);
+        let json = serde_json::to_string(&task_config).unwrap();
+        assert!(serde_json::from_str::<DapTaskConfig>(&json).is_err());
+    }
+
+    #[test]
+    fn aggregate_result_diff() {
+        let histogram = DapAggregateResult::U128Vec(vec![10, 20, 30]);
+        let rerun = DapAggregateResult::U128Vec(vec![12, 20, 25]);
+        assert_eq!(rerun.diff(&histogram).unwrap(), vec![2, 0, -5]);
+        assert_eq!(histogram.diff(&rerun).unwrap(), vec![-2, 0, 5]);
+        assert_eq!(histogram.diff(&histogram).unwrap(), vec![0, 0, 0]);
+
+        // Sum vs. Histogram
+        assert!(matches!(
+            DapAggregateResult::U128(60).diff(&histogram),
+            Err(DapError::Fatal(..))
+        ));
+
+        // Histograms with different numbers of buckets
+        assert!(matches!(
+            DapAggregateResult::U128Vec(vec![10, 20]).diff(&histogram),
+            Err(DapError::Fatal(..))
+        ));
+    }
+
+    #[test]
+    fn merge_aggregate_results() {
+        let histogram = VdafConfig::Prio3(Prio3Config::Histogram {
+            length: 3,
+            chunk_length: 1,
+        });
+        assert_eq!(
+            histogram
+                .merge_aggregate_results(&[
+                    DapAggregateResult::U128Vec(vec![10, 20, 30]),
+                    DapAggregateResult::U128Vec(vec![1, 0, 2]),
+                ])
+                .unwrap(),
+            DapAggregateResult::U128Vec(vec![11, 20, 32])
+        );
+
+        // Histogram and Count results
+        assert!(matches!(
+            histogram.merge_aggregate_results(&[
+                DapAggregateResult::U128Vec(vec![10, 20, 30]),
+                DapAggregateResult::U64(7),
+            ]),
+            Err(DapError::Fatal(..))
+        ));
+
+        // Histograms with a different number of buckets than the VDAF
+        assert!(matches!(
+            histogram.merge_aggregate_results(&[
+                DapAggregateResult::U128Vec(vec![10, 20, 30]),
+                DapAggregateResult::U128Vec(vec![10, 20]),
+            ]),
+            Err(DapError::Fatal(..))
+        ));
+
+        let count = VdafConfig::Prio3(Prio3Config::Count);
+        assert_eq!(
+            count
+                .merge_aggregate_results(&[
+                    DapAggregateResult::U64(3),
+                    DapAggregateResult::U64(4),
+                    DapAggregateResult::U64(5),
+                ])
+                .unwrap(),
+            DapAggregateResult::U64(12)
+        );
+        assert!(matches!(
+            count.merge_aggregate_results(&[]),
+            Err(DapError::Fatal(..))
+        ));
+        assert!(matches!(
+            count.merge_aggregate_results(&[
+                DapAggregateResult::U64(u64::MAX),
+                DapAggregateResult::U64(1),
+            ]),
+            Err(DapError::Fatal(..))
+        ));
+    }
+
+    #[test]
+    fn check_urls_require_https() {
+        let collector_hpke_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let (task_config, task_id, _taskprov_advertisement) = DapTaskParameters {
+            leader_url: "http://leader.example.com/".parse().unwrap(),
+            ..Default::default()
+        }
+        .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+        .unwrap();
+
+        assert!(matches!(
+            task_config.check_urls(&task_id, true),
+            Err(DapAbort::InvalidTask { .. })
+        ));
+        assert_eq!(task_config.check_urls(&task_id, false), Ok(()));
+
+        // Loopback URLs are exempt.
+        for leader_url in ["http://localhost:8787/", "http://127.0.0.1:8787/"] {
+            let (task_config, task_id, _taskprov_advertisement) = DapTaskParameters {
+                leader_url: leader_url.parse().unwrap(),
+                ..Default::default()
+            }
+            .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+            .unwrap();
+            assert_eq!(task_config.check_urls(&task_id, true), Ok(()));
+        }
+
+        let (task_config, task_id, _taskprov_advertisement) = DapTaskParameters::default()
+            .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+            .unwrap();
+        assert_eq!(task_config.check_urls(&task_id, true), Ok(()));
+    }
+
+    #[test]
+    fn time_interval_batch_id() {
+        let collector_hpke_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let (mut task_config, task_id, _taskprov_advertisement) = DapTaskParameters::default()
+            .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+            .unwrap();
+        let interval = Interval {
+            start: 1_700_000_000,
+            duration: 3600,
+        };
+        let batch_id = task_config
+            .time_interval_batch_id(&task_id, &interval)
+            .unwrap();
+
+        assert_eq!(
+            task_config
+                .time_interval_batch_id(&task_id, &interval)
+                .unwrap(),
+            batch_id
+        );
+        for other in [
+            Interval {
+                start: interval.start + 3600,
+                ..interval
+            },
+            Interval {
+                duration: 7200,
+                ..interval
+            },
+        ] {
+            assert_ne!(
+                task_config
+                    .time_interval_batch_id(&task_id, &other)
+                    .unwrap(),
+                batch_id
+            );
+        }
+        assert_ne!(
+            task_config
+                .time_interval_batch_id(&TaskId([1; 32]), &interval)
+                .unwrap(),
+            batch_id
+        );
+
+        task_config.query = DapQueryConfig::FixedSize {
+            max_batch_size: None,
+        };
+        assert_matches::assert_matches!(
+            task_config.time_interval_batch_id(&task_id, &interval),
+            Err(DapError::Fatal(..))
+        );
+    }
+
+    #[test]
+    fn check_batch_size_bounds() {
+        let collector_hpke_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let task_config = |min_batch_size| {
+            DapTaskParameters {
+                min_batch_size,
+                query: DapQueryConfig::FixedSize {
+                    max_batch_size: Some(10),
+                },
+                ..Default::default()
+            }
+            .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+            .unwrap()
+        };
+
+        let (task_config_min_too_large, task_id, _taskprov_advertisement) = task_config(20);
+        assert!(matches!(
+            task_config_min_too_large.check_batch_size_bounds(&task_id),
+            Err(DapAbort::InvalidTask { .. })
+        ));
+
+        let (task_config, task_id, _taskprov_advertisement) = task_config(5);
+        assert_eq!(task_config.check_batch_size_bounds(&task_id), Ok(()));
+    }
+
+    #[test]
+    fn batch_bucket_start() {
+        let collector_hpke_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let (task_config, _task_id, _taskprov_advertisement) = DapTaskParameters {
+            time_precision: 3600,
+            ..Default::default()
+        }
+        .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+        .unwrap();
+
+        // A time on a bucket boundary starts its own bucket.
+        assert_eq!(task_config.batch_bucket_start(7200), 7200);
+        assert_eq!(task_config.batch_bucket_start(7199), 3600);
+
+        // A time mid-bucket is rounded down to the start of the bucket.
+        assert_eq!(task_config.batch_bucket_start(7200 + 1800), 7200);
+        assert_eq!(task_config.batch_bucket_start(0), 0);
+    }
+}
@@ -6,8 +6,9 @@
 pub mod taskprov;
 
 use crate::{
+    error::DapAbort,
     hpke::{HpkeAeadId, HpkeConfig, HpkeKdfId, HpkeKemId},
-    DapVersion,
+    DapQueryConfig, DapTaskConfig, DapVersion,
 };
 use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine};
 use hpke_rs::HpkePublicKey;
@@ -32,6 +33,49 @@ const FIXED_SIZE_QUERY_TYPE_CURRENT_BATCH: u8 = 0x01;
 
 // Known extension types.
 const EXTENSION_TASKPROV: u16 = 0xff00;
+// Private-use codepoint, not registered with IANA. Only peers that run this implementation
+// understand the extension.
+const EXTENSION_VERIFY_KEY_EPOCH: u16 = 0xff01;
+
+/// How to decode a query type this implementation does not recognize in a taskprov
+/// [`QueryConfig`](taskprov::QueryConfig).
+///
+/// The query type of a [`Query`], [`BatchSelector`], or [`PartialBatchSelector`] is always decoded
+/// strictly: these are not length-prefixed in DAP messages, so the parameters of an unrecognized
+/// query type can't be skipped.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryTypeStrictness {
+    /// Fail to decode the message.
+    Strict,
+
+    /// Decode the query type as `NotImplemented` and keep its parameters as opaque bytes, so that
+    /// the message can be handled (or rejected) by the caller. This requires knowing the length of
+    /// the encoded value.
+    #[default]
+    Lenient,
+}
+
+/// Decode the parameters of a query type this implementation does not recognize. `len` is the
+/// number of bytes left in the encoded value, if known.
+fn decode_unrecognized_query_type_param(
+    strictness: QueryTypeStrictness,
+    len: Option<usize>,
+    bytes: &mut Cursor<&[u8]>,
+) -> Result<Vec<u8>, CodecError> {
+    match (strictness, len) {
+        (QueryTypeStrictness::Strict, _) => Err(CodecError::UnexpectedValue),
+        (QueryTypeStrictness::Lenient, Some(len)) => {
+            let mut param = vec![0; len];
+            bytes.read_exact(&mut param)?;
+            Ok(param)
+        }
+        (QueryTypeStrictness::Lenient, None) => Err(CodecError::Other(
+            "cannot decode an unrecognized query type without knowing the length of the remainder"
+                .into(),
+        )),
+    }
+}
 
 pub trait Base64Encode {
     /// Encode to URL-safe base64.
@@ -51,6 +95,7 @@ macro_rules! id_struct {
             Copy, Clone, Default, Deserialize, Hash, PartialEq, Eq, Serialize, PartialOrd, Ord,
         )]
         #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+        #[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
         pub struct $sname(#[serde(with = "hex")] pub [u8; $len]);
 
         impl $sname {
@@ -208,7 +253,16 @@ pub type Time = u64;
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
 pub enum Extension {
     Taskprov,
-    NotImplemented { typ: u16, payload: Vec<u8> },
+    /// The epoch of the VDAF verification key the report is to be aggregated with. See
+    /// [`DapTaskConfig::vdaf_verify_keys_by_epoch`](crate::DapTaskConfig). This is a private-use
+    /// extension (codepoint `0xff01`).
+    VerifyKeyEpoch {
+        epoch: u8,
+    },
+    NotImplemented {
+        typ: u16,
+        payload: Vec<u8>,
+    },
 }
 
 impl Extension {
@@ -216,6 +270,7 @@ impl Extension {
     pub(crate) fn type_code(&self) -> u16 {
         match self {
             Self::Taskprov { .. } => EXTENSION_TASKPROV,
+            Self::VerifyKeyEpoch { .. } => EXTENSION_VERIFY_KEY_EPOCH,
             Self::NotImplemented { typ, .. } => *typ,
         }
     }
@@ -232,6 +287,10 @@ impl ParameterizedEncode<DapVersion> for Extension {
                 EXTENSION_TASKPROV.encode(bytes)?;
                 encode_u16_prefixed(*version, bytes, |_, _| Ok(()))?;
             }
+            Self::VerifyKeyEpoch { epoch } => {
+                EXTENSION_VERIFY_KEY_EPOCH.encode(bytes)?;
+                encode_u16_prefixed(*version, bytes, |_, inner| epoch.encode(inner))?;
+            }
             Self::NotImplemented { typ, payload } => {
                 typ.encode(bytes)?;
                 encode_u16_bytes(bytes, payload)?;
@@ -252,6 +311,12 @@ impl ParameterizedDecode<DapVersion> for Extension {
                 decode_u16_prefixed(*version, bytes, |_version, inner, _len| <()>::decode(inner))?;
                 Ok(Self::Taskprov)
             }
+            EXTENSION_VERIFY_KEY_EPOCH => {
+                let epoch = decode_u16_prefixed(*version, bytes, |_version, inner, _len| {
+                    u8::decode(inner)
+                })?;
+                Ok(Self::VerifyKeyEpoch { epoch })
+            }
             _ => Ok(Self::NotImplemented {
                 typ,
                 payload: decode_u16_bytes(bytes)?,
@@ -264,6 +329,7 @@ impl ParameterizedDecode<DapVersion> for Extension {
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[allow(missing_docs)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub struct ReportMetadata {
     pub id: ReportId,
     pub time: Time,
@@ -298,6 +364,7 @@ impl ParameterizedDecode<DapVersion> for ReportMetadata {
 /// A report generated by a client.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub struct Report {
     pub report_metadata: ReportMetadata,
     pub public_share: Vec<u8>,
@@ -338,6 +405,7 @@ impl ParameterizedDecode<DapVersion> for Report {
 /// structure pertain to a single report.
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub struct ReportShare {
     pub report_metadata: ReportMetadata,
     pub public_share: Vec<u8>,
@@ -375,6 +443,7 @@ impl ParameterizedDecode<DapVersion> for ReportShare {
 #[derive(Clone, Debug, Eq, Deserialize, Hash, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub enum PartialBatchSelector {
     TimeInterval,
     FixedSizeByBatchId { batch_id: BatchId },
@@ -484,6 +553,7 @@ impl Default for BatchSelector {
 /// The `PrepareInit` message consisting of the report share and the Leader's initial prep share.
 #[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub struct PrepareInit {
     pub report_share: ReportShare,
     pub payload: Vec<u8>,
@@ -518,6 +588,7 @@ impl ParameterizedDecode<DapVersion> for PrepareInit {
 
 /// Aggregate initialization request.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub struct AggregationJobInitReq {
     pub agg_param: Vec<u8>,
     pub part_batch_sel: PartialBatchSelector,
@@ -683,6 +754,16 @@ pub struct AggregationJobResp {
     pub transitions: Vec<Transition>,
 }
 
+impl AggregationJobResp {
+    /// Return the number of reports that the Helper continued, i.e., that were not rejected.
+    pub fn continued_count(&self) -> usize {
+        self.transitions
+            .iter()
+            .filter(|t| matches!(t.var, TransitionVar::Continued(..)))
+            .count()
+    }
+}
+
 impl Encode for AggregationJobResp {
     fn encode(&self, bytes: &mut Vec<u8>) -> Result<(), CodecError> {
         encode_u32_items(bytes, &(), &self.transitions)
@@ -700,6 +781,7 @@ impl Decode for AggregationJobResp {
 /// A batch interval.
 #[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub struct Interval {
     pub start: Time,
     pub duration: Duration,
@@ -710,6 +792,37 @@ impl Interval {
     pub fn end(&self) -> Time {
         self.start + self.duration
     }
+
+    /// Validate the interval against the task's time precision and return its canonical form.
+    ///
+    /// The start and duration of the interval must both be multiples of the time precision and
+    /// the duration must not be zero. An interval that is not aligned this way is rejected rather
+    /// than rounded, as rounding would make the batch cover reports the Collector didn't ask for.
+    pub fn normalize_for(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+    ) -> Result<Interval, DapAbort> {
+        if self.start % task_config.time_precision != 0
+            || self.duration % task_config.time_precision != 0
+            || self.duration == 0
+            || self.start.checked_add(self.duration).is_none()
+        {
+            return Err(DapAbort::BatchInvalid {
+                detail: format!(
+                    "The queried batch interval ({self:?}) is empty or its boundaries are \
+                    misaligned. The time precision for this task is {}s.",
+                    task_config.time_precision
+                ),
+                task_id: *task_id,
+            });
+        }
+
+        Ok(Interval {
+            start: self.start,
+            duration: self.duration,
+        })
+    }
 }
 
 impl Encode for Interval {
@@ -733,6 +846,7 @@ impl Decode for Interval {
 #[derive(Clone, Copy, Debug, Deserialize, Hash, PartialEq, Eq, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+#[cfg_attr(any(test, feature = "arbitrary"), derive(arbitrary::Arbitrary))]
 pub enum Query {
     TimeInterval { batch_interval: Interval },
     FixedSizeByBatchId { batch_id: BatchId },
@@ -740,6 +854,52 @@ pub enum Query {
 }
 
 impl Query {
+    /// Check that the query is of the type configured for the task, i.e., a time-interval query
+    /// for a time-interval task and a fixed-size query for a fixed-size task.
+    pub fn matches_task_config(&self, task_config: &DapTaskConfig) -> bool {
+        matches!(
+            (&task_config.query, self),
+            (DapQueryConfig::TimeInterval, Self::TimeInterval { .. })
+                | (
+                    DapQueryConfig::FixedSize { .. },
+                    Self::FixedSizeByBatchId { .. } | Self::FixedSizeCurrentBatch
+                )
+        )
+    }
+
+    /// The batch interval of a time-interval query, or `None` for any other query type.
+    pub fn as_interval(&self) -> Option<&Interval> {
+        match self {
+            Self::TimeInterval { batch_interval } => Some(batch_interval),
+            Self::FixedSizeByBatchId { .. } | Self::FixedSizeCurrentBatch => None,
+        }
+    }
+
+    /// Build a time-interval query covering several adjacent intervals, e.g., consecutive windows
+    /// of a fixed analysis period. DAP has no query type for a list of intervals, but the union
+    /// of adjacent intervals is itself an interval: collecting it aggregates across all of them in
+    /// a single collection. The intervals may be listed in any order.
+    ///
+    /// Returns `None` if the list is empty or if the intervals overlap or leave a gap.
+    pub fn time_interval_list(intervals: &[Interval]) -> Option<Self> {
+        let mut intervals = intervals.to_vec();
+        intervals.sort_unstable_by_key(|interval| interval.start);
+        let (first, rest) = intervals.split_first()?;
+        let mut end = first.start.checked_add(first.duration)?;
+        for interval in rest {
+            if interval.start != end {
+                return None;
+            }
+            end = interval.start.checked_add(interval.duration)?;
+        }
+        Some(Self::TimeInterval {
+            batch_interval: Interval {
+                start: first.start,
+                duration: end - first.start,
+            },
+        })
+    }
+
     pub(crate) fn into_batch_sel(self) -> Option<BatchSelector> {
         match self {
             Self::TimeInterval { batch_interval } => {
@@ -950,6 +1110,15 @@ impl ParameterizedDecode<DapVersion> for AggregateShareReq {
             report_count: u64::decode(bytes)?,
             checksum: {
                 let mut checksum = [0u8; 32];
+                let remaining = usize::try_from(bytes.position())
+                    .ok()
+                    .and_then(|pos| bytes.get_ref().len().checked_sub(pos))
+                    .unwrap_or_default();
+                if remaining < checksum.len() {
+                    return Err(CodecError::Other(Box::new(TruncatedChecksumError {
+                        len: remaining,
+                    })));
+                }
                 bytes.read_exact(&mut checksum[..])?;
                 checksum
             },
@@ -957,6 +1126,15 @@ impl ParameterizedDecode<DapVersion> for AggregateShareReq {
     }
 }
 
+/// The body of an [`AggregateShareReq`] ends before the end of its 32-byte checksum. This is
+/// returned, wrapped in [`CodecError::Other`], instead of a generic I/O error.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("aggregate share request is truncated: expected a 32-byte checksum, found {len} bytes")]
+pub struct TruncatedChecksumError {
+    /// The number of checksum bytes that are present.
+    pub len: usize,
+}
+
 /// An aggregate-share response.
 #[derive(Debug)]
 pub struct AggregateShare {
@@ -1068,6 +1246,19 @@ pub struct HpkeCiphertext {
     pub payload: Vec<u8>,
 }
 
+// The encapsulated key is prefixed with a 16-bit length, so it can't be derived.
+#[cfg(any(test, feature = "arbitrary"))]
+impl<'a> arbitrary::Arbitrary<'a> for HpkeCiphertext {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let enc_len = u.arbitrary_len::<u8>()?.min(usize::from(u16::MAX));
+        Ok(Self {
+            config_id: u.arbitrary()?,
+            enc: u.bytes(enc_len)?.to_vec(),
+            payload: u.arbitrary()?,
+        })
+    }
+}
+
 impl Encode for HpkeCiphertext {
     fn encode(&self, bytes: &mut Vec<u8>) -> Result<(), CodecError> {
         self.config_id.encode(bytes)?;
@@ -1184,7 +1375,39 @@ pub fn decode_base64url<T: AsRef<[u8]>, const OUT_LEN: usize>(input: T) -> Optio
 
 /// Decode the input as a URL-safe, base64 encoding of a byte string of any length.
 pub fn decode_base64url_vec<T: AsRef<[u8]>>(input: T) -> Option<Vec<u8>> {
-    URL_SAFE_NO_PAD.decode(input).ok()
+    decode_base64url_vec_detailed(input).ok()
+}
+
+/// Reason why the input to [`decode_base64url_vec_detailed`] could not be decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Base64DecodeError {
+    /// The input is padded. URL-safe, base64 input is expected to be unpadded.
+    #[error("unexpected padding")]
+    InvalidPadding,
+
+    /// The input contains a character that is not in the URL-safe, base64 alphabet, or the final
+    /// character encodes trailing bits that are not zero.
+    #[error("invalid character {:?} at offset {offset}", char::from(*byte))]
+    InvalidCharacter { offset: usize, byte: u8 },
+
+    /// The length of the input is not the length of any valid encoding.
+    #[error("invalid length")]
+    InvalidLength,
+}
+
+/// Decode the input as a URL-safe, base64 encoding of a byte string of any length. Unlike
+/// [`decode_base64url_vec`], this function reports why decoding failed.
+pub fn decode_base64url_vec_detailed<T: AsRef<[u8]>>(
+    input: T,
+) -> Result<Vec<u8>, Base64DecodeError> {
+    URL_SAFE_NO_PAD.decode(input).map_err(|e| match e {
+        base64::DecodeError::InvalidPadding => Base64DecodeError::InvalidPadding,
+        base64::DecodeError::InvalidByte(offset, byte)
+        | base64::DecodeError::InvalidLastSymbol(offset, byte) => {
+            Base64DecodeError::InvalidCharacter { offset, byte }
+        }
+        base64::DecodeError::InvalidLength => Base64DecodeError::InvalidLength,
+    })
 }
 
 macro_rules! make_encode_len_prefixed {
@@ -1250,6 +1473,174 @@ mod test {
     use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
     use rand::prelude::*;
 
+    fn normalize_for_task_config() -> (TaskId, DapTaskConfig) {
+        let collector_hpke_config =
+            crate::hpke::HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+                .unwrap()
+                .config;
+        let (task_config, task_id, _taskprov_advertisement) = crate::DapTaskParameters::default()
+            .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+            .unwrap();
+        (task_id, task_config)
+    }
+
+    #[test]
+    fn query_matches_task_config() {
+        let (_task_id, mut task_config) = normalize_for_task_config();
+        let time_interval = Query::TimeInterval {
+            batch_interval: Interval {
+                start: 0,
+                duration: task_config.time_precision,
+            },
+        };
+        let fixed_size_by_batch_id = Query::FixedSizeByBatchId {
+            batch_id: BatchId([1; 32]),
+        };
+
+        task_config.query = DapQueryConfig::TimeInterval;
+        assert!(time_interval.matches_task_config(&task_config));
+        assert!(!fixed_size_by_batch_id.matches_task_config(&task_config));
+        assert!(!Query::FixedSizeCurrentBatch.matches_task_config(&task_config));
+
+        task_config.query = DapQueryConfig::FixedSize {
+            max_batch_size: None,
+        };
+        assert!(!time_interval.matches_task_config(&task_config));
+        assert!(fixed_size_by_batch_id.matches_task_config(&task_config));
+        assert!(Query::FixedSizeCurrentBatch.matches_task_config(&task_config));
+    }
+
+    #[test]
+    fn query_as_interval() {
+        let batch_interval = Interval {
+            start: 1_637_359_200,
+            duration: 3600,
+        };
+        assert_eq!(
+            Query::TimeInterval { batch_interval }.as_interval(),
+            Some(&batch_interval)
+        );
+        assert_eq!(
+            Query::FixedSizeByBatchId {
+                batch_id: BatchId([1; 32]),
+            }
+            .as_interval(),
+            None
+        );
+        assert_eq!(Query::FixedSizeCurrentBatch.as_interval(), None);
+    }
+
+    #[test]
+    fn query_time_interval_list() {
+        let interval = |start, duration| Interval { start, duration };
+
+        // Adjacent intervals are merged, regardless of the order in which they are listed.
+        assert_eq!(
+            Query::time_interval_list(&[
+                interval(1_637_362_800, 3600),
+                interval(1_637_359_200, 3600),
+                interval(1_637_366_400, 7200),
+            ]),
+            Some(Query::TimeInterval {
+                batch_interval: interval(1_637_359_200, 14_400),
+            })
+        );
+
+        // Overlapping intervals are rejected.
+        assert_eq!(
+            Query::time_interval_list(&[
+                interval(1_637_359_200, 7200),
+                interval(1_637_362_800, 3600),
+            ]),
+            None
+        );
+
+        // So are intervals that leave a gap, since the gap would be collected too.
+        assert_eq!(
+            Query::time_interval_list(&[
+                interval(1_637_359_200, 3600),
+                interval(1_637_366_400, 3600),
+            ]),
+            None
+        );
+
+        assert_eq!(Query::time_interval_list(&[]), None);
+    }
+
+    #[test]
+    fn interval_normalize_for_aligned() {
+        let (task_id, task_config) = normalize_for_task_config();
+        let interval = Interval {
+            start: 1_000 * task_config.time_precision,
+            duration: 2 * task_config.time_precision,
+        };
+        assert_eq!(interval.normalize_for(&task_id, &task_config), Ok(interval));
+    }
+
+    #[test]
+    fn interval_normalize_for_misaligned() {
+        let (task_id, task_config) = normalize_for_task_config();
+        let start = 1_000 * task_config.time_precision;
+        let precision = task_config.time_precision;
+        for interval in [
+            Interval {
+                start: start + 1,
+                duration: precision,
+            },
+            Interval { start, duration: 0 },
+            Interval { start, duration: 1 },
+            Interval {
+                start,
+                duration: precision + 1,
+            },
+            Interval {
+                start,
+                duration: 2 * precision - 1,
+            },
+        ] {
+            assert!(matches!(
+                interval.normalize_for(&task_id, &task_config),
+                Err(DapAbort::BatchInvalid { task_id: t, .. }) if t == task_id
+            ));
+        }
+    }
+
+    #[test]
+    fn decode_base64url_vec_detailed_ok() {
+        assert_eq!(
+            decode_base64url_vec_detailed("aGVsbG8").unwrap(),
+            b"hello".to_vec()
+        );
+    }
+
+    #[test]
+    fn decode_base64url_vec_detailed_invalid_padding() {
+        assert_eq!(
+            decode_base64url_vec_detailed("aGVsbG8="),
+            Err(Base64DecodeError::InvalidPadding)
+        );
+    }
+
+    #[test]
+    fn decode_base64url_vec_detailed_invalid_character() {
+        // `+` is in the standard alphabet, but not the URL-safe one.
+        assert_eq!(
+            decode_base64url_vec_detailed("aGV+bG8"),
+            Err(Base64DecodeError::InvalidCharacter {
+                offset: 3,
+                byte: b'+'
+            })
+        );
+    }
+
+    #[test]
+    fn decode_base64url_vec_detailed_invalid_length() {
+        assert_eq!(
+            decode_base64url_vec_detailed("aGVsb"),
+            Err(Base64DecodeError::InvalidLength)
+        );
+    }
+
     fn read_report(version: DapVersion) {
         let report = Report {
             report_metadata: ReportMetadata {
@@ -1393,6 +1784,41 @@ mod test {
 
     test_versions! { roundtrip_agg_job_init_req }
 
+    fn roundtrip_random_messages(version: DapVersion) {
+        fn roundtrip<M>(version: DapVersion, want: &M)
+        where
+            M: ParameterizedEncode<DapVersion> + ParameterizedDecode<DapVersion>,
+            M: PartialEq + std::fmt::Debug,
+        {
+            let got = M::get_decoded_with_param(
+                &version,
+                &want.get_encoded_with_param(&version).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(&got, want);
+        }
+
+        let mut rng = thread_rng();
+        for _ in 0..100 {
+            let mut data = vec![0; rng.gen_range(0..4096)];
+            rng.fill(&mut data[..]);
+            let mut u = arbitrary::Unstructured::new(&data);
+
+            roundtrip::<ReportMetadata>(version, &u.arbitrary().unwrap());
+            roundtrip::<Report>(version, &u.arbitrary().unwrap());
+            roundtrip::<AggregationJobInitReq>(version, &u.arbitrary().unwrap());
+            roundtrip::<Query>(version, &u.arbitrary().unwrap());
+
+            let want = u.arbitrary::<HpkeCiphertext>().unwrap();
+            assert_eq!(
+                HpkeCiphertext::get_decoded(&want.get_encoded().unwrap()).unwrap(),
+                want
+            );
+        }
+    }
+
+    test_versions! { roundtrip_random_messages }
+
     #[test]
     fn read_agg_job_resp() {
         const TEST_DATA: &[u8] = &[
@@ -1448,6 +1874,76 @@ mod test {
         assert_eq!(got, want);
     }
 
+    fn truncated_checksum_len(err: &CodecError) -> Option<usize> {
+        match err {
+            CodecError::Other(e) => e
+                .downcast_ref::<TruncatedChecksumError>()
+                .map(|TruncatedChecksumError { len }| *len),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn read_agg_share_req_truncated_checksum() {
+        let agg_share_req = AggregateShareReq {
+            batch_sel: BatchSelector::TimeInterval {
+                batch_interval: Interval {
+                    start: 1_637_359_200,
+                    duration: 7200,
+                },
+            },
+            agg_param: Vec::new(),
+            report_count: 100,
+            checksum: [1; 32],
+        };
+        let encoded = agg_share_req
+            .get_encoded_with_param(&DapVersion::Draft09)
+            .unwrap();
+        let checksum_start = encoded.len() - 32;
+
+        // The body stops just before the checksum.
+        let err = AggregateShareReq::get_decoded_with_param(
+            &DapVersion::Draft09,
+            &encoded[..checksum_start],
+        )
+        .unwrap_err();
+        assert_eq!(truncated_checksum_len(&err), Some(0), "{err:?}");
+
+        // The body stops in the middle of the checksum.
+        let err = AggregateShareReq::get_decoded_with_param(
+            &DapVersion::Draft09,
+            &encoded[..encoded.len() - 1],
+        )
+        .unwrap_err();
+        assert_eq!(truncated_checksum_len(&err), Some(31), "{err:?}");
+
+        // Truncating an earlier field is reported as a different error.
+        let err = AggregateShareReq::get_decoded_with_param(
+            &DapVersion::Draft09,
+            &encoded[..checksum_start - 1],
+        )
+        .unwrap_err();
+        assert_eq!(truncated_checksum_len(&err), None, "{err:?}");
+    }
+
+    #[test]
+    fn decode_unknown_query_type() {
+        // An unrecognized query type codepoint followed by its parameters.
+        let encoded = [0xff, 1, 2, 3];
+        assert_matches::assert_matches!(
+            Query::get_decoded_with_param(&DapVersion::Draft09, &encoded),
+            Err(CodecError::UnexpectedValue)
+        );
+        assert_matches::assert_matches!(
+            BatchSelector::get_decoded(&encoded),
+            Err(CodecError::UnexpectedValue)
+        );
+        assert_matches::assert_matches!(
+            PartialBatchSelector::get_decoded(&encoded),
+            Err(CodecError::UnexpectedValue)
+        );
+    }
+
     #[test]
     fn read_hpke_config() {
         let data = [
@@ -1488,6 +1984,45 @@ mod test {
         );
     }
 
+    #[test]
+    fn roundtrip_hpke_config_list() {
+        let hpke_config = |id| HpkeConfig {
+            id,
+            kem_id: HpkeKemId::X25519HkdfSha256,
+            kdf_id: HpkeKdfId::HkdfSha256,
+            aead_id: HpkeAeadId::Aes128Gcm,
+            public_key: HpkePublicKey::from(vec![id; 32]),
+        };
+
+        for hpke_configs in [
+            vec![],
+            vec![hpke_config(1)],
+            (1..=5).map(hpke_config).collect(),
+        ] {
+            let hpke_config_list = HpkeConfigList { hpke_configs };
+            let encoded = hpke_config_list.get_encoded().unwrap();
+
+            // The configs are prefixed by the length in bytes of their encoding.
+            assert_eq!(
+                usize::from(u16::from_be_bytes([encoded[0], encoded[1]])),
+                encoded.len() - 2
+            );
+            assert_eq!(
+                HpkeConfigList::get_decoded(&encoded).unwrap(),
+                hpke_config_list
+            );
+        }
+
+        // The length prefix must match the encoded configs.
+        let mut encoded = HpkeConfigList {
+            hpke_configs: vec![hpke_config(1), hpke_config(2)],
+        }
+        .get_encoded()
+        .unwrap();
+        encoded.pop();
+        assert!(HpkeConfigList::get_decoded(&encoded).is_err());
+    }
+
     #[test]
     fn test_base64url() {
         let mut rng = thread_rng();
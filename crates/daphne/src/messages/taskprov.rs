@@ -4,8 +4,8 @@
 //! draft-wang-ppm-dap-taskprov: Messages for the taskrpov extension for DAP.
 
 use crate::messages::{
-    decode_u16_bytes, encode_u16_bytes, Duration, Time, QUERY_TYPE_FIXED_SIZE,
-    QUERY_TYPE_TIME_INTERVAL,
+    decode_u16_bytes, decode_unrecognized_query_type_param, encode_u16_bytes, Duration,
+    QueryTypeStrictness, Time, QUERY_TYPE_FIXED_SIZE, QUERY_TYPE_TIME_INTERVAL,
 };
 use crate::pine::PineParam;
 use crate::DapVersion;
@@ -367,35 +367,32 @@ impl ParameterizedEncode<DapVersion> for QueryConfig {
     }
 }
 
-impl ParameterizedDecode<(DapVersion, Option<usize>)> for QueryConfig {
-    fn decode_with_param(
-        (_version, bytes_left): &(DapVersion, Option<usize>),
+impl QueryConfig {
+    /// Decode a query configuration, handling an unrecognized query type as indicated by
+    /// `strictness`. `len` is the length of the encoded value, if known.
+    pub fn decode_with_strictness(
+        strictness: QueryTypeStrictness,
+        len: Option<usize>,
         bytes: &mut Cursor<&[u8]>,
     ) -> Result<Self, CodecError> {
         let time_precision = Duration::decode(bytes)?;
         let max_batch_query_count = u16::decode(bytes)?;
         let min_batch_size = u32::decode(bytes)?;
         let query_type = u8::decode(bytes)?;
-        let var =
-            match (bytes_left, query_type) {
-                (.., QUERY_TYPE_TIME_INTERVAL) => QueryConfigVar::TimeInterval,
-                (.., QUERY_TYPE_FIXED_SIZE) => QueryConfigVar::FixedSize {
-                    max_batch_size: u32::decode(bytes)?,
-                },
-                (Some(bytes_left), ..) => {
-                    let mut param = vec![0; bytes_left - 15];
-                    bytes.read_exact(&mut param)?;
-
-                    QueryConfigVar::NotImplemented {
-                        typ: query_type,
-                        param,
-                    }
-                }
-                (None, ..) => return Err(CodecError::Other(
-                    "cannot decode QueryConfig variant without knowing the length of the remainder"
-                        .into(),
-                )),
-            };
+        let var = match query_type {
+            QUERY_TYPE_TIME_INTERVAL => QueryConfigVar::TimeInterval,
+            QUERY_TYPE_FIXED_SIZE => QueryConfigVar::FixedSize {
+                max_batch_size: u32::decode(bytes)?,
+            },
+            typ => QueryConfigVar::NotImplemented {
+                typ,
+                param: decode_unrecognized_query_type_param(
+                    strictness,
+                    len.map(|len| len.saturating_sub(15)),
+                    bytes,
+                )?,
+            },
+        };
 
         Ok(Self {
             time_precision,
@@ -406,6 +403,15 @@ impl ParameterizedDecode<(DapVersion, Option<usize>)> for QueryConfig {
     }
 }
 
+impl ParameterizedDecode<(DapVersion, Option<usize>)> for QueryConfig {
+    fn decode_with_param(
+        (_version, bytes_left): &(DapVersion, Option<usize>),
+        bytes: &mut Cursor<&[u8]>,
+    ) -> Result<Self, CodecError> {
+        Self::decode_with_strictness(QueryTypeStrictness::default(), *bytes_left, bytes)
+    }
+}
+
 /// A DAP task configuration.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub struct TaskConfig {
@@ -442,25 +448,59 @@ impl ParameterizedDecode<DapVersion> for TaskConfig {
         version: &DapVersion,
         bytes: &mut Cursor<&[u8]>,
     ) -> Result<Self, CodecError> {
-        let task_info = decode_u8_items(&(), bytes)?;
-        let leader_url = UrlBytes::decode(bytes)?;
-        let helper_url = UrlBytes::decode(bytes)?;
-        let query_config = decode_u16_prefixed(*version, bytes, |version, inner, len| {
-            // We need to know the length of the `QueryConfig` in order to decode variants we don't
-            // recognize. Likewise for `VdafConfig` below.
-            //
-            // Ideally the message can be decoded without knowing the length of the remainder. This
-            // is not possible because of taskprov's choice to prefix the `QueryConfig` with its
-            // length, rather than prefix the variant part (everything after the "select"). We
-            // could modify taskprov so that the length prefix immediately precedes the bits that
-            // we don't know how to parse. This would be consistent with other protocols that use
-            // TLS syntax. We could also consider dropping TLS syntax in the DAP spec in favor of a
-            // format that is better at being self-describing.
-            QueryConfig::decode_with_param(&(version, len), inner)
+        <Self as ParameterizedDecode<(DapVersion, QueryTypeStrictness)>>::decode_with_param(
+            &(*version, QueryTypeStrictness::default()),
+            bytes,
+        )
+    }
+}
+
+/// A field of a [`TaskConfig`] could not be decoded. This is returned, wrapped in
+/// [`CodecError::Other`], so that provisioning failures can be traced to the offending field.
+#[derive(Debug, thiserror::Error)]
+#[error("failed to decode {field}: {source}")]
+pub struct TaskConfigFieldError {
+    /// The name of the field, e.g., "query_config".
+    pub field: &'static str,
+    pub source: CodecError,
+}
+
+/// Decode the field `field` of a [`TaskConfig`], adding its name to the error on failure.
+fn decode_field<T>(
+    field: &'static str,
+    decode: impl FnOnce() -> Result<T, CodecError>,
+) -> Result<T, CodecError> {
+    decode().map_err(|source| CodecError::Other(Box::new(TaskConfigFieldError { field, source })))
+}
+
+impl ParameterizedDecode<(DapVersion, QueryTypeStrictness)> for TaskConfig {
+    fn decode_with_param(
+        (version, strictness): &(DapVersion, QueryTypeStrictness),
+        bytes: &mut Cursor<&[u8]>,
+    ) -> Result<Self, CodecError> {
+        let task_info = decode_field("task_info", || decode_u8_items(&(), bytes))?;
+        let leader_url = decode_field("leader_url", || UrlBytes::decode(bytes))?;
+        let helper_url = decode_field("helper_url", || UrlBytes::decode(bytes))?;
+        let query_config = decode_field("query_config", || {
+            decode_u16_prefixed(*version, bytes, |_version, inner, len| {
+                // We need to know the length of the `QueryConfig` in order to decode variants we
+                // don't recognize. Likewise for `VdafConfig` below.
+                //
+                // Ideally the message can be decoded without knowing the length of the remainder.
+                // This is not possible because of taskprov's choice to prefix the `QueryConfig`
+                // with its length, rather than prefix the variant part (everything after the
+                // "select"). We could modify taskprov so that the length prefix immediately
+                // precedes the bits that we don't know how to parse. This would be consistent with
+                // other protocols that use TLS syntax. We could also consider dropping TLS syntax
+                // in the DAP spec in favor of a format that is better at being self-describing.
+                QueryConfig::decode_with_strictness(*strictness, len, inner)
+            })
         })?;
-        let task_expiration = Time::decode(bytes)?;
-        let vdaf_config = decode_u16_prefixed(*version, bytes, |version, inner, len| {
-            VdafConfig::decode_with_param(&(version, len), inner)
+        let task_expiration = decode_field("task_expiration", || Time::decode(bytes))?;
+        let vdaf_config = decode_field("vdaf_config", || {
+            decode_u16_prefixed(*version, bytes, |version, inner, len| {
+                VdafConfig::decode_with_param(&(version, len), inner)
+            })
         })?;
 
         Ok(TaskConfig {
@@ -571,6 +611,115 @@ mod tests {
 
     test_versions! { roundtrip_query_config_not_implemented }
 
+    fn decode_query_config_unknown_query_type(version: DapVersion) {
+        let query_config = QueryConfig {
+            time_precision: 12_345_678,
+            max_batch_query_count: 1337,
+            min_batch_size: 12_345_678,
+            var: QueryConfigVar::NotImplemented {
+                typ: 0xff,
+                param: b"query config param".to_vec(),
+            },
+        };
+        let encoded = query_config.get_encoded_with_param(&version).unwrap();
+
+        let mut cursor = Cursor::new(encoded.as_slice());
+        assert_matches::assert_matches!(
+            QueryConfig::decode_with_strictness(
+                QueryTypeStrictness::Strict,
+                Some(encoded.len()),
+                &mut cursor
+            ),
+            Err(CodecError::UnexpectedValue)
+        );
+
+        let mut cursor = Cursor::new(encoded.as_slice());
+        assert_eq!(
+            QueryConfig::decode_with_strictness(
+                QueryTypeStrictness::Lenient,
+                Some(encoded.len()),
+                &mut cursor
+            )
+            .unwrap(),
+            query_config
+        );
+
+        // The strictness is threaded through when decoding a `TaskConfig`.
+        let task_config = TaskConfig {
+            task_info: b"this is a cool task!".to_vec(),
+            leader_url: UrlBytes {
+                bytes: b"https://leader.example.com".to_vec(),
+            },
+            helper_url: UrlBytes {
+                bytes: b"https://helper.example.com".to_vec(),
+            },
+            query_config,
+            task_expiration: 23_232_232_232,
+            vdaf_config: VdafConfig {
+                dp_config: DpConfig::None,
+                var: VdafTypeVar::Prio2 { dimension: 1337 },
+            },
+        };
+        let encoded = task_config.get_encoded_with_param(&version).unwrap();
+        assert!(TaskConfig::get_decoded_with_param(
+            &(version, QueryTypeStrictness::Strict),
+            &encoded
+        )
+        .is_err());
+        assert_eq!(
+            TaskConfig::get_decoded_with_param(&(version, QueryTypeStrictness::Lenient), &encoded)
+                .unwrap(),
+            task_config
+        );
+    }
+
+    test_versions! { decode_query_config_unknown_query_type }
+
+    fn decode_task_config_error_names_field(version: DapVersion) {
+        let task_config = TaskConfig {
+            task_info: b"this is a cool task!".to_vec(),
+            leader_url: UrlBytes {
+                bytes: b"https://leader.example.com".to_vec(),
+            },
+            helper_url: UrlBytes {
+                bytes: b"https://helper.example.com".to_vec(),
+            },
+            query_config: QueryConfig {
+                time_precision: 3600,
+                max_batch_query_count: 1,
+                min_batch_size: 10,
+                var: QueryConfigVar::TimeInterval,
+            },
+            task_expiration: 23_232_232_232,
+            vdaf_config: VdafConfig {
+                dp_config: DpConfig::None,
+                var: VdafTypeVar::Prio2 { dimension: 1337 },
+            },
+        };
+        let mut encoded = task_config.get_encoded_with_param(&version).unwrap();
+
+        // Truncate the length prefix of the query config so that it can't be decoded.
+        let query_config_offset = 1
+            + task_config.task_info.len()
+            + 2
+            + task_config.leader_url.bytes.len()
+            + 2
+            + task_config.helper_url.bytes.len();
+        encoded[query_config_offset..query_config_offset + 2].copy_from_slice(&3_u16.to_be_bytes());
+
+        let err = TaskConfig::get_decoded_with_param(&version, &encoded).unwrap_err();
+        let CodecError::Other(err) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        let err = err.downcast_ref::<TaskConfigFieldError>().unwrap();
+        assert_eq!(err.field, "query_config");
+        assert!(err
+            .to_string()
+            .starts_with("failed to decode query_config: "));
+    }
+
+    test_versions! { decode_task_config_error_names_field }
+
     fn roundtrip_dp_config(version: DapVersion) {
         let dp_config = DpConfig::None;
         let encoded = dp_config.get_encoded_with_param(&version).unwrap();
@@ -6,12 +6,15 @@ use crate::vdaf::mastic::{mastic_prep_finish, mastic_prep_finish_from_shares, ma
 use crate::{
     error::DapAbort,
     fatal_error,
-    hpke::{HpkeConfig, HpkeDecrypter},
+    hpke::{
+        hpke_aad_for_agg_share, hpke_aad_for_input_share, HpkeConfig, HpkeDecrypter,
+        HPKE_AEAD_TAG_LEN,
+    },
     messages::{
-        encode_u32_bytes, encode_u32_prefixed, AggregationJobInitReq, AggregationJobResp,
-        Base64Encode, BatchSelector, Extension, HpkeCiphertext, PartialBatchSelector,
-        PlaintextInputShare, PrepareInit, Report, ReportId, ReportMetadata, ReportShare, TaskId,
-        Transition, TransitionFailure, TransitionVar,
+        encode_u32_bytes, AggregationJobInitReq, AggregationJobResp, Base64Encode, BatchSelector,
+        Extension, HpkeCiphertext, PartialBatchSelector, PlaintextInputShare, PrepareInit, Report,
+        ReportId, ReportMetadata, ReportShare, TaskId, Transition, TransitionFailure,
+        TransitionVar,
     },
     metrics::{DaphneMetrics, ReportStatus},
     roles::DapReportInitializer,
@@ -23,7 +26,10 @@ use crate::{
     AggregationJobReportState, DapAggregateShare, DapAggregateSpan, DapAggregationJobState,
     DapAggregationParam, DapError, DapTaskConfig, DapVersion, VdafConfig,
 };
-use futures::{Stream, StreamExt};
+use futures::{
+    future::{self, Either},
+    stream, Stream, StreamExt, TryStreamExt,
+};
 use prio::codec::{
     encode_u32_items, CodecError, Decode, Encode, ParameterizedDecode, ParameterizedEncode,
 };
@@ -31,6 +37,7 @@ use std::{
     collections::{HashMap, HashSet},
     io::Cursor,
     iter::zip,
+    num::NonZeroUsize,
     pin::pin,
 };
 use tracing::{info_span, Instrument};
@@ -89,13 +96,58 @@ pub enum EarlyReportStateConsumed {
         input_share: Vec<u8>,
         // Set by the Helper.
         peer_prep_share: Option<Vec<u8>>,
+        // Set if the report indicates the epoch of the VDAF verification key to use.
+        vdaf_verify_key: Option<VdafVerifyKey>,
     },
     Rejected {
         metadata: ReportMetadata,
         failure: TransitionFailure,
+        /// Why the report was rejected. This is more specific than `failure` and is only used
+        /// for diagnostics.
+        reason: &'static str,
     },
 }
 
+/// The HPKE info string for an input share sent by the Client to the given Aggregator.
+fn input_share_info(is_leader: bool) -> Vec<u8> {
+    let input_share_text = CTX_INPUT_SHARE_DRAFT09;
+    let mut info = Vec::with_capacity(input_share_text.len() + 2);
+    info.extend_from_slice(input_share_text);
+    info.push(CTX_ROLE_CLIENT); // Sender role
+    info.push(if is_leader {
+        CTX_ROLE_LEADER
+    } else {
+        CTX_ROLE_HELPER
+    }); // Receiver role
+    info
+}
+
+/// Decrypt the Leader's (`is_leader`) or the Helper's input share of a report. The Client lists the
+/// Leader's share first, so this fails with [`TransitionFailure::HpkeDecryptError`] if the shares
+/// were swapped.
+pub(crate) async fn decrypt_input_share(
+    decrypter: &impl HpkeDecrypter,
+    task_id: &TaskId,
+    version: DapVersion,
+    report: &Report,
+    is_leader: bool,
+) -> Result<Vec<u8>, DapError> {
+    let aad = hpke_aad_for_input_share(
+        task_id,
+        version,
+        &report.report_metadata,
+        &report.public_share,
+    )?;
+    decrypter
+        .hpke_decrypt(
+            task_id,
+            &input_share_info(is_leader),
+            &aad,
+            &report.encrypted_input_shares[usize::from(!is_leader)],
+        )
+        .await
+}
+
 impl EarlyReportStateConsumed {
     pub(crate) async fn consume(
         decrypter: &impl HpkeDecrypter,
@@ -106,10 +158,22 @@ impl EarlyReportStateConsumed {
         report_share: ReportShare,
         prep_init_payload: Option<Vec<u8>>,
     ) -> Result<EarlyReportStateConsumed, DapError> {
-        if report_share.report_metadata.time >= task_config.not_after {
+        if !task_config.report_time_is_valid(report_share.report_metadata.time) {
+            let (failure, reason) = if report_share.report_metadata.time >= task_config.not_after {
+                (
+                    TransitionFailure::ReportDropped,
+                    "report time is past the task's expiration",
+                )
+            } else {
+                (
+                    TransitionFailure::ReportTooEarly,
+                    "report time is before the task's start",
+                )
+            };
             return Ok(Self::Rejected {
                 metadata: report_share.report_metadata,
-                failure: TransitionFailure::TaskExpired,
+                failure,
+                reason,
             });
         }
 
@@ -120,6 +184,7 @@ impl EarlyReportStateConsumed {
             return Ok(EarlyReportStateConsumed::Rejected {
                 metadata: report_share.report_metadata,
                 failure: TransitionFailure::ReportDropped,
+                reason: "report time is too old",
             });
         }
 
@@ -129,27 +194,27 @@ impl EarlyReportStateConsumed {
             return Ok(EarlyReportStateConsumed::Rejected {
                 metadata: report_share.report_metadata,
                 failure: TransitionFailure::ReportTooEarly,
+                reason: "report time is too far in the future",
+            });
+        }
+
+        if !task_config.report_time_is_rounded(report_share.report_metadata.time) {
+            // The report time must be a multiple of the task's time precision so that the
+            // report is bucketed correctly.
+            return Ok(EarlyReportStateConsumed::Rejected {
+                metadata: report_share.report_metadata,
+                failure: TransitionFailure::ReportDropped,
+                reason: "report time is not a multiple of the time precision",
             });
         }
 
-        let input_share_text = CTX_INPUT_SHARE_DRAFT09;
-        let n: usize = input_share_text.len();
-        let mut info = Vec::with_capacity(n + 2);
-        info.extend_from_slice(input_share_text);
-        info.push(CTX_ROLE_CLIENT); // Sender role (receiver role set below)
-        info.push(if is_leader {
-            CTX_ROLE_LEADER
-        } else {
-            CTX_ROLE_HELPER
-        }); // Receiver role
-
-        let mut aad = Vec::with_capacity(58);
-        task_id.encode(&mut aad).map_err(DapError::encoding)?;
-        report_share
-            .report_metadata
-            .encode_with_param(&task_config.version, &mut aad)
-            .map_err(DapError::encoding)?;
-        encode_u32_bytes(&mut aad, &report_share.public_share).map_err(DapError::encoding)?;
+        let info = input_share_info(is_leader);
+        let aad = hpke_aad_for_input_share(
+            task_id,
+            task_config.version,
+            &report_share.report_metadata,
+            &report_share.public_share,
+        )?;
 
         let encoded_input_share = match decrypter
             .hpke_decrypt(task_id, &info, &aad, &report_share.encrypted_input_share)
@@ -160,6 +225,11 @@ impl EarlyReportStateConsumed {
                 return Ok(Self::Rejected {
                     metadata: report_share.report_metadata,
                     failure,
+                    reason: if failure == TransitionFailure::HpkeUnknownConfigId {
+                        "input share is encrypted under an unknown HPKE config"
+                    } else {
+                        "failed to decrypt the input share"
+                    },
                 })
             }
             Err(e) => return Err(e),
@@ -175,6 +245,7 @@ impl EarlyReportStateConsumed {
                     return Ok(Self::Rejected {
                         metadata: report_share.report_metadata,
                         failure: TransitionFailure::InvalidMessage,
+                        reason: "failed to decode the plaintext input share",
                     })
                 }
             }
@@ -183,6 +254,7 @@ impl EarlyReportStateConsumed {
         // Handle report extensions.
         {
             let mut taskprov_indicated = false;
+            let mut vdaf_verify_key = None;
             let mut seen: HashSet<u16> = HashSet::with_capacity(extensions.len());
             for extension in extensions {
                 // Reject reports with duplicated extensions.
@@ -190,6 +262,7 @@ impl EarlyReportStateConsumed {
                     return Ok(Self::Rejected {
                         metadata: report_share.report_metadata,
                         failure: TransitionFailure::InvalidMessage,
+                        reason: "report has a duplicated extension",
                     });
                 }
 
@@ -198,11 +271,26 @@ impl EarlyReportStateConsumed {
                         taskprov_indicated = true;
                     }
 
+                    // Reject reports that indicate a verification key epoch we don't know about.
+                    Extension::VerifyKeyEpoch { epoch } => {
+                        match task_config.vdaf_verify_keys_by_epoch.get(&epoch) {
+                            Some(key) => vdaf_verify_key = Some(key.clone()),
+                            None => {
+                                return Ok(Self::Rejected {
+                                    metadata: report_share.report_metadata,
+                                    failure: TransitionFailure::InvalidMessage,
+                                    reason: "report indicates an unknown verification key epoch",
+                                })
+                            }
+                        }
+                    }
+
                     // Reject reports with unrecognized extensions.
                     _ => {
                         return Ok(Self::Rejected {
                             metadata: report_share.report_metadata,
                             failure: TransitionFailure::InvalidMessage,
+                            reason: "report has an unrecognized extension",
                         })
                     }
                 }
@@ -214,6 +302,7 @@ impl EarlyReportStateConsumed {
                 return Ok(Self::Rejected {
                     metadata: report_share.report_metadata,
                     failure: TransitionFailure::InvalidMessage,
+                    reason: "report doesn't indicate support for taskprov",
                 });
             }
         }
@@ -231,6 +320,7 @@ impl EarlyReportStateConsumed {
                 return Ok(Self::Rejected {
                     metadata: report_share.report_metadata,
                     failure: TransitionFailure::VdafPrepError,
+                    reason: "failed to decode the Leader's initial prep share",
                 });
             }
         };
@@ -240,6 +330,7 @@ impl EarlyReportStateConsumed {
             public_share: report_share.public_share,
             peer_prep_share,
             input_share,
+            vdaf_verify_key,
         })
     }
 }
@@ -272,6 +363,9 @@ pub enum EarlyReportStateInitialized {
     Rejected {
         metadata: ReportMetadata,
         failure: TransitionFailure,
+        /// Why the report was rejected. This is more specific than `failure` and is only used
+        /// for diagnostics.
+        reason: &'static str,
     },
 }
 
@@ -289,19 +383,35 @@ impl EarlyReportStateInitialized {
         // feature".
         let _ = agg_param;
 
-        let (metadata, public_share, input_share, peer_prep_share) =
+        let (metadata, public_share, input_share, peer_prep_share, epoch_verify_key) =
             match early_report_state_consumed {
                 EarlyReportStateConsumed::Ready {
                     metadata,
                     public_share,
                     input_share,
                     peer_prep_share,
-                } => (metadata, public_share, input_share, peer_prep_share),
-                EarlyReportStateConsumed::Rejected { metadata, failure } => {
-                    return Ok(Self::Rejected { metadata, failure })
+                    vdaf_verify_key,
+                } => (
+                    metadata,
+                    public_share,
+                    input_share,
+                    peer_prep_share,
+                    vdaf_verify_key,
+                ),
+                EarlyReportStateConsumed::Rejected {
+                    metadata,
+                    failure,
+                    reason,
+                } => {
+                    return Ok(Self::Rejected {
+                        metadata,
+                        failure,
+                        reason,
+                    })
                 }
             };
 
+        let vdaf_verify_key = epoch_verify_key.as_ref().unwrap_or(vdaf_verify_key);
         let agg_id = usize::from(!is_leader);
         let res = match vdaf_config {
             VdafConfig::Prio3(ref prio3_config) => prio3_prep_init(
@@ -354,6 +464,7 @@ impl EarlyReportStateInitialized {
                 Self::Rejected {
                     metadata,
                     failure: TransitionFailure::VdafPrepError,
+                    reason: "VDAF preparation failed to initialize",
                 }
             }
         };
@@ -582,7 +693,8 @@ impl DapTaskConfig {
     }
 
     /// Helper: Consume the `AggregationJobInitReq` sent by the Leader and return the initialized
-    /// reports.
+    /// reports. Up to `concurrency` input shares are decrypted at once; the initialized reports
+    /// are in the same order as in the request.
     pub async fn consume_agg_job_req(
         &self,
         decrypter: &impl HpkeDecrypter,
@@ -590,46 +702,84 @@ impl DapTaskConfig {
         task_id: &TaskId,
         agg_job_init_req: AggregationJobInitReq,
         replay_protection: ReplayProtection,
+        concurrency: NonZeroUsize,
     ) -> Result<Vec<EarlyReportStateInitialized>, DapError> {
         let num_reports = agg_job_init_req.prep_inits.len();
-        let mut consumed_reports = Vec::with_capacity(num_reports);
-        async {
-            let mut processed = replay_protection
-                .enabled()
-                .then(|| HashSet::with_capacity(num_reports));
-
-            for prep_init in agg_job_init_req.prep_inits {
-                if let Some(processed) = &mut processed {
-                    if processed.contains(&prep_init.report_share.report_metadata.id) {
-                        return Err(DapAbort::InvalidMessage {
-                            detail: format!(
-                                "report ID {} appears twice in the same aggregation job",
-                                prep_init.report_share.report_metadata.id.to_base64url()
-                            ),
-                            task_id: *task_id,
-                        }
-                        .into());
-                    }
-                    processed.insert(prep_init.report_share.report_metadata.id);
+        let mut pending_reports = Vec::with_capacity(num_reports);
+
+        // If the VDAF's input shares have a fixed length, then so do the ciphertexts, up to the
+        // report extensions: the plaintext is a `PlaintextInputShare`, i.e., the length-prefixed
+        // extensions followed by the length-prefixed input share, and is sealed with an AEAD tag.
+        // Reports may carry extensions, so this is only a lower bound. An input share that is too
+        // long is rejected by the VDAF once decrypted.
+        let min_ciphertext_len = self
+            .vdaf
+            .input_share_len(false)
+            .map(|input_share_len| 2 + 4 + input_share_len + HPKE_AEAD_TAG_LEN);
+        let mut processed = replay_protection
+            .enabled()
+            .then(|| HashSet::with_capacity(num_reports));
+        for prep_init in agg_job_init_req.prep_inits {
+            // A report ID that appears more than once in the same aggregation job is a replay.
+            // Only the first occurrence is processed.
+            if let Some(processed) = &mut processed {
+                if !processed.insert(prep_init.report_share.report_metadata.id) {
+                    pending_reports.push(Either::Left(future::ready(Ok::<_, DapError>(
+                        EarlyReportStateConsumed::Rejected {
+                            metadata: prep_init.report_share.report_metadata,
+                            failure: TransitionFailure::ReportReplayed,
+                        },
+                    ))));
+                    continue;
                 }
+            }
 
-                consumed_reports.push(
-                    EarlyReportStateConsumed::consume(
-                        decrypter,
-                        initializer,
-                        false,
-                        task_id,
-                        self,
-                        prep_init.report_share,
-                        Some(prep_init.payload),
-                    )
-                    .await?,
-                );
+            // A ciphertext without a payload or an encapsulated key can't be opened: reject the
+            // report as undecryptable rather than passing it on to HPKE.
+            let encrypted_input_share = &prep_init.report_share.encrypted_input_share;
+            if encrypted_input_share.payload.is_empty() || encrypted_input_share.enc.is_empty() {
+                pending_reports.push(Either::Left(future::ready(Ok::<_, DapError>(
+                    EarlyReportStateConsumed::Rejected {
+                        metadata: prep_init.report_share.report_metadata,
+                        failure: TransitionFailure::HpkeDecryptError,
+                        reason: "input share has an empty ciphertext or encapsulated key",
+                    },
+                ))));
+                continue;
+            }
+
+            // Drop reports whose ciphertext is too short to contain a valid input share without
+            // bothering to decrypt them.
+            if let Some(min_len) = min_ciphertext_len {
+                if encrypted_input_share.payload.len() < min_len {
+                    pending_reports.push(Either::Left(future::ready(Ok::<_, DapError>(
+                        EarlyReportStateConsumed::Rejected {
+                            metadata: prep_init.report_share.report_metadata,
+                            failure: TransitionFailure::ReportDropped,
+                            reason: "input share ciphertext is too short",
+                        },
+                    ))));
+                    continue;
+                }
             }
-            Ok::<_, DapError>(())
+
+            pending_reports.push(Either::Right(EarlyReportStateConsumed::consume(
+                decrypter,
+                initializer,
+                false,
+                task_id,
+                self,
+                prep_init.report_share,
+                Some(prep_init.payload),
+            )));
         }
-        .instrument(info_span!("consume reports"))
-        .await?;
+
+        // `buffered` yields the results in the order of the reports, however many are in flight.
+        let consumed_reports = stream::iter(pending_reports)
+            .buffered(concurrency.get())
+            .try_collect::<Vec<_>>()
+            .instrument(info_span!("consume reports"))
+            .await?;
 
         let agg_param =
             DapAggregationParam::get_decoded_with_param(&self.vdaf, &agg_job_init_req.agg_param)
@@ -736,10 +886,9 @@ impl DapTaskConfig {
                         ..
                     } => return Err(fatal_error!(err = "expected leader prep share, got none")),
 
-                    EarlyReportStateInitialized::Rejected {
-                        metadata: _,
-                        failure,
-                    } => TransitionVar::Failed(*failure),
+                    EarlyReportStateInitialized::Rejected { failure, .. } => {
+                        TransitionVar::Failed(*failure)
+                    }
                 },
             };
 
@@ -920,11 +1069,7 @@ fn produce_encrypted_agg_share(
     }); // Sender role
     info.push(CTX_ROLE_COLLECTOR); // Receiver role
 
-    let mut aad = Vec::with_capacity(40);
-    task_id.encode(&mut aad).map_err(DapError::encoding)?;
-    encode_u32_prefixed(version, &mut aad, |_version, bytes| agg_param.encode(bytes))
-        .map_err(DapError::encoding)?;
-    batch_sel.encode(&mut aad).map_err(DapError::encoding)?;
+    let aad = hpke_aad_for_agg_share(task_id, batch_sel, agg_param, version)?;
 
     hpke_config.encrypt(&info, &aad, &agg_share_data)
 }
@@ -4,15 +4,12 @@
 #[cfg(feature = "experimental")]
 use crate::vdaf::mastic::mastic_shard;
 use crate::{
-    hpke::HpkeConfig,
-    messages::{
-        encode_u32_bytes, Extension, PlaintextInputShare, Report, ReportId, ReportMetadata, TaskId,
-        Time,
-    },
+    hpke::{hpke_aad_for_input_share, HpkeConfig},
+    messages::{Extension, PlaintextInputShare, Report, ReportId, ReportMetadata, TaskId, Time},
     vdaf::{prio2::prio2_shard, prio3::prio3_shard, VdafError},
     DapError, DapMeasurement, DapVersion, VdafConfig,
 };
-use prio::codec::{Encode, ParameterizedEncode};
+use prio::codec::ParameterizedEncode;
 use rand::prelude::*;
 
 use super::{CTX_INPUT_SHARE_DRAFT09, CTX_ROLE_CLIENT, CTX_ROLE_HELPER, CTX_ROLE_LEADER};
@@ -63,6 +60,33 @@ impl VdafConfig {
         )
     }
 
+    /// Generate a report for a measurement with the given report ID, e.g., to check how the
+    /// Aggregators handle a repeated ID.
+    #[cfg(any(test, feature = "test-utils"))]
+    pub fn produce_report_with_id(
+        &self,
+        hpke_config_list: &[HpkeConfig; 2],
+        time: Time,
+        task_id: &TaskId,
+        report_id: ReportId,
+        measurement: DapMeasurement,
+        version: DapVersion,
+    ) -> Result<Report, DapError> {
+        let (public_share, input_shares) = self
+            .produce_input_shares(measurement, &report_id.0)
+            .map_err(DapError::from_vdaf)?;
+        Self::produce_report_with_extensions_for_shares(
+            public_share,
+            input_shares,
+            hpke_config_list,
+            time,
+            task_id,
+            &report_id,
+            Vec::new(),
+            version,
+        )
+    }
+
     /// Generate a report for the given public and input shares with the given extensions.
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn produce_report_with_extensions_for_shares(
@@ -97,12 +121,7 @@ impl VdafConfig {
         info.push(CTX_ROLE_CLIENT); // Sender role
         info.push(CTX_ROLE_LEADER); // Receiver role placeholder; updated below.
 
-        let mut aad = Vec::with_capacity(58);
-        task_id.encode(&mut aad).map_err(DapError::encoding)?;
-        metadata
-            .encode_with_param(&version, &mut aad)
-            .map_err(DapError::encoding)?;
-        encode_u32_bytes(&mut aad, &public_share).map_err(DapError::encoding)?;
+        let aad = hpke_aad_for_input_share(task_id, version, &metadata, &public_share)?;
 
         let mut encrypted_input_shares = Vec::with_capacity(2);
         for (i, (hpke_config, encoded_input_share)) in
@@ -5,16 +5,43 @@
 use crate::vdaf::mastic::mastic_unshard;
 use crate::{
     fatal_error,
-    hpke::HpkeDecrypter,
-    messages::{encode_u32_prefixed, BatchSelector, HpkeCiphertext, TaskId},
-    vdaf::{prio2::prio2_unshard, prio3::prio3_unshard},
+    hpke::{hpke_aad_for_agg_share, HpkeDecrypter},
+    messages::{BatchSelector, HpkeCiphertext, TaskId},
+    vdaf::{prio2::prio2_unshard, prio3::prio3_unshard, Prio3Config},
     DapAggregateResult, DapAggregationParam, DapError, DapVersion, VdafConfig,
 };
-use prio::codec::Encode;
 
 use super::{CTX_AGG_SHARE_DRAFT09, CTX_ROLE_COLLECTOR, CTX_ROLE_HELPER, CTX_ROLE_LEADER};
 
+/// Construct the associated data the Collector expects for the encrypted aggregate shares of a
+/// batch. The Aggregators bind each aggregate share to the task, the aggregation parameter, and
+/// the batch selector, so this must match the construction used to encrypt them.
+pub fn agg_share_aad_for_collector(
+    task_id: &TaskId,
+    batch_sel: &BatchSelector,
+    agg_param: &DapAggregationParam,
+    version: DapVersion,
+) -> Result<Vec<u8>, DapError> {
+    hpke_aad_for_agg_share(task_id, batch_sel, agg_param, version)
+}
+
 impl VdafConfig {
+    /// Check that a task's VDAF, as named by the Leader, is the VDAF the Collector is about to
+    /// decode the aggregate result with. This should be called before
+    /// [`Self::consume_encrypted_agg_shares`]: unsharding with the wrong VDAF does not necessarily
+    /// fail, but yields a meaningless result.
+    pub fn check_task_vdaf_name(&self, task_vdaf_name: &str) -> Result<(), DapError> {
+        let expected = self.to_string();
+        if task_vdaf_name != expected {
+            return Err(fatal_error!(
+                err = format!(
+                    "task uses VDAF {task_vdaf_name}, but the Collector expects {expected}"
+                )
+            ));
+        }
+        Ok(())
+    }
+
     /// Decrypt and unshard a sequence of aggregate shares. This method is run by the Collector
     /// after completing a collect request.
     ///
@@ -54,11 +81,7 @@ impl VdafConfig {
         info.push(CTX_ROLE_LEADER); // Sender role placeholder
         info.push(CTX_ROLE_COLLECTOR); // Receiver role
 
-        let mut aad = Vec::with_capacity(40);
-        task_id.encode(&mut aad).map_err(DapError::encoding)?;
-        encode_u32_prefixed(version, &mut aad, |_version, bytes| agg_param.encode(bytes))
-            .map_err(DapError::encoding)?;
-        batch_sel.encode(&mut aad).map_err(DapError::encoding)?;
+        let aad = agg_share_aad_for_collector(task_id, batch_sel, agg_param, version)?;
 
         let mut agg_shares = Vec::with_capacity(encrypted_agg_shares.len());
         for (i, agg_share_ciphertext) in encrypted_agg_shares.iter().enumerate() {
@@ -93,4 +116,97 @@ impl VdafConfig {
         }
         .map_err(|e| fatal_error!(err = ?e, "failed to unshard agg_shares"))
     }
+
+    /// Merge the aggregate results of several batches of a task, e.g., the batches of a fixed-size
+    /// task, into the aggregate result for the union of the batches. Returns an error if there are
+    /// no results, if a result is not of the shape this VDAF produces, or if the merge overflows.
+    pub fn merge_aggregate_results(
+        &self,
+        results: &[DapAggregateResult],
+    ) -> Result<DapAggregateResult, DapError> {
+        fn add_elements<T: Copy>(
+            acc: &mut [T],
+            other: &[T],
+            add: impl Fn(T, T) -> Option<T>,
+        ) -> Result<(), DapError> {
+            if acc.len() != other.len() {
+                return Err(fatal_error!(
+                    err = format!(
+                        "aggregate result length mismatch: {} != {}",
+                        acc.len(),
+                        other.len()
+                    )
+                ));
+            }
+            for (acc, &other) in acc.iter_mut().zip(other) {
+                *acc = add(*acc, other)
+                    .ok_or_else(|| fatal_error!(err = "merged aggregate result overflows"))?;
+            }
+            Ok(())
+        }
+
+        let (first, rest) = results
+            .split_first()
+            .ok_or_else(|| fatal_error!(err = "no aggregate results to merge"))?;
+        self.check_aggregate_result_shape(first)?;
+        let mut merged = first.clone();
+        for result in rest {
+            self.check_aggregate_result_shape(result)?;
+            match (&mut merged, result) {
+                (DapAggregateResult::U32Vec(acc), DapAggregateResult::U32Vec(other)) => {
+                    add_elements(acc, other, u32::checked_add)?;
+                }
+                (DapAggregateResult::U64(acc), DapAggregateResult::U64(other)) => {
+                    add_elements(std::slice::from_mut(acc), &[*other], u64::checked_add)?;
+                }
+                (DapAggregateResult::U64Vec(acc), DapAggregateResult::U64Vec(other)) => {
+                    add_elements(acc, other, u64::checked_add)?;
+                }
+                (DapAggregateResult::U128(acc), DapAggregateResult::U128(other)) => {
+                    add_elements(std::slice::from_mut(acc), &[*other], u128::checked_add)?;
+                }
+                (DapAggregateResult::U128Vec(acc), DapAggregateResult::U128Vec(other)) => {
+                    add_elements(acc, other, u128::checked_add)?;
+                }
+                (DapAggregateResult::F64Vec(acc), DapAggregateResult::F64Vec(other)) => {
+                    add_elements(acc, other, |a, b| Some(a + b))?;
+                }
+                _ => return Err(fatal_error!(err = "aggregate result type mismatch")),
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Check that an aggregate result has the type, and if it is known, the length of the
+    /// aggregate results produced by this VDAF.
+    fn check_aggregate_result_shape(&self, result: &DapAggregateResult) -> Result<(), DapError> {
+        let ok = match (self, result) {
+            (Self::Prio3(Prio3Config::Count), DapAggregateResult::U64(_))
+            | (Self::Prio3(Prio3Config::Sum { .. }), DapAggregateResult::U128(_))
+            | (Self::Pine(..), DapAggregateResult::F64Vec(_)) => true,
+            (
+                Self::Prio3(
+                    Prio3Config::Histogram { length, .. } | Prio3Config::SumVec { length, .. },
+                ),
+                DapAggregateResult::U128Vec(v),
+            )
+            | (
+                Self::Prio3(Prio3Config::SumVecField64MultiproofHmacSha256Aes128 {
+                    length, ..
+                }),
+                DapAggregateResult::U64Vec(v),
+            ) => v.len() == *length,
+            (Self::Prio2 { dimension }, DapAggregateResult::U32Vec(v)) => v.len() == *dimension,
+            #[cfg(feature = "experimental")]
+            (Self::Mastic { .. }, DapAggregateResult::U64Vec(_)) => true,
+            _ => false,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(fatal_error!(
+                err = format!("aggregate result does not match VDAF {self}")
+            ))
+        }
+    }
 }
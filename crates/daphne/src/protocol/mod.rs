@@ -5,6 +5,8 @@ pub(crate) mod aggregator;
 mod client;
 mod collector;
 
+pub use collector::agg_share_aad_for_collector;
+
 const CTX_INPUT_SHARE_DRAFT09: &[u8] = b"dap-09 input share";
 const CTX_AGG_SHARE_DRAFT09: &[u8] = b"dap-09 aggregate share";
 const CTX_ROLE_COLLECTOR: u8 = 0;
@@ -36,6 +38,7 @@ mod test {
     use assert_matches::assert_matches;
     use hpke_rs::HpkePublicKey;
     use prio::{
+        codec::Encode,
         field::Field64,
         vdaf::{
             prio3::Prio3, AggregateShare, Aggregator as VdafAggregator, Collector as VdafCollector,
@@ -43,7 +46,7 @@ mod test {
         },
     };
     use rand::prelude::*;
-    use std::iter::zip;
+    use std::{collections::HashMap, iter::zip};
 
     const TEST_VDAF: &VdafConfig = &VdafConfig::Prio3(Prio3Config::Count);
 
@@ -168,6 +171,69 @@ mod test {
 
     async_test_versions! { roundtrip_report }
 
+    async fn recover_measurement(version: DapVersion) {
+        for vdaf in [
+            VdafConfig::Prio2 { dimension: 10 },
+            VdafConfig::Prio3(Prio3Config::Count),
+            VdafConfig::Prio3(Prio3Config::Sum { bits: 23 }),
+            VdafConfig::Prio3(Prio3Config::Histogram {
+                length: 7,
+                chunk_length: 3,
+            }),
+            VdafConfig::Prio3(Prio3Config::SumVec {
+                bits: 8,
+                length: 5,
+                chunk_length: 2,
+                client_element_max: None,
+            }),
+            VdafConfig::Prio3(Prio3Config::SumVecField64MultiproofHmacSha256Aes128 {
+                bits: 8,
+                length: 5,
+                chunk_length: 2,
+                num_proofs: 2,
+            }),
+        ] {
+            let t = AggregationJobTest::new(&vdaf, HpkeKemId::X25519HkdfSha256, version);
+            let measurement = vdaf.gen_random_measurement(&mut thread_rng()).unwrap();
+            let report = vdaf
+                .produce_report(
+                    &t.client_hpke_config_list,
+                    t.now,
+                    &t.task_id,
+                    measurement.clone(),
+                    version,
+                )
+                .unwrap();
+
+            let recovered = vdaf
+                .recover_measurement(
+                    &t.task_id,
+                    version,
+                    &report,
+                    &t.leader_hpke_receiver_config,
+                    &t.helper_hpke_receiver_config,
+                )
+                .await
+                .unwrap();
+            assert_eq!(recovered, measurement, "{vdaf}");
+
+            // The input shares can't be decrypted with the wrong keys.
+            assert_matches!(
+                vdaf.recover_measurement(
+                    &t.task_id,
+                    version,
+                    &report,
+                    &t.helper_hpke_receiver_config,
+                    &t.leader_hpke_receiver_config,
+                )
+                .await,
+                Err(..)
+            );
+        }
+    }
+
+    async_test_versions! { recover_measurement }
+
     fn roundtrip_report_unsupported_hpke_suite(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
 
@@ -356,6 +422,45 @@ mod test {
 
     async_test_versions! { handle_agg_job_req_hpke_decrypt_err }
 
+    async fn handle_agg_job_req_duplicate_report_id(version: DapVersion) {
+        let mut t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
+        let (_, mut agg_job_init_req) = t
+            .produce_agg_job_req(&DapAggregationParam::Empty, reports)
+            .await;
+
+        // Include the first report twice in the aggregation job.
+        let duplicate = agg_job_init_req.prep_inits[0].clone();
+        agg_job_init_req.prep_inits.push(duplicate);
+        let (agg_span, agg_job_resp) = t.handle_agg_job_req(agg_job_init_req.clone()).await;
+
+        assert_eq!(agg_span.report_count(), 2);
+        assert_eq!(agg_job_resp.transitions.len(), 3);
+        assert_matches!(
+            agg_job_resp.transitions[0].var,
+            TransitionVar::Continued(..)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[1].var,
+            TransitionVar::Continued(..)
+        );
+        assert_eq!(
+            agg_job_resp.transitions[2].report_id,
+            agg_job_resp.transitions[0].report_id
+        );
+        assert_matches!(
+            agg_job_resp.transitions[2].var,
+            TransitionVar::Failed(TransitionFailure::ReportReplayed)
+        );
+
+        // Without replay protection, every occurrence is processed.
+        t.disable_replay_protection();
+        let (agg_span, _agg_job_resp) = t.handle_agg_job_req(agg_job_init_req).await;
+        assert_eq!(agg_span.report_count(), 3);
+    }
+
+    async_test_versions! { handle_agg_job_req_duplicate_report_id }
+
     async fn handle_agg_job_req_skip_time_too_stale(version: DapVersion) {
         let mut t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
         let reports = vec![t
@@ -428,6 +533,128 @@ mod test {
 
     async_test_versions! { handle_agg_job_req_skip_time_too_early }
 
+    // Test that the Helper rejects reports that fall outside of the task's validity window.
+    async fn handle_agg_job_req_report_time_outside_task_window(version: DapVersion) {
+        let mut t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        t.valid_report_range = 0..u64::MAX;
+        let not_before = t.task_config.not_before;
+        let not_after = t.task_config.not_after;
+        let time_precision = t.task_config.time_precision;
+        let reports = [
+            not_before - 1,
+            not_before,
+            not_after - time_precision,
+            not_after,
+        ]
+        .into_iter()
+        .map(|time| {
+            t.task_config
+                .vdaf
+                .produce_report(
+                    &t.client_hpke_config_list,
+                    time,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    t.task_config.version,
+                )
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+        let agg_job_init_req = {
+            // Temporarily widen the task's validity window so that the Leader accepts the
+            // out-of-range reports and produces the request.
+            t.task_config.not_before = 0;
+            t.task_config.not_after = u64::MAX;
+            let (_, agg_job_init_req) = t
+                .produce_agg_job_req(&DapAggregationParam::Empty, reports)
+                .await;
+            t.task_config.not_before = not_before;
+            t.task_config.not_after = not_after;
+            agg_job_init_req
+        };
+        let (_agg_span, agg_job_resp) = t.handle_agg_job_req(agg_job_init_req).await;
+
+        assert_eq!(agg_job_resp.transitions.len(), 4);
+        assert_matches!(
+            agg_job_resp.transitions[0].var,
+            TransitionVar::Failed(TransitionFailure::ReportTooEarly)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[1].var,
+            TransitionVar::Continued(..)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[2].var,
+            TransitionVar::Continued(..)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[3].var,
+            TransitionVar::Failed(TransitionFailure::ReportDropped)
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_report_time_outside_task_window }
+
+    #[test]
+    fn report_time_is_rounded() {
+        let mut t =
+            AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, DapVersion::Latest);
+        t.task_config.time_precision = 3600;
+        assert!(t.task_config.report_time_is_rounded(1_637_359_200));
+        assert!(!t.task_config.report_time_is_rounded(1_637_359_201));
+        assert!(!t.task_config.report_time_is_rounded(1_637_359_199));
+    }
+
+    // Test that the Helper drops reports whose timestamp is not a multiple of the time precision.
+    async fn handle_agg_job_req_report_time_not_rounded(version: DapVersion) {
+        let mut t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        t.task_config.time_precision = 3600;
+        t.valid_report_range = 0..u64::MAX;
+        t.task_config.not_before = 0;
+        t.task_config.not_after = u64::MAX;
+        let rounded = t.task_config.quantized_time_lower_bound(t.now);
+        let reports = [rounded, rounded + 1]
+            .into_iter()
+            .map(|time| {
+                t.task_config
+                    .vdaf
+                    .produce_report(
+                        &t.client_hpke_config_list,
+                        time,
+                        &t.task_id,
+                        DapMeasurement::U64(1),
+                        t.task_config.version,
+                    )
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let agg_job_init_req = {
+            // Temporarily relax the time precision so that the Leader accepts the mis-rounded
+            // report and produces the request.
+            t.task_config.time_precision = 1;
+            let (_, agg_job_init_req) = t
+                .produce_agg_job_req(&DapAggregationParam::Empty, reports)
+                .await;
+            t.task_config.time_precision = 3600;
+            agg_job_init_req
+        };
+        let (_agg_span, agg_job_resp) = t.handle_agg_job_req(agg_job_init_req).await;
+
+        assert_eq!(agg_job_resp.transitions.len(), 2);
+        assert_matches!(
+            agg_job_resp.transitions[0].var,
+            TransitionVar::Continued(..)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[1].var,
+            TransitionVar::Failed(TransitionFailure::ReportDropped)
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_report_time_not_rounded }
+
     async fn handle_agg_job_req_hpke_unknown_config_id(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
         let mut reports = t.produce_reports(vec![DapMeasurement::U64(1)]);
@@ -494,6 +721,77 @@ mod test {
 
     async_test_versions! { handle_agg_job_req_vdaf_prep_error }
 
+    async fn handle_agg_job_req_input_share_wrong_len(version: DapVersion) {
+        let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        let mut reports = t.produce_reports(vec![
+            DapMeasurement::U64(1),
+            DapMeasurement::U64(1),
+            DapMeasurement::U64(0),
+        ]);
+
+        // Truncate the Helper's ciphertext for the first report and pad it for the second. Only
+        // the first is too short to hold an input share: the second may carry extensions, so it
+        // is decrypted, which fails.
+        reports[0].encrypted_input_shares[1].payload.pop();
+        reports[1].encrypted_input_shares[1].payload.push(0);
+
+        let (_, agg_job_init_req) = t
+            .produce_agg_job_req(&DapAggregationParam::Empty, reports)
+            .await;
+        let (_agg_span, agg_job_resp) = t.handle_agg_job_req(agg_job_init_req).await;
+
+        assert_eq!(agg_job_resp.transitions.len(), 3);
+        assert_matches!(
+            agg_job_resp.transitions[0].var,
+            TransitionVar::Failed(TransitionFailure::ReportDropped)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[1].var,
+            TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[2].var,
+            TransitionVar::Continued(..)
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_input_share_wrong_len }
+
+    async fn handle_agg_job_req_input_share_empty_ciphertext(version: DapVersion) {
+        let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        let mut reports = t.produce_reports(vec![
+            DapMeasurement::U64(1),
+            DapMeasurement::U64(1),
+            DapMeasurement::U64(0),
+        ]);
+
+        // Empty the Helper's ciphertext for the first report and its encapsulated key for the
+        // second.
+        reports[0].encrypted_input_shares[1].payload.clear();
+        reports[1].encrypted_input_shares[1].enc.clear();
+
+        let (_, agg_job_init_req) = t
+            .produce_agg_job_req(&DapAggregationParam::Empty, reports)
+            .await;
+        let (_agg_span, agg_job_resp) = t.handle_agg_job_req(agg_job_init_req).await;
+
+        assert_eq!(agg_job_resp.transitions.len(), 3);
+        assert_matches!(
+            agg_job_resp.transitions[0].var,
+            TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[1].var,
+            TransitionVar::Failed(TransitionFailure::HpkeDecryptError)
+        );
+        assert_matches!(
+            agg_job_resp.transitions[2].var,
+            TransitionVar::Continued(..)
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_input_share_empty_ciphertext }
+
     async fn agg_job_resp_abort_transition_out_of_order(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
         let reports = t.produce_reports(vec![DapMeasurement::U64(1), DapMeasurement::U64(1)]);
@@ -705,6 +1003,63 @@ mod test {
 
     async_test_versions! { encrypted_agg_share }
 
+    async fn agg_share_aad_for_collector(version: DapVersion) {
+        let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        let agg_share = DapAggregateShare {
+            report_count: 50,
+            min_time: 1_637_359_200,
+            max_time: 1_637_359_200,
+            checksum: [0; 32],
+            data: Some(VdafAggregateShare::Field64(AggregateShare::from(
+                OutputShare::from(vec![Field64::from(23)]),
+            ))),
+        };
+        let batch_selector = BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start: 1_637_359_200,
+                duration: 7200,
+            },
+        };
+
+        let aad = super::agg_share_aad_for_collector(
+            &t.task_id,
+            &batch_selector,
+            &DapAggregationParam::Empty,
+            version,
+        )
+        .unwrap();
+        assert_eq!(
+            aad,
+            crate::hpke::hpke_aad_for_agg_share(
+                &t.task_id,
+                &batch_selector,
+                &DapAggregationParam::Empty,
+                version,
+            )
+            .unwrap()
+        );
+
+        // The Collector should be able to decrypt the aggregate share using the reconstructed AAD.
+        let encrypted_agg_share = t.produce_leader_encrypted_agg_share(
+            &batch_selector,
+            &DapAggregationParam::Empty,
+            &agg_share,
+        );
+        let mut info = super::CTX_AGG_SHARE_DRAFT09.to_vec();
+        info.push(super::CTX_ROLE_LEADER);
+        info.push(super::CTX_ROLE_COLLECTOR);
+        let agg_share_data = t
+            .collector_hpke_receiver_config
+            .decrypt(&info, &aad, &encrypted_agg_share)
+            .unwrap();
+        assert_eq!(
+            agg_share_data,
+            agg_share.data.as_ref().unwrap().get_encoded().unwrap()
+        );
+    }
+
+    async_test_versions! { agg_share_aad_for_collector }
+
     async fn handle_unrecognized_report_extensions(version: DapVersion) {
         let t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
         let report = t
@@ -797,6 +1152,62 @@ mod test {
 
     async_test_versions! { handle_repeated_report_extensions }
 
+    async fn handle_verify_key_epochs(version: DapVersion) {
+        let mut t = AggregationJobTest::new(TEST_VDAF, HpkeKemId::X25519HkdfSha256, version);
+        t.task_config.vdaf_verify_keys_by_epoch = HashMap::from([
+            (1, TEST_VDAF.gen_verify_key()),
+            (2, TEST_VDAF.gen_verify_key()),
+        ]);
+        let produce_report = |epoch: Option<u8>| {
+            t.task_config
+                .vdaf
+                .produce_report_with_extensions(
+                    &t.client_hpke_config_list,
+                    t.now,
+                    &t.task_id,
+                    DapMeasurement::U64(1),
+                    epoch
+                        .map(|epoch| Extension::VerifyKeyEpoch { epoch })
+                        .into_iter()
+                        .collect(),
+                    t.task_config.version,
+                )
+                .unwrap()
+        };
+
+        // Reports for an unknown epoch are rejected.
+        let report = produce_report(Some(3));
+        let [leader_share, _] = report.encrypted_input_shares;
+        let consumed_report = EarlyReportStateConsumed::consume(
+            &t.leader_hpke_receiver_config,
+            &t,
+            true,
+            &t.task_id,
+            &t.task_config,
+            ReportShare {
+                report_metadata: report.report_metadata,
+                public_share: report.public_share,
+                encrypted_input_share: leader_share,
+            },
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(!consumed_report.is_ready());
+
+        // Reports under either epoch, or under the default key, are aggregated together.
+        let reports = [None, Some(1), Some(1), Some(2)]
+            .into_iter()
+            .map(produce_report)
+            .collect();
+        let got = t
+            .roundtrip_reports(DapAggregationParam::Empty, reports)
+            .await;
+        assert_eq!(got, DapAggregateResult::U64(4));
+    }
+
+    async_test_versions! { handle_verify_key_epochs }
+
     impl AggregationJobTest {
         // Tweak the Helper's share so that decoding succeeds but preparation fails.
         fn produce_invalid_report_vdaf_prep_failure(
@@ -36,6 +36,20 @@ pub trait DapReportInitializer {
     ) -> Result<Vec<EarlyReportStateInitialized>, DapError>;
 }
 
+/// Return the key under which replay protection records that the report with the given ID was
+/// aggregated for the given task: the SHA-256 hash of the task ID followed by the report ID.
+/// Storage backends use this both to record reports and to check for replays, so that the two
+/// can't diverge.
+pub fn replay_key(task_id: &TaskId, report_id: &ReportId) -> [u8; 32] {
+    let mut ctx = ring::digest::Context::new(&ring::digest::SHA256);
+    ctx.update(task_id.as_ref());
+    ctx.update(report_id.as_ref());
+    ctx.finish()
+        .as_ref()
+        .try_into()
+        .expect("SHA-256 digest is 32 bytes")
+}
+
 #[derive(Debug)]
 pub enum MergeAggShareError {
     AlreadyCollected,
@@ -175,8 +189,8 @@ where
 {
     let metrics = aggregator.metrics();
 
-    let hpke_config = aggregator
-        .get_hpke_config_for(req.version, task_id.as_ref())
+    let hpke_configs = aggregator
+        .get_hpke_config_list_for(req.version, task_id.as_ref())
         .await?;
 
     if let Some(task_id) = task_id {
@@ -194,9 +208,7 @@ where
     }
 
     let payload = {
-        let hpke_config_list = HpkeConfigList {
-            hpke_configs: vec![hpke_config.clone()],
-        };
+        let hpke_config_list = HpkeConfigList { hpke_configs };
         hpke_config_list.get_encoded().map_err(DapError::encoding)?
     };
 
@@ -207,3 +219,26 @@ where
         payload,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::replay_key;
+    use crate::messages::{ReportId, TaskId};
+
+    #[test]
+    fn replay_key_is_deterministic() {
+        let report_id = ReportId([1; 16]);
+        assert_eq!(
+            replay_key(&TaskId([2; 32]), &report_id),
+            replay_key(&TaskId([2; 32]), &report_id)
+        );
+        assert_ne!(
+            replay_key(&TaskId([2; 32]), &report_id),
+            replay_key(&TaskId([3; 32]), &report_id)
+        );
+        assert_ne!(
+            replay_key(&TaskId([2; 32]), &report_id),
+            replay_key(&TaskId([2; 32]), &ReportId([4; 16]))
+        );
+    }
+}
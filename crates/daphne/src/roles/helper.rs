@@ -1,19 +1,25 @@
 // Copyright (c) 2022 Cloudflare, Inc. All rights reserved.
 // SPDX-License-Identifier: BSD-3-Clause
 
-use std::{collections::HashMap, sync::Once};
+use std::{collections::HashMap, iter::zip, sync::Once};
 
 use async_trait::async_trait;
 use prio::codec::{Encode, ParameterizedDecode};
-use tracing::error;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
 
-use super::{check_batch, check_request_content_type, resolve_taskprov, DapAggregator};
+use super::{
+    check_batch, check_request_content_type, record_agg_job_id, record_task_id, resolve_taskprov,
+    DapAggregator,
+};
 use crate::{
     constants::DapMediaType,
     error::DapAbort,
+    fatal_error,
     messages::{
-        constant_time_eq, AggregateShare, AggregateShareReq, AggregationJobInitReq,
-        AggregationJobResp, PartialBatchSelector, TaskId, TransitionFailure, TransitionVar,
+        constant_time_eq, encode_base64url, AggregateShare, AggregateShareReq, AggregationJobId,
+        AggregationJobInitReq, AggregationJobResp, Base64Encode, BatchSelector,
+        PartialBatchSelector, ReportId, TaskId, TransitionFailure, TransitionVar,
     },
     metrics::{DaphneMetrics, DaphneRequestType, ReportStatus},
     protocol::aggregator::{ReplayProtection, ReportProcessedStatus},
@@ -22,10 +28,188 @@ use crate::{
     EarlyReportStateInitialized,
 };
 
+/// The response to an aggregation job request, stored by the Helper so that the same response can
+/// be returned if the Leader retries the request.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct StoredAggregationJobResp {
+    /// SHA-256 digest of the request payload.
+    #[serde(with = "hex")]
+    pub req_digest: [u8; 32],
+
+    /// The encoded [`AggregationJobResp`].
+    #[serde(with = "hex")]
+    pub payload: Vec<u8>,
+}
+
+/// Identifies an aggregate share request. Requests for the same batch with the same checksum are
+/// answered with the same aggregate share, so a retried request can be served from a cache.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct AggShareReqKey {
+    pub task_id: TaskId,
+    pub batch_sel: BatchSelector,
+    pub checksum: [u8; 32],
+}
+
+impl std::fmt::Display for AggShareReqKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let batch_sel = self.batch_sel.get_encoded().map_err(|_| std::fmt::Error)?;
+        write!(
+            f,
+            "{}/{}/{}",
+            self.task_id,
+            encode_base64url(batch_sel),
+            hex::encode(self.checksum)
+        )
+    }
+}
+
+/// The response to an aggregate share request, cached by the Helper for a short time so that a
+/// retried request can be answered without recomputing the aggregate share.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct StoredAggregateShareResp {
+    /// SHA-256 digest of the request payload.
+    #[serde(with = "hex")]
+    pub req_digest: [u8; 32],
+
+    /// The encoded [`AggregateShare`].
+    #[serde(with = "hex")]
+    pub payload: Vec<u8>,
+}
+
+/// Why the Helper rejected some of the reports of an aggregation job. The [`AggregationJobResp`]
+/// only carries a [`TransitionFailure`] for each rejected report; this adds a short description
+/// of the cause the Helper observed. It is not part of DAP: it is only recorded for jobs with
+/// rejected reports if [`DapGlobalConfig::record_agg_job_diagnostics`](crate::DapGlobalConfig) is
+/// set, and is served on an internal endpoint from which the Leader fetches it for logging.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct AggregationJobDiagnostics {
+    pub failures: Vec<ReportFailure>,
+}
+
+/// A report rejected by the Helper. See [`AggregationJobDiagnostics`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct ReportFailure {
+    pub report_id: ReportId,
+    pub failure: TransitionFailure,
+    pub reason: String,
+}
+
+impl AggregationJobDiagnostics {
+    /// Collect the rejected reports of an aggregation job. The transitions of `agg_job_resp` are
+    /// in the same order as `initialized_reports`.
+    fn new(
+        initialized_reports: &[EarlyReportStateInitialized],
+        agg_job_resp: &AggregationJobResp,
+    ) -> Self {
+        let failures = zip(initialized_reports, &agg_job_resp.transitions)
+            .filter_map(|(initialized_report, transition)| {
+                let TransitionVar::Failed(failure) = transition.var else {
+                    return None;
+                };
+                let reason = match initialized_report {
+                    EarlyReportStateInitialized::Rejected { reason, .. } => *reason,
+                    // A report that was initialized is only rejected by replay protection, because
+                    // its batch was collected or because VDAF preparation failed to finish.
+                    EarlyReportStateInitialized::Ready { .. } => match failure {
+                        TransitionFailure::ReportReplayed => "report was already aggregated",
+                        TransitionFailure::BatchCollected => "report's batch was already collected",
+                        _ => "VDAF preparation failed to finish",
+                    },
+                };
+                Some(ReportFailure {
+                    report_id: transition.report_id,
+                    failure,
+                    reason: reason.into(),
+                })
+            })
+            .collect();
+        Self { failures }
+    }
+}
+
 /// DAP Helper functionality.
 #[async_trait]
-pub trait DapHelper<S: Sync>: DapAggregator<S> {}
+pub trait DapHelper<S: Sync>: DapAggregator<S> {
+    /// Store the response to an aggregation job request. Implementations may choose not to store
+    /// it, in which case a retried request is processed again and its reports are rejected as
+    /// replays.
+    ///
+    /// The response is stored after the job has been processed, so the check made with
+    /// [`Self::get_agg_job_resp`] is not atomic with the store: if two different requests for the
+    /// same job are processed concurrently, both may pass the check, and on an eventually
+    /// consistent store either response may be kept.
+    async fn put_agg_job_resp(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        agg_job_resp: StoredAggregationJobResp,
+    ) -> Result<(), DapError>;
+
+    /// Get the response to a previous request for an aggregation job, if any.
+    async fn get_agg_job_resp(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<StoredAggregationJobResp>, DapError>;
+
+    /// Store the diagnostics of an aggregation job with rejected reports. This is only called if
+    /// [`DapGlobalConfig::record_agg_job_diagnostics`](crate::DapGlobalConfig) is set.
+    async fn put_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        diagnostics: AggregationJobDiagnostics,
+    ) -> Result<(), DapError>;
+
+    /// Get the diagnostics of an aggregation job, if they were recorded.
+    async fn get_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobDiagnostics>, DapError>;
+
+    /// Mark an aggregation job as aborted. Subsequent requests for the job are rejected. Other
+    /// state stored for the job, such as its response or diagnostics, may be kept until it
+    /// expires.
+    async fn abort_agg_job(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<(), DapError>;
+
+    /// Check whether an aggregation job was aborted by [`DapHelper::abort_agg_job`]. This is called
+    /// for every aggregation job request, so implementations that never abort jobs should avoid
+    /// the lookup.
+    async fn agg_job_aborted(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<bool, DapError>;
+
+    /// Cache the response to an aggregate share request. The entry is expected to expire after a
+    /// short, implementation-defined window, and to be discarded if reports are added to the batch.
+    async fn put_agg_share_resp(
+        &self,
+        key: &AggShareReqKey,
+        agg_share_resp: StoredAggregateShareResp,
+    ) -> Result<(), DapError>;
+
+    /// Get the cached response to a previous aggregate share request, if any.
+    async fn get_agg_share_resp(
+        &self,
+        key: &AggShareReqKey,
+    ) -> Result<Option<StoredAggregateShareResp>, DapError>;
+}
 
+#[tracing::instrument(
+    skip_all,
+    fields(task_id = tracing::field::Empty, agg_job_id = tracing::field::Empty),
+)]
 pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
     aggregator: &A,
     req: &'req DapRequest<S>,
@@ -33,6 +217,10 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
 ) -> Result<DapResponse, DapError> {
     let global_config = aggregator.get_global_config().await?;
     let task_id = req.task_id()?;
+    record_task_id(task_id);
+    if let DapResource::AggregationJob(agg_job_id) = &req.resource {
+        record_agg_job_id(agg_job_id);
+    }
     let metrics = aggregator.metrics();
     let agg_job_init_req =
         AggregationJobInitReq::get_decoded_with_param(&req.version, &req.payload)
@@ -60,15 +248,61 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
         .into());
     }
 
-    let DapResource::AggregationJob(_agg_job_id) = req.resource else {
+    let DapResource::AggregationJob(agg_job_id) = req.resource else {
         return Err(DapAbort::BadRequest("missing aggregation job ID".to_string()).into());
     };
 
+    // Bound the work done for a single aggregation job. This is checked before any report is
+    // decrypted.
+    if let Some(max_reports) = global_config.max_reports_per_agg_job {
+        let num_reports = agg_job_init_req.prep_inits.len();
+        if num_reports > max_reports.get() {
+            return Err(DapAbort::BadRequest(format!(
+                "aggregation job has {num_reports} reports; the maximum is {max_reports}"
+            ))
+            .into());
+        }
+    }
+
+    if aggregator.agg_job_aborted(task_id, &agg_job_id).await? {
+        return Err(DapAbort::UnrecognizedAggregationJob {
+            task_id: *task_id,
+            agg_job_id,
+        }
+        .into());
+    }
+
     // Check whether the DAP version in the request matches the task config.
     if task_config.version != req.version {
         return Err(DapAbort::version_mismatch(req.version, task_config.version).into());
     }
 
+    // If the Leader is retrying a request, then respond as we did before. A different request for
+    // the same aggregation job is rejected. This is best-effort: see `DapHelper::put_agg_job_resp`.
+    let req_digest: [u8; 32] = ring::digest::digest(&ring::digest::SHA256, &req.payload)
+        .as_ref()
+        .try_into()
+        .map_err(|e| fatal_error!(err = ?e, "unexpected digest length"))?;
+    if let Some(stored) = aggregator.get_agg_job_resp(task_id, &agg_job_id).await? {
+        if stored.req_digest != req_digest {
+            return Err(DapAbort::InvalidMessage {
+                detail: format!(
+                    "aggregation job {} was initialized with a different request",
+                    agg_job_id.to_base64url()
+                ),
+                task_id: *task_id,
+            }
+            .into());
+        }
+
+        metrics.inbound_req_inc(DaphneRequestType::Aggregate);
+        return Ok(DapResponse {
+            version: req.version,
+            media_type: DapMediaType::AggregationJobResp,
+            payload: stored.payload,
+        });
+    }
+
     // Ensure we know which batch the request pertains to.
     check_part_batch(
         task_id,
@@ -77,6 +311,11 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
         &agg_job_init_req.agg_param,
     )?;
 
+    // An aggregation job without reports does no useful work.
+    if agg_job_init_req.prep_inits.is_empty() {
+        return Err(DapAbort::BadRequest("empty aggregation job".to_string()).into());
+    }
+
     let part_batch_sel = agg_job_init_req.part_batch_sel.clone();
     let initialized_reports = task_config
         .consume_agg_job_req(
@@ -85,8 +324,16 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
             task_id,
             agg_job_init_req,
             replay_protection,
+            global_config.agg_job_report_concurrency,
         )
         .await?;
+    info!(
+        state = "agg_job_initialized",
+        task_id = %task_id,
+        agg_job_id = %agg_job_id,
+        report_count = initialized_reports.len(),
+        "aggregation job initialized"
+    );
 
     let agg_job_resp = {
         let agg_job_resp = finish_agg_job_and_aggregate(
@@ -103,6 +350,13 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
         metrics.agg_job_completed_inc();
         agg_job_resp
     };
+    info!(
+        state = "agg_job_finished",
+        task_id = %task_id,
+        agg_job_id = %agg_job_id,
+        report_count = agg_job_resp.transitions.len(),
+        "aggregation job finished"
+    );
 
     aggregator.audit_log().on_aggregation_job(
         task_id,
@@ -111,11 +365,38 @@ pub async fn handle_agg_job_init_req<'req, S: Sync, A: DapHelper<S>>(
         0, /* vdaf step */
     );
 
+    if global_config.record_agg_job_diagnostics {
+        let diagnostics = AggregationJobDiagnostics::new(&initialized_reports, &agg_job_resp);
+        if !diagnostics.failures.is_empty() {
+            warn!(
+                task_id = %task_id,
+                agg_job_id = %agg_job_id,
+                failed_report_count = diagnostics.failures.len(),
+                "aggregation job has rejected reports"
+            );
+            aggregator
+                .put_agg_job_diagnostics(task_id, &agg_job_id, diagnostics)
+                .await?;
+        }
+    }
+
+    let payload = agg_job_resp.get_encoded().map_err(DapError::encoding)?;
+    aggregator
+        .put_agg_job_resp(
+            task_id,
+            &agg_job_id,
+            StoredAggregationJobResp {
+                req_digest,
+                payload: payload.clone(),
+            },
+        )
+        .await?;
+
     metrics.inbound_req_inc(DaphneRequestType::Aggregate);
     Ok(DapResponse {
         version: req.version,
         media_type: DapMediaType::AggregationJobResp,
-        payload: agg_job_resp.get_encoded().map_err(DapError::encoding)?,
+        payload,
     })
 }
 
@@ -125,16 +406,35 @@ pub async fn handle_agg_job_req<'req, S: Sync, A: DapHelper<S>>(
     req: &DapRequest<S>,
     replay_protection: ReplayProtection,
 ) -> Result<DapResponse, DapError> {
-    match req.media_type {
-        Some(DapMediaType::AggregationJobInitReq) => {
-            handle_agg_job_init_req(aggregator, req, replay_protection).await
-        }
-        _ => Err(DapAbort::BadRequest("unexpected media type".into()).into()),
+    check_request_content_type(req, DapMediaType::AggregationJobInitReq)?;
+    handle_agg_job_init_req(aggregator, req, replay_protection).await
+}
+
+/// Cancel an aggregation job. Subsequent requests for it are rejected with
+/// [`DapAbort::UnrecognizedAggregationJob`]. See [`DapHelper::abort_agg_job`] for what happens to
+/// the state stored for the job.
+pub async fn cancel_aggregation_job<S: Sync, A: DapHelper<S>>(
+    aggregator: &A,
+    task_id: &TaskId,
+    agg_job_id: &AggregationJobId,
+) -> Result<(), DapError> {
+    if aggregator.get_task_config_for(task_id).await?.is_none() {
+        return Err(DapAbort::UnrecognizedTask { task_id: *task_id }.into());
     }
+
+    aggregator.abort_agg_job(task_id, agg_job_id).await?;
+    info!(
+        state = "agg_job_cancelled",
+        task_id = %task_id,
+        agg_job_id = %agg_job_id,
+        "aggregation job cancelled"
+    );
+    Ok(())
 }
 
 /// Handle a request for an aggregate share. This is called by the Leader to complete a
 /// collection job.
+#[tracing::instrument(skip_all, fields(task_id = tracing::field::Empty))]
 pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
     aggregator: &A,
     req: &DapRequest<S>,
@@ -143,6 +443,7 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
     let now = aggregator.get_current_time();
     let metrics = aggregator.metrics();
     let task_id = req.task_id()?;
+    record_task_id(task_id);
 
     check_request_content_type(req, DapMediaType::AggregateShareReq)?;
 
@@ -177,6 +478,28 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
         DapAggregationParam::get_decoded_with_param(&task_config.vdaf, &agg_share_req.agg_param)
             .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
 
+    // If the Leader is retrying a request, then respond as we did before. Note that the batch has
+    // already been marked as collected, so the request would otherwise be rejected.
+    let cache_key = AggShareReqKey {
+        task_id: *task_id,
+        batch_sel: agg_share_req.batch_sel.clone(),
+        checksum: agg_share_req.checksum,
+    };
+    let req_digest: [u8; 32] = ring::digest::digest(&ring::digest::SHA256, &req.payload)
+        .as_ref()
+        .try_into()
+        .map_err(|e| fatal_error!(err = ?e, "unexpected digest length"))?;
+    if let Some(stored) = aggregator.get_agg_share_resp(&cache_key).await? {
+        if stored.req_digest == req_digest {
+            info!(task_id = %task_id, "serving aggregate share from cache");
+            return Ok(DapResponse {
+                version: req.version,
+                media_type: DapMediaType::AggregateShare,
+                payload: stored.payload,
+            });
+        }
+    }
+
     // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
     // collected batches.
     check_batch(
@@ -194,8 +517,22 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
         .get_agg_share(task_id, &agg_share_req.batch_sel)
         .await?;
 
-    // Check that we have aggreagted the same set of reports as the Leader.
-    if agg_share_req.report_count != agg_share.report_count
+    // Refuse to attest to an aggregate share that is evidently corrupted.
+    agg_share.check_invariants()?;
+
+    // Check that we have aggreagted the same set of reports as the Leader. A small difference in
+    // the report count may be tolerated while aggregate storage is being migrated; the checksums
+    // necessarily differ in that case.
+    let report_count_diff = agg_share_req.report_count.abs_diff(agg_share.report_count);
+    if report_count_diff > 0 && report_count_diff <= global_config.agg_share_report_count_tolerance
+    {
+        warn!(
+            task_id = %task_id,
+            leader_report_count = agg_share_req.report_count,
+            helper_report_count = agg_share.report_count,
+            "tolerating report count mismatch between the Leader and Helper; the aggregate result may be inaccurate"
+        );
+    } else if report_count_diff > 0
         || !constant_time_eq(&agg_share_req.checksum, &agg_share.checksum)
     {
         return Err(DapAbort::BatchMismatch{
@@ -210,7 +547,11 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
 
     // Check the batch size.
     if !task_config
-        .is_report_count_compatible(task_id, agg_share.report_count)
+        .is_report_count_compatible(
+            task_id,
+            agg_share.report_count,
+            global_config.allow_partial_batch,
+        )
         .unwrap_or(false)
     {
         return Err(DapAbort::InvalidBatchSize {
@@ -227,6 +568,12 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
     aggregator
         .mark_collected(task_id, &agg_share_req.batch_sel)
         .await?;
+    info!(
+        state = "batch_collected",
+        task_id = %task_id,
+        report_count = agg_share_req.report_count,
+        "batch collected"
+    );
 
     let encrypted_agg_share = task_config.produce_helper_encrypted_agg_share(
         &task_config.collector_hpke_config,
@@ -240,13 +587,24 @@ pub async fn handle_agg_share_req<'req, S: Sync, A: DapHelper<S>>(
     let agg_share_resp = AggregateShare {
         encrypted_agg_share,
     };
+    let payload = agg_share_resp.get_encoded().map_err(DapError::encoding)?;
+
+    aggregator
+        .put_agg_share_resp(
+            &cache_key,
+            StoredAggregateShareResp {
+                req_digest,
+                payload: payload.clone(),
+            },
+        )
+        .await?;
 
     metrics.report_inc_by(ReportStatus::Collected, agg_share_req.report_count);
     metrics.inbound_req_inc(DaphneRequestType::Collect);
     Ok(DapResponse {
         version: req.version,
         media_type: DapMediaType::AggregateShare,
-        payload: agg_share_resp.get_encoded().map_err(DapError::encoding)?,
+        payload,
     })
 }
 
@@ -265,13 +623,7 @@ fn check_part_batch(
     }
 
     // Check that the aggregation parameter is suitable for the given VDAF.
-    if !task_config.vdaf.is_valid_agg_param(agg_param) {
-        // TODO spec: Define this behavior.
-        return Err(DapAbort::InvalidMessage {
-            detail: "invalid aggregation parameter".into(),
-            task_id: *task_id,
-        });
-    }
+    task_config.vdaf.validate_agg_param(task_id, agg_param)?;
 
     Ok(())
 }
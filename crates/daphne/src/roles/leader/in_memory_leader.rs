@@ -6,26 +6,79 @@
 //! crash or shutdown would cause in progress tasks to be lost.
 
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     num::NonZeroUsize,
 };
 
 use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
 use url::Url;
 
 use crate::{
     error::DapAbort,
     fatal_error,
-    messages::{Base64Encode, BatchId, BatchSelector, Collection, CollectionJobId, Report, TaskId},
-    roles::leader::WorkItem,
+    messages::{
+        Base64Encode, BatchId, BatchSelector, Collection, CollectionJobId, Report, ReportId,
+        TaskId, Time,
+    },
+    roles::leader::{QuarantinedAggJob, WorkItem},
     DapAggregationParam, DapBatchBucket, DapCollectionJob, DapError, DapQueryConfig, DapTaskConfig,
 };
 
+/// Version of the format produced by [`InMemoryLeaderState::export_collect_jobs`].
+const COLLECT_JOBS_EXPORT_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct CollectJobsExportVersion {
+    version: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CollectJobsExport {
+    version: u32,
+    coll_jobs: Vec<ExportedCollectJob>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ExportedCollectJob {
+    task_id: TaskId,
+    coll_job_id: CollectionJobId,
+    status: DapCollectionJob,
+    /// The batch selector and aggregation parameter of the job if it is still in the work queue.
+    queued: Option<(BatchSelector, DapAggregationParam)>,
+    /// When the job was created, if it is still in the work queue. Older exports don't have it, in
+    /// which case the job is considered created when imported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    created_at: Option<Time>,
+}
+
 #[derive(Default)]
 #[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
 pub struct InMemoryLeaderState {
     work_queue: VecDeque<WorkItem>,
     per_task: HashMap<TaskId, MockLeaderMemoryPerTask>,
+    quarantine: JobQuarantine,
+}
+
+/// Aggregation jobs that were aborted by the Helper and are awaiting review by an operator.
+#[derive(Default)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct JobQuarantine {
+    jobs: Vec<QuarantinedAggJob>,
+}
+
+impl JobQuarantine {
+    pub fn put(&mut self, job: QuarantinedAggJob) {
+        self.jobs.push(job);
+    }
+
+    pub fn list(&self) -> &[QuarantinedAggJob] {
+        &self.jobs
+    }
+
+    pub fn clear(&mut self) {
+        self.jobs.clear();
+    }
 }
 
 impl InMemoryLeaderState {
@@ -55,6 +108,112 @@ impl InMemoryLeaderState {
     pub fn delete_all(&mut self) {
         self.work_queue.clear();
         self.per_task.clear();
+        self.quarantine.clear();
+    }
+
+    pub fn quarantine(&self) -> &JobQuarantine {
+        &self.quarantine
+    }
+
+    pub fn quarantine_mut(&mut self) -> &mut JobQuarantine {
+        &mut self.quarantine
+    }
+
+    /// Serialize the collection jobs, including the order in which pending jobs are queued.
+    /// Aggregation jobs and pending reports are not included.
+    pub fn export_collect_jobs(&self) -> Result<Vec<u8>, DapError> {
+        let mut coll_jobs = Vec::new();
+        let mut queued = HashSet::new();
+        for work_item in &self.work_queue {
+            if let WorkItem::CollectionJob {
+                task_id,
+                coll_job_id,
+                batch_sel,
+                agg_param,
+                created_at,
+            } = work_item
+            {
+                let status = self
+                    .per_task
+                    .get(task_id)
+                    .and_then(|per_task| per_task.coll_jobs.get(coll_job_id))
+                    .cloned()
+                    .ok_or_else(|| fatal_error!(err = "queued collect job not found", %task_id))?;
+                coll_jobs.push(ExportedCollectJob {
+                    task_id: *task_id,
+                    coll_job_id: *coll_job_id,
+                    status,
+                    queued: Some((batch_sel.clone(), agg_param.clone())),
+                    created_at: Some(*created_at),
+                });
+                queued.insert((*task_id, *coll_job_id));
+            }
+        }
+
+        for (task_id, per_task) in &self.per_task {
+            for (coll_job_id, status) in &per_task.coll_jobs {
+                if !queued.contains(&(*task_id, *coll_job_id)) {
+                    coll_jobs.push(ExportedCollectJob {
+                        task_id: *task_id,
+                        coll_job_id: *coll_job_id,
+                        status: status.clone(),
+                        queued: None,
+                        created_at: None,
+                    });
+                }
+            }
+        }
+
+        serde_json::to_vec(&CollectJobsExport {
+            version: COLLECT_JOBS_EXPORT_VERSION,
+            coll_jobs,
+        })
+        .map_err(|e| fatal_error!(err = ?e, "failed to serialize collect jobs"))
+    }
+
+    /// Restore collection jobs serialized by [`Self::export_collect_jobs`]. Jobs that were queued
+    /// are appended to the work queue.
+    pub fn import_collect_jobs(&mut self, bytes: &[u8], now: Time) -> Result<(), DapError> {
+        let CollectJobsExportVersion { version } = serde_json::from_slice(bytes)
+            .map_err(|e| fatal_error!(err = ?e, "failed to parse collect job export"))?;
+        if version != COLLECT_JOBS_EXPORT_VERSION {
+            return Err(fatal_error!(
+                err = format!("unsupported collect job export version {version}")
+            ));
+        }
+
+        let export: CollectJobsExport = serde_json::from_slice(bytes)
+            .map_err(|e| fatal_error!(err = ?e, "failed to parse collect job export"))?;
+        // Make sure no existing job is overwritten before modifying any state.
+        if let Some(coll_job) = export.coll_jobs.iter().find(|coll_job| {
+            self.per_task
+                .get(&coll_job.task_id)
+                .is_some_and(|per_task| per_task.coll_jobs.contains_key(&coll_job.coll_job_id))
+        }) {
+            return Err(DapError::Abort(DapAbort::BadRequest(format!(
+                "tried to overwrite collection job {}",
+                coll_job.coll_job_id.to_base64url()
+            ))));
+        }
+
+        for coll_job in export.coll_jobs {
+            self.per_task
+                .entry(coll_job.task_id)
+                .or_default()
+                .coll_jobs
+                .insert(coll_job.coll_job_id, coll_job.status);
+
+            if let Some((batch_sel, agg_param)) = coll_job.queued {
+                self.work_queue.push_back(WorkItem::CollectionJob {
+                    task_id: coll_job.task_id,
+                    coll_job_id: coll_job.coll_job_id,
+                    batch_sel,
+                    agg_param,
+                    created_at: coll_job.created_at.unwrap_or(now),
+                });
+            }
+        }
+        Ok(())
     }
 
     pub fn put_report(
@@ -77,6 +236,60 @@ impl InMemoryLeaderState {
         Ok(())
     }
 
+    /// Delete the pending reports with a timestamp before `before`. Reports already queued for
+    /// aggregation are kept. Return the number of reports deleted.
+    pub fn sweep_expired_reports(&mut self, before: Time) -> u64 {
+        let mut swept = 0;
+        for per_task in self.per_task.values_mut() {
+            per_task.pending_reports.retain(|bucket, reports| {
+                let count = reports.len();
+                reports.retain(|report| report.report_metadata.time >= before);
+                let bucket_swept = count - reports.len();
+                swept += bucket_swept as u64;
+
+                // Make room in the batch for new reports.
+                if let DapBatchBucket::FixedSize { batch_id, .. } = bucket {
+                    if let Some((_, report_count)) = per_task
+                        .batch_queue
+                        .iter_mut()
+                        .find(|(queued_batch_id, _)| queued_batch_id == batch_id)
+                    {
+                        *report_count = report_count.saturating_sub(bucket_swept as u64);
+                    }
+                }
+
+                !reports.is_empty()
+            });
+        }
+        swept
+    }
+
+    /// Return the reports for the given task with the given IDs that have not yet been aggregated,
+    /// i.e., are pending or queued for aggregation.
+    pub fn get_reports(&self, task_id: &TaskId, report_ids: &[ReportId]) -> Vec<Report> {
+        let pending = self
+            .per_task
+            .get(task_id)
+            .into_iter()
+            .flat_map(|per_task| per_task.pending_reports.values().flatten());
+        let queued = self
+            .work_queue
+            .iter()
+            .flat_map(|work_item| match work_item {
+                WorkItem::AggregationJob {
+                    task_id: queued_task_id,
+                    reports,
+                    ..
+                } if queued_task_id == task_id => reports.as_slice(),
+                _ => &[],
+            });
+        pending
+            .chain(queued)
+            .filter(|report| report_ids.contains(&report.report_metadata.id))
+            .cloned()
+            .collect()
+    }
+
     pub fn current_batch(
         &self,
         task_id: &TaskId,
@@ -101,6 +314,32 @@ impl InMemoryLeaderState {
             .ok_or_else(|| DapError::Abort(DapAbort::BadRequest("empty batch queue".into())))
     }
 
+    /// Fixed-size tasks: List the batches that have not yet been collected, oldest first, along
+    /// with the number of reports assigned to each. Batches that have reached the task's
+    /// `max_batch_size` are sealed and therefore excluded.
+    pub fn list_current_batches(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+    ) -> std::result::Result<Vec<(BatchId, u64)>, DapError> {
+        let DapQueryConfig::FixedSize { max_batch_size } = task_config.query else {
+            return Err(DapError::Abort(DapAbort::BadRequest(
+                "tried to list current batches of non fixed-size task".into(),
+            )));
+        };
+
+        Ok(self
+            .per_task
+            .get(task_id)
+            .into_iter()
+            .flat_map(|per_task| per_task.batch_queue.iter())
+            .filter(|(_batch_id, report_count)| {
+                max_batch_size.map_or(true, |max_batch_size| *report_count < max_batch_size)
+            })
+            .copied()
+            .collect())
+    }
+
     pub fn enqueue_work(&mut self, work_items: Vec<WorkItem>) -> Result<(), DapError> {
         self.work_queue.extend(work_items);
         Ok(())
@@ -124,6 +363,7 @@ impl InMemoryLeaderState {
         coll_job_id: &CollectionJobId,
         batch_sel: BatchSelector,
         agg_param: DapAggregationParam,
+        now: Time,
     ) -> Result<Url, DapError> {
         let per_task = self.per_task.entry(*task_id).or_default();
 
@@ -145,13 +385,37 @@ impl InMemoryLeaderState {
             ))));
         }
 
+        // Count the query against each bucket of the batch. Queries for overlapping time
+        // intervals share buckets, so each report is counted at most once per query.
+        let span = task_config.batch_span_for_sel(&batch_sel)?;
+        if let Some(bucket) = span.iter().find(|bucket| {
+            per_task
+                .batch_query_count
+                .get(bucket)
+                .is_some_and(|count| *count >= task_config.max_batch_query_count)
+        }) {
+            return Err(DapError::Abort(DapAbort::BatchQueriedTooManyTimes {
+                detail: format!(
+                    "The batch indicated by the request contains {bucket}, which has already been queried {} time(s).",
+                    task_config.max_batch_query_count
+                ),
+                task_id: *task_id,
+            }));
+        }
+        for bucket in &span {
+            *per_task
+                .batch_query_count
+                .entry(bucket.clone())
+                .or_default() += 1;
+        }
+
         per_task
             .coll_jobs
             .insert(*coll_job_id, DapCollectionJob::Pending);
 
         // Fill the work queue. Queue an aggregation job for each bucket of pending reports
         // incident to the collection job.
-        for bucket in task_config.batch_span_for_sel(&batch_sel)? {
+        for bucket in span {
             if let Some(reports) = per_task.pending_reports.remove(&bucket) {
                 self.work_queue.push_back(WorkItem::AggregationJob {
                     task_id: *task_id,
@@ -179,6 +443,7 @@ impl InMemoryLeaderState {
             coll_job_id: *coll_job_id,
             batch_sel,
             agg_param,
+            created_at: now,
         });
 
         Ok(coll_job_uri)
@@ -207,6 +472,28 @@ impl InMemoryLeaderState {
         task_id: &TaskId,
         coll_job_id: &CollectionJobId,
         collection: &Collection,
+    ) -> Result<(), DapError> {
+        self.update_pending_collect_job(
+            task_id,
+            coll_job_id,
+            DapCollectionJob::Done(collection.clone()),
+        )
+    }
+
+    pub fn fail_collect_job(
+        &mut self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        reason: String,
+    ) -> Result<(), DapError> {
+        self.update_pending_collect_job(task_id, coll_job_id, DapCollectionJob::Failed(reason))
+    }
+
+    fn update_pending_collect_job(
+        &mut self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        status: DapCollectionJob,
     ) -> Result<(), DapError> {
         let Some(per_task) = self.per_task.get_mut(task_id) else {
             return Err(fatal_error!(err = "collect job not found for task_id", %task_id));
@@ -218,13 +505,15 @@ impl InMemoryLeaderState {
 
         match coll_job {
             DapCollectionJob::Pending => {
-                // Mark collection job as complete.
-                *coll_job = DapCollectionJob::Done(collection.clone());
+                *coll_job = status;
                 Ok(())
             }
             DapCollectionJob::Done(_) => Err(fatal_error!(
                 err = "tried to overwrite completed collection job"
             )),
+            DapCollectionJob::Failed(_) => Err(fatal_error!(
+                err = "tried to overwrite failed collection job"
+            )),
             DapCollectionJob::Unknown => Err(fatal_error!(
                 err = "tried to overwrite collection job in unkonwn state"
             )),
@@ -238,6 +527,7 @@ struct MockLeaderMemoryPerTask {
     pending_reports: HashMap<DapBatchBucket, VecDeque<Report>>,
     coll_jobs: HashMap<CollectionJobId, DapCollectionJob>,
     batch_queue: VecDeque<(BatchId, u64)>, // Batch ID, batch size
+    batch_query_count: HashMap<DapBatchBucket, u16>,
 }
 
 impl MockLeaderMemoryPerTask {
@@ -282,9 +572,235 @@ impl MockLeaderMemoryPerTask {
             // For time-interval queries, the bucket is the batch window computed by truncating the
             // report timestamp.
             DapQueryConfig::TimeInterval => DapBatchBucket::TimeInterval {
-                batch_window: task_config.quantized_time_lower_bound(report.report_metadata.time),
+                batch_window: task_config.batch_bucket_start(report.report_metadata.time),
                 shard,
             },
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+    use rand::{thread_rng, Rng};
+
+    use super::InMemoryLeaderState;
+    use crate::{
+        error::DapAbort,
+        hpke::{HpkeKemId, HpkeReceiverConfig},
+        messages::{
+            BatchId, BatchSelector, CollectionJobId, HpkeCiphertext, Interval, Report, ReportId,
+            ReportMetadata, TaskId,
+        },
+        DapAggregationParam, DapError, DapQueryConfig, DapTaskConfig, DapTaskParameters,
+    };
+
+    fn task_config(query: DapQueryConfig, max_batch_query_count: u16) -> (TaskId, DapTaskConfig) {
+        let collector_hpke_config = HpkeReceiverConfig::gen(0, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let (mut task_config, task_id, _taskprov_advertisement) = DapTaskParameters {
+            query,
+            ..Default::default()
+        }
+        .to_config_with_taskprov(b"cool task".to_vec(), 0, &[0; 32], &collector_hpke_config)
+        .unwrap();
+        task_config.max_batch_query_count = max_batch_query_count;
+        (task_id, task_config)
+    }
+
+    fn init_collect_job(
+        state: &mut InMemoryLeaderState,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        batch_sel: BatchSelector,
+    ) -> Result<(), DapError> {
+        state
+            .init_collect_job(
+                task_id,
+                task_config,
+                &CollectionJobId(thread_rng().gen()),
+                batch_sel,
+                DapAggregationParam::Empty,
+                0,
+            )
+            .map(|_coll_job_uri| ())
+    }
+
+    #[test]
+    fn init_collect_job_time_interval_batch_queried_too_many_times() {
+        let (task_id, task_config) = task_config(DapQueryConfig::TimeInterval, 2);
+        let time_precision = task_config.time_precision;
+        let mut state = InMemoryLeaderState::default();
+        let batch_sel = |start, duration| BatchSelector::TimeInterval {
+            batch_interval: Interval { start, duration },
+        };
+
+        // Query the batch up to the limit.
+        for _ in 0..2 {
+            init_collect_job(
+                &mut state,
+                &task_id,
+                &task_config,
+                batch_sel(time_precision, time_precision),
+            )
+            .unwrap();
+        }
+
+        // A different query that overlaps with the same reports exceeds the limit.
+        assert_matches!(
+            init_collect_job(
+                &mut state,
+                &task_id,
+                &task_config,
+                batch_sel(0, 2 * time_precision),
+            ),
+            Err(DapError::Abort(DapAbort::BatchQueriedTooManyTimes { .. }))
+        );
+
+        // The rejected query is not counted, so the other bucket can still be queried twice.
+        for _ in 0..2 {
+            init_collect_job(
+                &mut state,
+                &task_id,
+                &task_config,
+                batch_sel(0, time_precision),
+            )
+            .unwrap();
+        }
+        assert_matches!(
+            init_collect_job(
+                &mut state,
+                &task_id,
+                &task_config,
+                batch_sel(0, time_precision),
+            ),
+            Err(DapError::Abort(DapAbort::BatchQueriedTooManyTimes { .. }))
+        );
+    }
+
+    #[test]
+    fn init_collect_job_fixed_size_batch_queried_too_many_times() {
+        let (task_id, task_config) = task_config(
+            DapQueryConfig::FixedSize {
+                max_batch_size: None,
+            },
+            1,
+        );
+        let mut state = InMemoryLeaderState::default();
+        let batch_id = BatchId(thread_rng().gen());
+
+        init_collect_job(
+            &mut state,
+            &task_id,
+            &task_config,
+            BatchSelector::FixedSizeByBatchId { batch_id },
+        )
+        .unwrap();
+        assert_matches!(
+            init_collect_job(
+                &mut state,
+                &task_id,
+                &task_config,
+                BatchSelector::FixedSizeByBatchId { batch_id },
+            ),
+            Err(DapError::Abort(DapAbort::BatchQueriedTooManyTimes { .. }))
+        );
+
+        // Other batches are unaffected.
+        init_collect_job(
+            &mut state,
+            &task_id,
+            &task_config,
+            BatchSelector::FixedSizeByBatchId {
+                batch_id: BatchId(thread_rng().gen()),
+            },
+        )
+        .unwrap();
+    }
+
+    fn put_reports(
+        state: &mut InMemoryLeaderState,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        count: usize,
+    ) {
+        let ciphertext = || HpkeCiphertext {
+            config_id: 0,
+            enc: Vec::new(),
+            payload: Vec::new(),
+        };
+        for _ in 0..count {
+            state
+                .put_report(
+                    task_id,
+                    task_config,
+                    Report {
+                        report_metadata: ReportMetadata {
+                            id: ReportId(thread_rng().gen()),
+                            time: 0,
+                        },
+                        public_share: Vec::new(),
+                        encrypted_input_shares: [ciphertext(), ciphertext()],
+                    },
+                )
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn list_current_batches() {
+        let (task_id, mut task_config) = task_config(
+            DapQueryConfig::FixedSize {
+                max_batch_size: None,
+            },
+            1,
+        );
+        task_config.min_batch_size = 2;
+        let mut state = InMemoryLeaderState::default();
+
+        // The first batch fills up before the second one is opened.
+        put_reports(&mut state, &task_id, &task_config, 3);
+        let batches = state.list_current_batches(&task_id, &task_config).unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            batches[0].0,
+            state.current_batch(&task_id, &task_config).unwrap()
+        );
+        assert_eq!(batches[0].1, 2);
+        assert_eq!(batches[1].1, 1);
+
+        // Batches that reached the maximum batch size are sealed.
+        task_config.query = DapQueryConfig::FixedSize {
+            max_batch_size: Some(2),
+        };
+        assert_eq!(
+            state.list_current_batches(&task_id, &task_config).unwrap(),
+            batches[1..]
+        );
+
+        // Collected batches are no longer listed.
+        init_collect_job(
+            &mut state,
+            &task_id,
+            &task_config,
+            BatchSelector::FixedSizeByBatchId {
+                batch_id: batches[0].0,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            state.list_current_batches(&task_id, &task_config).unwrap(),
+            batches[1..]
+        );
+    }
+
+    #[test]
+    fn list_current_batches_time_interval() {
+        let (task_id, task_config) = task_config(DapQueryConfig::TimeInterval, 1);
+        assert_matches!(
+            InMemoryLeaderState::default().list_current_batches(&task_id, &task_config),
+            Err(DapError::Abort(DapAbort::BadRequest(..)))
+        );
+    }
+}
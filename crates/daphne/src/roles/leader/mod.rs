@@ -9,11 +9,13 @@ use async_trait::async_trait;
 use futures::future::try_join_all;
 use prio::codec::{Decode, Encode, ParameterizedDecode, ParameterizedEncode};
 use rand::{thread_rng, Rng};
-use tracing::{debug, error};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
 use url::Url;
 
 use super::{
-    aggregator::MergeAggShareError, check_batch, check_request_content_type, resolve_taskprov,
+    aggregator::MergeAggShareError, check_batch, check_request_content_type,
+    helper::AggregationJobDiagnostics, record_agg_job_id, record_task_id, resolve_taskprov,
     DapAggregator,
 };
 use crate::{
@@ -23,11 +25,12 @@ use crate::{
     messages::{
         AggregateShare, AggregateShareReq, AggregationJobId, AggregationJobResp, Base64Encode,
         BatchId, BatchSelector, Collection, CollectionJobId, CollectionReq, Interval,
-        PartialBatchSelector, Query, Report, TaskId,
+        PartialBatchSelector, Query, Report, ReportMetadata, TaskId, Time,
     },
     metrics::{DaphneRequestType, ReportStatus},
+    protocol::aggregator::decrypt_input_share,
     DapAggregationParam, DapCollectionJob, DapError, DapLeaderProcessTelemetry, DapRequest,
-    DapResource, DapResponse, DapTaskConfig,
+    DapResource, DapResponse, DapTaskConfig, FutureReportHandling, HelperAbortPolicy,
 };
 
 struct LeaderHttpRequestOptions<'p> {
@@ -116,6 +119,9 @@ pub enum WorkItem {
         coll_job_id: CollectionJobId,
         batch_sel: BatchSelector,
         agg_param: DapAggregationParam,
+        /// When the collection job was created. Used to enforce
+        /// [`DapGlobalConfig::collect_aggregation_deadline`](crate::DapGlobalConfig::collect_aggregation_deadline).
+        created_at: Time,
     },
 }
 
@@ -128,12 +134,43 @@ impl WorkItem {
     }
 }
 
+/// An aggregation job that was set aside for review by an operator after the Helper aborted it.
+/// See [`HelperAbortPolicy::Quarantine`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[cfg_attr(any(test, feature = "test-utils"), derive(deepsize::DeepSizeOf))]
+pub struct QuarantinedAggJob {
+    pub task_id: TaskId,
+    pub agg_job_id: AggregationJobId,
+    pub part_batch_sel: PartialBatchSelector,
+    pub agg_param: DapAggregationParam,
+    /// The reports sent to the Helper in the job.
+    pub reports: Vec<ReportMetadata>,
+    /// The Helper's abort.
+    pub reason: String,
+}
+
 /// DAP Leader functionality.
 #[async_trait]
 pub trait DapLeader<S: Sync>: DapAuthorizedSender<S> + DapAggregator<S> {
+    /// Check that a Client is authorized to upload reports for the given task. The return value has
+    /// the same meaning as for [`DapAggregator::unauthorized_reason`]. By default, uploads are not
+    /// authenticated.
+    async fn upload_unauthorized_reason(
+        &self,
+        _task_config: &DapTaskConfig,
+        _req: &DapRequest<S>,
+    ) -> Result<Option<String>, DapError> {
+        Ok(None)
+    }
+
     /// Store a report for use later on.
     async fn put_report(&self, report: &Report, task_id: &TaskId) -> Result<(), DapError>;
 
+    /// Delete the stored reports with a timestamp before `before` that have not been aggregated.
+    /// Reports that are part of an aggregation job, whether queued or in progress, are kept.
+    /// Return the number of reports deleted.
+    async fn sweep_expired_reports(&self, before: Time) -> Result<u64, DapError>;
+
     /// Fixed-size tasks: Return the ID of the batch currently being filled.
     //
     // TODO draft02 cleanup: Consider removing this.
@@ -170,14 +207,47 @@ pub trait DapLeader<S: Sync>: DapAuthorizedSender<S> + DapAggregator<S> {
         collect_resp: &Collection,
     ) -> Result<(), DapError>;
 
+    /// Mark a pending collect job as failed. `reason` is a diagnostic for the Collector, so it must
+    /// not contain internal error details.
+    async fn fail_collect_job(
+        &self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        reason: String,
+    ) -> Result<(), DapError>;
+
+    /// Serialize the collection jobs known to the Leader, including those that are queued for
+    /// processing, so that they can be restored with [`DapLeader::import_collect_jobs`], e.g., by
+    /// a replacement Leader.
+    async fn export_collect_jobs(&self) -> Result<Vec<u8>, DapError>;
+
+    /// Restore the collection jobs serialized by [`DapLeader::export_collect_jobs`].
+    async fn import_collect_jobs(&self, bytes: &[u8]) -> Result<(), DapError>;
+
+    /// Set aside an aggregation job that the Helper aborted.
+    async fn quarantine_agg_job(&self, job: QuarantinedAggJob) -> Result<(), DapError>;
+
+    /// List the aggregation jobs set aside by [`DapLeader::quarantine_agg_job`].
+    async fn quarantined_agg_jobs(&self) -> Result<Vec<QuarantinedAggJob>, DapError>;
+
     /// Send an HTTP POST request.
     async fn send_http_post(&self, req: DapRequest<S>, url: Url) -> Result<DapResponse, DapError>;
 
     /// Send an HTTP PUT request.
     async fn send_http_put(&self, req: DapRequest<S>, url: Url) -> Result<DapResponse, DapError>;
+
+    /// Fetch the diagnostics the Helper recorded for an aggregation job, if any. See
+    /// [`DapGlobalConfig::record_agg_job_diagnostics`](crate::DapGlobalConfig).
+    async fn get_helper_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        task_config: &DapTaskConfig,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobDiagnostics>, DapError>;
 }
 
 /// Handle a report from a Client.
+#[tracing::instrument(skip_all, fields(task_id = tracing::field::Empty))]
 pub async fn handle_upload_req<S: Sync, A: DapLeader<S>>(
     aggregator: &A,
     req: &DapRequest<S>,
@@ -185,6 +255,7 @@ pub async fn handle_upload_req<S: Sync, A: DapLeader<S>>(
     let global_config = aggregator.get_global_config().await?;
     let metrics = aggregator.metrics();
     let task_id = req.task_id()?;
+    record_task_id(task_id);
     debug!("upload for task {task_id}");
 
     check_request_content_type(req, DapMediaType::Report)?;
@@ -206,6 +277,18 @@ pub async fn handle_upload_req<S: Sync, A: DapLeader<S>>(
         return Err(DapAbort::version_mismatch(req.version, task_config.as_ref().version).into());
     }
 
+    if let Some(reason) = aggregator
+        .upload_unauthorized_reason(task_config.as_ref(), req)
+        .await?
+    {
+        error!("aborted unauthorized upload request: {reason}");
+        return Err(DapAbort::UnauthorizedRequest {
+            detail: reason,
+            task_id: *task_id,
+        }
+        .into());
+    }
+
     if report.encrypted_input_shares.len() != 2 {
         return Err(DapAbort::InvalidMessage {
             detail: format!(
@@ -217,29 +300,75 @@ pub async fn handle_upload_req<S: Sync, A: DapLeader<S>>(
         .into());
     }
 
-    // Check that the indicated HpkeConfig is present.
-    if !aggregator
-        .can_hpke_decrypt(req.task_id()?, report.encrypted_input_shares[0].config_id)
-        .await?
+    // Check that the indicated HpkeConfig is present. If not, the Client likely encrypted the
+    // report under a config that has since been rotated out: reject the report now rather than
+    // during aggregation, and tell the Client which config is current so that it can fetch it and
+    // re-encrypt.
+    let config_id = report.encrypted_input_shares[0].config_id;
+    if let Err(e) = aggregator
+        .check_hpke_config_id_is_active(task_id, config_id)
+        .await
     {
-        return Err(DapAbort::ReportRejected {
-            detail: "No current HPKE configuration matches the indicated ID.".into(),
+        let DapError::Transition(failure) = e else {
+            return Err(e);
+        };
+        metrics.report_inc_by(ReportStatus::Rejected(failure), 1);
+        let current_config_id = aggregator
+            .get_hpke_config_for(req.version, Some(task_id))
+            .await?
+            .id;
+        return Err(DapAbort::OutdatedConfig {
+            detail: format!(
+                "No current HPKE configuration matches the indicated ID ({config_id}). The current \
+                configuration ID is {current_config_id}: fetch it from the hpke_config endpoint and \
+                re-encrypt the report."
+            ),
+            task_id: *task_id,
         }
         .into());
     }
 
-    // Check that the task has not expired.
-    if report.report_metadata.time >= task_config.as_ref().not_after {
-        return Err(DapAbort::ReportTooLate {
+    let now = aggregator.get_current_time();
+    let report_time = report.report_metadata.time;
+
+    // Handle reports from Clients whose clocks are ahead of ours.
+    if report_time > now.saturating_add(global_config.future_report_skew_tolerance)
+        && global_config.future_report_handling == FutureReportHandling::Reject
+    {
+        return Err(DapAbort::ReportTooEarly {
             report_id: report.report_metadata.id,
         }
         .into());
     }
-    if report.report_metadata.time
-        < task_config.as_ref().not_before - task_config.as_ref().time_precision
-    {
-        return Err(DapAbort::ReportRejected {
-            detail: "The timestamp preceeds the start of the task's validity window".into(),
+
+    // Check that the report falls within the task's validity window.
+    if !task_config.as_ref().report_time_is_valid(report_time) {
+        let report_id = report.report_metadata.id;
+        return Err(if report_time >= task_config.as_ref().not_after {
+            DapAbort::ReportTooLate { report_id }
+        } else {
+            DapAbort::ReportTooEarly { report_id }
+        }
+        .into());
+    }
+
+    // Check that the Leader's input share, which the Client is expected to list first, can be
+    // decrypted. This catches reports built with the Leader's and Helper's shares swapped, which
+    // would otherwise only be rejected once aggregation is under way, when the Client can no
+    // longer be told. The plaintext is discarded: reports are stored and put into aggregation
+    // jobs in their encrypted form, so the share is decrypted again during aggregation. This
+    // costs one extra HPKE decryption per report.
+    if let Err(e) = decrypt_input_share(aggregator, task_id, req.version, &report, true).await {
+        let DapError::Transition(failure) = e else {
+            return Err(e);
+        };
+        metrics.report_inc_by(ReportStatus::Rejected(failure), 1);
+        return Err(DapAbort::InvalidMessage {
+            detail: format!(
+                "failed to decrypt the Leader's input share ({failure}): the first encrypted input \
+                share must be the Leader's"
+            ),
+            task_id: *task_id,
         }
         .into());
     }
@@ -248,13 +377,97 @@ pub async fn handle_upload_req<S: Sync, A: DapLeader<S>>(
     // the Leader detects that the report was replayed or pertains to a batch that has already
     // been collected.
     aggregator.put_report(&report, req.task_id()?).await?;
+    info!(
+        state = "report_uploaded",
+        task_id = %task_id,
+        report_id = %report.report_metadata.id,
+        "report uploaded"
+    );
 
     metrics.inbound_req_inc(DaphneRequestType::Upload);
     Ok(())
 }
 
+/// Delete the reports that have not been aggregated within the report retention window set by
+/// [`DapGlobalConfig::report_retention`](crate::DapGlobalConfig::report_retention). Return the
+/// number of reports deleted.
+///
+/// This is meant to be invoked periodically, e.g., by a scheduled job.
+pub async fn sweep_expired_reports<S: Sync, A: DapLeader<S>>(
+    aggregator: &A,
+) -> Result<u64, DapError> {
+    let Some(report_retention) = aggregator.get_global_config().await?.report_retention else {
+        return Ok(0);
+    };
+    let before = aggregator
+        .get_current_time()
+        .saturating_sub(report_retention);
+    let swept = aggregator.sweep_expired_reports(before).await?;
+    if swept > 0 {
+        info!("deleted {swept} expired reports");
+    }
+    Ok(swept)
+}
+
+/// Generate `count` reports with random measurements for the given task and upload them as if
+/// they had been sent by Clients. The reports are encrypted to the Leader's current HPKE config
+/// and to `helper_hpke_config` and are timestamped with the current time, rounded down to the
+/// task's time precision. Return the measurements so that the caller can check the aggregate
+/// result.
+///
+/// This is meant for generating synthetic load in test deployments.
+#[cfg(any(test, feature = "test-utils"))]
+pub async fn inject_synthetic_reports<S: Sync, A: DapLeader<S>>(
+    aggregator: &A,
+    task_id: &TaskId,
+    helper_hpke_config: &crate::hpke::HpkeConfig,
+    count: usize,
+) -> Result<Vec<crate::DapMeasurement>, DapError> {
+    let task_config = aggregator
+        .get_task_config_for(task_id)
+        .await?
+        .ok_or(DapAbort::UnrecognizedTask { task_id: *task_id })?;
+    let task_config = task_config.as_ref();
+    let hpke_config_list = [
+        (*aggregator
+            .get_hpke_config_for(task_config.version, Some(task_id))
+            .await?)
+            .clone(),
+        helper_hpke_config.clone(),
+    ];
+    let time = task_config.quantized_time_lower_bound(aggregator.get_current_time());
+
+    let mut measurements = Vec::with_capacity(count);
+    for _ in 0..count {
+        let measurement = task_config.vdaf.gen_random_measurement(&mut thread_rng())?;
+        let report = task_config.vdaf.produce_report(
+            &hpke_config_list,
+            time,
+            task_id,
+            measurement.clone(),
+            task_config.version,
+        )?;
+        let req = DapRequest {
+            version: task_config.version,
+            media_type: Some(DapMediaType::Report),
+            task_id: Some(*task_id),
+            resource: DapResource::Undefined,
+            payload: report
+                .get_encoded_with_param(&task_config.version)
+                .map_err(DapError::encoding)?,
+            sender_auth: None,
+            taskprov: None,
+        };
+        handle_upload_req(aggregator, &req).await?;
+        measurements.push(measurement);
+    }
+
+    Ok(measurements)
+}
+
 /// Handle a collect job from the Collector. The response is the URI that the Collector will
 /// poll later on to get the collection.
+#[tracing::instrument(skip_all, fields(task_id = tracing::field::Empty))]
 pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
     aggregator: &A,
     req: &DapRequest<S>,
@@ -263,6 +476,7 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
     let now = aggregator.get_current_time();
     let metrics = aggregator.metrics();
     let task_id = req.task_id()?;
+    record_task_id(task_id);
     debug!("collect for task {task_id}");
 
     check_request_content_type(req, DapMediaType::CollectReq)?;
@@ -290,6 +504,12 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
     let coll_job_req = CollectionReq::get_decoded_with_param(&req.version, req.payload.as_ref())
         .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
 
+    if !coll_job_req.query.matches_task_config(task_config) {
+        return Err(
+            DapAbort::query_mismatch(task_id, &task_config.query, &coll_job_req.query).into(),
+        );
+    }
+
     let agg_param =
         DapAggregationParam::get_decoded_with_param(&task_config.vdaf, &coll_job_req.agg_param)
             .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
@@ -299,13 +519,22 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
         return Err(DapAbort::version_mismatch(req.version, task_config.version).into());
     }
 
+    // Store the canonical form of the batch interval. A batch interval that isn't aligned to the
+    // task's time precision is rejected.
+    let query = match coll_job_req.query {
+        Query::TimeInterval { batch_interval } => Query::TimeInterval {
+            batch_interval: batch_interval.normalize_for(task_id, task_config)?,
+        },
+        query => query,
+    };
+
     // Ensure the batch boundaries are valid and that the batch doesn't overlap with previosuly
     // collected batches.
     check_batch(
         aggregator,
         task_config,
         task_id,
-        &coll_job_req.query,
+        &query,
         &coll_job_req.agg_param,
         now,
         &global_config,
@@ -316,7 +545,7 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
         return Err(DapAbort::BadRequest("missing collection ID".into()).into());
     };
 
-    let batch_sel = match coll_job_req.query {
+    let batch_sel = match query {
         Query::TimeInterval { batch_interval } => BatchSelector::TimeInterval { batch_interval },
         Query::FixedSizeByBatchId { batch_id } => BatchSelector::FixedSizeByBatchId { batch_id },
         Query::FixedSizeCurrentBatch => BatchSelector::FixedSizeByBatchId {
@@ -334,6 +563,44 @@ pub async fn handle_coll_job_req<S: Sync, A: DapLeader<S>>(
 
 /// Run an aggregation job for a set of reports. Return the number of reports that were
 /// aggregated successfully.
+#[tracing::instrument(
+    skip_all,
+    fields(task_id = %task_id.to_base64url(), agg_job_id = tracing::field::Empty),
+)]
+/// Log why the Helper rejected reports of an aggregation job. The diagnostics are only a debugging
+/// aid, so failing to fetch them doesn't fail the job.
+async fn log_helper_agg_job_diagnostics<S: Sync, A: DapLeader<S>>(
+    aggregator: &A,
+    task_id: &TaskId,
+    task_config: &DapTaskConfig,
+    agg_job_id: &AggregationJobId,
+) {
+    match aggregator
+        .get_helper_agg_job_diagnostics(task_id, task_config, agg_job_id)
+        .await
+    {
+        Ok(Some(diagnostics)) => {
+            for failure in diagnostics.failures {
+                warn!(
+                    task_id = %task_id,
+                    agg_job_id = %agg_job_id,
+                    report_id = %failure.report_id,
+                    failure = %failure.failure,
+                    reason = failure.reason,
+                    "helper rejected report"
+                );
+            }
+        }
+        Ok(None) => (),
+        Err(e) => warn!(
+            task_id = %task_id,
+            agg_job_id = %agg_job_id,
+            error = ?e,
+            "failed to fetch aggregation job diagnostics from the helper"
+        ),
+    }
+}
+
 async fn run_agg_job<S: Sync, A: DapLeader<S>>(
     aggregator: &A,
     task_id: &TaskId,
@@ -341,6 +608,7 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
     part_batch_sel: &PartialBatchSelector,
     agg_param: &DapAggregationParam,
     reports: Vec<Report>,
+    helper_abort_policy: HelperAbortPolicy,
 ) -> Result<u64, DapError> {
     let metrics = aggregator.metrics();
 
@@ -348,6 +616,7 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
 
     // Prepare AggregationJobInitReq.
     let agg_job_id = AggregationJobId(thread_rng().gen());
+    record_agg_job_id(&agg_job_id);
     let (agg_job_state, agg_job_init_req) = task_config
         .produce_agg_job_req(
             aggregator,
@@ -363,6 +632,13 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
     if agg_job_state.report_count() == 0 {
         return Ok(0);
     }
+    info!(
+        state = "agg_job_initialized",
+        task_id = %task_id,
+        agg_job_id = %agg_job_id,
+        report_count = agg_job_state.report_count(),
+        "aggregation job initialized"
+    );
 
     let url_path = format!(
         "tasks/{}/aggregation_jobs/{}",
@@ -371,7 +647,7 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
     );
 
     // Send AggregationJobInitReq and receive AggregationJobResp.
-    let resp = leader_send_http_request(
+    let resp = match leader_send_http_request(
         aggregator,
         task_id,
         task_config,
@@ -387,16 +663,69 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
             taskprov: taskprov.clone(),
         },
     )
-    .await?;
+    .await
+    {
+        Ok(resp) => resp,
+        // Only an abort by the Helper is a verdict on the job itself. Other errors, e.g., if the
+        // Helper couldn't be reached, are returned as is.
+        Err(DapError::Abort(abort)) if helper_abort_policy == HelperAbortPolicy::Quarantine => {
+            error!(
+                state = "agg_job_quarantined",
+                task_id = %task_id,
+                agg_job_id = %agg_job_id,
+                error = ?abort,
+                "aggregation job aborted by the helper; quarantined"
+            );
+            aggregator
+                .quarantine_agg_job(QuarantinedAggJob {
+                    task_id: *task_id,
+                    agg_job_id,
+                    part_batch_sel: part_batch_sel.clone(),
+                    agg_param: agg_param.clone(),
+                    reports: agg_job_init_req
+                        .prep_inits
+                        .into_iter()
+                        .map(|prep_init| prep_init.report_share.report_metadata)
+                        .collect(),
+                    reason: format!("{abort:?}"),
+                })
+                .await?;
+            return Ok(0);
+        }
+        Err(e) => return Err(e),
+    };
     let agg_job_resp = AggregationJobResp::get_decoded(&resp.payload)
         .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
+    if agg_job_resp.continued_count() < agg_job_resp.transitions.len()
+        && aggregator
+            .get_global_config()
+            .await?
+            .record_agg_job_diagnostics
+    {
+        log_helper_agg_job_diagnostics(aggregator, task_id, task_config, &agg_job_id).await;
+    }
+    info!(
+        state = "agg_job_continued",
+        task_id = %task_id,
+        agg_job_id = %agg_job_id,
+        "aggregation job continued"
+    );
 
     // Handle AggregationJobResp.
     let agg_span =
         task_config.consume_agg_job_resp(task_id, agg_job_state, agg_job_resp, metrics)?;
 
+    // If the Helper rejected every report, then there is nothing left to aggregate and the job
+    // can be finished right away.
     let out_shares_count = agg_span.report_count() as u64;
     if out_shares_count == 0 {
+        info!(
+            state = "agg_job_finished",
+            task_id = %task_id,
+            agg_job_id = %agg_job_id,
+            report_count = 0,
+            "aggregation job finished"
+        );
         return Ok(0);
     }
 
@@ -433,6 +762,14 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
         );
     }
 
+    info!(
+        state = "agg_job_finished",
+        task_id = %task_id,
+        agg_job_id = %agg_job_id,
+        report_count = out_shares_count,
+        "aggregation job finished"
+    );
+
     metrics.report_inc_by(ReportStatus::Aggregated, out_shares_count);
     Ok(out_shares_count)
 }
@@ -440,6 +777,7 @@ async fn run_agg_job<S: Sync, A: DapLeader<S>>(
 /// Handle a pending collection job. If the results are ready, then compute the aggregate
 /// results and store them to be retrieved by the Collector later. Returns the number of
 /// reports in the batch.
+#[tracing::instrument(skip_all, fields(task_id = %task_id.to_base64url()))]
 async fn run_coll_job<S: Sync, A: DapLeader<S>>(
     aggregator: &A,
     task_id: &TaskId,
@@ -447,6 +785,7 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
     coll_job_id: &CollectionJobId,
     batch_sel: &BatchSelector,
     agg_param: &DapAggregationParam,
+    allow_partial_batch: bool,
 ) -> Result<u64, DapError> {
     let metrics = aggregator.metrics();
 
@@ -458,10 +797,22 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
     // Check the batch size. If not not ready, then return early.
     //
     // TODO Consider logging this error, as it should never happen.
-    if !task_config.is_report_count_compatible(task_id, leader_agg_share.report_count)? {
+    if !task_config.is_report_count_compatible(
+        task_id,
+        leader_agg_share.report_count,
+        allow_partial_batch,
+    )? {
         return Ok(0);
     }
 
+    if leader_agg_share.report_count < task_config.min_batch_size {
+        tracing::warn!(
+            report_count = leader_agg_share.report_count,
+            min_batch_size = task_config.min_batch_size,
+            "collecting partial batch"
+        );
+    }
+
     // Prepare the Leader's aggregate share.
     let leader_enc_agg_share = task_config.produce_leader_encrypted_agg_share(
         &task_config.collector_hpke_config,
@@ -535,6 +886,13 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
     aggregator
         .mark_collected(task_id, &agg_share_req.batch_sel)
         .await?;
+    info!(
+        state = "batch_collected",
+        task_id = %task_id,
+        coll_job_id = %coll_job_id,
+        report_count = agg_share_req.report_count,
+        "batch collected"
+    );
 
     metrics.report_inc_by(ReportStatus::Collected, agg_share_req.report_count);
     Ok(agg_share_req.report_count)
@@ -546,13 +904,17 @@ async fn run_coll_job<S: Sync, A: DapLeader<S>>(
 /// pertaining to a task are completed before processing any collection job for the same task.
 ///
 /// Collection jobs are processed in order. If a collection job is still pending once processed, it
-/// is pushed to the back of the work queue.
+/// is pushed to the back of the work queue, unless it has exceeded the
+/// [`DapGlobalConfig::collect_aggregation_deadline`](crate::DapGlobalConfig::collect_aggregation_deadline), in which case it is
+/// marked as failed.
 pub async fn process<S: Sync, A: DapLeader<S>>(
     aggregator: &A,
     host: &str,
     num_items: usize,
 ) -> Result<DapLeaderProcessTelemetry, DapError> {
     let mut telem = DapLeaderProcessTelemetry::default();
+    let global_config = aggregator.get_global_config().await?;
+    let helper_abort_policy = global_config.helper_abort_policy;
 
     tracing::debug!("RUNNING read_work_stream");
 
@@ -588,6 +950,7 @@ pub async fn process<S: Sync, A: DapLeader<S>>(
                         &part_batch_sel,
                         &agg_param,
                         reports,
+                        helper_abort_policy,
                     )
                     .await
                 });
@@ -597,6 +960,7 @@ pub async fn process<S: Sync, A: DapLeader<S>>(
                 coll_job_id,
                 batch_sel,
                 agg_param,
+                created_at,
             } => {
                 // Wait for all pending aggregation jobs for this task to complete before
                 // processing the next collection job. This is to prevent a race condition
@@ -616,25 +980,48 @@ pub async fn process<S: Sync, A: DapLeader<S>>(
                     .ok_or(DapAbort::UnrecognizedTask { task_id })?;
 
                 tracing::debug!("RUNNING run_collect_job FOR TID {task_id} AND {coll_job_id} AND {batch_sel:?} AND {agg_param:?} AND {host}");
-                let collected = run_coll_job(
+                let result = run_coll_job(
                     aggregator,
                     &task_id,
                     task_config.as_ref(),
                     &coll_job_id,
                     &batch_sel,
                     &agg_param,
+                    global_config.allow_partial_batch,
                 )
-                .await?;
+                .await;
 
-                if collected > 0 {
-                    telem.reports_collected += collected;
-                } else {
-                    pending_coll_jobs.push(WorkItem::CollectionJob {
+                let deadline = global_config
+                    .collect_aggregation_deadline
+                    .filter(|deadline| {
+                        aggregator.get_current_time() >= created_at.saturating_add(*deadline)
+                    });
+                match (result, deadline) {
+                    (Ok(collected), _) if collected > 0 => telem.reports_collected += collected,
+                    (Ok(_), None) => pending_coll_jobs.push(WorkItem::CollectionJob {
                         task_id,
                         coll_job_id,
                         batch_sel,
                         agg_param,
-                    });
+                        created_at,
+                    }),
+                    (Err(e), None) => return Err(e),
+                    (result, Some(deadline)) => {
+                        // The reason is sent to the Collector, so the error, if any, is only
+                        // logged.
+                        let reason = format!(
+                            "aggregation did not complete within the deadline of {deadline} seconds"
+                        );
+                        error!(
+                            task_id = %task_id,
+                            coll_job_id = %coll_job_id,
+                            error = ?result.err(),
+                            "collection job failed: {reason}"
+                        );
+                        aggregator
+                            .fail_collect_job(&task_id, &coll_job_id, reason)
+                            .await?;
+                    }
                 }
             }
         }
@@ -9,13 +9,16 @@ pub mod leader;
 
 use crate::{
     constants::DapMediaType,
-    messages::{Base64Encode, Query, TaskId, Time},
+    messages::{AggregationJobId, Base64Encode, Query, TaskId, Time},
     taskprov, DapAbort, DapError, DapGlobalConfig, DapQueryConfig, DapRequest, DapTaskConfig,
 };
 use tracing::warn;
 
 pub use aggregator::{DapAggregator, DapReportInitializer};
-pub use helper::DapHelper;
+pub use helper::{
+    AggShareReqKey, AggregationJobDiagnostics, DapHelper, StoredAggregateShareResp,
+    StoredAggregationJobResp,
+};
 pub use leader::{DapAuthorizedSender, DapLeader};
 
 async fn check_batch<S: Sync>(
@@ -28,14 +31,7 @@ async fn check_batch<S: Sync>(
     global_config: &DapGlobalConfig,
 ) -> Result<(), DapError> {
     // Check that the aggregation parameter is suitable for the given VDAF.
-    if !task_config.vdaf.is_valid_agg_param(agg_param) {
-        // TODO spec: Define this behavior.
-        return Err(DapAbort::InvalidMessage {
-            detail: "invalid aggregation parameter".into(),
-            task_id: *task_id,
-        }
-        .into());
-    }
+    task_config.vdaf.validate_agg_param(task_id, agg_param)?;
 
     // Check that the batch boundaries are valid.
     match (&task_config.query, query) {
@@ -103,6 +99,19 @@ fn check_request_content_type<S>(
     }
 }
 
+/// Record the task ID in the `task_id` field of the current span.
+fn record_task_id(task_id: &TaskId) {
+    tracing::Span::current().record("task_id", tracing::field::display(task_id.to_base64url()));
+}
+
+/// Record the aggregation job ID in the `agg_job_id` field of the current span.
+fn record_agg_job_id(agg_job_id: &AggregationJobId) {
+    tracing::Span::current().record(
+        "agg_job_id",
+        tracing::field::display(agg_job_id.to_base64url()),
+    );
+}
+
 async fn resolve_taskprov<S: Sync>(
     agg: &impl DapAggregator<S>,
     task_id: &TaskId,
@@ -129,6 +138,8 @@ async fn resolve_taskprov<S: Sync>(
         vdaf_verify_key_init,
         collector_hpke_config,
         task_id,
+        global_config.max_prio2_dimension,
+        global_config.query_type_strictness,
     )?
     else {
         // No task configuration advertised, so nothing to do.
@@ -139,6 +150,8 @@ async fn resolve_taskprov<S: Sync>(
     let task_config = agg
         .taskprov_opt_in(task_id, task_config, global_config)
         .await?;
+    task_config.check_urls(task_id, global_config.require_https)?;
+    task_config.check_batch_size_bounds(task_id)?;
 
     agg.taskprov_put(req, task_config).await?;
     Ok(())
@@ -146,7 +159,7 @@ async fn resolve_taskprov<S: Sync>(
 
 #[cfg(test)]
 mod test {
-    use super::{aggregator, helper, leader, DapAuthorizedSender, DapLeader};
+    use super::{aggregator, helper, leader, DapAuthorizedSender, DapHelper, DapLeader};
     #[cfg(feature = "experimental")]
     use crate::vdaf::{mastic::MasticWeight, MasticWeightConfig};
     use crate::{
@@ -163,9 +176,10 @@ mod test {
         roles::{leader::WorkItem, DapAggregator},
         testing::InMemoryAggregator,
         vdaf::{Prio3Config, VdafConfig},
-        DapAbort, DapAggregationJobState, DapAggregationParam, DapBatchBucket, DapCollectionJob,
-        DapError, DapGlobalConfig, DapMeasurement, DapQueryConfig, DapRequest, DapResource,
-        DapTaskConfig, DapTaskParameters, DapVersion,
+        DapAbort, DapAggregateResult, DapAggregationJobState, DapAggregationParam, DapBatchBucket,
+        DapCollectionJob, DapError, DapGlobalConfig, DapMeasurement, DapQueryConfig, DapRequest,
+        DapResource, DapResponse, DapTaskConfig, DapTaskParameters, DapVersion,
+        FutureReportHandling, HelperAbortPolicy,
     };
     use assert_matches::assert_matches;
     use matchit::Router;
@@ -173,7 +187,9 @@ mod test {
     #[cfg(feature = "experimental")]
     use prio::{idpf::IdpfInput, vdaf::poplar1::Poplar1AggregationParam};
     use rand::{thread_rng, Rng};
-    use std::{collections::HashMap, num::NonZeroUsize, sync::Arc, time::SystemTime, vec};
+    use std::{
+        collections::HashMap, fmt::Debug, num::NonZeroUsize, sync::Arc, time::SystemTime, vec,
+    };
     use url::Url;
 
     pub(super) struct TestData {
@@ -199,10 +215,12 @@ mod test {
         const TASK_TIME_PRECISION: u64 = 3600;
 
         pub fn new(version: DapVersion) -> Self {
+            // Clients round report timestamps down to a multiple of the time precision.
             let now = SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
                 .as_secs();
+            let now = now - (now % Self::TASK_TIME_PRECISION);
             let mut rng = thread_rng();
 
             // Global config. In a real deployment, the Leader and Helper may make different choices
@@ -214,6 +232,19 @@ mod test {
                 supported_hpke_kems: vec![HpkeKemId::X25519HkdfSha256],
                 allow_taskprov: true,
                 default_num_agg_span_shards: NonZeroUsize::new(4).unwrap(),
+                require_https: false,
+                max_reports_per_agg_job: None,
+                max_prio2_dimension: None,
+                helper_abort_policy: Default::default(),
+                allow_partial_batch: false,
+                agg_share_report_count_tolerance: 0,
+                collect_aggregation_deadline: None,
+                future_report_handling: Default::default(),
+                future_report_skew_tolerance: 0,
+                report_retention: None,
+                record_agg_job_diagnostics: false,
+                agg_job_report_concurrency: NonZeroUsize::new(1).unwrap(),
+                query_type_strictness: Default::default(),
             };
 
             // Task Parameters that the Leader and Helper must agree on.
@@ -244,8 +275,10 @@ mod test {
                     query: DapQueryConfig::TimeInterval,
                     vdaf: vdaf_config,
                     vdaf_verify_key: vdaf_config.gen_verify_key(),
+                    vdaf_verify_keys_by_epoch: Default::default(),
                     method: Default::default(),
                     num_agg_span_shards: global_config.default_num_agg_span_shards,
+                    max_batch_query_count: 1,
                 },
             );
             tasks.insert(
@@ -264,8 +297,10 @@ mod test {
                     },
                     vdaf: vdaf_config,
                     vdaf_verify_key: vdaf_config.gen_verify_key(),
+                    vdaf_verify_keys_by_epoch: Default::default(),
                     method: Default::default(),
                     num_agg_span_shards: global_config.default_num_agg_span_shards,
+                    max_batch_query_count: 1,
                 },
             );
             tasks.insert(
@@ -282,8 +317,10 @@ mod test {
                     query: DapQueryConfig::TimeInterval,
                     vdaf: vdaf_config,
                     vdaf_verify_key: vdaf_config.gen_verify_key(),
+                    vdaf_verify_keys_by_epoch: Default::default(),
                     method: Default::default(),
                     num_agg_span_shards: global_config.default_num_agg_span_shards,
+                    max_batch_query_count: 1,
                 },
             );
 
@@ -307,8 +344,10 @@ mod test {
                         query: DapQueryConfig::TimeInterval,
                         vdaf: mastic,
                         vdaf_verify_key: mastic.gen_verify_key(),
+                        vdaf_verify_keys_by_epoch: Default::default(),
                         method: Default::default(),
                         num_agg_span_shards: global_config.default_num_agg_span_shards,
+                        max_batch_query_count: 1,
                     },
                 );
             }
@@ -402,6 +441,7 @@ mod test {
                 expired_task_id: self.expired_task_id,
                 #[cfg(feature = "experimental")]
                 mastic_task_id: self.mastic_task_id,
+                collector_hpke_receiver_config: self.collector_hpke_receiver_config,
                 helper_registry: self.helper_registry,
                 leader_registry: self.leader_registry,
             }
@@ -419,6 +459,7 @@ mod test {
         expired_task_id: TaskId,
         #[cfg(feature = "experimental")]
         mastic_task_id: TaskId,
+        collector_hpke_receiver_config: HpkeReceiverConfig,
         pub helper_registry: prometheus::Registry,
         pub leader_registry: prometheus::Registry,
     }
@@ -476,6 +517,46 @@ mod test {
             )
         }
 
+        /// Have the Collector collect the batch selected by `query`, with the Leader running the
+        /// aggregation jobs it needs. Return the number of reports in the batch and the aggregate
+        /// result.
+        pub async fn collect(&self, task_id: &TaskId, query: Query) -> (u64, DapAggregateResult) {
+            let task_config = self.leader.unchecked_get_task_config(task_id).await;
+            let req = self.gen_test_coll_job_req(query, task_id).await;
+            let DapResource::CollectionJob(coll_job_id) = req.resource else {
+                panic!("request is missing the collection job ID");
+            };
+            leader::handle_coll_job_req(&*self.leader, &req)
+                .await
+                .unwrap();
+
+            leader::process(&*self.leader, "leader.com", 100)
+                .await
+                .unwrap();
+
+            let DapCollectionJob::Done(collection) = self
+                .leader
+                .poll_collect_job(task_id, &coll_job_id)
+                .await
+                .unwrap()
+            else {
+                panic!("collection job is not done");
+            };
+            let agg_result = task_config
+                .consume_encrypted_agg_shares(
+                    &self.collector_hpke_receiver_config,
+                    task_id,
+                    &query.into_batch_sel().unwrap(),
+                    collection.report_count,
+                    &DapAggregationParam::Empty,
+                    collection.encrypted_agg_shares.to_vec(),
+                    task_config.version,
+                )
+                .await
+                .unwrap();
+            (collection.report_count, agg_result)
+        }
+
         pub async fn gen_test_agg_job_init_req(
             &self,
             task_id: &TaskId,
@@ -556,6 +637,16 @@ mod test {
             &self,
             task_id: &TaskId,
             measurement: DapMeasurement,
+        ) -> Report {
+            self.gen_test_report_for_measurement_at(task_id, measurement, self.now)
+                .await
+        }
+
+        pub async fn gen_test_report_for_measurement_at(
+            &self,
+            task_id: &TaskId,
+            measurement: DapMeasurement,
+            time: Time,
         ) -> Report {
             let task_config = self.leader.unchecked_get_task_config(task_id).await;
 
@@ -577,7 +668,7 @@ mod test {
                 .vdaf
                 .produce_report(
                     &hpke_config_list,
-                    self.now,
+                    time,
                     task_id,
                     measurement,
                     task_config.version,
@@ -866,6 +957,118 @@ mod test {
 
     async_test_versions! { handle_agg_share_req_invalid_batch_sel }
 
+    // Test that the Helper responds to a retried aggregate share request without recomputing the
+    // aggregate share.
+    async fn handle_agg_share_req_retry(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Aggregate a report.
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        let batch_sel = BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start: task_config.quantized_time_lower_bound(t.now),
+                duration: task_config.time_precision,
+            },
+        };
+        let agg_share = t.helper.get_agg_share(task_id, &batch_sel).await.unwrap();
+        assert_eq!(agg_share.report_count, 1);
+        let computations = t.helper.agg_share_computations();
+
+        let req = t
+            .leader_authorized_req(
+                task_id,
+                &task_config,
+                None,
+                DapMediaType::AggregateShareReq,
+                AggregateShareReq {
+                    batch_sel,
+                    agg_param: Vec::default(),
+                    report_count: agg_share.report_count,
+                    checksum: agg_share.checksum,
+                },
+            )
+            .await;
+        let resp = helper::handle_agg_share_req(&*t.helper, &req)
+            .await
+            .unwrap();
+        assert_eq!(t.helper.agg_share_computations(), computations + 1);
+
+        // The batch has been collected, but the retried request is served from the cache.
+        let retried_resp = helper::handle_agg_share_req(&*t.helper, &req)
+            .await
+            .unwrap();
+        assert_eq!(retried_resp.payload, resp.payload);
+        assert_eq!(t.helper.agg_share_computations(), computations + 1);
+    }
+
+    async_test_versions! { handle_agg_share_req_retry }
+
+    async fn handle_agg_share_req_report_count_tolerance(version: DapVersion) {
+        let mut data = TestData::new(version);
+        data.global_config.agg_share_report_count_tolerance = 1;
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Aggregate some reports.
+        for _ in 0..3 {
+            let report = t.gen_test_report(task_id).await;
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap();
+        }
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        let batch_sel = BatchSelector::TimeInterval {
+            batch_interval: Interval {
+                start: task_config.quantized_time_lower_bound(t.now),
+                duration: task_config.time_precision,
+            },
+        };
+        let agg_share = t.helper.get_agg_share(task_id, &batch_sel).await.unwrap();
+        assert_eq!(agg_share.report_count, 3);
+
+        let agg_share_req = |report_count| {
+            t.leader_authorized_req(
+                task_id,
+                &task_config,
+                None,
+                DapMediaType::AggregateShareReq,
+                AggregateShareReq {
+                    batch_sel: batch_sel.clone(),
+                    agg_param: Vec::default(),
+                    report_count,
+                    checksum: [0; 32],
+                },
+            )
+        };
+
+        // A report count that is off by more than the tolerance is rejected.
+        assert_matches!(
+            helper::handle_agg_share_req(&*t.helper, &agg_share_req(1).await).await,
+            Err(DapError::Abort(DapAbort::BatchMismatch { .. }))
+        );
+
+        // A report count within the tolerance is accepted.
+        helper::handle_agg_share_req(&*t.helper, &agg_share_req(2).await)
+            .await
+            .unwrap();
+    }
+
+    async_test_versions! { handle_agg_share_req_report_count_tolerance }
+
     async fn handle_coll_job_req_unauthorized_request(version: DapVersion) {
         let mut rng = thread_rng();
         let t = Test::new(version);
@@ -931,16 +1134,33 @@ mod test {
 
     async_test_versions! { handle_agg_job_req_failure_hpke_decrypt_error }
 
-    async fn handle_agg_job_req_transition_continue(version: DapVersion) {
-        let t = Test::new(version);
+    async fn handle_agg_job_req_diagnostics(version: DapVersion) {
+        let mut data = TestData::new(version);
+        data.global_config.record_agg_job_diagnostics = true;
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
         let task_id = &t.time_interval_task_id;
 
-        let report = t.gen_test_report(task_id).await;
+        let mut reports = Vec::new();
+        for _ in 0..4 {
+            reports.push(t.gen_test_report(task_id).await);
+        }
+        // Cause decryption of the Helper's share of the first two reports to fail.
+        for report in &mut reports[..2] {
+            report.encrypted_input_shares[1].payload[0] ^= 0xff;
+        }
+        let failed_report_ids = reports[..2]
+            .iter()
+            .map(|report| report.report_metadata.id)
+            .collect::<Vec<_>>();
         let (_, req) = t
-            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, reports)
             .await;
+        let DapResource::AggregationJob(agg_job_id) = req.resource else {
+            panic!("aggregation job ID not set");
+        };
 
-        // Get AggregationJobResp and then extract the transition data from inside.
+        // The response to the Leader only indicates the transition failures.
         let agg_job_resp = AggregationJobResp::get_decoded(
             &helper::handle_agg_job_req(&*t.helper, &req, Default::default())
                 .await
@@ -948,111 +1168,493 @@ mod test {
                 .payload,
         )
         .unwrap();
-        let transition = &agg_job_resp.transitions[0];
+        assert_eq!(agg_job_resp.continued_count(), 2);
 
-        // Expect success due to valid ciphertext.
-        assert_matches!(transition.var, TransitionVar::Continued(_));
-    }
+        let diagnostics = t
+            .helper
+            .get_agg_job_diagnostics(task_id, &agg_job_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            diagnostics
+                .failures
+                .iter()
+                .map(|failure| failure.report_id)
+                .collect::<Vec<_>>(),
+            failed_report_ids
+        );
+        for failure in &diagnostics.failures {
+            assert_eq!(failure.failure, TransitionFailure::HpkeDecryptError);
+            assert_eq!(failure.reason, "failed to decrypt the input share");
+        }
 
-    async_test_versions! { handle_agg_job_req_transition_continue }
+        // Nothing is recorded for a job without rejected reports.
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        let DapResource::AggregationJob(agg_job_id) = req.resource else {
+            panic!("aggregation job ID not set");
+        };
+        helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            t.helper
+                .get_agg_job_diagnostics(task_id, &agg_job_id)
+                .await
+                .unwrap(),
+            None
+        );
+    }
 
-    async fn handle_agg_job_req_failure_report_replayed(version: DapVersion) {
-        let t = Test::new(version);
-        let task_id = &t.time_interval_task_id;
-        let task_config = t.helper.unchecked_get_task_config(task_id).await;
+    async_test_versions! { handle_agg_job_req_diagnostics }
 
-        let report = t.gen_test_report(task_id).await;
-        let req = t.gen_test_upload_req(report.clone(), task_id).await;
-        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+    async fn handle_agg_job_req_concurrency_preserves_order(version: DapVersion) {
+        for concurrency in [1, 4, 16] {
+            let mut data = TestData::new(version);
+            data.global_config.agg_job_report_concurrency = NonZeroUsize::new(concurrency).unwrap();
+            let helper = data.new_helper();
+            let t = data.with_leader(helper);
+            let task_id = &t.time_interval_task_id;
 
-        let query = task_config.query_for_current_batch_window(t.now);
-        let req = t.gen_test_coll_job_req(query, task_id).await;
-        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+            let mut reports = Vec::new();
+            for _ in 0..20 {
+                reports.push(t.gen_test_report(task_id).await);
+            }
+            // Cause decryption of the Helper's share of every third report to fail.
+            for report in reports.iter_mut().step_by(3) {
+                report.encrypted_input_shares[1].payload[0] ^= 0xff;
+            }
+            let report_ids = reports
+                .iter()
+                .map(|report| report.report_metadata.id)
+                .collect::<Vec<_>>();
+            let (_, req) = t
+                .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, reports)
+                .await;
 
-        // Add dummy data to report store backend. This is done in a new scope so that the lock on the
-        // report store is released before running the test.
-        {
-            let bucket = DapBatchBucket::TimeInterval {
-                batch_window: task_config.quantized_time_lower_bound(t.now),
-                shard: report
-                    .report_metadata
-                    .id
-                    .shard(task_config.num_agg_span_shards),
-            };
-            let mut agg_store = t.helper.agg_store.lock().unwrap();
-            agg_store
-                .for_bucket(task_id, &bucket)
-                .reports
-                .insert(report.report_metadata.id);
+            let agg_job_resp = AggregationJobResp::get_decoded(
+                &helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+                    .await
+                    .unwrap()
+                    .payload,
+            )
+            .unwrap();
+            assert_eq!(
+                agg_job_resp
+                    .transitions
+                    .iter()
+                    .map(|transition| transition.report_id)
+                    .collect::<Vec<_>>(),
+                report_ids,
+                "concurrency {concurrency}"
+            );
+            for (i, transition) in agg_job_resp.transitions.iter().enumerate() {
+                if i % 3 == 0 {
+                    assert_eq!(
+                        transition.var,
+                        TransitionVar::Failed(TransitionFailure::HpkeDecryptError),
+                        "concurrency {concurrency}"
+                    );
+                } else {
+                    assert_matches!(
+                        transition.var,
+                        TransitionVar::Continued(..),
+                        "concurrency {concurrency}"
+                    );
+                }
+            }
         }
+    }
 
-        leader::process(&*t.leader, "leader.com", 100)
-            .await
-            .unwrap();
+    async_test_versions! { handle_agg_job_req_concurrency_preserves_order }
 
-        assert_metrics_include!(t.leader_registry, {
-            r#"report_counter{env="test_leader",host="leader.com",status="rejected_report_replayed"}"#: 1,
-        });
+    async fn handle_agg_job_req_concurrency_large_job(version: DapVersion) {
+        let mut data = TestData::new(version);
+        data.global_config.agg_job_report_concurrency = NonZeroUsize::new(32).unwrap();
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
+        let task_id = &t.time_interval_task_id;
 
-        assert_metrics_include!(t.helper_registry, {
-            r#"report_counter{env="test_helper",host="helper.org",status="rejected_report_replayed"}"#: 1,
-            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 1,
-            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
-        });
+        let mut reports = Vec::new();
+        for _ in 0..200 {
+            reports.push(t.gen_test_report(task_id).await);
+        }
+        let report_ids = reports
+            .iter()
+            .map(|report| report.report_metadata.id)
+            .collect::<Vec<_>>();
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, reports)
+            .await;
+
+        let agg_job_resp = AggregationJobResp::get_decoded(
+            &helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+                .await
+                .unwrap()
+                .payload,
+        )
+        .unwrap();
+        assert_eq!(agg_job_resp.continued_count(), report_ids.len());
+        assert_eq!(
+            agg_job_resp
+                .transitions
+                .iter()
+                .map(|transition| transition.report_id)
+                .collect::<Vec<_>>(),
+            report_ids
+        );
     }
 
-    async_test_versions! { handle_agg_job_req_failure_report_replayed }
+    async_test_versions! { handle_agg_job_req_concurrency_large_job }
 
-    async fn handle_agg_job_req_failure_batch_collected(version: DapVersion) {
-        let t = Test::new(version);
+    async fn handle_agg_job_req_too_many_reports(version: DapVersion) {
+        let mut data = TestData::new(version);
+        data.global_config.max_reports_per_agg_job = NonZeroUsize::new(2);
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
         let task_id = &t.time_interval_task_id;
-        let task_config = t.helper.unchecked_get_task_config(task_id).await;
-
-        let report = t.gen_test_report(task_id).await;
-        let req = t.gen_test_upload_req(report.clone(), task_id).await;
-        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
 
-        // Add mock data to the aggreagte store backend. This is done in its own scope so that the lock
-        // is released before running the test. Otherwise the test will deadlock.
-        {
-            let bucket = DapBatchBucket::TimeInterval {
-                batch_window: task_config.quantized_time_lower_bound(t.now),
-                shard: report
-                    .report_metadata
-                    .id
-                    .shard(task_config.num_agg_span_shards),
-            };
-            let mut agg_store = t.helper.agg_store.lock().unwrap();
-            agg_store.for_bucket(task_id, &bucket).collected = true;
+        let mut reports = Vec::new();
+        for _ in 0..3 {
+            reports.push(t.gen_test_report(task_id).await);
         }
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, reports)
+            .await;
 
-        let query = task_config.query_for_current_batch_window(t.now);
-        let req = t.gen_test_coll_job_req(query, task_id).await;
-        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BadRequest(..))
+        );
+        assert_eq!(t.helper.hpke_decryptions(), 0);
 
-        leader::process(&*t.leader, "leader.com", 100)
+        // A job at the limit is accepted.
+        let mut reports = Vec::new();
+        for _ in 0..2 {
+            reports.push(t.gen_test_report(task_id).await);
+        }
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, reports)
+            .await;
+        helper::handle_agg_job_req(&*t.helper, &req, Default::default())
             .await
             .unwrap();
+        assert_eq!(t.helper.hpke_decryptions(), 2);
+    }
 
-        assert_metrics_include!(t.leader_registry, {
-            r#"report_counter{env="test_leader",host="leader.com",status="rejected_batch_collected"}"#: 1,
-        });
+    async_test_versions! { handle_agg_job_req_too_many_reports }
 
-        assert_metrics_include!(t.helper_registry, {
-            r#"report_counter{env="test_helper",host="helper.org",status="rejected_batch_collected"}"#: 1,
-            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 1,
-            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
-        });
+    async fn handle_agg_job_req_empty(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, Vec::new())
+            .await;
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BadRequest(detail)) if detail == "empty aggregation job"
+        );
+        assert_eq!(t.helper.audit_log.invocations(), 0);
     }
 
-    async_test_versions! { handle_agg_job_req_failure_batch_collected }
+    async_test_versions! { handle_agg_job_req_empty }
 
-    async fn handle_upload_req_fail_send_invalid_report(version: DapVersion) {
+    // Test that each handler rejects a request whose content type doesn't match the endpoint.
+    async fn handle_req_unexpected_content_type(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
         let task_config = t.leader.unchecked_get_task_config(task_id).await;
-
-        // Construct a report payload with an invalid task ID.
+        let with_media_type = |req: &DapRequest<BearerToken>, media_type| DapRequest {
+            version: req.version,
+            media_type,
+            task_id: req.task_id,
+            resource: req.resource.clone(),
+            payload: req.payload.clone(),
+            sender_auth: req.sender_auth.clone(),
+            taskprov: req.taskprov.clone(),
+        };
+        let is_content_type_error = |result: Result<DapResponse, DapError>| {
+            matches!(
+                result,
+                Err(DapError::Abort(DapAbort::BadRequest(detail))) if detail.contains("content-type")
+            )
+        };
+
+        // Upload
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        for media_type in [None, Some(DapMediaType::AggregationJobInitReq)] {
+            assert!(is_content_type_error(
+                leader::handle_upload_req(&*t.leader, &with_media_type(&req, media_type)).await
+            ));
+        }
+        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+
+        // Collection job
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        assert!(is_content_type_error(
+            leader::handle_coll_job_req(
+                &*t.leader,
+                &with_media_type(&req, Some(DapMediaType::Report))
+            )
+            .await
+        ));
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        // Aggregation job
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        assert!(is_content_type_error(
+            helper::handle_agg_job_req(
+                &*t.helper,
+                &with_media_type(&req, Some(DapMediaType::AggregateShareReq)),
+                Default::default(),
+            )
+            .await
+        ));
+        helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+            .await
+            .unwrap();
+
+        // Aggregate share
+        let req = t.gen_test_agg_share_req(0, [0; 32]).await;
+        assert!(is_content_type_error(
+            helper::handle_agg_share_req(
+                &*t.helper,
+                &with_media_type(&req, Some(DapMediaType::AggregationJobInitReq)),
+            )
+            .await
+        ));
+    }
+
+    async_test_versions! { handle_req_unexpected_content_type }
+
+    async fn handle_agg_job_req_cancelled(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        let DapResource::AggregationJob(agg_job_id) = req.resource else {
+            panic!("request is missing the aggregation job ID");
+        };
+
+        helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+            .await
+            .unwrap();
+
+        helper::cancel_aggregation_job(&*t.helper, task_id, &agg_job_id)
+            .await
+            .unwrap();
+
+        // Expect subsequent requests for the job to be rejected.
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &req, Default::default()).await,
+            Err(DapError::Abort(DapAbort::UnrecognizedAggregationJob { task_id: id, agg_job_id: job_id }))
+                if id == *task_id && job_id == agg_job_id
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_cancelled }
+
+    async fn handle_agg_job_req_retry(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+
+        let resp = helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+            .await
+            .unwrap();
+
+        // Expect the Helper to respond to a retried request as it did before instead of rejecting
+        // the reports as replays.
+        let retry_resp = helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(retry_resp.payload, resp.payload);
+        assert_matches!(
+            AggregationJobResp::get_decoded(&retry_resp.payload)
+                .unwrap()
+                .transitions[0]
+                .var,
+            TransitionVar::Continued(..)
+        );
+
+        assert_metrics_include!(t.helper_registry, {
+            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 2,
+            r#"report_counter{env="test_helper",host="helper.org",status="aggregated"}"#: 1,
+            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
+        });
+    }
+
+    async_test_versions! { handle_agg_job_req_retry }
+
+    async fn handle_agg_job_req_conflicting_retry(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+            .await
+            .unwrap();
+
+        // Send a different request for the same aggregation job.
+        let report = t.gen_test_report(task_id).await;
+        let (_, mut conflicting_req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+        conflicting_req.resource = req.resource.clone();
+
+        assert_matches!(
+            helper::handle_agg_job_req(&*t.helper, &conflicting_req, Default::default()).await,
+            Err(DapError::Abort(DapAbort::InvalidMessage { .. }))
+        );
+    }
+
+    async_test_versions! { handle_agg_job_req_conflicting_retry }
+
+    async fn handle_agg_job_req_transition_continue(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let report = t.gen_test_report(task_id).await;
+        let (_, req) = t
+            .gen_test_agg_job_init_req(task_id, DapAggregationParam::Empty, vec![report])
+            .await;
+
+        // Get AggregationJobResp and then extract the transition data from inside.
+        let agg_job_resp = AggregationJobResp::get_decoded(
+            &helper::handle_agg_job_req(&*t.helper, &req, Default::default())
+                .await
+                .unwrap()
+                .payload,
+        )
+        .unwrap();
+        let transition = &agg_job_resp.transitions[0];
+
+        // Expect success due to valid ciphertext.
+        assert_matches!(transition.var, TransitionVar::Continued(_));
+    }
+
+    async_test_versions! { handle_agg_job_req_transition_continue }
+
+    async fn handle_agg_job_req_failure_report_replayed(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.helper.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report.clone(), task_id).await;
+        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        // Add dummy data to report store backend. This is done in a new scope so that the lock on the
+        // report store is released before running the test.
+        {
+            let bucket = DapBatchBucket::TimeInterval {
+                batch_window: task_config.quantized_time_lower_bound(t.now),
+                shard: report
+                    .report_metadata
+                    .id
+                    .shard(task_config.num_agg_span_shards),
+            };
+            let mut agg_store = t.helper.agg_store.lock().unwrap();
+            agg_store
+                .for_bucket(task_id, &bucket)
+                .reports
+                .insert(aggregator::replay_key(task_id, &report.report_metadata.id));
+        }
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        assert_metrics_include!(t.leader_registry, {
+            r#"report_counter{env="test_leader",host="leader.com",status="rejected_report_replayed"}"#: 1,
+        });
+
+        assert_metrics_include!(t.helper_registry, {
+            r#"report_counter{env="test_helper",host="helper.org",status="rejected_report_replayed"}"#: 1,
+            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 1,
+            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
+        });
+    }
+
+    async_test_versions! { handle_agg_job_req_failure_report_replayed }
+
+    async fn handle_agg_job_req_failure_batch_collected(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.helper.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report.clone(), task_id).await;
+        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+
+        // Add mock data to the aggreagte store backend. This is done in its own scope so that the lock
+        // is released before running the test. Otherwise the test will deadlock.
+        {
+            let bucket = DapBatchBucket::TimeInterval {
+                batch_window: task_config.quantized_time_lower_bound(t.now),
+                shard: report
+                    .report_metadata
+                    .id
+                    .shard(task_config.num_agg_span_shards),
+            };
+            let mut agg_store = t.helper.agg_store.lock().unwrap();
+            agg_store.for_bucket(task_id, &bucket).collected = true;
+        }
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        assert_metrics_include!(t.leader_registry, {
+            r#"report_counter{env="test_leader",host="leader.com",status="rejected_batch_collected"}"#: 1,
+        });
+
+        assert_metrics_include!(t.helper_registry, {
+            r#"report_counter{env="test_helper",host="helper.org",status="rejected_batch_collected"}"#: 1,
+            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 1,
+            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
+        });
+    }
+
+    async_test_versions! { handle_agg_job_req_failure_batch_collected }
+
+    async fn handle_upload_req_fail_send_invalid_report(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Construct a report payload with an invalid task ID.
         let report_invalid_task_id = t.gen_test_report(task_id).await;
         let req = DapRequest {
             version: task_config.version,
@@ -1076,25 +1678,101 @@ mod test {
 
     async_test_versions! { handle_upload_req_fail_send_invalid_report }
 
-    // Test that the Leader rejects reports past the expiration date.
-    async fn handle_upload_req_task_expired(version: DapVersion) {
+    // Test that a report for an unknown task is accepted if it advertises the task via taskprov,
+    // and that the task is provisioned as a result.
+    async fn handle_upload_req_unknown_task_taskprov(version: DapVersion) {
         let t = Test::new(version);
-        let task_id = &t.expired_task_id;
-        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+        let (task_config, task_id, taskprov_advertisement) = DapTaskParameters {
+            version,
+            ..Default::default()
+        }
+        .to_config_with_taskprov(
+            b"cool task".to_vec(),
+            t.now,
+            t.leader.taskprov_vdaf_verify_key_init().unwrap(),
+            t.leader.taskprov_collector_hpke_config().unwrap(),
+        )
+        .unwrap();
+        assert!(t
+            .leader
+            .get_task_config_for(&task_id)
+            .await
+            .unwrap()
+            .is_none());
 
-        let report = t.gen_test_report(task_id).await;
-        let req = DapRequest {
-            version: task_config.version,
+        let hpke_config_list = [
+            t.leader
+                .get_hpke_config_for(version, Some(&task_id))
+                .await
+                .unwrap()
+                .clone(),
+            t.helper
+                .get_hpke_config_for(version, Some(&task_id))
+                .await
+                .unwrap()
+                .clone(),
+        ];
+        let report = task_config
+            .vdaf
+            .produce_report_with_extensions(
+                &hpke_config_list,
+                t.now,
+                &task_id,
+                DapMeasurement::U32Vec(vec![1; 10]),
+                vec![Extension::Taskprov],
+                version,
+            )
+            .unwrap();
+        let req = |task_id| DapRequest {
+            version,
             media_type: Some(DapMediaType::Report),
-            task_id: Some(*task_id),
+            task_id: Some(task_id),
             resource: DapResource::Undefined,
             payload: report.get_encoded_with_param(&version).unwrap(),
+            taskprov: Some(taskprov_advertisement.clone()),
             ..Default::default()
         };
 
+        // The advertised task config doesn't match the task ID.
         assert_eq!(
-            leader::handle_upload_req(&*t.leader, &req)
-                .await
+            leader::handle_upload_req(&*t.leader, &req(TaskId([0; 32]))).await,
+            Err(DapError::Abort(DapAbort::UnrecognizedTask {
+                task_id: TaskId([0; 32])
+            }))
+        );
+
+        leader::handle_upload_req(&*t.leader, &req(task_id))
+            .await
+            .unwrap();
+        assert!(t
+            .leader
+            .get_task_config_for(&task_id)
+            .await
+            .unwrap()
+            .is_some());
+    }
+
+    async_test_versions! { handle_upload_req_unknown_task_taskprov }
+
+    // Test that the Leader rejects reports past the expiration date.
+    async fn handle_upload_req_task_expired(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.expired_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        let req = DapRequest {
+            version: task_config.version,
+            media_type: Some(DapMediaType::Report),
+            task_id: Some(*task_id),
+            resource: DapResource::Undefined,
+            payload: report.get_encoded_with_param(&version).unwrap(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            leader::handle_upload_req(&*t.leader, &req)
+                .await
                 .unwrap_err(),
             DapError::Abort(DapAbort::ReportTooLate {
                 report_id: report.report_metadata.id
@@ -1102,449 +1780,1439 @@ mod test {
         );
     }
 
-    async_test_versions! { handle_upload_req_task_expired }
+    async_test_versions! { handle_upload_req_task_expired }
+
+    // Test that the Leader accepts reports at the boundaries of the task's validity window and
+    // rejects reports just outside of them.
+    async fn handle_upload_req_report_time_boundaries(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+        let earliest = task_config.not_before;
+        let latest = task_config.not_after - 1;
+
+        for time in [earliest, latest] {
+            let report = t
+                .gen_test_report_for_measurement_at(task_id, DapMeasurement::U64(1), time)
+                .await;
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap();
+        }
+
+        let report = t
+            .gen_test_report_for_measurement_at(task_id, DapMeasurement::U64(1), earliest - 1)
+            .await;
+        let report_id = report.report_metadata.id;
+        assert_eq!(
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::ReportTooEarly { report_id })
+        );
+
+        let report = t
+            .gen_test_report_for_measurement_at(task_id, DapMeasurement::U64(1), latest + 1)
+            .await;
+        let report_id = report.report_metadata.id;
+        assert_eq!(
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::ReportTooLate { report_id })
+        );
+    }
+
+    async_test_versions! { handle_upload_req_report_time_boundaries }
+
+    // Test that a report from a Client whose clock is behind the Leader's is aggregated and
+    // collected in the batch of the time set by the Client.
+    async fn e2e_report_time_set_by_client(version: DapVersion) {
+        let mut data = TestData::new(version);
+        let task_id = data.time_interval_task_id;
+        let task = data.tasks.get_mut(&task_id).unwrap();
+        let time_precision = task.time_precision;
+        task.not_before -= time_precision;
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
+        let task_config = t.leader.unchecked_get_task_config(&task_id).await;
+
+        // The Client's clock is behind by an hour.
+        let client_time = t.now - time_precision;
+        let report = t
+            .gen_test_report_for_measurement_at(&task_id, DapMeasurement::U64(1), client_time)
+            .await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, &task_id).await)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            t.collect(
+                &task_id,
+                task_config.query_for_current_batch_window(client_time)
+            )
+            .await,
+            (1, DapAggregateResult::U64(1))
+        );
+    }
+
+    async_test_versions! { e2e_report_time_set_by_client }
+
+    // Test that the Leader handles a report from a Client whose clock is ahead as configured and
+    // that the reports it accepts are aggregated in the batch of the time set by the Client.
+    async fn handle_upload_req_future_report(version: DapVersion, policy: FutureReportHandling) {
+        let mut data = TestData::new(version);
+        let task_id = data.time_interval_task_id;
+        let task = data.tasks.get_mut(&task_id).unwrap();
+        let time_precision = task.time_precision;
+        // Make sure the future reports are within the task's validity window.
+        task.not_after += 10 * time_precision;
+        data.global_config.future_report_handling = policy;
+        data.global_config.future_report_skew_tolerance = time_precision;
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
+        let task_config = t.leader.unchecked_get_task_config(&task_id).await;
+        let now = task_config.quantized_time_lower_bound(t.leader.get_current_time());
+
+        // A report within the skew tolerance is always accepted.
+        let report = t
+            .gen_test_report_for_measurement_at(
+                &task_id,
+                DapMeasurement::U64(1),
+                now + time_precision,
+            )
+            .await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, &task_id).await)
+            .await
+            .unwrap();
+
+        // The Client's clock is ahead by more than the tolerance.
+        let report = t
+            .gen_test_report_for_measurement_at(
+                &task_id,
+                DapMeasurement::U64(1),
+                now + 2 * time_precision,
+            )
+            .await;
+        let report_id = report.report_metadata.id;
+        let result =
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, &task_id).await)
+                .await;
+        let expected = match policy {
+            FutureReportHandling::Accept => {
+                result.unwrap();
+                (2, DapAggregateResult::U64(2))
+            }
+            FutureReportHandling::Reject => {
+                assert_matches!(
+                    result,
+                    Err(DapError::Abort(DapAbort::ReportTooEarly { report_id: id })) if id == report_id
+                );
+                (1, DapAggregateResult::U64(1))
+            }
+        };
+
+        assert_eq!(
+            t.collect(
+                &task_id,
+                Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: now + time_precision,
+                        duration: 2 * time_precision,
+                    },
+                },
+            )
+            .await,
+            expected
+        );
+    }
+
+    async fn handle_upload_req_future_report_accept(version: DapVersion) {
+        handle_upload_req_future_report(version, FutureReportHandling::Accept).await;
+    }
+
+    async_test_versions! { handle_upload_req_future_report_accept }
+
+    async fn handle_upload_req_future_report_reject(version: DapVersion) {
+        handle_upload_req_future_report(version, FutureReportHandling::Reject).await;
+    }
+
+    async_test_versions! { handle_upload_req_future_report_reject }
+
+    async fn sweep_expired_reports(version: DapVersion) {
+        let mut data = TestData::new(version);
+        let task_id = data.time_interval_task_id;
+        let time_precision = data.tasks[&task_id].time_precision;
+        data.global_config.report_retention = Some(2 * time_precision);
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
+        let task_config = t.leader.unchecked_get_task_config(&task_id).await;
+        let now = task_config.quantized_time_lower_bound(t.leader.get_current_time());
+
+        let put_reports = |time| {
+            let t = &t;
+            async move {
+                let mut report_ids = Vec::new();
+                for _ in 0..3 {
+                    let report = t
+                        .gen_test_report_for_measurement_at(&task_id, DapMeasurement::U64(1), time)
+                        .await;
+                    report_ids.push(report.report_metadata.id);
+                    t.leader.put_report(&report, &task_id).await.unwrap();
+                }
+                report_ids
+            }
+        };
+        let expired = put_reports(now - 3 * time_precision).await;
+        let fresh = put_reports(now - time_precision).await;
+
+        // Expired reports that are part of an aggregation job are kept.
+        let in_flight_time = now - 4 * time_precision;
+        let in_flight = put_reports(in_flight_time).await;
+        t.leader
+            .init_collect_job(
+                &task_id,
+                &CollectionJobId(thread_rng().gen()),
+                BatchSelector::TimeInterval {
+                    batch_interval: Interval {
+                        start: in_flight_time,
+                        duration: time_precision,
+                    },
+                },
+                DapAggregationParam::Empty,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(leader::sweep_expired_reports(&*t.leader).await.unwrap(), 3);
+        assert!(t.leader.get_reports(&task_id, &expired).is_empty());
+        assert_eq!(t.leader.get_reports(&task_id, &fresh).len(), 3);
+        assert_eq!(t.leader.get_reports(&task_id, &in_flight).len(), 3);
+
+        // Nothing is left to sweep.
+        assert_eq!(leader::sweep_expired_reports(&*t.leader).await.unwrap(), 0);
+    }
+
+    async_test_versions! { sweep_expired_reports }
+
+    async fn dequeue_work_empty(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        for _ in 0..10 {
+            let report = t.gen_test_report(task_id).await;
+            let req = t.gen_test_upload_req(report.clone(), task_id).await;
+            leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+        }
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        // Get the next work item. This should be an aggregation job for the reports that were
+        // uploaded.
+        let mut work_items = t.leader.dequeue_work(1).await.unwrap();
+        assert_eq!(work_items.len(), 1);
+        let WorkItem::AggregationJob {
+            task_id: returned_task_id,
+            part_batch_sel: _,
+            agg_param: _,
+            reports,
+        } = work_items.pop().unwrap()
+        else {
+            panic!("unexpected work item type");
+        };
+        assert_eq!(reports.len(), 10);
+        assert_eq!(&returned_task_id, task_id);
+
+        // Get the next work item. This should be the collection job.
+        let mut work_items = t.leader.dequeue_work(1).await.unwrap();
+        assert_eq!(work_items.len(), 1);
+        let WorkItem::CollectionJob {
+            task_id: returned_task_id,
+            coll_job_id: _,
+            batch_sel: _,
+            agg_param: _,
+            created_at: _,
+        } = work_items.pop().unwrap()
+        else {
+            panic!("unexpected work item type");
+        };
+        assert_eq!(&returned_task_id, task_id);
+
+        // Get the next work item. Expect the return value to be empty because there is no more
+        // work to process.
+        assert_eq!(t.leader.dequeue_work(1).await.unwrap().len(), 0);
+    }
+
+    async_test_versions! { dequeue_work_empty }
+
+    async fn poll_collect_job_test_results(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Collector: Create a CollectReq.
+        let req = t.collector_authorized_req(
+            task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+            },
+        );
+
+        // Leader: Handle the CollectReq received from Collector.
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        // Expect DapCollectionJob::Unknown due to invalid collect ID.
+        assert_eq!(
+            t.leader
+                .poll_collect_job(task_id, &CollectionJobId::default())
+                .await
+                .unwrap(),
+            DapCollectionJob::Unknown
+        );
+
+        // Leader: Get pending collect job to obtain collect_id
+        let WorkItem::CollectionJob {
+            task_id: _,
+            coll_job_id,
+            batch_sel: _,
+            agg_param: _,
+            created_at: _,
+        } = t.leader.dequeue_work(1).await.unwrap().pop().unwrap()
+        else {
+            panic!("unexpected work item type")
+        };
+        let collection = Collection {
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            report_count: 0,
+            interval: Interval {
+                start: 0,
+                duration: 2_000_000_000,
+            },
+            encrypted_agg_shares: [
+                HpkeCiphertext {
+                    config_id: Default::default(),
+                    enc: Default::default(),
+                    payload: Default::default(),
+                },
+                HpkeCiphertext {
+                    config_id: Default::default(),
+                    enc: Default::default(),
+                    payload: Default::default(),
+                },
+            ],
+        };
+
+        // Expect DapCollectionJob::Pending due to pending collect job.
+        assert_eq!(
+            t.leader
+                .poll_collect_job(task_id, &coll_job_id)
+                .await
+                .unwrap(),
+            DapCollectionJob::Pending
+        );
+
+        // Leader: Complete the collect job by storing CollectResp in LeaderStore.processed.
+        t.leader
+            .finish_collect_job(task_id, &coll_job_id, &collection)
+            .await
+            .unwrap();
+
+        // Expect DapCollectionJob::Done due to processed collect job.
+        assert_matches!(
+            t.leader
+                .poll_collect_job(task_id, &coll_job_id)
+                .await
+                .unwrap(),
+            DapCollectionJob::Done(..)
+        );
+    }
+
+    async_test_versions! { poll_collect_job_test_results }
+
+    async fn export_import_collect_jobs(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+        let query = task_config.query_for_current_batch_window(t.now);
+
+        // Collector: Create two collection jobs, one for the previous batch window and one for
+        // the current one.
+        for query in [
+            task_config.query_for_current_batch_window(t.now - task_config.time_precision),
+            query,
+        ] {
+            let req = t.gen_test_coll_job_req(query, task_id).await;
+            leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+        }
+
+        // Leader: Complete the first collection job and leave the second one in the queue.
+        let WorkItem::CollectionJob {
+            coll_job_id: done_coll_job_id,
+            ..
+        } = t.leader.dequeue_work(1).await.unwrap().pop().unwrap()
+        else {
+            panic!("unexpected work item type")
+        };
+        let collection = Collection {
+            part_batch_sel: PartialBatchSelector::TimeInterval,
+            report_count: 0,
+            interval: Interval {
+                start: 0,
+                duration: 2_000_000_000,
+            },
+            encrypted_agg_shares: [
+                HpkeCiphertext {
+                    config_id: Default::default(),
+                    enc: Default::default(),
+                    payload: Default::default(),
+                },
+                HpkeCiphertext {
+                    config_id: Default::default(),
+                    enc: Default::default(),
+                    payload: Default::default(),
+                },
+            ],
+        };
+        t.leader
+            .finish_collect_job(task_id, &done_coll_job_id, &collection)
+            .await
+            .unwrap();
+
+        let exported = t.leader.export_collect_jobs().await.unwrap();
+        t.leader.clear_storage();
+        assert!(t.leader.dequeue_work(1).await.unwrap().is_empty());
+
+        t.leader.import_collect_jobs(&exported).await.unwrap();
+
+        // Expect the completed job to be restored.
+        assert_eq!(
+            t.leader
+                .poll_collect_job(task_id, &done_coll_job_id)
+                .await
+                .unwrap(),
+            DapCollectionJob::Done(collection)
+        );
+
+        // Expect the pending job to be restored and queued for processing.
+        let WorkItem::CollectionJob {
+            task_id: pending_task_id,
+            coll_job_id: pending_coll_job_id,
+            batch_sel,
+            agg_param,
+            created_at: _,
+        } = t.leader.dequeue_work(2).await.unwrap().pop().unwrap()
+        else {
+            panic!("unexpected work item type")
+        };
+        assert_eq!(pending_task_id, *task_id);
+        assert_ne!(pending_coll_job_id, done_coll_job_id);
+        assert_eq!(Some(batch_sel), query.into_batch_sel());
+        assert_eq!(agg_param, DapAggregationParam::Empty);
+        assert_eq!(
+            t.leader
+                .poll_collect_job(task_id, &pending_coll_job_id)
+                .await
+                .unwrap(),
+            DapCollectionJob::Pending
+        );
+
+        // Importing the same jobs again would overwrite them.
+        assert_matches!(
+            t.leader.import_collect_jobs(&exported).await,
+            Err(DapError::Abort(DapAbort::BadRequest(..)))
+        );
+    }
+
+    async_test_versions! { export_import_collect_jobs }
+
+    async fn handle_coll_job_req_fail_invalid_batch_interval(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Collector: Create a CollectReq with a very large batch interval.
+        let req = t.collector_authorized_req(
+            task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now),
+                        duration: t.leader.global_config.max_batch_duration
+                            + task_config.time_precision,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+        );
+
+        // Leader: Handle the CollectReq received from Collector.
+        let err = leader::handle_coll_job_req(&*t.leader, &req)
+            .await
+            .unwrap_err();
+
+        // Fails because the requested batch interval is too large.
+        assert_matches!(err, DapError::Abort(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too large".to_string()));
+
+        // Collector: Create a CollectReq with a batch interval in the past.
+        let req = t.collector_authorized_req(
+            task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now)
+                            - t.leader.global_config.min_batch_interval_start
+                            - task_config.time_precision,
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+        );
+
+        // Leader: Handle the CollectReq received from Collector.
+        let err = leader::handle_coll_job_req(&*t.leader, &req)
+            .await
+            .unwrap_err();
+
+        // Fails because the requested batch interval is too far into the past.
+        assert_matches!(err, DapError::Abort(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too far into past".to_string()));
+
+        // Collector: Create a CollectReq with a batch interval in the future.
+        let req = t.collector_authorized_req(
+            task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now)
+                            + t.leader.global_config.max_batch_interval_end
+                            - task_config.time_precision,
+                        duration: task_config.time_precision * 2,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+        );
+
+        // Leader: Handle the CollectReq received from Collector.
+        let err = leader::handle_coll_job_req(&*t.leader, &req)
+            .await
+            .unwrap_err();
+
+        // Fails because the requested batch interval is too far into the future.
+        assert_matches!(err, DapError::Abort(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too far into future".to_string()));
+    }
+
+    async_test_versions! { handle_coll_job_req_fail_invalid_batch_interval }
+
+    async fn handle_coll_job_req_succeed_max_batch_interval(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Collector: Create a CollectReq with a very large batch interval.
+        let req = t.collector_authorized_req(
+            task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: Query::TimeInterval {
+                    batch_interval: Interval {
+                        start: task_config.quantized_time_lower_bound(t.now)
+                            - t.leader.global_config.max_batch_duration / 2,
+                        duration: t.leader.global_config.max_batch_duration,
+                    },
+                },
+                agg_param: Vec::default(),
+            },
+        );
+
+        // Leader: Handle the CollectReq received from Collector.
+        let _collect_uri = leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+    }
+
+    async_test_versions! { handle_coll_job_req_succeed_max_batch_interval }
+
+    async fn handle_coll_job_req_fail_overlapping_batch_interval(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report.clone(), task_id).await;
+        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        // Repeat the request. Expect failure due to overlapping batch.
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BatchOverlap { .. })
+        );
+    }
+
+    async_test_versions! { handle_coll_job_req_fail_overlapping_batch_interval }
+
+    async fn handle_coll_job_req_fail_batch_queried_too_many_times(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+        assert_eq!(task_config.max_batch_query_count, 1);
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+
+        // Query an interval containing the same batch before the first collection job completes.
+        let query = Query::TimeInterval {
+            batch_interval: Interval {
+                start: t.now - task_config.time_precision,
+                duration: 2 * task_config.time_precision,
+            },
+        };
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BatchQueriedTooManyTimes { .. })
+        );
+    }
+
+    async_test_versions! { handle_coll_job_req_fail_batch_queried_too_many_times }
+
+    async fn handle_coll_job_req_fail_unrecongized_batch(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.fixed_size_task_id;
+
+        let req = t
+            .gen_test_coll_job_req(
+                Query::FixedSizeByBatchId {
+                    batch_id: BatchId(thread_rng().gen()),
+                },
+                task_id,
+            )
+            .await;
+
+        // Expect failure due to unrecognized batch
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BatchInvalid { .. })
+        );
+    }
+
+    async_test_versions! { handle_coll_job_req_fail_unrecongized_batch }
+
+    // Test a successful collect request submission.
+    // This checks that the Leader reponds with the collect ID with the ID associated to the request.
+    async fn handle_coll_job_req_success(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        // Collector: Create a CollectReq.
+        let collector_collect_req = CollectionReq {
+            query: task_config.query_for_current_batch_window(t.now),
+            agg_param: Vec::default(),
+        };
+        let req = t.collector_authorized_req(
+            task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            collector_collect_req.clone(),
+        );
+
+        // Leader: Handle the CollectReq received from Collector.
+        let url = leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+        let WorkItem::CollectionJob {
+            task_id: _,
+            coll_job_id: leader_collect_id,
+            batch_sel: leader_batch_sel,
+            agg_param: leader_agg_param,
+            created_at: _,
+        } = t.leader.dequeue_work(1).await.unwrap().pop().unwrap()
+        else {
+            panic!("unexpected work item type");
+        };
+
+        // Check that the CollectReq sent by Collector is the same that is received by Leader.
+        assert_eq!(collector_collect_req.query, leader_batch_sel.into());
+        assert_eq!(
+            collector_collect_req.agg_param,
+            leader_agg_param.get_encoded().unwrap()
+        );
+
+        // Check that the collect_id included in the URI is the same with the one received
+        // by Leader.
+        let path = url.path().to_string();
+        let mut router = Router::new();
+        router
+            .insert("/:version/collect/task/:task_id/req/:collect_id", true)
+            .unwrap();
+        let url_match = router.at(&path).unwrap();
+        let collector_collect_id = url_match.params.get("collect_id").unwrap();
+        assert_eq!(
+            collector_collect_id.to_string(),
+            leader_collect_id.to_base64url()
+        );
+    }
+
+    async_test_versions! { handle_coll_job_req_success }
+
+    // Test that the Leader handles queries from the Collector properly.
+    async fn handle_coll_job_req_invalid_query(version: DapVersion) {
+        let mut rng = thread_rng();
+        let t = Test::new(version);
+
+        // Leader expects "time_interval" query, but Collector sent "fixed_size".
+        let task_config = t
+            .leader
+            .unchecked_get_task_config(&t.time_interval_task_id)
+            .await;
+        let req = t.collector_authorized_req(
+            &t.time_interval_task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: Query::FixedSizeByBatchId {
+                    batch_id: BatchId(rng.gen()),
+                },
+                agg_param: Vec::default(),
+            },
+        );
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::QueryMismatch { .. })
+        );
+
+        // Leader expects "fixed_size" query, but Collector sent "time_interval".
+        let task_config = t
+            .leader
+            .unchecked_get_task_config(&t.fixed_size_task_id)
+            .await;
+        let req = t.collector_authorized_req(
+            &t.fixed_size_task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: task_config.query_for_current_batch_window(t.now),
+                agg_param: Vec::default(),
+            },
+        );
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::QueryMismatch { .. })
+        );
+
+        // Collector indicates unrecognized batch ID.
+        let req = t.collector_authorized_req(
+            &t.fixed_size_task_id,
+            &task_config,
+            DapMediaType::CollectReq,
+            CollectionReq {
+                query: Query::FixedSizeByBatchId {
+                    batch_id: BatchId(rng.gen()), // Unrecognized batch ID
+                },
+                agg_param: Vec::default(),
+            },
+        );
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req)
+                .await
+                .unwrap_err(),
+            DapError::Abort(DapAbort::BatchInvalid { .. })
+        );
+    }
+
+    async_test_versions! { handle_coll_job_req_invalid_query }
+
+    async fn handle_upload_req(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        let report = t.gen_test_report(task_id).await;
+        let req = t.gen_test_upload_req(report, task_id).await;
+
+        leader::handle_upload_req(&*t.leader, &req)
+            .await
+            .expect("upload failed unexpectedly");
+    }
+
+    async_test_versions! { handle_upload_req }
+
+    // Test that request bodies with trailing bytes are rejected rather than silently truncated.
+    async fn handle_req_trailing_bytes(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+
+        let report = t.gen_test_report(task_id).await;
+        let mut req = t.gen_test_upload_req(report, task_id).await;
+        req.payload.push(0);
+        assert_matches!(
+            leader::handle_upload_req(&*t.leader, &req).await,
+            Err(DapError::Abort(DapAbort::InvalidMessage { .. }))
+        );
+        req.payload.pop();
+        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+
+        let query = task_config.query_for_current_batch_window(t.now);
+        let mut req = t.gen_test_coll_job_req(query, task_id).await;
+        req.payload.extend_from_slice(b"junk");
+        assert_matches!(
+            leader::handle_coll_job_req(&*t.leader, &req).await,
+            Err(DapError::Abort(DapAbort::InvalidMessage { .. }))
+        );
+        req.payload.truncate(req.payload.len() - 4);
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+    }
+
+    async_test_versions! { handle_req_trailing_bytes }
+
+    // Test that the Leader tells the Client to re-encrypt a report that was encrypted under an HPKE
+    // config it doesn't recognize, and that the re-encrypted report is accepted.
+    async fn handle_upload_req_outdated_hpke_config(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let current_config_id = t
+            .leader
+            .get_hpke_config_for(version, Some(task_id))
+            .await
+            .unwrap()
+            .id;
+
+        // Upload a report encrypted under a stale config, i.e., one whose ID is not in the
+        // Leader's list.
+        let stale_config_id = (0..=u8::MAX)
+            .find(|id| {
+                !t.leader
+                    .hpke_receiver_config_list
+                    .iter()
+                    .any(|receiver_config| receiver_config.config.id == *id)
+            })
+            .unwrap();
+        let mut report = t.gen_test_report(task_id).await;
+        report.encrypted_input_shares[0].config_id = stale_config_id;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        match leader::handle_upload_req(&*t.leader, &req).await {
+            Err(DapError::Abort(DapAbort::OutdatedConfig {
+                detail,
+                task_id: abort_task_id,
+            })) => {
+                assert_eq!(abort_task_id, *task_id);
+                assert!(
+                    detail.contains(&format!("current configuration ID is {current_config_id}"))
+                );
+            }
+            r => panic!("unexpected result: {r:?}"),
+        }
+        assert_metrics_include!(t.leader_registry, {
+            r#"report_counter{env="test_leader",host="leader.com",status="rejected_hpke_unknown_config_id"}"#: 1,
+        });
+
+        // Re-encrypt under the current config and try again.
+        let report = t.gen_test_report(task_id).await;
+        assert_eq!(
+            report.encrypted_input_shares[0].config_id,
+            current_config_id
+        );
+        let req = t.gen_test_upload_req(report, task_id).await;
+        leader::handle_upload_req(&*t.leader, &req)
+            .await
+            .expect("upload failed unexpectedly");
+    }
+
+    async_test_versions! { handle_upload_req_outdated_hpke_config }
+
+    async fn handle_upload_req_swapped_input_shares(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+
+        // Swap the Leader's and Helper's shares. The Helper's share now in the first position is
+        // labelled with the Leader's config ID so that it passes the config check.
+        let mut report = t.gen_test_report(task_id).await;
+        let leader_config_id = report.encrypted_input_shares[0].config_id;
+        report.encrypted_input_shares.swap(0, 1);
+        report.encrypted_input_shares[0].config_id = leader_config_id;
+        let req = t.gen_test_upload_req(report, task_id).await;
+        match leader::handle_upload_req(&*t.leader, &req).await {
+            Err(DapError::Abort(DapAbort::InvalidMessage {
+                detail,
+                task_id: abort_task_id,
+            })) => {
+                assert_eq!(abort_task_id, *task_id);
+                assert!(detail.contains("Leader's input share"));
+            }
+            r => panic!("unexpected result: {r:?}"),
+        }
+        assert_metrics_include!(t.leader_registry, {
+            r#"report_counter{env="test_leader",host="leader.com",status="rejected_hpke_decrypt_error"}"#: 1,
+        });
+    }
+
+    async_test_versions! { handle_upload_req_swapped_input_shares }
+
+    async fn e2e_time_interval(version: DapVersion) {
+        let t = Test::new(version);
+        let task_id = &t.time_interval_task_id;
+        let task_config = t
+            .leader
+            .unchecked_get_task_config(&t.fixed_size_task_id)
+            .await;
+
+        // Client: Send upload request to Leader.
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
+
+        // Collector: Request result from the Leader.
+        let query = task_config.query_for_current_batch_window(t.now);
+        leader::handle_coll_job_req(&*t.leader, &t.gen_test_coll_job_req(query, task_id).await)
+            .await
+            .unwrap();
+
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+
+        assert_metrics_include!(t.helper_registry, {
+            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 1,
+            r#"inbound_request_counter{env="test_helper",host="helper.org",type="collect"}"#: 1,
+            r#"report_counter{env="test_helper",host="helper.org",status="aggregated"}"#: 1,
+            r#"report_counter{env="test_helper",host="helper.org",status="collected"}"#: 1,
+            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
+            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="completed"}"#: 1,
+        });
+        assert_metrics_include!(t.leader_registry, {
+            r#"report_counter{env="test_leader",host="leader.com",status="aggregated"}"#: 1,
+            r#"report_counter{env="test_leader",host="leader.com",status="collected"}"#: 1,
+        });
+    }
+
+    async_test_versions! { e2e_time_interval }
 
-    async fn dequeue_work_empty(version: DapVersion) {
+    async fn e2e_collect_aggregate_result(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
         let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-        for _ in 0..10 {
-            let report = t.gen_test_report(task_id).await;
-            let req = t.gen_test_upload_req(report.clone(), task_id).await;
-            leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+        // Client: Send upload requests to Leader.
+        for measurement in [1, 1, 0] {
+            let report = t
+                .gen_test_report_for_measurement(task_id, DapMeasurement::U64(measurement))
+                .await;
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap();
         }
 
+        // Collector: Request result from the Leader.
         let query = task_config.query_for_current_batch_window(t.now);
         let req = t.gen_test_coll_job_req(query, task_id).await;
+        let DapResource::CollectionJob(coll_job_id) = req.resource else {
+            panic!("request is missing the collection job ID");
+        };
         leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
 
-        // Get the next work item. This should be an aggregation job for the reports that were
-        // uploaded.
-        let mut work_items = t.leader.dequeue_work(1).await.unwrap();
-        assert_eq!(work_items.len(), 1);
-        let WorkItem::AggregationJob {
-            task_id: returned_task_id,
-            part_batch_sel: _,
-            agg_param: _,
-            reports,
-        } = work_items.pop().unwrap()
-        else {
-            panic!("unexpected work item type");
-        };
-        assert_eq!(reports.len(), 10);
-        assert_eq!(&returned_task_id, task_id);
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
 
-        // Get the next work item. This should be the collection job.
-        let mut work_items = t.leader.dequeue_work(1).await.unwrap();
-        assert_eq!(work_items.len(), 1);
-        let WorkItem::CollectionJob {
-            task_id: returned_task_id,
-            coll_job_id: _,
-            batch_sel: _,
-            agg_param: _,
-        } = work_items.pop().unwrap()
+        // Collector: Poll the collection job and unshard the aggregate result.
+        let DapCollectionJob::Done(collection) = t
+            .leader
+            .poll_collect_job(task_id, &coll_job_id)
+            .await
+            .unwrap()
         else {
-            panic!("unexpected work item type");
+            panic!("collection job is not done");
         };
-        assert_eq!(&returned_task_id, task_id);
+        assert_eq!(collection.report_count, 3);
 
-        // Get the next work item. Expect the return value to be empty because there is no more
-        // work to process.
-        assert_eq!(t.leader.dequeue_work(1).await.unwrap().len(), 0);
+        let agg_result = task_config
+            .consume_encrypted_agg_shares(
+                &t.collector_hpke_receiver_config,
+                task_id,
+                &query.into_batch_sel().unwrap(),
+                collection.report_count,
+                &DapAggregationParam::Empty,
+                collection.encrypted_agg_shares.to_vec(),
+                version,
+            )
+            .await
+            .unwrap();
+        assert_eq!(agg_result, DapAggregateResult::U64(2));
     }
 
-    async_test_versions! { dequeue_work_empty }
+    async_test_versions! { e2e_collect_aggregate_result }
 
-    async fn poll_collect_job_test_results(version: DapVersion) {
+    async fn e2e_inject_synthetic_reports(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
         let task_config = t.leader.unchecked_get_task_config(task_id).await;
+        let helper_hpke_config = t
+            .helper
+            .get_hpke_config_for(version, Some(task_id))
+            .await
+            .unwrap()
+            .clone();
 
-        // Collector: Create a CollectReq.
-        let req = t.collector_authorized_req(
-            task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            CollectionReq {
-                query: task_config.query_for_current_batch_window(t.now),
-                agg_param: Vec::default(),
-            },
-        );
+        let measurements =
+            leader::inject_synthetic_reports(&*t.leader, task_id, &helper_hpke_config, 100)
+                .await
+                .unwrap();
+        assert_eq!(measurements.len(), 100);
+        let expected_count = measurements
+            .iter()
+            .map(|measurement| match measurement {
+                DapMeasurement::U64(bit) => *bit,
+                _ => panic!("unexpected measurement type"),
+            })
+            .sum::<u64>();
 
-        // Leader: Handle the CollectReq received from Collector.
+        // Collector: Request result from the Leader.
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, task_id).await;
+        let DapResource::CollectionJob(coll_job_id) = req.resource else {
+            panic!("request is missing the collection job ID");
+        };
         leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
 
-        // Expect DapCollectionJob::Unknown due to invalid collect ID.
-        assert_eq!(
-            t.leader
-                .poll_collect_job(task_id, &CollectionJobId::default())
-                .await
-                .unwrap(),
-            DapCollectionJob::Unknown
-        );
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
 
-        // Leader: Get pending collect job to obtain collect_id
-        let WorkItem::CollectionJob {
-            task_id: _,
-            coll_job_id,
-            batch_sel: _,
-            agg_param: _,
-        } = t.leader.dequeue_work(1).await.unwrap().pop().unwrap()
+        let DapCollectionJob::Done(collection) = t
+            .leader
+            .poll_collect_job(task_id, &coll_job_id)
+            .await
+            .unwrap()
         else {
-            panic!("unexpected work item type")
-        };
-        let collection = Collection {
-            part_batch_sel: PartialBatchSelector::TimeInterval,
-            report_count: 0,
-            interval: Interval {
-                start: 0,
-                duration: 2_000_000_000,
-            },
-            encrypted_agg_shares: [
-                HpkeCiphertext {
-                    config_id: Default::default(),
-                    enc: Default::default(),
-                    payload: Default::default(),
-                },
-                HpkeCiphertext {
-                    config_id: Default::default(),
-                    enc: Default::default(),
-                    payload: Default::default(),
-                },
-            ],
+            panic!("collection job is not done");
         };
+        assert_eq!(collection.report_count, 100);
 
-        // Expect DapCollectionJob::Pending due to pending collect job.
-        assert_eq!(
-            t.leader
-                .poll_collect_job(task_id, &coll_job_id)
-                .await
-                .unwrap(),
-            DapCollectionJob::Pending
-        );
-
-        // Leader: Complete the collect job by storing CollectResp in LeaderStore.processed.
-        t.leader
-            .finish_collect_job(task_id, &coll_job_id, &collection)
+        let agg_result = task_config
+            .consume_encrypted_agg_shares(
+                &t.collector_hpke_receiver_config,
+                task_id,
+                &query.into_batch_sel().unwrap(),
+                collection.report_count,
+                &DapAggregationParam::Empty,
+                collection.encrypted_agg_shares.to_vec(),
+                version,
+            )
             .await
             .unwrap();
-
-        // Expect DapCollectionJob::Done due to processed collect job.
-        assert_matches!(
-            t.leader
-                .poll_collect_job(task_id, &coll_job_id)
-                .await
-                .unwrap(),
-            DapCollectionJob::Done(..)
-        );
+        assert_eq!(agg_result, DapAggregateResult::U64(expected_count));
     }
 
-    async_test_versions! { poll_collect_job_test_results }
+    async_test_versions! { e2e_inject_synthetic_reports }
 
-    async fn handle_coll_job_req_fail_invalid_batch_interval(version: DapVersion) {
+    // Test that the Leader finishes an aggregation job early if the Helper rejects every report.
+    async fn e2e_all_reports_rejected_by_helper(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
-        let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-        // Collector: Create a CollectReq with a very large batch interval.
-        let req = t.collector_authorized_req(
-            task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            CollectionReq {
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now),
-                        duration: t.leader.global_config.max_batch_duration
-                            + task_config.time_precision,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-        );
+        // Client: Send upload requests to Leader. The Helper's input shares are corrupted so that
+        // it rejects every report.
+        for _ in 0..2 {
+            let mut report = t.gen_test_report(task_id).await;
+            report.encrypted_input_shares[1].payload[0] ^= 1;
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap();
+        }
 
-        // Leader: Handle the CollectReq received from Collector.
-        let err = leader::handle_coll_job_req(&*t.leader, &req)
+        let telem = leader::process(&*t.leader, "leader.com", 100)
             .await
-            .unwrap_err();
-
-        // Fails because the requested batch interval is too large.
-        assert_matches!(err, DapError::Abort(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too large".to_string()));
+            .unwrap();
+        assert_eq!(telem.reports_processed, 2);
+        assert_eq!(telem.reports_aggregated, 0);
 
-        // Collector: Create a CollectReq with a batch interval in the past.
-        let req = t.collector_authorized_req(
-            task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            CollectionReq {
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now)
-                            - t.leader.global_config.min_batch_interval_start
-                            - task_config.time_precision,
-                        duration: task_config.time_precision * 2,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-        );
+        assert_metrics_include!(t.helper_registry, {
+            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 1,
+            r#"report_counter{env="test_helper",host="helper.org",status="rejected_hpke_decrypt_error"}"#: 2,
+        });
+        assert_metrics_include!(t.leader_registry, {
+            r#"report_counter{env="test_leader",host="leader.com",status="rejected_hpke_decrypt_error"}"#: 2,
+        });
+    }
 
-        // Leader: Handle the CollectReq received from Collector.
-        let err = leader::handle_coll_job_req(&*t.leader, &req)
-            .await
-            .unwrap_err();
+    async_test_versions! { e2e_all_reports_rejected_by_helper }
 
-        // Fails because the requested batch interval is too far into the past.
-        assert_matches!(err, DapError::Abort(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too far into past".to_string()));
+    // Test that, if so configured, the Leader quarantines an aggregation job aborted by the Helper
+    // and carries on with the others.
+    async fn e2e_quarantine_agg_job_aborted_by_helper(version: DapVersion) {
+        let mut data = TestData::new(version);
+        data.global_config.helper_abort_policy = HelperAbortPolicy::Quarantine;
 
-        // Collector: Create a CollectReq with a batch interval in the future.
-        let req = t.collector_authorized_req(
-            task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            CollectionReq {
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now)
-                            + t.leader.global_config.max_batch_interval_end
-                            - task_config.time_precision,
-                        duration: task_config.time_precision * 2,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-        );
+        // The Helper doesn't know about the time-interval task, so it aborts aggregation jobs for
+        // it with "unrecognizedTask".
+        let aborted_task_id = data.time_interval_task_id;
+        let aborted_task_config = data.tasks.remove(&aborted_task_id).unwrap();
+        let helper = data.new_helper();
+        data.tasks.insert(aborted_task_id, aborted_task_config);
+        let t = data.with_leader(helper);
 
-        // Leader: Handle the CollectReq received from Collector.
-        let err = leader::handle_coll_job_req(&*t.leader, &req)
+        let time_interval_query = t
+            .leader
+            .unchecked_get_task_config(&t.time_interval_task_id)
             .await
-            .unwrap_err();
-
-        // Fails because the requested batch interval is too far into the future.
-        assert_matches!(err, DapError::Abort(DapAbort::BadRequest(s)) => assert_eq!(s, "batch interval too far into future".to_string()));
-    }
-
-    async_test_versions! { handle_coll_job_req_fail_invalid_batch_interval }
-
-    async fn handle_coll_job_req_succeed_max_batch_interval(version: DapVersion) {
-        let t = Test::new(version);
-        let task_id = &t.time_interval_task_id;
-        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+            .query_for_current_batch_window(t.now);
+        for (task_id, query) in [
+            (&t.time_interval_task_id, time_interval_query),
+            (&t.fixed_size_task_id, Query::FixedSizeCurrentBatch),
+        ] {
+            let report = t.gen_test_report(task_id).await;
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+                .await
+                .unwrap();
 
-        // Collector: Create a CollectReq with a very large batch interval.
-        let req = t.collector_authorized_req(
-            task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            CollectionReq {
-                query: Query::TimeInterval {
-                    batch_interval: Interval {
-                        start: task_config.quantized_time_lower_bound(t.now)
-                            - t.leader.global_config.max_batch_duration / 2,
-                        duration: t.leader.global_config.max_batch_duration,
-                    },
-                },
-                agg_param: Vec::default(),
-            },
-        );
+            leader::handle_coll_job_req(&*t.leader, &t.gen_test_coll_job_req(query, task_id).await)
+                .await
+                .unwrap();
+        }
 
-        // Leader: Handle the CollectReq received from Collector.
-        let _collect_uri = leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+        let telem = leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+        assert_eq!(telem.reports_processed, 2);
+        assert_eq!(telem.reports_aggregated, 1);
+        assert_eq!(telem.reports_collected, 1);
+
+        let quarantined = t.leader.quarantined_agg_jobs().await.unwrap();
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].task_id, aborted_task_id);
+        assert_eq!(quarantined[0].reports.len(), 1);
+        assert!(quarantined[0].reason.contains("UnrecognizedTask"));
     }
 
-    async_test_versions! { handle_coll_job_req_succeed_max_batch_interval }
+    async_test_versions! { e2e_quarantine_agg_job_aborted_by_helper }
 
-    async fn handle_coll_job_req_fail_overlapping_batch_interval(version: DapVersion) {
-        let t = Test::new(version);
-        let task_id = &t.time_interval_task_id;
-        let task_config = t.leader.unchecked_get_task_config(task_id).await;
+    // Test that a collection job that is still pending after the aggregation deadline, e.g.,
+    // because the Helper keeps failing, is marked as failed rather than retried forever.
+    async fn e2e_collect_aggregation_deadline_exceeded(version: DapVersion) {
+        let mut data = TestData::new(version);
+        data.global_config.helper_abort_policy = HelperAbortPolicy::Quarantine;
+        data.global_config.collect_aggregation_deadline = Some(3600);
 
-        let report = t.gen_test_report(task_id).await;
-        let req = t.gen_test_upload_req(report.clone(), task_id).await;
-        leader::handle_upload_req(&*t.leader, &req).await.unwrap();
+        // The Helper doesn't know about the time-interval task, so aggregation never completes.
+        let task_id = data.time_interval_task_id;
+        let task_config = data.tasks.remove(&task_id).unwrap();
+        let helper = data.new_helper();
+        data.tasks.insert(task_id, task_config);
+        let t = data.with_leader(helper);
 
-        let query = task_config.query_for_current_batch_window(t.now);
-        let req = t.gen_test_coll_job_req(query, task_id).await;
-        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
+        let query = t
+            .leader
+            .unchecked_get_task_config(&task_id)
+            .await
+            .query_for_current_batch_window(t.now);
+        let report = t.gen_test_report(&task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, &task_id).await)
+            .await
+            .unwrap();
+        leader::handle_coll_job_req(&*t.leader, &t.gen_test_coll_job_req(query, &task_id).await)
+            .await
+            .unwrap();
 
-        leader::process(&*t.leader, "leader.com", 100)
+        // Within the deadline, the collection job stays pending.
+        let telem = leader::process(&*t.leader, "leader.com", 100)
             .await
             .unwrap();
+        assert_eq!(telem.reports_collected, 0);
+        let mut work_items = t.leader.dequeue_work(100).await.unwrap();
+        assert_eq!(work_items.len(), 1);
+        let WorkItem::CollectionJob {
+            coll_job_id,
+            created_at,
+            ..
+        } = &mut work_items[0]
+        else {
+            panic!("unexpected work item type")
+        };
+        let coll_job_id = *coll_job_id;
+        assert_eq!(
+            t.leader
+                .poll_collect_job(&task_id, &coll_job_id)
+                .await
+                .unwrap(),
+            DapCollectionJob::Pending
+        );
 
-        // Repeat the request. Expect failure due to overlapping batch.
+        // Once the deadline has passed, the collection job is failed and removed from the queue.
+        *created_at -= 3600;
+        t.leader.enqueue_work(work_items).await.unwrap();
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
         assert_matches!(
-            leader::handle_coll_job_req(&*t.leader, &req)
+            t.leader
+                .poll_collect_job(&task_id, &coll_job_id)
                 .await
-                .unwrap_err(),
-            DapError::Abort(DapAbort::BatchOverlap { .. })
+                .unwrap(),
+            DapCollectionJob::Failed(reason)
+                if reason == "aggregation did not complete within the deadline of 3600 seconds"
         );
+        assert!(t.leader.dequeue_work(100).await.unwrap().is_empty());
     }
 
-    async_test_versions! { handle_coll_job_req_fail_overlapping_batch_interval }
+    async_test_versions! { e2e_collect_aggregation_deadline_exceeded }
+
+    // Test that a batch below the task's minimum batch size is only collected if partial batches
+    // are allowed.
+    async fn e2e_partial_batch(version: DapVersion, allow_partial_batch: bool) {
+        let mut data = TestData::new(version);
+        data.global_config.allow_partial_batch = allow_partial_batch;
+        let task_id = data.time_interval_task_id;
+        data.tasks.get_mut(&task_id).unwrap().min_batch_size = 3;
+        let helper = data.new_helper();
+        let t = data.with_leader(helper);
+        let task_config = t.leader.unchecked_get_task_config(&task_id).await;
+
+        // Client: Send upload requests to Leader, one fewer than the minimum batch size.
+        for _ in 0..2 {
+            let report = t.gen_test_report(&task_id).await;
+            leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, &task_id).await)
+                .await
+                .unwrap();
+        }
 
-    async fn handle_coll_job_req_fail_unrecongized_batch(version: DapVersion) {
-        let t = Test::new(version);
-        let task_id = &t.fixed_size_task_id;
+        // Collector: Request result from the Leader.
+        let query = task_config.query_for_current_batch_window(t.now);
+        let req = t.gen_test_coll_job_req(query, &task_id).await;
+        let DapResource::CollectionJob(coll_job_id) = req.resource else {
+            panic!("request is missing the collection job ID");
+        };
+        leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
 
-        let req = t
-            .gen_test_coll_job_req(
-                Query::FixedSizeByBatchId {
-                    batch_id: BatchId(thread_rng().gen()),
-                },
-                task_id,
-            )
-            .await;
+        let telem = leader::process(&*t.leader, "leader.com", 100)
+            .await
+            .unwrap();
+        assert_eq!(telem.reports_aggregated, 2);
 
-        // Expect failure due to unrecognized batch
-        assert_matches!(
-            leader::handle_coll_job_req(&*t.leader, &req)
-                .await
-                .unwrap_err(),
-            DapError::Abort(DapAbort::BatchInvalid { .. })
-        );
+        let coll_job = t
+            .leader
+            .poll_collect_job(&task_id, &coll_job_id)
+            .await
+            .unwrap();
+        if allow_partial_batch {
+            assert_eq!(telem.reports_collected, 2);
+            let DapCollectionJob::Done(collection) = coll_job else {
+                panic!("collection job is not done");
+            };
+            assert_eq!(collection.report_count, 2);
+        } else {
+            assert_eq!(telem.reports_collected, 0);
+            assert_matches!(coll_job, DapCollectionJob::Pending);
+
+            // The Helper refuses to release its aggregate share for the batch.
+            let batch_sel = query.into_batch_sel().unwrap();
+            let leader_agg_share = t.leader.get_agg_share(&task_id, &batch_sel).await.unwrap();
+            let req = t
+                .leader_authorized_req(
+                    &task_id,
+                    &task_config,
+                    None,
+                    DapMediaType::AggregateShareReq,
+                    AggregateShareReq {
+                        batch_sel,
+                        agg_param: Vec::default(),
+                        report_count: leader_agg_share.report_count,
+                        checksum: leader_agg_share.checksum,
+                    },
+                )
+                .await;
+            assert_matches!(
+                helper::handle_agg_share_req(&*t.helper, &req).await,
+                Err(DapError::Abort(DapAbort::InvalidBatchSize { .. }))
+            );
+        }
     }
 
-    async_test_versions! { handle_coll_job_req_fail_unrecongized_batch }
+    async fn e2e_partial_batch_allowed(version: DapVersion) {
+        e2e_partial_batch(version, true).await;
+    }
+
+    async_test_versions! { e2e_partial_batch_allowed }
+
+    async fn e2e_partial_batch_not_allowed(version: DapVersion) {
+        e2e_partial_batch(version, false).await;
+    }
+
+    async_test_versions! { e2e_partial_batch_not_allowed }
+
+    /// Records the `state` field of each event emitted at a protocol state transition.
+    #[derive(Clone, Default)]
+    struct StateTransitionRecorder(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for StateTransitionRecorder {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct StateVisitor(Option<String>);
+
+            impl tracing::field::Visit for StateVisitor {
+                fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+                    if field.name() == "state" {
+                        self.0 = Some(value.to_string());
+                    }
+                }
+
+                fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn Debug) {}
+            }
+
+            let mut visitor = StateVisitor(None);
+            event.record(&mut visitor);
+            if let Some(state) = visitor.0 {
+                self.0.lock().unwrap().push(state);
+            }
+        }
+    }
+
+    async fn e2e_state_transition_events(version: DapVersion) {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let recorder = StateTransitionRecorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
 
-    // Test a successful collect request submission.
-    // This checks that the Leader reponds with the collect ID with the ID associated to the request.
-    async fn handle_coll_job_req_success(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
         let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-        // Collector: Create a CollectReq.
-        let collector_collect_req = CollectionReq {
-            query: task_config.query_for_current_batch_window(t.now),
-            agg_param: Vec::default(),
-        };
-        let req = t.collector_authorized_req(
-            task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            collector_collect_req.clone(),
-        );
-
-        // Leader: Handle the CollectReq received from Collector.
-        let url = leader::handle_coll_job_req(&*t.leader, &req).await.unwrap();
-        let WorkItem::CollectionJob {
-            task_id: _,
-            coll_job_id: leader_collect_id,
-            batch_sel: leader_batch_sel,
-            agg_param: leader_agg_param,
-        } = t.leader.dequeue_work(1).await.unwrap().pop().unwrap()
-        else {
-            panic!("unexpected work item type");
-        };
+        let report = t.gen_test_report(task_id).await;
+        leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
+            .await
+            .unwrap();
 
-        // Check that the CollectReq sent by Collector is the same that is received by Leader.
-        assert_eq!(collector_collect_req.query, leader_batch_sel.into());
-        assert_eq!(
-            collector_collect_req.agg_param,
-            leader_agg_param.get_encoded().unwrap()
-        );
+        let query = task_config.query_for_current_batch_window(t.now);
+        leader::handle_coll_job_req(&*t.leader, &t.gen_test_coll_job_req(query, task_id).await)
+            .await
+            .unwrap();
 
-        // Check that the collect_id included in the URI is the same with the one received
-        // by Leader.
-        let path = url.path().to_string();
-        let mut router = Router::new();
-        router
-            .insert("/:version/collect/task/:task_id/req/:collect_id", true)
+        leader::process(&*t.leader, "leader.com", 100)
+            .await
             .unwrap();
-        let url_match = router.at(&path).unwrap();
-        let collector_collect_id = url_match.params.get("collect_id").unwrap();
+
         assert_eq!(
-            collector_collect_id.to_string(),
-            leader_collect_id.to_base64url()
+            *recorder.0.lock().unwrap(),
+            [
+                "report_uploaded",     // Leader
+                "agg_job_initialized", // Leader
+                "agg_job_initialized", // Helper
+                "agg_job_finished",    // Helper
+                "agg_job_continued",   // Leader
+                "agg_job_finished",    // Leader
+                "batch_collected",     // Helper
+                "batch_collected",     // Leader
+            ]
         );
     }
 
-    async_test_versions! { handle_coll_job_req_success }
-
-    // Test that the Leader handles queries from the Collector properly.
-    async fn handle_coll_job_req_invalid_query(version: DapVersion) {
-        let mut rng = thread_rng();
-        let t = Test::new(version);
+    async_test_versions! { e2e_state_transition_events }
 
-        // Leader expects "time_interval" query, but Collector sent "fixed_size".
-        let task_config = t
-            .leader
-            .unchecked_get_task_config(&t.time_interval_task_id)
-            .await;
-        let req = t.collector_authorized_req(
-            &t.time_interval_task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            CollectionReq {
-                query: Query::FixedSizeByBatchId {
-                    batch_id: BatchId(rng.gen()),
-                },
-                agg_param: Vec::default(),
-            },
-        );
-        assert_matches!(
-            leader::handle_coll_job_req(&*t.leader, &req)
-                .await
-                .unwrap_err(),
-            DapError::Abort(DapAbort::QueryMismatch { .. })
-        );
+    /// Records the name and fields of each span.
+    #[derive(Clone, Default)]
+    struct SpanRecorder(Arc<std::sync::Mutex<HashMap<tracing::Id, SpanFields>>>);
 
-        // Collector indicates unrecognized batch ID.
-        let task_config = t
-            .leader
-            .unchecked_get_task_config(&t.fixed_size_task_id)
-            .await;
-        let req = t.collector_authorized_req(
-            &t.fixed_size_task_id,
-            &task_config,
-            DapMediaType::CollectReq,
-            CollectionReq {
-                query: Query::FixedSizeByBatchId {
-                    batch_id: BatchId(rng.gen()), // Unrecognized batch ID
-                },
-                agg_param: Vec::default(),
-            },
-        );
-        assert_matches!(
-            leader::handle_coll_job_req(&*t.leader, &req)
-                .await
-                .unwrap_err(),
-            DapError::Abort(DapAbort::BatchInvalid { .. })
-        );
+    #[derive(Debug, Default)]
+    struct SpanFields {
+        name: &'static str,
+        fields: HashMap<&'static str, String>,
     }
 
-    async_test_versions! { handle_coll_job_req_invalid_query }
+    impl tracing::field::Visit for SpanFields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn Debug) {
+            self.fields.insert(field.name(), format!("{value:?}"));
+        }
+    }
 
-    async fn handle_upload_req(version: DapVersion) {
-        let t = Test::new(version);
-        let task_id = &t.time_interval_task_id;
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanRecorder {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut span = SpanFields {
+                name: attrs.metadata().name(),
+                ..Default::default()
+            };
+            attrs.record(&mut span);
+            self.0.lock().unwrap().insert(id.clone(), span);
+        }
 
-        let report = t.gen_test_report(task_id).await;
-        let req = t.gen_test_upload_req(report, task_id).await;
+        fn on_record(
+            &self,
+            id: &tracing::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if let Some(span) = self.0.lock().unwrap().get_mut(id) {
+                values.record(span);
+            }
+        }
+    }
 
-        leader::handle_upload_req(&*t.leader, &req)
-            .await
-            .expect("upload failed unexpectedly");
+    impl SpanRecorder {
+        /// Return the fields of each span with the given name.
+        fn fields_of(&self, name: &str) -> Vec<HashMap<&'static str, String>> {
+            self.0
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|span| span.name == name)
+                .map(|span| span.fields.clone())
+                .collect()
+        }
     }
 
-    async_test_versions! { handle_upload_req }
+    async fn e2e_span_fields(version: DapVersion) {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let recorder = SpanRecorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
 
-    async fn e2e_time_interval(version: DapVersion) {
         let t = Test::new(version);
         let task_id = &t.time_interval_task_id;
-        let task_config = t
-            .leader
-            .unchecked_get_task_config(&t.fixed_size_task_id)
-            .await;
+        let task_config = t.leader.unchecked_get_task_config(task_id).await;
 
-        // Client: Send upload request to Leader.
         let report = t.gen_test_report(task_id).await;
         leader::handle_upload_req(&*t.leader, &t.gen_test_upload_req(report, task_id).await)
             .await
             .unwrap();
 
-        // Collector: Request result from the Leader.
         let query = task_config.query_for_current_batch_window(t.now);
         leader::handle_coll_job_req(&*t.leader, &t.gen_test_coll_job_req(query, task_id).await)
             .await
@@ -1554,21 +3222,33 @@ mod test {
             .await
             .unwrap();
 
-        assert_metrics_include!(t.helper_registry, {
-            r#"inbound_request_counter{env="test_helper",host="helper.org",type="aggregate"}"#: 1,
-            r#"inbound_request_counter{env="test_helper",host="helper.org",type="collect"}"#: 1,
-            r#"report_counter{env="test_helper",host="helper.org",status="aggregated"}"#: 1,
-            r#"report_counter{env="test_helper",host="helper.org",status="collected"}"#: 1,
-            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="started"}"#: 1,
-            r#"aggregation_job_counter{env="test_helper",host="helper.org",status="completed"}"#: 1,
-        });
-        assert_metrics_include!(t.leader_registry, {
-            r#"report_counter{env="test_leader",host="leader.com",status="aggregated"}"#: 1,
-            r#"report_counter{env="test_leader",host="leader.com",status="collected"}"#: 1,
-        });
+        let task_id = task_id.to_base64url();
+        for name in [
+            "handle_upload_req",
+            "handle_coll_job_req",
+            "run_agg_job",
+            "handle_agg_job_init_req",
+            "run_coll_job",
+            "handle_agg_share_req",
+        ] {
+            let spans = recorder.fields_of(name);
+            assert_eq!(spans.len(), 1, "{name}: {spans:?}");
+            assert_eq!(spans[0].get("task_id"), Some(&task_id), "{name}");
+        }
+
+        // The Leader and Helper agree on the aggregation job ID.
+        let leader_agg_job_id = recorder.fields_of("run_agg_job")[0]
+            .get("agg_job_id")
+            .cloned()
+            .unwrap();
+        let helper_agg_job_id = recorder.fields_of("handle_agg_job_init_req")[0]
+            .get("agg_job_id")
+            .cloned()
+            .unwrap();
+        assert_eq!(leader_agg_job_id, helper_agg_job_id);
     }
 
-    async_test_versions! { e2e_time_interval }
+    async_test_versions! { e2e_span_fields }
 
     async fn e2e_fixed_size(version: DapVersion) {
         let t = Test::new(version);
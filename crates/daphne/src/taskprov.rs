@@ -13,7 +13,7 @@ use crate::{
     messages::{
         self, decode_base64url_vec,
         taskprov::{QueryConfigVar, TaskConfig, VdafTypeVar},
-        Duration, TaskId, Time,
+        Duration, QueryTypeStrictness, TaskId, Time,
     },
     vdaf::VdafVerifyKey,
     DapAbort, DapError, DapQueryConfig, DapRequest, DapTaskConfig, DapTaskConfigMethod, DapVersion,
@@ -23,7 +23,7 @@ use crate::{
     pine::PineParam,
     vdaf::pine::{pine32_hmac_sha256_aes128, pine64_hmac_sha256_aes128, PineConfig},
 };
-use prio::codec::ParameterizedDecode;
+use prio::codec::{ParameterizedDecode, ParameterizedEncode};
 use ring::{
     digest,
     hkdf::{Prk, Salt, HKDF_SHA256},
@@ -111,24 +111,34 @@ pub(crate) fn resolve_advertised_task_config<S>(
     verify_key_init: &[u8; 32],
     collector_hpke_config: &HpkeConfig,
     task_id: &TaskId,
-) -> Result<Option<DapTaskConfigNeedsOptIn>, DapAbort> {
-    get_taskprov_task_config(req, task_id)?
-        .map(|task_config_msg| {
-            DapTaskConfigNeedsOptIn::try_from_taskprov(
-                req.version,
-                task_id, // get_taskprov_task_config() checks that this matches the derived ID
-                task_config_msg,
-                verify_key_init,
-                collector_hpke_config,
-            )
-        })
-        .transpose()
+    max_prio2_dimension: Option<usize>,
+    query_type_strictness: QueryTypeStrictness,
+) -> Result<Option<DapTaskConfigNeedsOptIn>, DapError> {
+    let Some(task_config_msg) = get_taskprov_task_config(req, task_id, query_type_strictness)?
+    else {
+        return Ok(None);
+    };
+
+    // get_taskprov_task_config() checks that the advertised bytes hash to the task ID. Check that
+    // the config we decoded from them does too, so that we never provision a task from a config
+    // that doesn't match its ID.
+    task_config_msg.verify_task_id(task_id, req.version)?;
+
+    Ok(Some(DapTaskConfigNeedsOptIn::try_from_taskprov(
+        req.version,
+        task_id,
+        task_config_msg,
+        verify_key_init,
+        collector_hpke_config,
+        max_prio2_dimension,
+    )?))
 }
 
 /// Check for a taskprov extension in the report, and return it if found.
 fn get_taskprov_task_config<S>(
     req: &'_ DapRequest<S>,
     task_id: &TaskId,
+    query_type_strictness: QueryTypeStrictness,
 ) -> Result<Option<TaskConfig>, DapAbort> {
     let taskprov_data = if let Some(ref taskprov_base64url) = req.taskprov {
         decode_base64url_vec(taskprov_base64url).ok_or_else(|| {
@@ -147,8 +157,11 @@ fn get_taskprov_task_config<S>(
     }
 
     // Return unrecognizedMessage if parsing fails following section 5.1 of the taskprov draft.
-    let task_config = TaskConfig::get_decoded_with_param(&req.version, taskprov_data.as_ref())
-        .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
+    let task_config = TaskConfig::get_decoded_with_param(
+        &(req.version, query_type_strictness),
+        taskprov_data.as_ref(),
+    )
+    .map_err(|e| DapAbort::from_codec_error(e, *task_id))?;
 
     Ok(Some(task_config))
 }
@@ -222,16 +235,26 @@ impl VdafConfig {
         task_id: &TaskId,
         version: DapVersion,
         var: VdafTypeVar,
+        max_prio2_dimension: Option<usize>,
     ) -> Result<Self, DapAbort> {
         const PRIO3_MAX_PROOFS: u8 = 3;
 
         match (version, var) {
-            (_, VdafTypeVar::Prio2 { dimension }) => Ok(VdafConfig::Prio2 {
-                dimension: dimension.try_into().map_err(|_| DapAbort::InvalidTask {
+            (_, VdafTypeVar::Prio2 { dimension }) => {
+                let dimension = dimension.try_into().map_err(|_| DapAbort::InvalidTask {
                     detail: "dimension is larger than the system's word size".to_string(),
                     task_id: *task_id,
-                })?,
-            }),
+                })?;
+                if let Some(max_dimension) = max_prio2_dimension {
+                    if dimension > max_dimension {
+                        return Err(DapAbort::InvalidTask {
+                            detail: format!("dimension must not exceed {max_dimension}"),
+                            task_id: *task_id,
+                        });
+                    }
+                }
+                Ok(VdafConfig::Prio2 { dimension })
+            }
             (
                 DapVersion::Draft09 | DapVersion::Latest,
                 VdafTypeVar::Prio3SumVecField64MultiproofHmacSha256Aes128 {
@@ -311,7 +334,8 @@ impl VdafConfig {
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct OptInParam {
-    /// Same as [`DapTaskConfig`].
+    /// Same as [`DapTaskConfig`], except that it is rounded down to a multiple of the task's time
+    /// precision when opting in.
     pub not_before: Time,
 
     /// Same as [`DapTaskConfig`].
@@ -333,6 +357,7 @@ pub struct DapTaskConfigNeedsOptIn {
     pub(crate) vdaf_verify_key: VdafVerifyKey,
     pub(crate) collector_hpke_config: HpkeConfig,
     pub(crate) method: DapTaskConfigMethod,
+    pub(crate) max_batch_query_count: u16,
 
     /// The time at which the task expires.
     pub(crate) task_expiration: Time,
@@ -345,6 +370,7 @@ impl DapTaskConfigNeedsOptIn {
         task_config: TaskConfig,
         vdaf_verify_key_init: &[u8; 32],
         collector_hpke_config: &HpkeConfig,
+        max_prio2_dimension: Option<usize>,
     ) -> Result<Self, DapAbort> {
         // Only one query per batch is currently supported.
         if task_config.query_config.max_batch_query_count != 1 {
@@ -357,7 +383,12 @@ impl DapTaskConfigNeedsOptIn {
             });
         }
 
-        let vdaf = VdafConfig::try_from_taskprov(task_id, version, task_config.vdaf_config.var)?;
+        let vdaf = VdafConfig::try_from_taskprov(
+            task_id,
+            version,
+            task_config.vdaf_config.var,
+            max_prio2_dimension,
+        )?;
         let vdaf_verify_key =
             compute_vdaf_verify_key(version, vdaf_verify_key_init, task_id, &vdaf);
         Ok(Self {
@@ -374,6 +405,7 @@ impl DapTaskConfigNeedsOptIn {
             method: DapTaskConfigMethod::Taskprov {
                 info: Some(task_config.task_info),
             },
+            max_batch_query_count: task_config.query_config.max_batch_query_count,
         })
     }
 
@@ -387,12 +419,20 @@ impl DapTaskConfigNeedsOptIn {
             min_batch_size: self.min_batch_size,
             query: self.query,
             vdaf: self.vdaf,
-            not_before: param.not_before,
+            // Clients round their report timestamps down, so a task that starts in the middle of a
+            // time precision window would reject every report of that window.
+            not_before: param.not_before
+                - param
+                    .not_before
+                    .checked_rem(self.time_precision)
+                    .unwrap_or_default(),
             not_after: self.task_expiration,
             vdaf_verify_key: self.vdaf_verify_key,
+            vdaf_verify_keys_by_epoch: Default::default(),
             collector_hpke_config: self.collector_hpke_config,
             method: self.method,
             num_agg_span_shards: param.num_agg_span_shards,
+            max_batch_query_count: self.max_batch_query_count,
         }
     }
 }
@@ -495,6 +535,26 @@ impl TryFrom<&DapTaskConfig> for messages::taskprov::TaskConfig {
     }
 }
 
+impl TaskConfig {
+    /// taskprov: Derive the task ID from this config, i.e., the SHA-256 hash of its encoding.
+    pub fn compute_task_id(&self, version: DapVersion) -> Result<TaskId, DapError> {
+        let encoded = self
+            .get_encoded_with_param(&version)
+            .map_err(|e| fatal_error!(err = ?e, "failed to encode taskprov task config"))?;
+        Ok(compute_task_id(&encoded))
+    }
+
+    /// taskprov: Check that `claimed` is the task ID derived from this config, i.e., the hash of
+    /// its encoding. A mismatch means the config was tampered with or isn't the one the peer
+    /// advertised, in which case we abort with "unrecognizedTask".
+    pub fn verify_task_id(&self, claimed: &TaskId, version: DapVersion) -> Result<(), DapError> {
+        if self.compute_task_id(version)? != *claimed {
+            return Err(DapAbort::UnrecognizedTask { task_id: *claimed }.into());
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::num::NonZeroUsize;
@@ -505,11 +565,11 @@ mod test {
     use crate::{
         error::DapAbort,
         hpke::{HpkeKemId, HpkeReceiverConfig},
-        messages::{self, encode_base64url, TaskId},
+        messages::{self, encode_base64url, QueryTypeStrictness, TaskId},
         taskprov::{DapTaskConfigNeedsOptIn, OptInParam},
         test_versions,
         vdaf::{VdafConfig, VdafVerifyKey},
-        DapRequest, DapResource, DapVersion,
+        DapError, DapRequest, DapResource, DapVersion,
     };
 
     /// Test conversion between the serialized task configuration and a `DapTaskConfig`.
@@ -545,6 +605,7 @@ mod test {
             &HpkeReceiverConfig::gen(23, HpkeKemId::P256HkdfSha256)
                 .unwrap()
                 .config,
+            None,
         )
         .unwrap()
         .into_opted_in(&OptInParam {
@@ -560,6 +621,56 @@ mod test {
 
     test_versions! { try_from_taskprov }
 
+    fn try_from_taskprov_prio2_max_dimension(version: DapVersion) {
+        let taskprov_config = |dimension| messages::taskprov::TaskConfig {
+            task_info: "cool task".as_bytes().to_vec(),
+            leader_url: messages::taskprov::UrlBytes {
+                bytes: b"https://leader.com/".to_vec(),
+            },
+            helper_url: messages::taskprov::UrlBytes {
+                bytes: b"http://helper.org:8788/".to_vec(),
+            },
+            query_config: messages::taskprov::QueryConfig {
+                time_precision: 3600,
+                max_batch_query_count: 1,
+                min_batch_size: 1,
+                var: messages::taskprov::QueryConfigVar::TimeInterval,
+            },
+            task_expiration: 1337,
+            vdaf_config: messages::taskprov::VdafConfig {
+                dp_config: messages::taskprov::DpConfig::None,
+                var: messages::taskprov::VdafTypeVar::Prio2 { dimension },
+            },
+        };
+        let collector_hpke_config = HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+        let try_from_taskprov = |dimension| {
+            let taskprov_config = taskprov_config(dimension);
+            let task_id =
+                compute_task_id(&taskprov_config.get_encoded_with_param(&version).unwrap());
+            DapTaskConfigNeedsOptIn::try_from_taskprov(
+                version,
+                &task_id,
+                taskprov_config,
+                &[0; 32],
+                &collector_hpke_config,
+                Some(1000),
+            )
+        };
+
+        assert_eq!(
+            try_from_taskprov(1000).unwrap().vdaf,
+            VdafConfig::Prio2 { dimension: 1000 }
+        );
+        assert_matches::assert_matches!(
+            try_from_taskprov(1001).unwrap_err(),
+            DapAbort::InvalidTask { detail, .. } if detail == "dimension must not exceed 1000"
+        );
+    }
+
+    test_versions! { try_from_taskprov_prio2_max_dimension }
+
     fn check_vdaf_key_computation(version: DapVersion) {
         let task_id = TaskId([
             0xb4, 0x76, 0x9b, 0xb0, 0x63, 0xa8, 0xb3, 0x31, 0x2a, 0xf7, 0x42, 0x97, 0xf3, 0x0f,
@@ -639,8 +750,8 @@ mod test {
             .config;
 
         assert_matches::assert_matches!(
-            resolve_advertised_task_config(&req, &[0; 32], &collector_hpke_config, &task_id).unwrap_err(),
-            DapAbort::InvalidTask{ detail, .. } if detail == "unimplemented VDAF type (1337)"
+            resolve_advertised_task_config(&req, &[0; 32], &collector_hpke_config, &task_id, None, QueryTypeStrictness::default()).unwrap_err(),
+            DapError::Abort(DapAbort::InvalidTask{ detail, .. }) if detail == "unimplemented VDAF type (1337)"
         );
     }
 
@@ -694,9 +805,157 @@ mod test {
             .unwrap()
             .config;
 
-        let _ = resolve_advertised_task_config(&req, &[0; 32], &collector_hpke_config, &task_id)
-            .unwrap();
+        let _ = resolve_advertised_task_config(
+            &req,
+            &[0; 32],
+            &collector_hpke_config,
+            &task_id,
+            None,
+            QueryTypeStrictness::default(),
+        )
+        .unwrap();
     }
 
     test_versions! { resolve_advertised_task_config_ignore_unimplemented_dp_ocnfig }
+
+    fn resolve_advertised_task_config_unrecognized_query_type(version: DapVersion) {
+        // Create a request for a taskprov task with an unrecognized query type.
+        let (req, task_id) = {
+            let taskprov_task_config_bytes = messages::taskprov::TaskConfig {
+                task_info: "cool task".as_bytes().to_vec(),
+                leader_url: messages::taskprov::UrlBytes {
+                    bytes: b"https://leader.com/".to_vec(),
+                },
+                helper_url: messages::taskprov::UrlBytes {
+                    bytes: b"http://helper.org:8788/".to_vec(),
+                },
+                query_config: messages::taskprov::QueryConfig {
+                    time_precision: 3600,
+                    max_batch_query_count: 1,
+                    min_batch_size: 1,
+                    var: messages::taskprov::QueryConfigVar::NotImplemented {
+                        typ: 0xff,
+                        param: b"query type param".to_vec(),
+                    },
+                },
+                task_expiration: 0,
+                vdaf_config: messages::taskprov::VdafConfig {
+                    dp_config: messages::taskprov::DpConfig::None,
+                    var: messages::taskprov::VdafTypeVar::Prio2 { dimension: 1337 },
+                },
+            }
+            .get_encoded_with_param(&version)
+            .unwrap();
+            let task_id = compute_task_id(&taskprov_task_config_bytes);
+            let taskprov_task_config_base64url = encode_base64url(&taskprov_task_config_bytes);
+
+            let req = DapRequest::<()> {
+                version,
+                media_type: None, // ignored by test
+                task_id: Some(task_id),
+                resource: DapResource::Undefined, // ignored by test
+                payload: Vec::default(),          // ignored by test
+                sender_auth: None,                // ignored by test
+                taskprov: Some(taskprov_task_config_base64url),
+            };
+
+            (req, task_id)
+        };
+
+        let collector_hpke_config = HpkeReceiverConfig::gen(23, HpkeKemId::X25519HkdfSha256)
+            .unwrap()
+            .config;
+
+        // Strict: The advertisement is malformed.
+        assert_matches::assert_matches!(
+            resolve_advertised_task_config(
+                &req,
+                &[0; 32],
+                &collector_hpke_config,
+                &task_id,
+                None,
+                QueryTypeStrictness::Strict
+            )
+            .unwrap_err(),
+            DapError::Abort(DapAbort::InvalidMessage { .. })
+        );
+
+        // Lenient: The advertisement is decoded, but the task is rejected.
+        assert_matches::assert_matches!(
+            resolve_advertised_task_config(&req, &[0; 32], &collector_hpke_config, &task_id, None, QueryTypeStrictness::Lenient).unwrap_err(),
+            DapError::Abort(DapAbort::InvalidTask{ detail, .. }) if detail == "unimplemented query type (255)"
+        );
+    }
+
+    test_versions! { resolve_advertised_task_config_unrecognized_query_type }
+
+    fn verify_task_id(version: DapVersion) {
+        let taskprov_config = messages::taskprov::TaskConfig {
+            task_info: "cool task".as_bytes().to_vec(),
+            leader_url: messages::taskprov::UrlBytes {
+                bytes: b"https://leader.com/".to_vec(),
+            },
+            helper_url: messages::taskprov::UrlBytes {
+                bytes: b"http://helper.org:8788/".to_vec(),
+            },
+            query_config: messages::taskprov::QueryConfig {
+                time_precision: 3600,
+                max_batch_query_count: 1,
+                min_batch_size: 1,
+                var: messages::taskprov::QueryConfigVar::TimeInterval,
+            },
+            task_expiration: 1337,
+            vdaf_config: messages::taskprov::VdafConfig {
+                dp_config: messages::taskprov::DpConfig::None,
+                var: messages::taskprov::VdafTypeVar::Prio2 { dimension: 10 },
+            },
+        };
+        let task_id = compute_task_id(&taskprov_config.get_encoded_with_param(&version).unwrap());
+        assert_eq!(taskprov_config.verify_task_id(&task_id, version), Ok(()));
+
+        // Tampering with the config invalidates the task ID.
+        let tampered = messages::taskprov::TaskConfig {
+            helper_url: messages::taskprov::UrlBytes {
+                bytes: b"https://evil.example.com/".to_vec(),
+            },
+            ..taskprov_config
+        };
+        assert_eq!(
+            tampered.verify_task_id(&task_id, version),
+            Err(DapError::Abort(DapAbort::UnrecognizedTask { task_id }))
+        );
+    }
+
+    test_versions! { verify_task_id }
+
+    fn compute_task_id_known_answer(version: DapVersion) {
+        let taskprov_config = messages::taskprov::TaskConfig {
+            task_info: "cool task".as_bytes().to_vec(),
+            leader_url: messages::taskprov::UrlBytes {
+                bytes: b"https://leader.com/".to_vec(),
+            },
+            helper_url: messages::taskprov::UrlBytes {
+                bytes: b"http://helper.org:8788/".to_vec(),
+            },
+            query_config: messages::taskprov::QueryConfig {
+                time_precision: 3600,
+                max_batch_query_count: 1,
+                min_batch_size: 1,
+                var: messages::taskprov::QueryConfigVar::TimeInterval,
+            },
+            task_expiration: 1337,
+            vdaf_config: messages::taskprov::VdafConfig {
+                dp_config: messages::taskprov::DpConfig::None,
+                var: messages::taskprov::VdafTypeVar::Prio2 { dimension: 10 },
+            },
+        };
+        let expected = TaskId([
+            0x15, 0x0b, 0x72, 0xe3, 0x8e, 0x12, 0x42, 0xf4, 0x94, 0xa5, 0xca, 0xe2, 0x16, 0x82,
+            0x63, 0xdb, 0x01, 0xf9, 0x52, 0x54, 0xc3, 0xf9, 0xdd, 0x23, 0x54, 0x05, 0x56, 0x4c,
+            0xf4, 0xa7, 0xe1, 0x2a,
+        ]);
+        assert_eq!(taskprov_config.compute_task_id(version).unwrap(), expected);
+    }
+
+    test_versions! { compute_task_id_known_answer }
 }
@@ -11,19 +11,21 @@ use crate::{
     auth::{BearerToken, BearerTokenProvider},
     constants::DapMediaType,
     fatal_error,
+    fixture::AggregationFixture,
     hpke::{HpkeConfig, HpkeDecrypter, HpkeKemId, HpkeProvider, HpkeReceiverConfig},
     messages::{
         self, AggregationJobId, AggregationJobInitReq, AggregationJobResp, BatchId, BatchSelector,
-        Collection, CollectionJobId, HpkeCiphertext, Interval, PartialBatchSelector, Report,
-        ReportId, TaskId, Time, TransitionFailure,
+        Collection, CollectionJobId, HpkeCiphertext, Interval, PartialBatchSelector,
+        PlaintextInputShare, Report, ReportId, TaskId, Time, TransitionFailure,
     },
     metrics::{prometheus::DaphnePromMetrics, DaphneMetrics},
     protocol::aggregator::{EarlyReportStateConsumed, EarlyReportStateInitialized},
     roles::{
-        aggregator::MergeAggShareError,
+        aggregator::{self, MergeAggShareError},
         helper,
-        leader::{in_memory_leader::InMemoryLeaderState, WorkItem},
-        DapAggregator, DapAuthorizedSender, DapHelper, DapLeader, DapReportInitializer,
+        leader::{in_memory_leader::InMemoryLeaderState, QuarantinedAggJob, WorkItem},
+        AggShareReqKey, AggregationJobDiagnostics, DapAggregator, DapAuthorizedSender, DapHelper,
+        DapLeader, DapReportInitializer, StoredAggregateShareResp, StoredAggregationJobResp,
     },
     taskprov,
     vdaf::VdafVerifyKey,
@@ -34,6 +36,7 @@ use crate::{
 };
 use async_trait::async_trait;
 use deepsize::DeepSizeOf;
+use prio::codec::ParameterizedDecode;
 use rand::{thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -151,10 +154,13 @@ impl AggregationJobTest {
     /// version. The KEM algorithm is used to generate an HPKE config for each party.
     pub fn new(vdaf: &VdafConfig, kem_id: HpkeKemId, version: DapVersion) -> Self {
         let mut rng = thread_rng();
+        let time_precision = 500;
+        // Clients round report timestamps down to a multiple of the time precision.
         let now = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
+        let now = now - (now % time_precision);
         let task_id = TaskId(rng.gen());
         let vdaf_verify_key = vdaf.gen_verify_key();
         let leader_hpke_receiver_config = HpkeReceiverConfig::gen(rng.gen(), kem_id).unwrap();
@@ -186,16 +192,18 @@ impl AggregationJobTest {
                 version,
                 leader_url: Url::parse("http://leader.com").unwrap(),
                 helper_url: Url::parse("https://helper.org").unwrap(),
-                time_precision: 500,
+                time_precision,
                 not_before: now,
-                not_after: now + 500,
+                not_after: now + time_precision,
                 min_batch_size: 10,
                 query: DapQueryConfig::TimeInterval,
                 vdaf: *vdaf,
                 vdaf_verify_key,
+                vdaf_verify_keys_by_epoch: Default::default(),
                 collector_hpke_config,
                 method: Default::default(),
                 num_agg_span_shards: NonZeroUsize::new(3).unwrap(),
+                max_batch_query_count: 1,
             },
             replay_protection: ReplayProtection::Enabled,
             leader_registry,
@@ -212,6 +220,19 @@ impl AggregationJobTest {
         self.task_config.vdaf_verify_key = vdaf.gen_verify_key();
     }
 
+    pub fn task_id(&self) -> &TaskId {
+        &self.task_id
+    }
+
+    pub fn task_config(&self) -> &DapTaskConfig {
+        &self.task_config
+    }
+
+    /// The current time, at which reports are generated.
+    pub fn now(&self) -> Time {
+        self.now
+    }
+
     /// For each measurement, generate a report for the given task.
     ///
     /// Panics if a measurement is incompatible with the given VDAF.
@@ -295,6 +316,7 @@ impl AggregationJobTest {
                         &self.task_id,
                         agg_job_init_req,
                         self.replay_protection,
+                        NonZeroUsize::new(1).unwrap(),
                     )
                     .await
                     .unwrap(),
@@ -399,6 +421,38 @@ impl AggregationJobTest {
         agg_param: DapAggregationParam,
         measurements: Vec<DapMeasurement>,
     ) -> DapAggregateResult {
+        // Clients: Shard
+        let reports = self.produce_reports(measurements);
+
+        self.roundtrip_reports(agg_param, reports).await
+    }
+
+    /// Aggregate a set of reports and unshard the result.
+    pub async fn roundtrip_reports(
+        &mut self,
+        agg_param: DapAggregationParam,
+        reports: Vec<Report>,
+    ) -> DapAggregateResult {
+        let (batch_selector, report_count, encrypted_agg_shares) =
+            self.collect_reports(&agg_param, reports).await;
+
+        // Collector: Unshard
+        self.consume_encrypted_agg_shares(
+            &batch_selector,
+            report_count,
+            &agg_param,
+            encrypted_agg_shares,
+        )
+        .await
+    }
+
+    /// Aggregate a set of reports and produce what the Collector receives for the batch: the
+    /// batch selector, the report count and the encrypted aggregate shares.
+    pub async fn collect_reports(
+        &self,
+        agg_param: &DapAggregationParam,
+        reports: Vec<Report>,
+    ) -> (BatchSelector, u64, Vec<HpkeCiphertext>) {
         let batch_selector = BatchSelector::TimeInterval {
             batch_interval: Interval {
                 start: self.now,
@@ -406,11 +460,8 @@ impl AggregationJobTest {
             },
         };
 
-        // Clients: Shard
-        let reports = self.produce_reports(measurements);
-
         // Aggregators: Preparation
-        let (leader_state, agg_job_init_req) = self.produce_agg_job_req(&agg_param, reports).await;
+        let (leader_state, agg_job_init_req) = self.produce_agg_job_req(agg_param, reports).await;
 
         let (leader_agg_span, helper_agg_span) = {
             let (helper_agg_span, agg_job_resp) = self.handle_agg_job_req(agg_job_init_req).await;
@@ -423,23 +474,71 @@ impl AggregationJobTest {
         // Leader: Aggregation
         let leader_agg_share = leader_agg_span.collapsed();
         let leader_encrypted_agg_share =
-            self.produce_leader_encrypted_agg_share(&batch_selector, &agg_param, &leader_agg_share);
+            self.produce_leader_encrypted_agg_share(&batch_selector, agg_param, &leader_agg_share);
 
         // Helper: Aggregation
         let helper_encrypted_agg_share = self.produce_helper_encrypted_agg_share(
             &batch_selector,
-            &agg_param,
+            agg_param,
             &helper_agg_span.collapsed(),
         );
 
-        // Collector: Unshard
-        self.consume_encrypted_agg_shares(
-            &batch_selector,
+        (
+            batch_selector,
             report_count,
-            &agg_param,
             vec![leader_encrypted_agg_share, helper_encrypted_agg_share],
         )
-        .await
+    }
+}
+
+impl AggregationFixture {
+    /// Replay the aggregation of the captured reports and unshard the result. The input shares
+    /// are re-encrypted under freshly generated HPKE configs.
+    pub async fn replay(
+        &self,
+        agg_param: DapAggregationParam,
+    ) -> Result<DapAggregateResult, DapError> {
+        let version = self.task_config.version;
+        let mut t =
+            AggregationJobTest::new(&self.task_config.vdaf, HpkeKemId::X25519HkdfSha256, version);
+        t.task_id = self.task_id;
+        t.task_config = DapTaskConfig {
+            collector_hpke_config: t.task_config.collector_hpke_config.clone(),
+            ..self.task_config.clone()
+        };
+
+        // Accept the reports regardless of when the fixture was captured.
+        let report_times = self
+            .reports
+            .iter()
+            .map(|report| report.report_metadata.time);
+        if let (Some(start), Some(end)) = (report_times.clone().min(), report_times.max()) {
+            t.valid_report_range = start..end + 1;
+        }
+
+        let mut reports = Vec::with_capacity(self.reports.len());
+        for report in &self.reports {
+            let [leader_input_share, helper_input_share] =
+                [&report.leader_input_share, &report.helper_input_share].map(|input_share| {
+                    PlaintextInputShare::get_decoded_with_param(&version, input_share)
+                });
+            let (leader_input_share, helper_input_share) = (
+                leader_input_share.map_err(DapError::encoding)?,
+                helper_input_share.map_err(DapError::encoding)?,
+            );
+            reports.push(VdafConfig::produce_report_with_extensions_for_shares(
+                report.public_share.clone(),
+                [leader_input_share.payload, helper_input_share.payload],
+                &t.client_hpke_config_list,
+                report.report_metadata.time,
+                &self.task_id,
+                &report.report_metadata.id,
+                leader_input_share.extensions,
+                version,
+            )?);
+        }
+
+        Ok(t.roundtrip_reports(agg_param, reports).await)
     }
 }
 
@@ -538,9 +637,9 @@ pub struct AggregateStore {
     /// this bucket will be rejected.
     pub collected: bool,
 
-    /// The reports included in the current aggregate share. If a report wants to be aggregated is
-    /// already in this set, it will be rejected.
-    pub reports: HashSet<ReportId>,
+    /// The [replay keys](aggregator::replay_key) of the reports included in the current aggregate
+    /// share. If a report wants to be aggregated is already in this set, it will be rejected.
+    pub reports: HashSet<[u8; 32]>,
 }
 
 #[derive(Default)]
@@ -572,7 +671,8 @@ impl InMemoryAggregateStore {
 }
 
 /// An implementation of a DAP Aggregator without long-term storage. This is intended to be used
-/// for testing purposes only.
+/// for testing purposes only, e.g., to test a Client or Collector against a Leader and Helper
+/// pair without any storage infrastructure. It is available with the `test-utils` feature.
 pub struct InMemoryAggregator {
     pub(crate) global_config: DapGlobalConfig,
     tasks: Mutex<HashMap<TaskId, DapTaskConfig>>,
@@ -584,6 +684,12 @@ pub struct InMemoryAggregator {
     // aggregation state
     leader_state_store: Arc<Mutex<InMemoryLeaderState>>,
     pub(crate) agg_store: Arc<Mutex<InMemoryAggregateStore>>,
+    aborted_agg_jobs: Mutex<HashSet<(TaskId, AggregationJobId)>>,
+    agg_job_resps: Mutex<HashMap<(TaskId, AggregationJobId), StoredAggregationJobResp>>,
+    agg_job_diagnostics: Mutex<HashMap<(TaskId, AggregationJobId), AggregationJobDiagnostics>>,
+    agg_share_resps: Mutex<HashMap<AggShareReqKey, StoredAggregateShareResp>>,
+    agg_share_computations: AtomicU32,
+    hpke_decryptions: AtomicU32,
 
     // telemetry
     metrics: DaphnePromMetrics,
@@ -609,6 +715,12 @@ impl DeepSizeOf for InMemoryAggregator {
             collector_token,
             leader_state_store,
             agg_store,
+            aborted_agg_jobs,
+            agg_job_resps,
+            agg_job_diagnostics,
+            agg_share_resps,
+            agg_share_computations: _,
+            hpke_decryptions: _,
             collector_hpke_config,
             metrics: _,
             audit_log: _,
@@ -624,6 +736,10 @@ impl DeepSizeOf for InMemoryAggregator {
             + collector_token.deep_size_of_children(context)
             + leader_state_store.deep_size_of_children(context)
             + agg_store.deep_size_of_children(context)
+            + aborted_agg_jobs.deep_size_of_children(context)
+            + agg_job_resps.deep_size_of_children(context)
+            + agg_job_diagnostics.deep_size_of_children(context)
+            + agg_share_resps.deep_size_of_children(context)
             + collector_hpke_config.deep_size_of_children(context)
             + taskprov_vdaf_verify_key_init.deep_size_of_children(context)
             + taskprov_leader_token.deep_size_of_children(context)
@@ -652,6 +768,12 @@ impl InMemoryAggregator {
             collector_token: None,
             leader_state_store: Default::default(),
             agg_store: Default::default(),
+            aborted_agg_jobs: Default::default(),
+            agg_job_resps: Default::default(),
+            agg_job_diagnostics: Default::default(),
+            agg_share_resps: Default::default(),
+            agg_share_computations: Default::default(),
+            hpke_decryptions: Default::default(),
             collector_hpke_config,
             metrics: DaphnePromMetrics::register(registry).unwrap(),
             audit_log: MockAuditLog::default(),
@@ -684,6 +806,12 @@ impl InMemoryAggregator {
             collector_token: collector_token.into(),
             leader_state_store: Default::default(),
             agg_store: Default::default(),
+            aborted_agg_jobs: Default::default(),
+            agg_job_resps: Default::default(),
+            agg_job_diagnostics: Default::default(),
+            agg_share_resps: Default::default(),
+            agg_share_computations: Default::default(),
+            hpke_decryptions: Default::default(),
             collector_hpke_config,
             metrics: DaphnePromMetrics::register(registry).unwrap(),
             audit_log: MockAuditLog::default(),
@@ -711,9 +839,31 @@ impl InMemoryAggregator {
             .expect("missing task config")
     }
 
+    /// Return the reports for the given task with the given IDs that the Leader has stored but
+    /// not yet aggregated.
+    pub fn get_reports(&self, task_id: &TaskId, report_ids: &[ReportId]) -> Vec<Report> {
+        self.leader_state_store
+            .lock()
+            .unwrap()
+            .get_reports(task_id, report_ids)
+    }
+
     pub fn clear_storage(&self) {
         self.leader_state_store.lock().unwrap().delete_all();
         self.agg_store.lock().unwrap().clear();
+        self.aborted_agg_jobs.lock().unwrap().clear();
+        self.agg_job_resps.lock().unwrap().clear();
+        self.agg_share_resps.lock().unwrap().clear();
+    }
+
+    /// Number of times an aggregate share was computed by [`DapAggregator::get_agg_share`].
+    pub fn agg_share_computations(&self) -> u32 {
+        self.agg_share_computations.load(Ordering::Relaxed)
+    }
+
+    /// Number of HPKE ciphertexts this Aggregator has attempted to decrypt.
+    pub fn hpke_decryptions(&self) -> u32 {
+        self.hpke_decryptions.load(Ordering::Relaxed)
     }
 }
 
@@ -790,6 +940,7 @@ impl HpkeDecrypter for InMemoryAggregator {
         aad: &[u8],
         ciphertext: &HpkeCiphertext,
     ) -> Result<Vec<u8>, DapError> {
+        self.hpke_decryptions.fetch_add(1, Ordering::Relaxed);
         if let Some(hpke_receiver_config) = self.get_hpke_receiver_config_for(ciphertext.config_id)
         {
             Ok(hpke_receiver_config.decrypt(info, aad, ciphertext)?)
@@ -975,6 +1126,12 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
     ) -> DapAggregateSpan<Result<(), MergeAggShareError>> {
         let mut agg_store = self.agg_store.lock().unwrap();
 
+        // Cached aggregate shares are stale once new reports are added to the batch.
+        self.agg_share_resps
+            .lock()
+            .unwrap()
+            .retain(|key, _| key.task_id != *task_id);
+
         agg_span
             .into_iter()
             .map(|(bucket, (agg_share_delta, report_metadatas))| {
@@ -983,13 +1140,19 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
                 let replayed = report_metadatas
                     .iter()
                     .map(|(id, _)| *id)
-                    .filter(|id| agg_store_for_bucket.reports.contains(id))
+                    .filter(|id| {
+                        agg_store_for_bucket
+                            .reports
+                            .contains(&aggregator::replay_key(task_id, id))
+                    })
                     .collect::<HashSet<_>>();
 
                 let result = if replayed.is_empty() {
-                    agg_store_for_bucket
-                        .reports
-                        .extend(report_metadatas.iter().map(|(id, _)| *id));
+                    agg_store_for_bucket.reports.extend(
+                        report_metadatas
+                            .iter()
+                            .map(|(id, _)| aggregator::replay_key(task_id, id)),
+                    );
                     // Add to aggregate share.
                     if agg_store_for_bucket.collected {
                         Err(MergeAggShareError::AlreadyCollected)
@@ -1022,6 +1185,8 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
             .lock()
             .map_err(|_| fatal_error!(err = "agg_store poisoned"))?;
 
+        self.agg_share_computations.fetch_add(1, Ordering::Relaxed);
+
         // Fetch aggregate shares.
         let mut agg_share = DapAggregateShare::default();
         for bucket in task_config.batch_span_for_sel(batch_sel)? {
@@ -1063,7 +1228,115 @@ impl DapAggregator<BearerToken> for InMemoryAggregator {
 }
 
 #[async_trait]
-impl DapHelper<BearerToken> for InMemoryAggregator {}
+impl DapHelper<BearerToken> for InMemoryAggregator {
+    async fn abort_agg_job(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<(), DapError> {
+        self.agg_job_resps
+            .lock()
+            .unwrap()
+            .remove(&(*task_id, *agg_job_id));
+        self.aborted_agg_jobs
+            .lock()
+            .unwrap()
+            .insert((*task_id, *agg_job_id));
+        Ok(())
+    }
+
+    async fn agg_job_aborted(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<bool, DapError> {
+        Ok(self
+            .aborted_agg_jobs
+            .lock()
+            .unwrap()
+            .contains(&(*task_id, *agg_job_id)))
+    }
+
+    async fn put_agg_job_resp(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        agg_job_resp: StoredAggregationJobResp,
+    ) -> Result<(), DapError> {
+        self.agg_job_resps
+            .lock()
+            .unwrap()
+            .insert((*task_id, *agg_job_id), agg_job_resp);
+        Ok(())
+    }
+
+    async fn get_agg_job_resp(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<StoredAggregationJobResp>, DapError> {
+        Ok(self
+            .agg_job_resps
+            .lock()
+            .unwrap()
+            .get(&(*task_id, *agg_job_id))
+            .cloned())
+    }
+
+    async fn put_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+        diagnostics: AggregationJobDiagnostics,
+    ) -> Result<(), DapError> {
+        self.agg_job_diagnostics
+            .lock()
+            .unwrap()
+            .insert((*task_id, *agg_job_id), diagnostics);
+        Ok(())
+    }
+
+    async fn get_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobDiagnostics>, DapError> {
+        Ok(self
+            .agg_job_diagnostics
+            .lock()
+            .unwrap()
+            .get(&(*task_id, *agg_job_id))
+            .cloned())
+    }
+
+    async fn put_agg_share_resp(
+        &self,
+        key: &AggShareReqKey,
+        agg_share_resp: StoredAggregateShareResp,
+    ) -> Result<(), DapError> {
+        self.agg_share_resps
+            .lock()
+            .unwrap()
+            .insert(key.clone(), agg_share_resp);
+        Ok(())
+    }
+
+    async fn get_agg_share_resp(
+        &self,
+        key: &AggShareReqKey,
+    ) -> Result<Option<StoredAggregateShareResp>, DapError> {
+        Ok(self.agg_share_resps.lock().unwrap().get(key).cloned())
+    }
+}
+
+/// Convert an error returned by the peer into the error seen by the sender of the request: an abort
+/// by the peer is passed on, while any other error is internal to the peer.
+fn peer_error(e: DapError) -> DapError {
+    match e {
+        DapError::Abort(abort) => DapError::Abort(abort),
+        e => fatal_error!(err = ?e, "request aborted by peer"),
+    }
+}
 
 #[async_trait]
 impl DapLeader<BearerToken> for InMemoryAggregator {
@@ -1079,6 +1352,14 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
             .put_report(task_id, &task_config, report.clone())
     }
 
+    async fn sweep_expired_reports(&self, before: Time) -> Result<u64, DapError> {
+        Ok(self
+            .leader_state_store
+            .lock()
+            .map_err(|_| fatal_error!(err = "leader_state_store poisoned"))?
+            .sweep_expired_reports(before))
+    }
+
     async fn current_batch(&self, task_id: &TaskId) -> std::result::Result<BatchId, DapError> {
         let task_config = self
             .get_task_config_for(task_id)
@@ -1126,7 +1407,14 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
         self.leader_state_store
             .lock()
             .map_err(|_| fatal_error!(err = "leader_state_store poisoned"))?
-            .init_collect_job(task_id, &task_config, coll_job_id, batch_sel, agg_param)
+            .init_collect_job(
+                task_id,
+                &task_config,
+                coll_job_id,
+                batch_sel,
+                agg_param,
+                self.get_current_time(),
+            )
     }
 
     async fn poll_collect_job(
@@ -1152,6 +1440,51 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
             .finish_collect_job(task_id, coll_job_id, collection)
     }
 
+    async fn fail_collect_job(
+        &self,
+        task_id: &TaskId,
+        coll_job_id: &CollectionJobId,
+        reason: String,
+    ) -> Result<(), DapError> {
+        self.leader_state_store
+            .lock()
+            .map_err(|_| fatal_error!(err = "leader_state_store poisoned"))?
+            .fail_collect_job(task_id, coll_job_id, reason)
+    }
+
+    async fn export_collect_jobs(&self) -> Result<Vec<u8>, DapError> {
+        self.leader_state_store
+            .lock()
+            .map_err(|_| fatal_error!(err = "leader_state_store poisoned"))?
+            .export_collect_jobs()
+    }
+
+    async fn import_collect_jobs(&self, bytes: &[u8]) -> Result<(), DapError> {
+        self.leader_state_store
+            .lock()
+            .map_err(|_| fatal_error!(err = "leader_state_store poisoned"))?
+            .import_collect_jobs(bytes, self.get_current_time())
+    }
+
+    async fn quarantine_agg_job(&self, job: QuarantinedAggJob) -> Result<(), DapError> {
+        self.leader_state_store
+            .lock()
+            .map_err(|_| fatal_error!(err = "leader_state_store poisoned"))?
+            .quarantine_mut()
+            .put(job);
+        Ok(())
+    }
+
+    async fn quarantined_agg_jobs(&self) -> Result<Vec<QuarantinedAggJob>, DapError> {
+        Ok(self
+            .leader_state_store
+            .lock()
+            .map_err(|_| fatal_error!(err = "leader_state_store poisoned"))?
+            .quarantine()
+            .list()
+            .to_vec())
+    }
+
     async fn send_http_post(
         &self,
         req: DapRequest<BearerToken>,
@@ -1164,13 +1497,13 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
                 Default::default(),
             )
             .await
-            .expect("peer aborted unexpectedly")),
+            .map_err(peer_error)?),
             Some(DapMediaType::AggregateShareReq) => Ok(helper::handle_agg_share_req(
                 &**self.peer.as_ref().expect("peer not configured"),
                 &req,
             )
             .await
-            .expect("peer aborted unexpectedly")),
+            .map_err(peer_error)?),
             _ => unreachable!("unhandled media type: {:?}", req.media_type),
         }
     }
@@ -1187,11 +1520,24 @@ impl DapLeader<BearerToken> for InMemoryAggregator {
                 Default::default(),
             )
             .await
-            .expect("peer aborted unexpectedly"))
+            .map_err(peer_error)?)
         } else {
             unreachable!("unhandled media type: {:?}", req.media_type)
         }
     }
+
+    async fn get_helper_agg_job_diagnostics(
+        &self,
+        task_id: &TaskId,
+        _task_config: &DapTaskConfig,
+        agg_job_id: &AggregationJobId,
+    ) -> Result<Option<AggregationJobDiagnostics>, DapError> {
+        self.peer
+            .as_ref()
+            .expect("peer not configured")
+            .get_agg_job_diagnostics(task_id, agg_job_id)
+            .await
+    }
 }
 
 /// Information associated to a certain helper state for a given task ID and aggregate job ID.
@@ -1281,4 +1627,191 @@ impl VdafConfig {
             )),
         }
     }
+
+    /// Generate a measurement chosen uniformly at random from the VDAF's input domain.
+    pub fn gen_random_measurement(&self, rng: &mut impl Rng) -> Result<DapMeasurement, DapError> {
+        use crate::vdaf::Prio3Config;
+
+        // A random integer in range `[0, 2^bits)`.
+        fn gen_bits(rng: &mut impl Rng, bits: usize) -> u64 {
+            let shift = u32::try_from(64_usize.saturating_sub(bits)).unwrap();
+            rng.gen::<u64>().checked_shr(shift).unwrap_or(0)
+        }
+
+        match self {
+            Self::Prio2 { dimension } => Ok(DapMeasurement::U32Vec(
+                (0..*dimension).map(|_| rng.gen_range(0..2)).collect(),
+            )),
+            Self::Prio3(Prio3Config::Count) => Ok(DapMeasurement::U64(rng.gen_range(0..2))),
+            Self::Prio3(Prio3Config::Sum { bits }) => Ok(DapMeasurement::U64(gen_bits(rng, *bits))),
+            Self::Prio3(Prio3Config::Histogram { length, .. }) => Ok(DapMeasurement::U64(
+                rng.gen_range(0..u64::try_from(*length).unwrap()),
+            )),
+            Self::Prio3(Prio3Config::SumVec {
+                bits,
+                length,
+                client_element_max,
+                ..
+            }) => Ok(DapMeasurement::U128Vec(
+                (0..*length)
+                    .map(|_| {
+                        u128::from(gen_bits(rng, *bits).min(client_element_max.unwrap_or(u64::MAX)))
+                    })
+                    .collect(),
+            )),
+            Self::Prio3(Prio3Config::SumVecField64MultiproofHmacSha256Aes128 {
+                bits,
+                length,
+                ..
+            }) => Ok(DapMeasurement::U64Vec(
+                (0..*length).map(|_| gen_bits(rng, *bits)).collect(),
+            )),
+            _ => Err(fatal_error!(
+                err = format!("gen_random_measurement currently does not support {self:?}")
+            )),
+        }
+    }
+
+    /// Reconstruct the measurement from which a Client generated `report`, e.g., to check that the
+    /// Client is behaving correctly. Both input shares are decrypted and prepared, then the output
+    /// shares are unsharded.
+    ///
+    /// Mastic is not supported, since its aggregate result depends on the aggregation parameter.
+    /// For Pine, the measurement is recovered up to the precision of its fixed-point encoding.
+    pub async fn recover_measurement(
+        &self,
+        task_id: &TaskId,
+        version: DapVersion,
+        report: &Report,
+        leader_receiver: &HpkeReceiverConfig,
+        helper_receiver: &HpkeReceiverConfig,
+    ) -> Result<DapMeasurement, DapError> {
+        use crate::{
+            protocol::aggregator::decrypt_input_share,
+            vdaf::{
+                prio2::{
+                    prio2_prep_finish, prio2_prep_finish_from_shares, prio2_prep_init,
+                    prio2_unshard,
+                },
+                prio3::{
+                    prio3_prep_finish, prio3_prep_finish_from_shares, prio3_prep_init,
+                    prio3_unshard,
+                },
+                Prio3Config,
+            },
+        };
+        use prio::codec::Encode;
+
+        #[cfg(feature = "experimental")]
+        if matches!(self, Self::Mastic { .. }) {
+            return Err(fatal_error!(
+                err = "recover_measurement does not support Mastic"
+            ));
+        }
+
+        let verify_key = self.gen_verify_key();
+        let nonce = &report.report_metadata.id.0;
+        let mut prep = Vec::with_capacity(2);
+        for (is_leader, receiver) in [(true, leader_receiver), (false, helper_receiver)] {
+            let encoded_input_share =
+                decrypt_input_share(receiver, task_id, version, report, is_leader).await?;
+            let input_share =
+                PlaintextInputShare::get_decoded_with_param(&version, &encoded_input_share)
+                    .map_err(DapError::encoding)?
+                    .payload;
+            let agg_id = usize::from(!is_leader);
+            prep.push(
+                match self {
+                    Self::Prio3(config) => prio3_prep_init(
+                        config,
+                        &verify_key,
+                        agg_id,
+                        nonce,
+                        &report.public_share,
+                        &input_share,
+                    ),
+                    Self::Prio2 { dimension } => prio2_prep_init(
+                        *dimension,
+                        &verify_key,
+                        agg_id,
+                        nonce,
+                        &report.public_share,
+                        &input_share,
+                    ),
+                    Self::Pine(pine) => pine.prep_init(
+                        &verify_key,
+                        agg_id,
+                        nonce,
+                        &report.public_share,
+                        &input_share,
+                    ),
+                    #[cfg(feature = "experimental")]
+                    Self::Mastic { .. } => unreachable!("checked above"),
+                }
+                .map_err(|e| fatal_error!(err = ?e, "failed to initialize preparation"))?,
+            );
+        }
+        let (helper_state, helper_share) = prep.pop().unwrap();
+        let (leader_state, leader_share) = prep.pop().unwrap();
+        let leader_share = leader_share.get_encoded().map_err(DapError::encoding)?;
+
+        let (helper_out_share, prep_msg) = match self {
+            Self::Prio3(config) => {
+                prio3_prep_finish_from_shares(config, 1, helper_state, helper_share, &leader_share)
+            }
+            Self::Prio2 { dimension } => {
+                prio2_prep_finish_from_shares(*dimension, helper_state, helper_share, &leader_share)
+            }
+            Self::Pine(pine) => {
+                pine.prep_finish_from_shares(1, helper_state, helper_share, &leader_share)
+            }
+            #[cfg(feature = "experimental")]
+            Self::Mastic { .. } => unreachable!("checked above"),
+        }
+        .map_err(|e| fatal_error!(err = ?e, "failed to prepare the Helper's input share"))?;
+        let leader_out_share = match self {
+            Self::Prio3(config) => prio3_prep_finish(config, leader_state, &prep_msg),
+            Self::Prio2 { dimension } => prio2_prep_finish(*dimension, leader_state, &prep_msg),
+            Self::Pine(pine) => pine.prep_finish(leader_state, &prep_msg),
+            #[cfg(feature = "experimental")]
+            Self::Mastic { .. } => unreachable!("checked above"),
+        }
+        .map_err(|e| fatal_error!(err = ?e, "failed to prepare the Leader's input share"))?;
+
+        // The aggregate of a single output share is the encoded measurement.
+        let agg_shares = [
+            leader_out_share.get_encoded().map_err(DapError::encoding)?,
+            helper_out_share.get_encoded().map_err(DapError::encoding)?,
+        ];
+        let agg_result = match self {
+            Self::Prio3(config) => prio3_unshard(config, 1, agg_shares),
+            Self::Prio2 { dimension } => prio2_unshard(*dimension, 1, agg_shares),
+            Self::Pine(pine) => pine.unshard(1, agg_shares),
+            #[cfg(feature = "experimental")]
+            Self::Mastic { .. } => unreachable!("checked above"),
+        }
+        .map_err(|e| fatal_error!(err = ?e, "failed to unshard output shares"))?;
+
+        match (self, agg_result) {
+            (Self::Prio3(Prio3Config::Histogram { .. }), DapAggregateResult::U128Vec(counts)) => {
+                // The measurement is the index of the bucket with a count of one.
+                match counts.iter().position(|count| *count != 0) {
+                    Some(i)
+                        if counts[i] == 1 && counts[i + 1..].iter().all(|count| *count == 0) =>
+                    {
+                        Ok(DapMeasurement::U64(u64::try_from(i).unwrap()))
+                    }
+                    _ => Err(fatal_error!(err = "histogram output is not one-hot")),
+                }
+            }
+            (_, DapAggregateResult::U64(x)) => Ok(DapMeasurement::U64(x)),
+            (_, DapAggregateResult::U128(x)) => Ok(DapMeasurement::U64(
+                u64::try_from(x).map_err(|e| fatal_error!(err = ?e, "sum out of range"))?,
+            )),
+            (_, DapAggregateResult::U32Vec(v)) => Ok(DapMeasurement::U32Vec(v)),
+            (_, DapAggregateResult::U64Vec(v)) => Ok(DapMeasurement::U64Vec(v)),
+            (_, DapAggregateResult::U128Vec(v)) => Ok(DapMeasurement::U128Vec(v)),
+            (_, DapAggregateResult::F64Vec(v)) => Ok(DapMeasurement::F64Vec(v)),
+        }
+    }
 }
@@ -39,7 +39,7 @@ impl std::fmt::Display for MasticWeightConfig {
 }
 
 /// A weight.
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub enum MasticWeight {
     Bool(bool),
 }
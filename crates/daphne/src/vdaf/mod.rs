@@ -11,10 +11,12 @@ pub(crate) mod prio2;
 pub(crate) mod prio3;
 
 use crate::pine::vdaf::PinePrepState;
-use crate::{fatal_error, DapError};
+use crate::{error::DapAbort, fatal_error, messages::TaskId, DapError};
 use pine::PineConfig;
 #[cfg(any(test, feature = "test-utils", feature = "experimental"))]
 use prio::field::FieldElement;
+#[cfg(feature = "experimental")]
+use prio::{codec::Decode, vdaf::poplar1::Poplar1AggregationParam};
 use prio::{
     codec::{CodecError, Encode, ParameterizedDecode},
     field::{Field128, Field64, FieldPrio2},
@@ -24,6 +26,7 @@ use prio::{
         Aggregator, Client, Collector, PrepareTransition, Vdaf,
     },
 };
+use prio3::prio3_input_share_len;
 use rand::prelude::*;
 use ring::hkdf::KeyType;
 use serde::{Deserialize, Serialize};
@@ -109,6 +112,12 @@ pub enum Prio3Config {
         bits: usize,
         length: usize,
         chunk_length: usize,
+        /// An optional, tighter bound on each element, checked by the Client only: measurements
+        /// with an element greater than this are rejected before they are sharded. The VDAF only
+        /// proves that each element fits in `bits`, so the Aggregators can't enforce this bound
+        /// and a misbehaving Client may exceed it. It must not be greater than `2^bits - 1`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        client_element_max: Option<u64>,
     },
 
     /// A variant of `SumVec` that uses a smaller field (`Field64`), multiple proofs, and a custom
@@ -134,7 +143,14 @@ impl std::fmt::Display for Prio3Config {
                 bits,
                 length,
                 chunk_length,
+                client_element_max: None,
             } => write!(f, "SumVec({bits},{length},{chunk_length})"),
+            Prio3Config::SumVec {
+                bits,
+                length,
+                chunk_length,
+                client_element_max: Some(element_max),
+            } => write!(f, "SumVec({bits},{length},{chunk_length},{element_max})"),
             Prio3Config::SumVecField64MultiproofHmacSha256Aes128 {
                 bits,
                 length,
@@ -145,6 +161,25 @@ impl std::fmt::Display for Prio3Config {
     }
 }
 
+impl Prio3Config {
+    /// Check that the parameters are consistent with one another.
+    pub(crate) fn validate(&self) -> Result<(), DapError> {
+        if let Self::SumVec {
+            bits,
+            client_element_max: Some(element_max),
+            ..
+        } = self
+        {
+            if *bits < 64 && *element_max > (1 << *bits) - 1 {
+                return Err(fatal_error!(
+                    err = format!("SumVec element bound {element_max} does not fit in {bits} bits")
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 /// A VDAF verification key.
 #[derive(Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -353,6 +388,11 @@ impl VdafConfig {
         }
     }
 
+    /// The length in bytes of the VDAF verification key.
+    pub fn verify_key_len(&self) -> usize {
+        self.uninitialized_verify_key().as_ref().len()
+    }
+
     /// Parse a verification key from raw bytes.
     pub fn get_decoded_verify_key(&self, bytes: &[u8]) -> Result<VdafVerifyKey, CodecError> {
         match self {
@@ -373,6 +413,16 @@ impl VdafConfig {
         }
     }
 
+    /// Return the length of the encoded input share sent to the Leader (if `is_leader` is set) or
+    /// to the Helper. This is `None` for VDAFs whose input share length is not fixed by the task
+    /// configuration.
+    pub fn input_share_len(&self, is_leader: bool) -> Option<usize> {
+        match self {
+            Self::Prio3(prio3_config) => prio3_input_share_len(prio3_config, is_leader),
+            _ => None,
+        }
+    }
+
     /// Generate the Aggregators' shared verification parameters.
     pub fn gen_verify_key(&self) -> VdafVerifyKey {
         let mut rng = thread_rng();
@@ -381,14 +431,28 @@ impl VdafConfig {
         verify_key
     }
 
-    /// Checks if the provided aggregation parameter is valid for the underling VDAF being
-    /// executed.
-    pub fn is_valid_agg_param(&self, agg_param: &[u8]) -> bool {
+    /// Check that the encoded aggregation parameter is valid for the VDAF. VDAFs that don't take
+    /// an aggregation parameter expect it to be empty; Mastic expects a well-formed Poplar1
+    /// aggregation parameter.
+    pub fn validate_agg_param(&self, task_id: &TaskId, agg_param: &[u8]) -> Result<(), DapAbort> {
+        let invalid = |detail: &str| {
+            // TODO spec: Define this behavior.
+            Err(DapAbort::InvalidMessage {
+                detail: format!("invalid aggregation parameter: {detail}"),
+                task_id: *task_id,
+            })
+        };
+
         match self {
-            Self::Prio3(..) | Self::Prio2 { .. } => agg_param.is_empty(),
+            Self::Prio3(..) | Self::Prio2 { .. } | Self::Pine(..) if !agg_param.is_empty() => {
+                invalid(&format!("{self} does not take an aggregation parameter"))
+            }
             #[cfg(feature = "experimental")]
-            Self::Mastic { .. } => true,
-            Self::Pine(..) => agg_param.is_empty(),
+            Self::Mastic { .. } => match Poplar1AggregationParam::get_decoded(agg_param) {
+                Ok(..) => Ok(()),
+                Err(e) => invalid(&e.to_string()),
+            },
+            _ => Ok(()),
         }
     }
 }
@@ -500,3 +564,71 @@ where
     }
     Ok(vdaf.unshard(&(), agg_shares_vec, num_measurements)?)
 }
+
+#[cfg(test)]
+mod test {
+    use assert_matches::assert_matches;
+
+    use super::{Prio3Config, VdafConfig};
+    use crate::{error::DapAbort, messages::TaskId};
+
+    #[test]
+    fn validate_agg_param() {
+        let task_id = TaskId([1; 32]);
+        let vdaf = VdafConfig::Prio3(Prio3Config::Sum { bits: 8 });
+        assert_eq!(vdaf.validate_agg_param(&task_id, &[]), Ok(()));
+        assert_matches!(
+            vdaf.validate_agg_param(&task_id, b"not empty"),
+            Err(DapAbort::InvalidMessage { task_id: t, .. }) if t == task_id
+        );
+    }
+
+    #[test]
+    fn verify_key_len() {
+        for vdaf in [
+            VdafConfig::Prio2 { dimension: 10 },
+            VdafConfig::Prio3(Prio3Config::Sum { bits: 8 }),
+            VdafConfig::Prio3(Prio3Config::SumVecField64MultiproofHmacSha256Aes128 {
+                bits: 1,
+                length: 10,
+                chunk_length: 2,
+                num_proofs: 2,
+            }),
+        ] {
+            let len = vdaf.verify_key_len();
+            assert_eq!(vdaf.gen_verify_key().as_ref().len(), len);
+            vdaf.get_decoded_verify_key(&vec![0; len]).unwrap();
+            vdaf.get_decoded_verify_key(&vec![0; len - 1]).unwrap_err();
+        }
+    }
+
+    #[cfg(feature = "experimental")]
+    #[test]
+    fn validate_agg_param_mastic() {
+        use prio::{codec::Encode, idpf::IdpfInput, vdaf::poplar1::Poplar1AggregationParam};
+
+        use super::MasticWeightConfig;
+
+        let task_id = TaskId([1; 32]);
+        let vdaf = VdafConfig::Mastic {
+            input_size: 4,
+            weight_config: MasticWeightConfig::Count,
+        };
+        let agg_param = Poplar1AggregationParam::try_from_prefixes(vec![
+            IdpfInput::from_bytes(b"cool"),
+            IdpfInput::from_bytes(b"trip"),
+        ])
+        .unwrap()
+        .get_encoded()
+        .unwrap();
+        assert_eq!(vdaf.validate_agg_param(&task_id, &agg_param), Ok(()));
+        assert_matches!(
+            vdaf.validate_agg_param(&task_id, &[]),
+            Err(DapAbort::InvalidMessage { .. })
+        );
+        assert_matches!(
+            vdaf.validate_agg_param(&task_id, &agg_param[..agg_param.len() - 1]),
+            Err(DapAbort::InvalidMessage { .. })
+        );
+    }
+}
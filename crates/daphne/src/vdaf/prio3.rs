@@ -11,11 +11,12 @@ use crate::{
     VdafPrepState,
 };
 use prio::{
-    codec::ParameterizedDecode,
-    field::Field64,
+    codec::{CodecError, ParameterizedDecode},
+    field::{Field128, Field64, FieldElement},
     flp::{
         gadgets::{Mul, ParallelSum},
-        types::SumVec,
+        types::{Count, Sum, SumVec},
+        Type,
     },
     vdaf::{
         prio3::{Prio3, Prio3InputShare, Prio3PrepareShare, Prio3PrepareState, Prio3PublicShare},
@@ -51,6 +52,68 @@ fn new_prio3_sum_vec_field64_multiproof_hmac_sha256_aes128(
     .map_err(|e| VdafError::Dap(fatal_error!(err = ?e, "failed to create prio3")))
 }
 
+/// Length of the seeds used by Prio3, except for the multiproof variant.
+const PRIO3_SEED_SIZE: usize = 16;
+
+/// Length of an encoded Prio3 input share for the given FLP type. The Leader's share is its share
+/// of the encoded measurement and of the proofs. The Helper's share is a seed for each of these.
+/// If the type uses joint randomness, both shares are followed by a joint randomness blind.
+fn input_share_len_for_type<T: Type>(
+    typ: &T,
+    num_proofs: usize,
+    seed_size: usize,
+    is_leader: bool,
+) -> usize {
+    let joint_rand_blind_len = if typ.joint_rand_len() > 0 {
+        seed_size
+    } else {
+        0
+    };
+    let shares_len = if is_leader {
+        (typ.input_len() + typ.proof_len() * num_proofs) * T::Field::ENCODED_SIZE
+    } else {
+        2 * seed_size
+    };
+    shares_len + joint_rand_blind_len
+}
+
+/// Length of an encoded input share for the given Prio3 config, sent to the Leader (if
+/// `is_leader` is set) or to the Helper. This is `None` if the length isn't computed for the
+/// config or if the config is invalid.
+pub(crate) fn prio3_input_share_len(config: &Prio3Config, is_leader: bool) -> Option<usize> {
+    match config {
+        Prio3Config::Count => Some(input_share_len_for_type(
+            &Count::<Field64>::new(),
+            1,
+            PRIO3_SEED_SIZE,
+            is_leader,
+        )),
+        Prio3Config::Sum { bits } => Some(input_share_len_for_type(
+            &Sum::<Field128>::new(*bits).ok()?,
+            1,
+            PRIO3_SEED_SIZE,
+            is_leader,
+        )),
+        Prio3Config::SumVec {
+            bits,
+            length,
+            chunk_length,
+            client_element_max: _,
+        } => Some(input_share_len_for_type(
+            &SumVec::<Field128, ParallelSum<Field128, Mul<Field128>>>::new(
+                *bits,
+                *length,
+                *chunk_length,
+            )
+            .ok()?,
+            1,
+            PRIO3_SEED_SIZE,
+            is_leader,
+        )),
+        _ => None,
+    }
+}
+
 /// Split the given measurement into a sequence of encoded input shares.
 pub(crate) fn prio3_shard(
     config: &Prio3Config,
@@ -98,9 +161,17 @@ pub(crate) fn prio3_shard(
                 bits,
                 length,
                 chunk_length,
+                client_element_max,
             },
             DapMeasurement::U128Vec(measurement),
         ) => {
+            if let Some(element_max) = client_element_max {
+                if measurement.iter().any(|e| *e > u128::from(*element_max)) {
+                    return Err(VdafError::Dap(fatal_error!(
+                        err = format!("measurement has an element greater than {element_max}")
+                    )));
+                }
+            }
             let vdaf = Prio3::new_sum_vec(2, *bits, *length, *chunk_length)
                 .map_err(|e| VdafError::Dap(fatal_error!(err = ?e, "failed to create prio3 sum vec from num_aggregators(2), bits({bits}), length({length}), chunk_length({chunk_length})")))?;
             shard_then_encode(&vdaf, &measurement, nonce)
@@ -200,9 +271,25 @@ pub(crate) fn prio3_prep_init(
                 bits,
                 length,
                 chunk_length,
+                client_element_max: _,
             },
             VdafVerifyKey::L16(verify_key),
         ) => {
+            // Check that the input share encodes a vector of the expected length before
+            // attempting to decode it.
+            let expected_len = prio3_input_share_len(config, agg_id == 0).ok_or_else(|| {
+                VdafError::Dap(fatal_error!(
+                    err = "failed to compute the length of a prio3 sum vec input share"
+                ))
+            })?;
+            if input_share_data.len() != expected_len {
+                tracing::warn!(
+                    len = input_share_data.len(),
+                    expected_len,
+                    "prio3 sum vec input share has unexpected length"
+                );
+                return Err(VdafError::Codec(CodecError::UnexpectedValue));
+            }
             let vdaf = Prio3::new_sum_vec(2, *bits, *length, *chunk_length)
                 .map_err(|e| VdafError::Dap(fatal_error!(err = ?e, "failed to create prio3 sum vec from num_aggregators(2), bits({bits}), length({length}), chunk_length({chunk_length})")))?;
             let (state, share) = prep_init(
@@ -338,6 +425,7 @@ pub(crate) fn prio3_prep_finish_from_shares(
                 bits,
                 length,
                 chunk_length,
+                client_element_max: _,
             },
             VdafPrepState::Prio3Field128(state),
             VdafPrepShare::Prio3Field128(share),
@@ -419,6 +507,7 @@ pub(crate) fn prio3_prep_finish(
                 bits,
                 length,
                 chunk_length,
+                client_element_max: _,
             },
             VdafPrepState::Prio3Field128(state),
         ) => {
@@ -491,6 +580,7 @@ pub(crate) fn prio3_unshard<M: IntoIterator<Item = Vec<u8>>>(
             bits,
             length,
             chunk_length,
+            client_element_max: _,
         } => {
             let vdaf = Prio3::new_sum_vec(2, *bits, *length, *chunk_length)
                 .map_err(|e| VdafError::Dap(fatal_error!(err = ?e, "failed to create prio3 sum vec from num_aggregators(2), bits({bits}), length({length}), chunk_length({chunk_length})")))?;
@@ -518,6 +608,7 @@ pub(crate) fn prio3_unshard<M: IntoIterator<Item = Vec<u8>>>(
 #[cfg(test)]
 mod test {
 
+    use assert_matches::assert_matches;
     use prio::vdaf::prio3_test::check_test_vec;
 
     use crate::{
@@ -525,7 +616,11 @@ mod test {
         hpke::HpkeKemId,
         testing::AggregationJobTest,
         vdaf::{
-            prio3::new_prio3_sum_vec_field64_multiproof_hmac_sha256_aes128, Prio3Config, VdafConfig,
+            prio3::{
+                new_prio3_sum_vec_field64_multiproof_hmac_sha256_aes128, prio3_prep_init,
+                prio3_shard,
+            },
+            Prio3Config, VdafConfig, VdafError, VdafVerifyKey,
         },
         DapAggregateResult, DapAggregationParam, DapMeasurement, DapVersion,
     };
@@ -582,6 +677,7 @@ mod test {
                 bits: 23,
                 length: 2,
                 chunk_length: 1,
+                client_element_max: None,
             }),
             HpkeKemId::X25519HkdfSha256,
             version,
@@ -601,6 +697,112 @@ mod test {
 
     async_test_versions! { roundtrip_sum_vec }
 
+    #[test]
+    fn shard_sum_vec_client_element_max() {
+        let config = Prio3Config::SumVec {
+            bits: 3,
+            length: 2,
+            chunk_length: 1,
+            client_element_max: Some(5),
+        };
+
+        assert!(prio3_shard(&config, DapMeasurement::U128Vec(vec![5, 0]), &[0; 16]).is_ok());
+
+        // The measurement fits in `bits`, but one of its elements exceeds `client_element_max`.
+        assert!(prio3_shard(&config, DapMeasurement::U128Vec(vec![6, 0]), &[0; 16]).is_err());
+    }
+
+    #[test]
+    fn prep_init_sum_vec_input_share_len() {
+        let sum_vec = |length| Prio3Config::SumVec {
+            bits: 3,
+            length,
+            chunk_length: 1,
+            client_element_max: None,
+        };
+        let config = sum_vec(2);
+        let verify_key = VdafVerifyKey::L16([0; 16]);
+        let nonce = [1; 16];
+
+        let (public_share, input_shares) =
+            prio3_shard(&config, DapMeasurement::U128Vec(vec![5, 0]), &nonce).unwrap();
+        for (agg_id, input_share) in input_shares.iter().enumerate() {
+            prio3_prep_init(
+                &config,
+                &verify_key,
+                agg_id,
+                &nonce,
+                &public_share,
+                input_share,
+            )
+            .unwrap();
+        }
+
+        // The Leader's share of a vector of the wrong length is rejected.
+        let (public_share, [leader_input_share, _helper_input_share]) =
+            prio3_shard(&sum_vec(3), DapMeasurement::U128Vec(vec![5, 0, 1]), &nonce).unwrap();
+        assert_matches!(
+            prio3_prep_init(
+                &config,
+                &verify_key,
+                0,
+                &nonce,
+                &public_share,
+                &leader_input_share,
+            ),
+            Err(VdafError::Codec(..))
+        );
+    }
+
+    #[test]
+    fn input_share_len() {
+        for (config, measurement) in [
+            (Prio3Config::Count, DapMeasurement::U64(1)),
+            (Prio3Config::Sum { bits: 23 }, DapMeasurement::U64(1337)),
+            (
+                Prio3Config::SumVec {
+                    bits: 3,
+                    length: 4,
+                    chunk_length: 2,
+                    client_element_max: None,
+                },
+                DapMeasurement::U128Vec(vec![5, 0, 7, 1]),
+            ),
+        ] {
+            let vdaf_config = VdafConfig::Prio3(config);
+            let (_public_share, [leader_input_share, helper_input_share]) =
+                prio3_shard(&config, measurement, &[1; 16]).unwrap();
+            assert_eq!(
+                vdaf_config.input_share_len(true),
+                Some(leader_input_share.len())
+            );
+            assert_eq!(
+                vdaf_config.input_share_len(false),
+                Some(helper_input_share.len())
+            );
+        }
+
+        // The Helper's share is a seed for its measurement share and a seed for its proof share.
+        // Sum also requires a joint randomness blind.
+        assert_eq!(
+            VdafConfig::Prio3(Prio3Config::Count).input_share_len(false),
+            Some(32)
+        );
+        assert_eq!(
+            VdafConfig::Prio3(Prio3Config::Sum { bits: 23 }).input_share_len(false),
+            Some(48)
+        );
+
+        assert_eq!(
+            VdafConfig::Prio3(Prio3Config::Histogram {
+                length: 3,
+                chunk_length: 1,
+            })
+            .input_share_len(false),
+            None
+        );
+    }
+
     async fn roundtrip_histogram(version: DapVersion) {
         let mut t = AggregationJobTest::new(
             &VdafConfig::Prio3(Prio3Config::Histogram {